@@ -0,0 +1,60 @@
+//! 分组聚合在不同内存阈值下的耗时对比，观察自适应分区和落盘的效果
+//!
+//!     cargo run --release --example groupby_spill -- [行数]
+use cache::adaptive::AdaptiveAggregateConfig;
+use cache::pool::DB;
+use datafusion::arrow::array::Int64Array;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::MemTable;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let rows: i64 = match std::env::args().nth(1) {
+        Some(rows) => rows.parse()?,
+        None => 2_000_000,
+    };
+    let db = DB::<()>::new("groupby_spill");
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("user_id", DataType::Int64, false),
+        Field::new("amount", DataType::Int64, false),
+    ]));
+    let batches = (0..rows)
+        .step_by(8192)
+        .map(|start| {
+            let end = (start + 8192).min(rows);
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from_iter_values(start..end)),
+                    Arc::new(Int64Array::from_iter_values((start..end).map(|v| v % 100))),
+                ],
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    db.ctx.register_table(
+        "events",
+        Arc::new(MemTable::try_new(schema, vec![batches])?),
+    )?;
+    let sql = "SELECT user_id, SUM(amount) FROM events GROUP BY user_id";
+
+    for mb in [256, 64, 16, 4] {
+        let config = AdaptiveAggregateConfig {
+            memory_threshold: mb * 1024 * 1024,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let run = db.query_adaptive(sql, &config).await?;
+        println!(
+            "threshold {:>3}MB: {:>8?}, {} attempts, {} partitions, spilled: {}",
+            mb,
+            start.elapsed(),
+            run.attempts,
+            run.target_partitions,
+            run.spilled
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,370 @@
+use crate::pool::DB;
+use async_trait::async_trait;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::datasource::listing::ListingTable;
+use datafusion::datasource::physical_plan::{
+    ArrowExec, CsvExec, FileScanConfig, NdJsonExec, ParquetExec,
+};
+use datafusion::datasource::{source_as_provider, TableProvider};
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::physical_plan::ExecutionPlan;
+use futures::stream::BoxStream;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOpts,
+    PutOptions, PutPayload, PutResult, Result, UploadPart,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "storage")]
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+/// 单个存储的读写计数
+#[derive(Debug, Default)]
+pub struct StorageCounters {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    requests: AtomicU64,
+}
+
+impl StorageCounters {
+    fn read(&self, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn write(&self, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 统计读写字节数的 ObjectStore 封装，注册存储时自动套上
+#[derive(Debug)]
+pub struct AccountingStore {
+    inner: Arc<dyn ObjectStore>,
+    counters: Arc<StorageCounters>,
+}
+
+impl AccountingStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, counters: Arc<StorageCounters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl Display for AccountingStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AccountingStore({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for AccountingStore {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        self.counters.write(payload.content_length());
+        self.inner.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        self.counters.request();
+        let upload = self.inner.put_multipart_opts(location, opts).await?;
+        Ok(Box::new(AccountingUpload {
+            inner: upload,
+            counters: self.counters.clone(),
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let head = options.head;
+        let result = self.inner.get_opts(location, options).await?;
+        let bytes = if head {
+            0
+        } else {
+            result.range.end - result.range.start
+        };
+        self.counters.read(bytes);
+        Ok(result)
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.counters.request();
+        self.inner.delete(location).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.counters.request();
+        self.inner.list(prefix)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.counters.request();
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.counters.request();
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.counters.request();
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[derive(Debug)]
+struct AccountingUpload {
+    inner: Box<dyn MultipartUpload>,
+    counters: Arc<StorageCounters>,
+}
+
+#[async_trait]
+impl MultipartUpload for AccountingUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        self.counters.write(data.content_length());
+        self.inner.put_part(data)
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        self.inner.complete().await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.inner.abort().await
+    }
+}
+
+/// 单个存储的用量
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageUsage {
+    pub storage: String,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub requests: u64,
+    // 根据配置的单价估算，没有配置单价时为 0
+    pub estimated_cost: f64,
+}
+
+/// 单个外部表的扫描用量
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableUsage {
+    pub table: String,
+    pub scans: u64,
+    pub bytes_read: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountingReport {
+    pub storages: Vec<StorageUsage>,
+    pub tables: Vec<TableUsage>,
+    pub total_estimated_cost: f64,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 按存储和表汇总的读写字节数和估算费用
+    pub fn accounting_report(&self) -> AccountingReport {
+        #[cfg(feature = "storage")]
+        let mut storages: Vec<StorageUsage> = {
+            let registered = self.registered_storages.read().unwrap();
+            registered
+                .iter()
+                .map(|(name, entry)| {
+                    let bytes_read = entry.counters.bytes_read.load(Ordering::Relaxed);
+                    let bytes_written = entry.counters.bytes_written.load(Ordering::Relaxed);
+                    let estimated_cost = bytes_read as f64 / BYTES_PER_GB
+                        * entry.config.cost_per_gb_read.unwrap_or(0.0)
+                        + bytes_written as f64 / BYTES_PER_GB
+                            * entry.config.cost_per_gb_written.unwrap_or(0.0);
+                    StorageUsage {
+                        storage: name.clone(),
+                        bytes_read,
+                        bytes_written,
+                        requests: entry.counters.requests.load(Ordering::Relaxed),
+                        estimated_cost,
+                    }
+                })
+                .collect()
+        };
+        #[cfg(not(feature = "storage"))]
+        let mut storages: Vec<StorageUsage> = Vec::new();
+        storages.sort_by(|a, b| a.storage.cmp(&b.storage));
+
+        let mut tables: Vec<TableUsage> =
+            self.table_usage.read().unwrap().values().cloned().collect();
+        tables.sort_by(|a, b| b.bytes_read.cmp(&a.bytes_read).then(a.table.cmp(&b.table)));
+
+        let total_estimated_cost = storages.iter().map(|s| s.estimated_cost).sum();
+        AccountingReport {
+            storages,
+            tables,
+            total_estimated_cost,
+        }
+    }
+
+    // 把执行计划中各个文件扫描节点读取的字节数记到对应的外部表上
+    pub(crate) fn record_table_usage(
+        &self,
+        logical: &LogicalPlan,
+        physical: &Arc<dyn ExecutionPlan>,
+    ) {
+        let tables = listing_tables(logical);
+        if tables.is_empty() {
+            return;
+        }
+
+        let mut scans = Vec::new();
+        collect_file_scans(physical, &mut scans);
+
+        let mut usage = self.table_usage.write().unwrap();
+        for (table, listing) in tables {
+            let bytes: u64 = scans
+                .iter()
+                .filter(|(config, _)| scans_table(config, listing.as_ref()))
+                .map(|(_, bytes)| *bytes)
+                .sum();
+            let entry = usage.entry(table.clone()).or_insert_with(|| TableUsage {
+                table,
+                ..Default::default()
+            });
+            entry.scans += 1;
+            entry.bytes_read += bytes;
+        }
+    }
+}
+
+// 逻辑计划中扫描的外部表（表名, ListingTable）
+fn listing_tables(plan: &LogicalPlan) -> Vec<(String, Arc<dyn TableProvider>)> {
+    let mut tables = Vec::new();
+    let _ = plan.apply(|node| {
+        if let LogicalPlan::TableScan(scan) = node {
+            if let Ok(provider) = source_as_provider(&scan.source) {
+                if provider.as_any().is::<ListingTable>() {
+                    tables.push((scan.table_name.to_string(), provider));
+                }
+            }
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    tables
+}
+
+// 收集所有文件扫描节点和它们读取的字节数
+// parquet 有 bytes_scanned 指标（只算实际读取的 row group），其他格式按文件大小算
+fn collect_file_scans(plan: &Arc<dyn ExecutionPlan>, scans: &mut Vec<(FileScanConfig, u64)>) {
+    let any = plan.as_any();
+    let config = if let Some(exec) = any.downcast_ref::<ParquetExec>() {
+        Some(exec.base_config())
+    } else if let Some(exec) = any.downcast_ref::<CsvExec>() {
+        Some(exec.base_config())
+    } else if let Some(exec) = any.downcast_ref::<NdJsonExec>() {
+        Some(exec.base_config())
+    } else if let Some(exec) = any.downcast_ref::<ArrowExec>() {
+        Some(exec.base_config())
+    } else {
+        None
+    };
+
+    if let Some(config) = config {
+        let bytes = plan
+            .metrics()
+            .and_then(|m| m.sum_by_name("bytes_scanned"))
+            .map(|v| v.as_usize() as u64)
+            .unwrap_or_else(|| {
+                config
+                    .file_groups
+                    .iter()
+                    .flatten()
+                    .map(|f| f.object_meta.size as u64)
+                    .sum()
+            });
+        scans.push((config.clone(), bytes));
+    }
+    for child in plan.children() {
+        collect_file_scans(child, scans);
+    }
+}
+
+fn scans_table(config: &FileScanConfig, provider: &dyn TableProvider) -> bool {
+    let Some(table) = provider.as_any().downcast_ref::<ListingTable>() else {
+        return false;
+    };
+    table.table_paths().iter().any(|url| {
+        url.object_store() == config.object_store_url
+            && config.file_groups.iter().flatten().any(|f| {
+                f.object_meta
+                    .location
+                    .as_ref()
+                    .starts_with(url.prefix().as_ref())
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_accounting_store_counts_bytes() -> anyhow::Result<()> {
+        let counters = Arc::new(StorageCounters::default());
+        let store = AccountingStore::new(Arc::new(InMemory::new()), counters.clone());
+        let path = Path::from("a/b.csv");
+
+        store.put(&path, PutPayload::from("0123456789")).await?;
+        store.get(&path).await?.bytes().await?;
+        store.get_range(&path, 2..5).await?;
+        store.head(&path).await?;
+
+        assert_eq!(counters.bytes_written.load(Ordering::Relaxed), 10);
+        assert_eq!(counters.bytes_read.load(Ordering::Relaxed), 13);
+        assert_eq!(counters.requests.load(Ordering::Relaxed), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_table_usage() -> anyhow::Result<()> {
+        let db = DB::<()>::new("test_db");
+        let input_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("data")
+            .join("test1.csv");
+        let size = std::fs::metadata(&input_path)?.len();
+        db.execute(&format!(
+            "CREATE EXTERNAL TABLE users STORED AS CSV LOCATION '{}'",
+            input_path.to_string_lossy()
+        ))
+        .await?;
+
+        db.query_to_batches("SELECT * FROM users").await?;
+        db.query_to_batches("SELECT COUNT(*) FROM users").await?;
+
+        let report = db.accounting_report();
+        assert_eq!(
+            report.tables,
+            vec![TableUsage {
+                table: "users".to_string(),
+                scans: 2,
+                bytes_read: size * 2,
+            }]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::DataFusionError;
+use datafusion::execution::disk_manager::DiskManagerConfig;
+use datafusion::execution::memory_pool::{FairSpillPool, GreedyMemoryPool, MemoryPool};
+use datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use datafusion::execution::SessionStateBuilder;
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::prelude::SessionContext;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+/// 分组聚合的内存限制
+#[derive(Debug, Clone)]
+pub struct AdaptiveAggregateConfig {
+    // 每个分区的哈希表可以使用的内存，总内存 = 阈值 * 分区数
+    pub memory_threshold: usize,
+    // 超过阈值时分区数翻倍，最多到这个值，再不够就落盘
+    pub max_partitions: usize,
+}
+
+impl Default for AdaptiveAggregateConfig {
+    fn default() -> Self {
+        Self {
+            memory_threshold: 256 * 1024 * 1024,
+            max_partitions: 64,
+        }
+    }
+}
+
+/// 自适应执行的结果，以及最终使用的设置
+#[derive(Debug, Clone)]
+pub struct AdaptiveRun {
+    pub batches: Vec<RecordBatch>,
+    pub target_partitions: usize,
+    // 最后一次执行是否允许落盘
+    pub spilled: bool,
+    // 执行次数，第一次就成功时为 1
+    pub attempts: usize,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 在内存限制下执行查询，哈希表超过阈值时先增加分区数，仍然不够时允许落盘后重试
+    ///
+    /// 查询会被重复执行，所以只接受只读查询
+    pub async fn query_adaptive(
+        &self,
+        sql: &str,
+        config: &AdaptiveAggregateConfig,
+    ) -> Result<AdaptiveRun> {
+        let plan = self.ctx.state().create_logical_plan(sql).await?;
+        if matches!(
+            plan,
+            LogicalPlan::Dml(_) | LogicalPlan::Ddl(_) | LogicalPlan::Copy(_)
+        ) {
+            return Err(anyhow::anyhow!(
+                "query_adaptive only accepts read-only queries"
+            ));
+        }
+
+        let mut partitions = self.ctx.state().config().target_partitions();
+        let mut spill = false;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let ctx = self.limited_context(config.memory_threshold, partitions, spill)?;
+            match ctx
+                .execute_logical_plan(plan.clone())
+                .await?
+                .collect()
+                .await
+            {
+                Ok(batches) => {
+                    return Ok(AdaptiveRun {
+                        batches,
+                        target_partitions: partitions,
+                        spilled: spill,
+                        attempts,
+                    })
+                }
+                Err(e) if !spill && is_resources_exhausted(&e) => {
+                    if partitions * 2 <= config.max_partitions {
+                        partitions *= 2;
+                    } else {
+                        spill = true;
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // 共享表和配置，只替换运行时的内存池和分区数；落盘到 TempPlacement 配置的本地目录
+    fn limited_context(
+        &self,
+        threshold: usize,
+        partitions: usize,
+        spill: bool,
+    ) -> Result<SessionContext> {
+        let limit = threshold.saturating_mul(partitions);
+        let (pool, disk): (Arc<dyn MemoryPool>, _) = if spill {
+            (
+                Arc::new(FairSpillPool::new(limit)),
+                DiskManagerConfig::NewSpecified(vec![self.spill_dir()]),
+            )
+        } else {
+            (
+                Arc::new(GreedyMemoryPool::new(limit)),
+                DiskManagerConfig::Disabled,
+            )
+        };
+        let runtime = RuntimeEnvBuilder::new()
+            .with_memory_pool(pool)
+            .with_disk_manager(disk)
+            .build_arc()?;
+        let state = self.ctx.state();
+        // 默认 catalog 已经存在，不能再创建一个空的覆盖掉
+        let mut config = state
+            .config()
+            .clone()
+            .with_target_partitions(partitions)
+            .with_create_default_catalog_and_schema(false);
+        if spill {
+            // 落盘合并时预留的内存默认是 10MB，不能超过内存池的大小
+            let execution = &mut config.options_mut().execution;
+            execution.sort_spill_reservation_bytes =
+                execution.sort_spill_reservation_bytes.min(limit / 4);
+        }
+        let state = SessionStateBuilder::new_from_existing(state)
+            .with_config(config)
+            .with_runtime_env(runtime)
+            .build();
+        Ok(SessionContext::new_with_state(state))
+    }
+}
+
+// 内存不足的错误可能被 RepartitionExec 等算子包装成 External 错误
+fn is_resources_exhausted(e: &DataFusionError) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+    while let Some(err) = source {
+        if let Some(DataFusionError::ResourcesExhausted(_)) = err.downcast_ref() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::datasource::MemTable;
+
+    #[tokio::test]
+    async fn test_query_adaptive() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        // 分成多个批次，哈希表随着批次逐渐变大
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("user_id", DataType::Int64, false),
+            Field::new("amount", DataType::Int64, false),
+        ]));
+        let batches = (0..25)
+            .map(|i| {
+                let ids = Int64Array::from_iter_values(i * 8192..(i + 1) * 8192);
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(ids.clone()), Arc::new(ids)])
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        db.ctx.register_table(
+            "events",
+            Arc::new(MemTable::try_new(schema, vec![batches])?),
+        )?;
+        let sql = "SELECT user_id, SUM(amount) FROM events GROUP BY user_id";
+
+        let run = db
+            .query_adaptive(sql, &AdaptiveAggregateConfig::default())
+            .await?;
+        assert_eq!(run.attempts, 1);
+        assert!(!run.spilled);
+        let rows: usize = run.batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 25 * 8192);
+
+        // 固定从 1 个分区开始，结果不受机器核数影响
+        db.ctx
+            .state_ref()
+            .write()
+            .config_mut()
+            .options_mut()
+            .execution
+            .target_partitions = 1;
+        let config = AdaptiveAggregateConfig {
+            memory_threshold: 4 * 1024 * 1024,
+            max_partitions: 2,
+        };
+        let run = db.query_adaptive(sql, &config).await?;
+        assert_eq!((run.attempts, run.target_partitions), (2, 2));
+        assert!(!run.spilled);
+        let rows: usize = run.batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 25 * 8192);
+
+        // 不允许增加分区时直接落盘
+        let run = db
+            .query_adaptive(
+                sql,
+                &AdaptiveAggregateConfig {
+                    max_partitions: 1,
+                    ..config
+                },
+            )
+            .await?;
+        assert_eq!((run.attempts, run.target_partitions), (2, 1));
+        assert!(run.spilled);
+        let rows: usize = run.batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 25 * 8192);
+
+        assert!(db
+            .query_adaptive("DROP TABLE events", &Default::default())
+            .await
+            .is_err());
+        Ok(())
+    }
+}
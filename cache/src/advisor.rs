@@ -0,0 +1,285 @@
+use crate::ident::quote_ident;
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use arrow_schema::{DataType, SchemaRef};
+use datafusion::arrow::array::{Array, Int64Array};
+use datafusion::physical_plan::ExecutionPlanProperties;
+use serde::{de::DeserializeOwned, Serialize};
+
+// 同一类聚合查询至少执行多少次才建议物化
+const MIN_EXECUTIONS: u64 = 3;
+// 平均每次扫描多少行以上才值得物化
+const MIN_ROWS_PER_EXECUTION: u64 = 1000;
+// 表至少多少行才考虑字典编码
+const MIN_DICTIONARY_ROWS: usize = 1000;
+// 不同值占比低于这个比例才建议字典编码
+const MAX_DICTIONARY_RATIO: f64 = 0.1;
+// 超过这个行数的表建议按 target_partitions 分区
+const LARGE_TABLE_ROWS: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdviceKind {
+    // 把频繁执行的聚合查询物化成表
+    MaterializeAggregation,
+    // 低基数的字符串列使用字典编码
+    DictionaryEncode,
+    // 经常按日期过滤的表按日期列分区
+    PartitionBy,
+    // 大表的分区数少于 target_partitions，扫描并行度不够
+    IncreasePartitions,
+}
+
+/// 一条优化建议
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advice {
+    pub kind: AdviceKind,
+    // 表名或者查询的 fingerprint
+    pub target: String,
+    // 建议的具体操作
+    pub action: String,
+    // 预估收益的说明
+    pub estimated_benefit: String,
+    // 用于排序的收益估值，越大越值得做
+    pub score: f64,
+}
+
+// 内存表的统计信息
+struct TableStats {
+    name: String,
+    schema: SchemaRef,
+    rows: usize,
+    partitions: usize,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 根据 workload 统计和表的统计信息给出优化建议，按收益从大到小排序
+    pub async fn advise(&self) -> Result<Vec<Advice>> {
+        let mut advice = Vec::new();
+        let workload = self.workload_summary();
+        let tables = self.memory_table_stats().await?;
+        let target_partitions = self.ctx.state().config().target_partitions();
+
+        for summary in &workload {
+            let per_execution = summary.rows_scanned / summary.executions.max(1);
+            if summary.normalized_sql.starts_with("select")
+                && summary.normalized_sql.contains(" group by ")
+                && summary.executions >= MIN_EXECUTIONS
+                && per_execution >= MIN_ROWS_PER_EXECUTION
+            {
+                let saved_ms = summary.mean_ms * summary.executions as f64;
+                advice.push(Advice {
+                    kind: AdviceKind::MaterializeAggregation,
+                    target: summary.fingerprint.clone(),
+                    action: format!(
+                        "CREATE TABLE agg_{} AS {}",
+                        summary.fingerprint, summary.sample_sql
+                    ),
+                    estimated_benefit: format!(
+                        "avoid scanning ~{} rows per run, ~{:.0} ms over {} runs",
+                        per_execution, saved_ms, summary.executions
+                    ),
+                    score: saved_ms,
+                });
+            }
+        }
+
+        for table in &tables {
+            if table.rows >= MIN_DICTIONARY_ROWS {
+                for field in table.schema.fields() {
+                    if field.data_type() != &DataType::Utf8 {
+                        continue;
+                    }
+                    let (distinct, bytes) =
+                        self.string_column_stats(&table.name, field.name()).await?;
+                    let ratio = distinct as f64 / table.rows as f64;
+                    if ratio > MAX_DICTIONARY_RATIO {
+                        continue;
+                    }
+                    // 字典编码后：不同值各存一份 + 每行一个 i32 key
+                    let avg_len = bytes as f64 / table.rows as f64;
+                    let saved = bytes as f64 - avg_len * distinct as f64 - table.rows as f64 * 4.0;
+                    if saved <= 0.0 {
+                        continue;
+                    }
+                    advice.push(Advice {
+                        kind: AdviceKind::DictionaryEncode,
+                        target: table.name.clone(),
+                        action: format!(
+                            "dictionary-encode column {}.{} ({} distinct values in {} rows)",
+                            table.name,
+                            field.name(),
+                            distinct,
+                            table.rows
+                        ),
+                        estimated_benefit: format!("save ~{:.0} bytes of memory", saved),
+                        score: saved / 1024.0,
+                    });
+                }
+            }
+
+            for field in table.schema.fields() {
+                if !is_date_like(field.name(), field.data_type()) {
+                    continue;
+                }
+                let column = field.name().to_lowercase();
+                let filtered: Vec<_> = workload
+                    .iter()
+                    .filter(|s| references_table(&s.normalized_sql, &table.name))
+                    .filter(|s| {
+                        s.normalized_sql
+                            .split_once(" where ")
+                            .map(|(_, predicate)| predicate.contains(&column))
+                            .unwrap_or(false)
+                    })
+                    .collect();
+                if filtered.is_empty() {
+                    continue;
+                }
+                let rows: u64 = filtered.iter().map(|s| s.rows_scanned).sum();
+                advice.push(Advice {
+                    kind: AdviceKind::PartitionBy,
+                    target: table.name.clone(),
+                    action: format!("partition table {} by {}", table.name, field.name()),
+                    estimated_benefit: format!(
+                        "{} queries filter on {}, scanning {} rows in total",
+                        filtered.len(),
+                        field.name(),
+                        rows
+                    ),
+                    score: rows as f64 / 1000.0,
+                });
+            }
+
+            if table.rows >= LARGE_TABLE_ROWS && table.partitions < target_partitions {
+                advice.push(Advice {
+                    kind: AdviceKind::IncreasePartitions,
+                    target: table.name.clone(),
+                    action: format!(
+                        "repartition table {} from {} to {} partitions",
+                        table.name, table.partitions, target_partitions
+                    ),
+                    estimated_benefit: format!(
+                        "scan parallelism x{:.1} for {} rows",
+                        target_partitions as f64 / table.partitions.max(1) as f64,
+                        table.rows
+                    ),
+                    score: table.rows as f64 / 1000.0,
+                });
+            }
+        }
+
+        advice.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        Ok(advice)
+    }
+
+    async fn memory_table_stats(&self) -> Result<Vec<TableStats>> {
+        let schema = self.default_schema()?;
+        let state = self.ctx.state();
+        let mut tables = Vec::new();
+        for name in schema.table_names() {
+            let Some(provider) = schema.table(&name).await? else {
+                continue;
+            };
+            if !is_memory_table(provider.as_ref()) {
+                continue;
+            }
+            let plan = provider.scan(&state, None, &[], None).await?;
+            tables.push(TableStats {
+                name,
+                schema: provider.schema(),
+                rows: plan
+                    .statistics()?
+                    .num_rows
+                    .get_value()
+                    .copied()
+                    .unwrap_or(0),
+                partitions: plan.output_partitioning().partition_count(),
+            });
+        }
+        Ok(tables)
+    }
+
+    // 返回 (不同值的数量, 总字节数)
+    async fn string_column_stats(&self, table: &str, column: &str) -> Result<(i64, i64)> {
+        let batches = self
+            .ctx
+            .sql(&format!(
+                "SELECT COUNT(DISTINCT {column}), COALESCE(SUM(octet_length({column})), 0) FROM {table}",
+                column = quote_ident(column),
+                table = quote_ident(table)
+            ))
+            .await?
+            .collect()
+            .await?;
+        let batch = batches
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Empty stats result"))?;
+        let value = |i: usize| -> Result<i64> {
+            let column = batch
+                .column(i)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .ok_or_else(|| anyhow::anyhow!("Unexpected stats type"))?;
+            Ok(if column.is_null(0) {
+                0
+            } else {
+                column.value(0)
+            })
+        };
+        Ok((value(0)?, value(1)?))
+    }
+}
+
+fn is_date_like(name: &str, data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Date32 | DataType::Date64 | DataType::Timestamp(_, _)
+    ) || name == "dt"
+        || name == "date"
+        || name.ends_with("_date")
+}
+
+fn references_table(normalized_sql: &str, table: &str) -> bool {
+    let table = table.to_lowercase();
+    normalized_sql.contains(&format!("from {}", table))
+        || normalized_sql.contains(&format!("join {}", table))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_advise() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE ledger (id BIGINT, currency VARCHAR, dt DATE, amount DOUBLE)")
+            .await?;
+        db.execute(
+            "INSERT INTO ledger SELECT value, \
+             CASE WHEN value % 2 = 0 THEN 'Hong Kong Dollar' ELSE 'US Dollar' END, \
+             CAST('2024-05-01' AS DATE), value * 1.5 \
+             FROM (SELECT unnest(range(1, 2001)) AS value)",
+        )
+        .await?;
+
+        for _ in 0..3 {
+            db.query_to_batches("SELECT currency, SUM(amount) FROM ledger GROUP BY currency")
+                .await?;
+        }
+        db.query_to_batches("SELECT * FROM ledger WHERE dt = '2024-05-01'")
+            .await?;
+
+        let advice = db.advise().await?;
+        let kinds: Vec<_> = advice.iter().map(|a| a.kind.clone()).collect();
+        assert!(kinds.contains(&AdviceKind::MaterializeAggregation));
+        assert!(kinds.contains(&AdviceKind::PartitionBy));
+        let dictionary = advice
+            .iter()
+            .find(|a| a.kind == AdviceKind::DictionaryEncode)
+            .unwrap();
+        assert!(dictionary.action.contains("ledger.currency"));
+        assert!(!kinds.contains(&AdviceKind::IncreasePartitions));
+        Ok(())
+    }
+}
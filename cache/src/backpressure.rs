@@ -0,0 +1,304 @@
+use crate::job::{JobHandle, JobOptions};
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+// 写入线程一次最多合并写入的 batch 数
+const MAX_COALESCED_BATCHES: usize = 64;
+
+/// 队列放不下时返回，batch 原样交还给调用方，可以稍后重试或者改用 send 等待
+#[derive(Debug)]
+pub enum Backpressure {
+    QueueFull {
+        table: String,
+        capacity: usize,
+        batch: RecordBatch,
+    },
+    // 队列已经关闭，或者写入任务已经退出
+    Closed {
+        table: String,
+        batch: RecordBatch,
+    },
+}
+
+impl Backpressure {
+    pub fn into_batch(self) -> RecordBatch {
+        match self {
+            Backpressure::QueueFull { batch, .. } | Backpressure::Closed { batch, .. } => batch,
+        }
+    }
+}
+
+impl fmt::Display for Backpressure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backpressure::QueueFull {
+                table, capacity, ..
+            } => write!(
+                f,
+                "ingest queue for {} is full ({} batches), slow down",
+                table, capacity
+            ),
+            Backpressure::Closed { table, .. } => {
+                write!(f, "ingest queue for {} is closed", table)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Backpressure {}
+
+/// 写入队列的累计结果
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IngestQueueStats {
+    pub written_batches: usize,
+    pub written_rows: usize,
+    pub failed_batches: usize,
+    pub last_error: Option<String>,
+}
+
+enum QueueItem {
+    Batch(RecordBatch),
+    // 之前入队的 batch 都处理完后通知
+    Flush(oneshot::Sender<()>),
+}
+
+/// 有界的写入队列，后台任务按顺序把 batch 写入表
+///
+/// 写入跟不上时 try_send 返回 Backpressure::QueueFull，send 等待队列有空位，
+/// 生产者据此降低速度，而不是在内存中堆积数据
+pub struct IngestQueue {
+    table: String,
+    capacity: usize,
+    sender: mpsc::Sender<QueueItem>,
+    stats: Arc<Mutex<IngestQueueStats>>,
+    job: JobHandle<()>,
+}
+
+impl IngestQueue {
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 排队等待写入的数量
+    pub fn queued(&self) -> usize {
+        self.capacity - self.sender.capacity()
+    }
+
+    /// 不等待，队列满时立即返回 QueueFull
+    pub fn try_send(&self, batch: RecordBatch) -> std::result::Result<(), Backpressure> {
+        self.sender
+            .try_send(QueueItem::Batch(batch))
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(item) => Backpressure::QueueFull {
+                    table: self.table.clone(),
+                    capacity: self.capacity,
+                    batch: item.into_batch(),
+                },
+                mpsc::error::TrySendError::Closed(item) => Backpressure::Closed {
+                    table: self.table.clone(),
+                    batch: item.into_batch(),
+                },
+            })
+    }
+
+    /// 等待队列有空位后入队
+    pub async fn send(&self, batch: RecordBatch) -> std::result::Result<(), Backpressure> {
+        self.sender
+            .send(QueueItem::Batch(batch))
+            .await
+            .map_err(|e| Backpressure::Closed {
+                table: self.table.clone(),
+                batch: e.0.into_batch(),
+            })
+    }
+
+    /// 最多等待 timeout，仍然没有空位时返回 QueueFull
+    pub async fn send_timeout(
+        &self,
+        batch: RecordBatch,
+        timeout: Duration,
+    ) -> std::result::Result<(), Backpressure> {
+        self.sender
+            .send_timeout(QueueItem::Batch(batch), timeout)
+            .await
+            .map_err(|e| match e {
+                mpsc::error::SendTimeoutError::Timeout(item) => Backpressure::QueueFull {
+                    table: self.table.clone(),
+                    capacity: self.capacity,
+                    batch: item.into_batch(),
+                },
+                mpsc::error::SendTimeoutError::Closed(item) => Backpressure::Closed {
+                    table: self.table.clone(),
+                    batch: item.into_batch(),
+                },
+            })
+    }
+
+    /// 等待之前入队的 batch 都写入（或者写入失败）
+    pub async fn flush(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(QueueItem::Flush(tx))
+            .await
+            .map_err(|_| anyhow::anyhow!("ingest queue for {} is closed", self.table))?;
+        rx.await
+            .map_err(|_| anyhow::anyhow!("ingest queue for {} stopped", self.table))
+    }
+
+    pub fn stats(&self) -> IngestQueueStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// 不再接受新的 batch，等待已经入队的写完
+    pub async fn close(self) -> Result<IngestQueueStats> {
+        drop(self.sender);
+        self.job.wait().await?;
+        let stats = self.stats.lock().unwrap().clone();
+        Ok(stats)
+    }
+}
+
+impl QueueItem {
+    fn into_batch(self) -> RecordBatch {
+        match self {
+            QueueItem::Batch(batch) => batch,
+            QueueItem::Flush(_) => unreachable!("only batches are returned to the producer"),
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync + 'static> DB<V> {
+    /// 为表创建最多排队 capacity 个 batch 的写入队列
+    ///
+    /// 写入经过 insert_batches，会做类型转换、检查 schema 并写入 WAL；
+    /// 单个 batch 写入失败只计入 stats，不会停止队列
+    pub fn ingest_queue(self: &Arc<Self>, table: &str, capacity: usize) -> Result<IngestQueue> {
+        if capacity == 0 {
+            return Err(anyhow::anyhow!(
+                "ingest queue capacity must be greater than 0"
+            ));
+        }
+        let (sender, mut receiver) = mpsc::channel::<QueueItem>(capacity);
+        let stats = Arc::new(Mutex::new(IngestQueueStats::default()));
+        let table = table.to_string();
+
+        let (job_table, job_stats) = (table.clone(), stats.clone());
+        let job = self.spawn_job(JobOptions::default(), |db, _| async move {
+            // schema 和正在合并的 batch 不同的留到下一轮单独写入
+            let mut next = None;
+            loop {
+                let item = match next.take() {
+                    Some(item) => item,
+                    None => match receiver.recv().await {
+                        Some(item) => item,
+                        None => break,
+                    },
+                };
+                let mut batches = Vec::new();
+                let mut flushes = Vec::new();
+                match item {
+                    QueueItem::Batch(batch) => batches.push(batch),
+                    QueueItem::Flush(tx) => flushes.push(tx),
+                }
+                // 合并已经在队列中的 batch，一次写入
+                while batches.len() < MAX_COALESCED_BATCHES {
+                    match receiver.try_recv() {
+                        Ok(QueueItem::Batch(batch)) => {
+                            if batches
+                                .first()
+                                .is_some_and(|first: &RecordBatch| first.schema() != batch.schema())
+                            {
+                                next = Some(QueueItem::Batch(batch));
+                                break;
+                            }
+                            batches.push(batch);
+                        }
+                        Ok(QueueItem::Flush(tx)) => flushes.push(tx),
+                        Err(_) => break,
+                    }
+                }
+                if !batches.is_empty() {
+                    let (count, rows) = (
+                        batches.len(),
+                        batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+                    );
+                    let result = db.insert_batches(&job_table, batches).await;
+                    let mut stats = job_stats.lock().unwrap();
+                    match result {
+                        Ok(_) => {
+                            stats.written_batches += count;
+                            stats.written_rows += rows;
+                        }
+                        Err(e) => {
+                            stats.failed_batches += count;
+                            stats.last_error = Some(e.to_string());
+                        }
+                    }
+                }
+                for tx in flushes {
+                    let _ = tx.send(());
+                }
+            }
+            Ok(())
+        });
+
+        Ok(IngestQueue {
+            table,
+            capacity,
+            sender,
+            stats,
+            job,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ingest_queue_backpressure() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        let batch = db.query_to_batches("SELECT 1::BIGINT AS id").await?[0].clone();
+
+        let queue = db.ingest_queue("t", 2)?;
+        // 后台任务还没有运行之前队列会被填满
+        let mut full = None;
+        for _ in 0..10 {
+            if let Err(e) = queue.try_send(batch.clone()) {
+                full = Some(e);
+                break;
+            }
+        }
+        let full = full.expect("queue should fill up");
+        assert!(matches!(full, Backpressure::QueueFull { capacity: 2, .. }));
+        assert_eq!(full.into_batch().num_rows(), 1);
+
+        queue.send(batch.clone()).await.unwrap();
+        queue.flush().await?;
+        assert_eq!(queue.queued(), 0);
+        let written = queue.stats().written_rows;
+        assert_eq!(db.ctx.table("t").await?.count().await?, written);
+
+        // 写入失败不会停止队列
+        let bad = db.query_to_batches("SELECT 'x' AS name").await?[0].clone();
+        queue.send(bad).await.unwrap();
+        queue.send(batch).await.unwrap();
+        let stats = queue.close().await?;
+        assert_eq!(stats.failed_batches, 1);
+        assert!(stats.last_error.is_some());
+        assert_eq!(stats.written_rows, written + 1);
+        Ok(())
+    }
+}
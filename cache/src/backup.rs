@@ -0,0 +1,664 @@
+use crate::compression::Artifact;
+use crate::integrity::{crc32c, verify_crc32c};
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use crate::wal::{read_segment_entries, WalRecord, SEGMENT_EXTENSION};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::file::metadata::KeyValue;
+use datafusion::parquet::file::properties::WriterProperties;
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// 备份目录名使用的时间格式，按字典序排序就是时间顺序
+const BACKUP_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+const MANIFEST_FILE: &str = "manifest.json";
+// snapshot 写的 Parquet 文件旁边的校验和文件，内容是十六进制的 CRC32C
+const CHECKSUM_SUFFIX: &str = ".crc32c";
+// snapshot 的 Parquet 元数据中保存表自定义信息（JSON）的 key
+const TABLE_META_KEY: &str = "cache.table_meta";
+// 备份前缀下归档 WAL segment 的目录
+const WAL_DIR: &str = "wal";
+
+/// 一次备份包含的表
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub created_at: DateTime<Utc>,
+    pub tables: Vec<BackedUpTable>,
+    // 备份之后的第一个 WAL segment，从这里开始重放归档的 WAL 可以恢复到备份之后的时间点；
+    // 备份时没有开启 WAL 或者旧版本的备份没有
+    #[serde(default)]
+    pub wal_seq: Option<u64>,
+    // 表的自定义信息（负责人、TTL 设置等），恢复时和表一起替换
+    #[serde(default)]
+    pub table_meta: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackedUpTable {
+    pub name: String,
+    pub rows: usize,
+    // 相对于备份目录的文件名
+    pub file: String,
+    // 文件内容（压缩后）的 CRC32C，恢复时校验，旧版本的备份没有
+    #[serde(default)]
+    pub crc32c: Option<u32>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 把所有内存表备份到存储的 `{prefix}/{时间}/` 下，每个表一个 Arrow IPC 文件
+    ///
+    /// 选择了 IPC 压缩时文件名是 `{表名}.arrow.{codec}`，整个文件用这个 codec 压缩
+    ///
+    /// 读取时锁住所有表，备份是同一时刻的状态；开启 WAL 时在同一时刻切换到新的 WAL segment，
+    /// 把它的序号记在 manifest 中，并把已经关闭的 segment 归档到 `{prefix}/wal/`，
+    /// restore_from_storage 用它们恢复到备份之后的时间点
+    pub async fn backup_to_storage(&self, storage: &str, prefix: &str) -> Result<BackupManifest> {
+        let store = self.storage_store(storage)?;
+        let codec = self.compression_codec(Artifact::Ipc);
+        let created_at = Utc::now();
+        let dir = backup_dir(prefix, created_at);
+
+        let schema = self.default_schema()?;
+        let mut names = schema.table_names();
+        names.sort();
+        let guards = self.lock_tables(&names).await;
+        let mut contents = Vec::new();
+        let mut table_meta = BTreeMap::new();
+        for name in names {
+            let Some(provider) = schema.table(&name).await? else {
+                continue;
+            };
+            if !is_memory_table(provider.as_ref()) {
+                continue;
+            }
+            let batches = self.ctx.table(name.as_str()).await?.collect().await?;
+            let meta = self.table_meta(&name);
+            if !meta.is_empty() {
+                table_meta.insert(name.clone(), meta);
+            }
+            contents.push((name, provider.schema(), batches));
+        }
+        let wal_seq = self.wal().map(|wal| wal.rotate()).transpose()?;
+        drop(guards);
+
+        let mut tables = Vec::new();
+        for (name, table_schema, batches) in contents {
+            let mut buffer = Vec::new();
+            let mut writer = StreamWriter::try_new(&mut buffer, &table_schema)?;
+            for batch in &batches {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+            drop(writer);
+
+            let mut file = format!("{}.arrow", name);
+            if codec.name() != "none" {
+                buffer = codec.compress(&buffer)?;
+                file = format!("{}.{}", file, codec.name());
+            }
+            let checksum = crc32c(&buffer);
+            store
+                .put(
+                    &Path::from(format!("{}/{}", dir, file)),
+                    PutPayload::from(buffer),
+                )
+                .await?;
+            tables.push(BackedUpTable {
+                rows: batches.iter().map(|b| b.num_rows()).sum(),
+                name,
+                file,
+                crc32c: Some(checksum),
+            });
+        }
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // manifest 最后写，没有 manifest 的目录是不完整的备份
+        let manifest = BackupManifest {
+            created_at,
+            tables,
+            wal_seq,
+            table_meta,
+        };
+        store
+            .put(
+                &Path::from(format!("{}/{}", dir, MANIFEST_FILE)),
+                PutPayload::from(serde_json::to_vec_pretty(&manifest)?),
+            )
+            .await?;
+        if wal_seq.is_some() {
+            self.archive_wal(storage, prefix).await?;
+        }
+        Ok(manifest)
+    }
+
+    /// 把已经关闭的 WAL segment 上传到 `{prefix}/wal/`，已经归档过的跳过，返回这次上传的数量
+    ///
+    /// 当前正在写的 segment 不会上传，需要定期调用（比如每次备份）让归档跟上写入
+    pub async fn archive_wal(&self, storage: &str, prefix: &str) -> Result<usize> {
+        let wal = self
+            .wal()
+            .ok_or_else(|| anyhow::anyhow!("WAL is not enabled"))?;
+        let store = self.storage_store(storage)?;
+        let dir = wal_archive_dir(prefix);
+        let mut uploaded = 0;
+        for (_, segment) in wal.closed_segments()? {
+            let Some(file) = segment.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let path = Path::from(format!("{}/{}", dir, file));
+            if store.head(&path).await.is_ok() {
+                continue;
+            }
+            let data =
+                std::fs::read(&segment).with_context(|| format!("read {}", segment.display()))?;
+            store.put(&path, PutPayload::from(data)).await?;
+            uploaded += 1;
+        }
+        Ok(uploaded)
+    }
+
+    /// 存储中 prefix 下所有完整的备份，按时间排序
+    pub async fn list_backups(&self, storage: &str, prefix: &str) -> Result<Vec<DateTime<Utc>>> {
+        let store = self.storage_store(storage)?;
+        let listing = store.list_with_delimiter(Some(&Path::from(prefix))).await?;
+        let mut backups = Vec::new();
+        for dir in listing.common_prefixes {
+            let Some(name) = dir.filename() else {
+                continue;
+            };
+            let Ok(time) = NaiveDateTime::parse_from_str(name, BACKUP_TIME_FORMAT) else {
+                continue;
+            };
+            if store.head(&dir.child(MANIFEST_FILE)).await.is_ok() {
+                backups.push(time.and_utc());
+            }
+        }
+        backups.sort();
+        Ok(backups)
+    }
+
+    /// 恢复到 at 时的状态，at 为 None 时恢复到归档中最新的状态
+    ///
+    /// 先恢复不晚于 at 的最近一次备份，备份中的表在同一把锁里一起替换同名表，其他表不受影响；
+    /// 备份时开启了 WAL 的，再按顺序重放 `{prefix}/wal/` 中备份之后、写入时间不晚于 at 的记录。
+    /// 重放和普通写入一样写入当前的 WAL。没有归档 WAL 的备份只能恢复到备份的时间点。
+    ///
+    /// 备份文件或者归档的 segment 校验失败时返回 IntegrityError，前者不会替换任何表；
+    /// 归档的 segment 序号不连续时返回错误，不会跳过中间的写入
+    pub async fn restore_from_storage(
+        &self,
+        storage: &str,
+        prefix: &str,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<BackupManifest> {
+        let backup = self
+            .list_backups(storage, prefix)
+            .await?
+            .into_iter()
+            .filter(|time| at.map_or(true, |at| *time <= at))
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("No backup found under {}/{}", storage, prefix))?;
+
+        let store = self.storage_store(storage)?;
+        let dir = backup_dir(prefix, backup);
+        let manifest: BackupManifest = serde_json::from_slice(
+            &store
+                .get(&Path::from(format!("{}/{}", dir, MANIFEST_FILE)))
+                .await?
+                .bytes()
+                .await?,
+        )?;
+
+        // 先读出所有表再替换，读取失败时不会只恢复一部分
+        let codecs = self.compression_registry();
+        let mut restored = Vec::new();
+        for table in &manifest.tables {
+            let bytes = store
+                .get(&Path::from(format!("{}/{}", dir, table.file)))
+                .await?
+                .bytes()
+                .await?;
+            if let Some(expected) = table.crc32c {
+                verify_crc32c(&format!("{}/{}", dir, table.file), &bytes, expected)?;
+            }
+            let bytes = match table.file.rsplit_once(".arrow.") {
+                Some((_, name)) => codecs
+                    .get(name)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Backup file {} uses unknown codec {}", table.file, name)
+                    })?
+                    .decompress(&bytes)?,
+                None => bytes.to_vec(),
+            };
+            let reader = StreamReader::try_new(bytes.as_slice(), None)?;
+            let schema = reader.schema();
+            let batches = reader.collect::<std::result::Result<Vec<RecordBatch>, _>>()?;
+            restored.push((
+                table.name.clone(),
+                MemTable::try_new(schema, vec![batches.clone()])?,
+                batches,
+            ));
+        }
+        let names: Vec<String> = restored.iter().map(|(name, ..)| name.clone()).collect();
+        let guards = self.lock_tables(&names).await;
+        let mut changes: Vec<(String, Option<Arc<dyn TableProvider>>)> = Vec::new();
+        for (name, table, batches) in restored {
+            if let Some(wal) = self.wal() {
+                wal.append(&WalRecord::Replace {
+                    table: name.clone(),
+                    schema: table.schema(),
+                    batches,
+                })?;
+            }
+            changes.push((name, Some(Arc::new(table))));
+        }
+        self.replace_tables(changes)?;
+        for name in &names {
+            let meta = manifest.table_meta.get(name).cloned().unwrap_or_default();
+            self.put_table_meta(name, meta)?;
+        }
+        drop(guards);
+        // 备份可能来自旧版本，恢复后把内部表升级到当前版本
+        self.run_migrations().await?;
+
+        if let Some(wal_seq) = manifest.wal_seq {
+            let replayed = self
+                .replay_archived_wal(storage, prefix, wal_seq, at)
+                .await?;
+            tracing::info!(
+                backup = %manifest.created_at,
+                replayed,
+                "replayed archived WAL after backup"
+            );
+        }
+        Ok(manifest)
+    }
+
+    // 按顺序重放 {prefix}/wal/ 中序号不小于 from 的 segment，直到第一条晚于 at 的记录，返回重放的记录数
+    async fn replay_archived_wal(
+        &self,
+        storage: &str,
+        prefix: &str,
+        from: u64,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<usize> {
+        let store = self.storage_store(storage)?;
+        let dir = wal_archive_dir(prefix);
+        let mut segments: Vec<(u64, Path)> = store
+            .list(Some(&Path::from(dir.as_str())))
+            .map_ok(|meta| meta.location)
+            .try_filter_map(|location| async move {
+                let seq = location
+                    .filename()
+                    .and_then(|name| name.strip_suffix(&format!(".{}", SEGMENT_EXTENSION)))
+                    .and_then(|seq| seq.parse::<u64>().ok());
+                Ok(seq.filter(|seq| *seq >= from).map(|seq| (seq, location)))
+            })
+            .try_collect()
+            .await?;
+        segments.sort_by_key(|(seq, _)| *seq);
+
+        let codecs = self.compression_registry();
+        let mut replayed = 0;
+        for (expected, (seq, location)) in (from..).zip(segments) {
+            if seq != expected {
+                return Err(anyhow::anyhow!(
+                    "Archived WAL under {} is missing segment {}",
+                    dir,
+                    expected
+                ));
+            }
+            let data = store.get(&location).await?.bytes().await?;
+            for entry in read_segment_entries(location.as_ref(), &data, &codecs)? {
+                if at.is_some_and(|at| entry.written_at > at) {
+                    return Ok(replayed);
+                }
+                self.replay_record(entry.record).await?;
+                replayed += 1;
+            }
+        }
+        Ok(replayed)
+    }
+
+    // 和普通写入一样经过写锁和 WAL
+    async fn replay_record(&self, record: WalRecord) -> Result<()> {
+        match record {
+            WalRecord::Ddl { sql } => self
+                .execute(&sql)
+                .await
+                .with_context(|| format!("Replay {} failed", sql)),
+            WalRecord::Insert { table, batches } => {
+                let _guard = self.lock_table(&table).await;
+                self.write_append(&table, batches, true)
+                    .await
+                    .with_context(|| format!("Replay insert into {} failed", table))
+            }
+            WalRecord::Replace {
+                table,
+                schema,
+                batches,
+            } => {
+                let _guard = self.lock_table(&table).await;
+                let base = self.ctx.table_provider(self.table_ref(&table)).await.ok();
+                self.rewrite_table(&table, schema, vec![batches], base.as_ref())
+                    .with_context(|| format!("Replay rewrite of {} failed", table))
+            }
+            WalRecord::Meta { table, meta } => self
+                .put_table_meta(&table, meta)
+                .with_context(|| format!("Replay metadata of {} failed", table)),
+        }
+    }
+
+    /// 把一个内存表当前的内容写成存储中 path 处的 Parquet 文件，返回行数
+    ///
+    /// 只包含这个表和它的自定义信息，适合启动时用 restore 预热单个表；同时写入 {path}.crc32c 供 restore 校验
+    pub async fn snapshot(&self, table: &str, storage: &str, path: &str) -> Result<usize> {
+        let store = self.storage_store(storage)?;
+        let provider = self
+            .ctx
+            .table_provider(self.table_ref(table))
+            .await
+            .map_err(|_| anyhow::anyhow!("Table {} not found", table))?;
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!("Table {} is not a memory table", table));
+        }
+        let batches = self
+            .ctx
+            .table(self.table_ref(table))
+            .await?
+            .collect()
+            .await?;
+        let codec = self.compression_codec(Artifact::Snapshot);
+        let compression = codec.parquet_compression().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Codec {} cannot be used for Parquet snapshots",
+                codec.name()
+            )
+        })?;
+        let properties = WriterProperties::builder()
+            .set_compression(compression)
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                TABLE_META_KEY.to_string(),
+                serde_json::to_string(&self.table_meta(table))?,
+            )]))
+            .build();
+        let mut buffer = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buffer, provider.schema(), Some(properties))?;
+        for batch in &batches {
+            writer.write(batch)?;
+        }
+        writer.close()?;
+
+        // 先写数据再写校验和，中途失败时 restore 会发现两者不一致
+        let checksum = format!("{:08x}", crc32c(&buffer));
+        store
+            .put(&Path::from(path), PutPayload::from(buffer))
+            .await?;
+        store
+            .put(
+                &Path::from(format!("{}{}", path, CHECKSUM_SUFFIX)),
+                PutPayload::from(checksum.into_bytes()),
+            )
+            .await?;
+        Ok(batches.iter().map(|b| b.num_rows()).sum())
+    }
+
+    /// 从 snapshot 写的 Parquet 文件重新加载表，原子替换同名表，表不存在时创建
+    ///
+    /// 有 {path}.crc32c 时先校验，不一致返回 IntegrityError，原表不受影响
+    pub async fn restore(&self, table: &str, storage: &str, path: &str) -> Result<usize> {
+        let store = self.storage_store(storage)?;
+        let bytes = store.get(&Path::from(path)).await?.bytes().await?;
+        match store
+            .get(&Path::from(format!("{}{}", path, CHECKSUM_SUFFIX)))
+            .await
+        {
+            Ok(checksum) => {
+                let checksum = checksum.bytes().await?;
+                let expected = u32::from_str_radix(std::str::from_utf8(&checksum)?.trim(), 16)?;
+                verify_crc32c(path, &bytes, expected)?;
+            }
+            // 旧版本写的快照没有校验和
+            Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes)?;
+        // 旧版本写的快照没有自定义信息，保留表现有的
+        let meta: Option<BTreeMap<String, String>> = reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|kv| kv.iter().find(|kv| kv.key == TABLE_META_KEY))
+            .and_then(|kv| kv.value.as_deref())
+            .map(serde_json::from_str)
+            .transpose()?;
+        let schema = reader.schema().clone();
+        let batches = reader
+            .build()?
+            .collect::<std::result::Result<Vec<RecordBatch>, _>>()?;
+        let rows = batches.iter().map(|b| b.num_rows()).sum();
+        let provider = MemTable::try_new(schema, vec![batches])?;
+        self.replace_table(self.table_ref(table).table(), Arc::new(provider))?;
+        if let Some(meta) = meta {
+            self.put_table_meta(self.table_ref(table).table(), meta)?;
+        }
+        Ok(rows)
+    }
+
+    pub(crate) fn storage_store(&self, storage: &str) -> Result<Arc<dyn ObjectStore>> {
+        let storages = self.registered_storages.read().unwrap();
+        let entry = storages
+            .get(storage)
+            .with_context(|| format!("Storage {} not found", storage))?;
+        Ok(entry.store.clone())
+    }
+}
+
+fn wal_archive_dir(prefix: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        WAL_DIR.to_string()
+    } else {
+        format!("{}/{}", prefix, WAL_DIR)
+    }
+}
+
+fn backup_dir(prefix: &str, time: DateTime<Utc>) -> String {
+    let prefix = prefix.trim_matches('/');
+    let name = time.format(BACKUP_TIME_FORMAT);
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StorageConfig;
+    use crate::pool::StorageEntry;
+    use crate::wal::WalConfig;
+    use object_store::aws::AwsCredential;
+    use object_store::local::LocalFileSystem;
+    use object_store::StaticCredentialProvider;
+    use tempfile::tempdir;
+
+    fn register_local_storage(db: &DB<()>, root: &std::path::Path) -> Result<()> {
+        let config = StorageConfig {
+            access_key: String::new(),
+            access_secret: String::new(),
+            endpoint: None,
+            region: String::new(),
+            bucket: String::new(),
+            schema: "file".to_string(),
+            session_token: None,
+            token_provider: None,
+            create_bucket_if_missing: false,
+            prefix: None,
+            cost_per_gb_read: None,
+            cost_per_gb_written: None,
+            rate_limit: None,
+        };
+        let credentials = Arc::new(StaticCredentialProvider::new(AwsCredential {
+            key_id: String::new(),
+            secret_key: String::new(),
+            token: None,
+        }));
+        db.registered_storages.write().unwrap().insert(
+            "backup".to_string(),
+            StorageEntry {
+                store: Arc::new(LocalFileSystem::new_with_prefix(root)?),
+                credentials,
+                config,
+                counters: Default::default(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn count(db: &DB<()>, table: &str) -> Result<usize> {
+        Ok(db.ctx.table(table).await?.count().await?)
+    }
+
+    #[tokio::test]
+    async fn test_backup_and_restore() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        register_local_storage(&db, dir.path())?;
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a')").await?;
+        db.set_table_meta("t", "owner", "finance")?;
+        let first = db.backup_to_storage("backup", "backups").await?;
+        assert_eq!(first.tables[0].rows, 1);
+        db.set_table_meta("t", "owner", "treasury")?;
+
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        db.execute("INSERT INTO t VALUES (2, 'b')").await?;
+        // 第二次备份压缩，恢复时两种文件都能读取
+        db.set_compression(Artifact::Ipc, &crate::config::CodecConfig::new("lz4"))?;
+        let second = db.backup_to_storage("backup", "backups").await?;
+        assert_eq!(second.tables[0].file, "t.arrow.lz4");
+        db.execute("INSERT INTO t VALUES (3, 'c')").await?;
+        assert_eq!(db.list_backups("backup", "backups").await?.len(), 2);
+
+        db.restore_from_storage("backup", "backups", None).await?;
+        assert_eq!(count(&db, "t").await?, 2);
+        let manifest = db
+            .restore_from_storage("backup", "backups", Some(first.created_at))
+            .await?;
+        assert_eq!(manifest, first);
+        assert_eq!(count(&db, "t").await?, 1);
+        assert_eq!(db.get_table_meta("t", "owner").as_deref(), Some("finance"));
+        assert!(db
+            .restore_from_storage(
+                "backup",
+                "backups",
+                Some(first.created_at - chrono::Duration::seconds(1))
+            )
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_point_in_time_restore() -> Result<()> {
+        let dir = tempdir()?;
+        let wal_dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        register_local_storage(&db, dir.path())?;
+        let wal = db.enable_wal(WalConfig::new(wal_dir.path()))?;
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1)").await?;
+        let manifest = db.backup_to_storage("backup", "backups").await?;
+        assert_eq!(manifest.wal_seq, Some(1));
+        assert!(dir.path().join("backups/wal").read_dir()?.next().is_some());
+
+        db.execute("INSERT INTO t VALUES (2)").await?;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        let between = Utc::now();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        db.execute("INSERT INTO t VALUES (3)").await?;
+        wal.rotate()?;
+        assert_eq!(db.archive_wal("backup", "backups").await?, 1);
+        assert_eq!(db.archive_wal("backup", "backups").await?, 0);
+
+        // 在另一个实例上恢复，重放的写入进入它自己的 WAL
+        let restored_wal = tempdir()?;
+        let restored = DB::<()>::new("test_db");
+        register_local_storage(&restored, dir.path())?;
+        restored.enable_wal(WalConfig::new(restored_wal.path()))?;
+        restored
+            .restore_from_storage("backup", "backups", Some(between))
+            .await?;
+        assert_eq!(count(&restored, "t").await?, 2);
+        restored
+            .restore_from_storage("backup", "backups", None)
+            .await?;
+        assert_eq!(count(&restored, "t").await?, 3);
+
+        let recovered = DB::<()>::new("test_db");
+        recovered.enable_wal(WalConfig::new(restored_wal.path()))?;
+        recovered.recovery().await?;
+        assert_eq!(count(&recovered, "t").await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_restore_table() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        register_local_storage(&db, dir.path())?;
+        db.execute("CREATE TABLE t (id BIGINT NOT NULL, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, NULL)")
+            .await?;
+        db.set_table_meta("t", "owner", "finance")?;
+        assert_eq!(db.snapshot("t", "backup", "snapshots/t.parquet").await?, 2);
+        assert!(dir.path().join("snapshots/t.parquet").exists());
+
+        db.execute("INSERT INTO t VALUES (3, 'c')").await?;
+        assert_eq!(db.restore("t", "backup", "snapshots/t.parquet").await?, 2);
+        assert_eq!(count(&db, "t").await?, 2);
+
+        // 预热到新的表，schema 和原表一样
+        db.restore("t_warm", "backup", "snapshots/t.parquet")
+            .await?;
+        assert_eq!(
+            db.ctx.table("t_warm").await?.schema().as_arrow(),
+            db.ctx.table("t").await?.schema().as_arrow()
+        );
+        assert_eq!(
+            db.get_table_meta("t_warm", "owner").as_deref(),
+            Some("finance")
+        );
+        assert!(db.snapshot("missing", "backup", "x.parquet").await.is_err());
+
+        // 快照文件损坏时不替换原表
+        let file = dir.path().join("snapshots/t.parquet");
+        let mut data = std::fs::read(&file)?;
+        data[10] ^= 0xff;
+        std::fs::write(&file, data)?;
+        let err = db
+            .restore("t", "backup", "snapshots/t.parquet")
+            .await
+            .unwrap_err();
+        assert!(err
+            .downcast_ref::<crate::integrity::IntegrityError>()
+            .is_some());
+        assert_eq!(count(&db, "t").await?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+//! 备份和按时间点恢复整个 DB 的命令行工具
+//!
+//!     cache-cli backup --to oss://bucket/backups --wal data/wal [--config config/default]
+//!     cache-cli restore --from oss://bucket/backups --at "2024-05-01T00:00" --wal data/wal
+//!
+//! URL 的 scheme 和 bucket 对应配置中的一个存储，路径是备份的 prefix。
+//! backup 先从 --wal 目录恢复实例的状态，再备份并把 WAL 归档到 {prefix}/wal/；
+//! 目录属于正在运行的实例时，应该在实例中调用 DB::backup_to_storage，不要用这个命令。
+//! restore 恢复不晚于 --at 的备份，再重放归档的 WAL 到 --at，结果写入 --wal 目录，
+//! 实例用这个目录启动并 recovery 后就是那个时间点的状态。--wal 目录需要是空的。
+//! 不指定 --at 时恢复到归档中最新的状态；--at 没有时区时按 UTC 解析。
+use anyhow::{Context, Result};
+use cache::config::Config;
+use cache::pool::DB;
+use cache::wal::WalConfig;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::HashMap;
+
+const USAGE: &str = "usage:
+    cache-cli backup --to <url> --wal <dir> [--config <path>]
+    cache-cli restore --from <url> --wal <dir> [--at <time>] [--config <path>]";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().context(USAGE)?;
+    let options = parse_options(args)?;
+    let wal = options.get("wal").context(USAGE)?;
+
+    let config = match options.get("config") {
+        Some(path) => Config::from_file(path)?,
+        None => Config::load()?,
+    };
+    let url = match command.as_str() {
+        "backup" => options.get("to"),
+        "restore" => options.get("from"),
+        _ => anyhow::bail!("unknown command {}\n{}", command, USAGE),
+    };
+    let (storage, prefix) = resolve_url(&config, url.context(USAGE)?)?;
+    let db = DB::<()>::new("cache-cli");
+    db.set_compression_config(&config.compression)?;
+    db.init_storages(config)?;
+
+    match command.as_str() {
+        "backup" => {
+            db.enable_wal(WalConfig::new(wal))?;
+            let report = db.recovery().await?;
+            println!("recovered {} tables from {}", report.tables.len(), wal);
+            let manifest = db.backup_to_storage(&storage, &prefix).await?;
+            println!(
+                "backed up {} tables at {}",
+                manifest.tables.len(),
+                manifest.created_at
+            );
+        }
+        "restore" => {
+            let at = options.get("at").map(|at| parse_time(at)).transpose()?;
+            let wal = db.enable_wal(WalConfig::new(wal))?;
+            if !wal.read_all()?.is_empty() {
+                anyhow::bail!(
+                    "WAL directory {} is not empty, restore into an empty directory",
+                    wal.dir().display()
+                );
+            }
+            let manifest = db.restore_from_storage(&storage, &prefix, at).await?;
+            println!(
+                "restored {} tables from the backup at {}, WAL written to {}",
+                manifest.tables.len(),
+                manifest.created_at,
+                wal.dir().display()
+            );
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+// --name value 形式的参数
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<HashMap<String, String>> {
+    let mut options = HashMap::new();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .with_context(|| format!("unexpected argument {}\n{}", arg, USAGE))?;
+        let value = args
+            .next()
+            .with_context(|| format!("--{} needs a value", name))?;
+        options.insert(name.to_string(), value);
+    }
+    Ok(options)
+}
+
+// oss://bucket/backups 对应 schema 为 oss、bucket 为 bucket 的存储，返回存储名和备份的 prefix
+fn resolve_url(config: &Config, url: &str) -> Result<(String, String)> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .with_context(|| format!("{} is not a URL", url))?;
+    let (bucket, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let mut storages: Vec<_> = config.storages.iter().collect();
+    storages.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, storage) in storages {
+        if storage.schema != scheme || storage.bucket != bucket {
+            continue;
+        }
+        // 存储自己的 prefix 之外的部分才是备份的 prefix
+        let prefix = match storage.prefix.as_deref().map(|p| p.trim_matches('/')) {
+            Some(storage_prefix) if !storage_prefix.is_empty() => {
+                match path.trim_matches('/').strip_prefix(storage_prefix) {
+                    Some(rest) => rest,
+                    None => continue,
+                }
+            }
+            _ => path,
+        };
+        return Ok((name.clone(), prefix.trim_matches('/').to_string()));
+    }
+    anyhow::bail!("No storage in the config matches {}", url)
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .map(|time| time.and_utc())
+    .with_context(|| format!("Cannot parse time {}", value))
+}
@@ -0,0 +1,434 @@
+use crate::pool::DB;
+use crate::swap::{is_memory_table, replacement_table, IndexedTable};
+use anyhow::Result;
+use arrow_schema::{DataType, SchemaRef};
+use async_trait::async_trait;
+use datafusion::arrow::array::{Array, ArrayRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::array_value_to_string;
+use datafusion::catalog::Session;
+use datafusion::common::Constraints;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::execution::TaskContext;
+use datafusion::logical_expr::dml::InsertOp;
+use datafusion::logical_expr::{BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::ExecutionPlan;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+// 默认的误判率
+const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+// 预留的容量，避免小表的过滤器太小
+const MIN_EXPECTED_ITEMS: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+    items: usize,
+}
+
+impl BloomFilter {
+    fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(MIN_EXPECTED_ITEMS) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as usize;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64)],
+            num_hashes,
+            items: 0,
+        }
+    }
+
+    // 两个种子不同的 FNV-1a 做双重哈希
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = |seed: u64| {
+            key.iter()
+                .fold(seed, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+        };
+        let (h1, h2) = (hash(0xcbf29ce484222325), hash(0x84222325cbf29ce4) | 1);
+        let num_bits = self.bits.len() as u64 * 64;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        for pos in self.positions(key).collect::<Vec<_>>() {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+        self.items += 1;
+    }
+
+    fn might_contain(&self, key: &[u8]) -> bool {
+        self.positions(key)
+            .all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    // 用 key 列的所有值建立过滤器
+    fn build<'a>(columns: impl Iterator<Item = &'a ArrayRef> + Clone) -> Result<Self> {
+        let rows = columns.clone().map(|c| c.len()).sum::<usize>();
+        let mut filter = Self::new(rows * 2, DEFAULT_FALSE_POSITIVE_RATE);
+        for column in columns {
+            for i in 0..column.len() {
+                if column.is_valid(i) {
+                    filter.insert(array_value_to_string(column, i)?.as_bytes());
+                }
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// 一个 Bloom 过滤器的状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilterInfo {
+    pub table: String,
+    pub column: String,
+    pub items: usize,
+    pub memory_bytes: usize,
+    // 建立之后有新的写入，在重建之前不会用来跳过扫描
+    pub stale: bool,
+    // 被过滤器跳过的扫描次数
+    pub skipped_scans: u64,
+}
+
+/// 在 key 列上带 Bloom 过滤器的表，`key = 常量` 的查询在 key 一定不存在时不扫描数据
+#[derive(Debug)]
+pub struct BloomFilteredTable {
+    inner: Arc<dyn TableProvider>,
+    column: String,
+    data_type: DataType,
+    filter: RwLock<BloomFilter>,
+    // 每次写入加一，重建时记下来，用来判断过滤器是否过期
+    writes: AtomicU64,
+    built_at_write: AtomicU64,
+    skipped_scans: AtomicU64,
+}
+
+impl BloomFilteredTable {
+    fn is_stale(&self) -> bool {
+        self.writes.load(Ordering::SeqCst) != self.built_at_write.load(Ordering::SeqCst)
+    }
+
+    // 过滤条件中 key 列等于的常量，格式化成和建立过滤器时一样的字符串
+    fn equality_key(&self, filter: &Expr) -> Option<String> {
+        let Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Eq,
+            right,
+        }) = filter
+        else {
+            return None;
+        };
+        let value = match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(c), Expr::Literal(v)) | (Expr::Literal(v), Expr::Column(c))
+                if c.name == self.column =>
+            {
+                v
+            }
+            _ => return None,
+        };
+        if value.is_null() {
+            return None;
+        }
+        let array = value.cast_to(&self.data_type).ok()?.to_array().ok()?;
+        array_value_to_string(&array, 0).ok()
+    }
+
+    async fn rebuild(&self, state: &dyn Session) -> Result<()> {
+        let writes = self.writes.load(Ordering::SeqCst);
+        let index = self.inner.schema().index_of(&self.column)?;
+        let plan = self
+            .inner
+            .scan(state, Some(&vec![index]), &[], None)
+            .await?;
+        let batches: Vec<_> =
+            datafusion::physical_plan::collect(plan, Arc::new(TaskContext::from(state))).await?;
+        *self.filter.write().unwrap() = BloomFilter::build(batches.iter().map(|b| b.column(0)))?;
+        self.built_at_write.store(writes, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TableProvider for BloomFilteredTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        self.inner.table_type()
+    }
+
+    fn constraints(&self) -> Option<&Constraints> {
+        self.inner.constraints()
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> datafusion::error::Result<Vec<TableProviderFilterPushDown>> {
+        // key 列上的等值条件只用来判断是否跳过，DataFusion 仍然会再过滤一次
+        let inner = self.inner.supports_filters_pushdown(filters)?;
+        Ok(filters
+            .iter()
+            .zip(inner)
+            .map(|(filter, pushdown)| match pushdown {
+                TableProviderFilterPushDown::Unsupported if self.equality_key(filter).is_some() => {
+                    TableProviderFilterPushDown::Inexact
+                }
+                other => other,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        if !self.is_stale() {
+            let filter = self.filter.read().unwrap();
+            let absent = filters
+                .iter()
+                .filter_map(|f| self.equality_key(f))
+                .any(|key| !filter.might_contain(key.as_bytes()));
+            if absent {
+                self.skipped_scans.fetch_add(1, Ordering::Relaxed);
+                let schema = match projection {
+                    Some(projection) => Arc::new(self.schema().project(projection)?),
+                    None => self.schema(),
+                };
+                return Ok(Arc::new(EmptyExec::new(schema)));
+            }
+        }
+        self.inner.scan(state, projection, filters, limit).await
+    }
+
+    async fn insert_into(
+        &self,
+        state: &dyn Session,
+        input: Arc<dyn ExecutionPlan>,
+        insert_op: InsertOp,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        self.inner.insert_into(state, input, insert_op).await
+    }
+}
+
+impl IndexedTable for BloomFilteredTable {
+    fn in_memory(&self) -> bool {
+        is_memory_table(self.inner.as_ref())
+    }
+
+    // 数据已经在手上，直接建立新的过滤器，重写之后不会过期
+    fn reindex(
+        &self,
+        schema: SchemaRef,
+        partitions: Vec<Vec<RecordBatch>>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        let index = schema.index_of(&self.column)?;
+        let filter = BloomFilter::build(partitions.iter().flatten().map(|b| b.column(index)))?;
+        Ok(Arc::new(BloomFilteredTable {
+            inner: replacement_table(schema.clone(), partitions, Some(&self.inner))?,
+            column: self.column.clone(),
+            data_type: schema.field(index).data_type().clone(),
+            filter: RwLock::new(filter),
+            writes: AtomicU64::new(0),
+            built_at_write: AtomicU64::new(0),
+            skipped_scans: AtomicU64::new(self.skipped_scans.load(Ordering::Relaxed)),
+        }))
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 在表的 key 列上建立 Bloom 过滤器，用于加速点查
+    ///
+    /// 之后的写入会让过滤器过期，过期期间点查照常扫描，需要定期调用 rebuild_bloom_filters
+    pub async fn create_bloom_filter(&self, table: &str, column: &str) -> Result<()> {
+        let _guard = self.lock_table(table).await;
+        let inner = self.ctx.table_provider(self.table_ref(table)).await?;
+        if inner
+            .as_any()
+            .downcast_ref::<BloomFilteredTable>()
+            .is_some()
+        {
+            return Err(anyhow::anyhow!(
+                "Table {} already has a bloom filter",
+                table
+            ));
+        }
+        let data_type = inner.schema().field_with_name(column)?.data_type().clone();
+        let bloom = BloomFilteredTable {
+            inner,
+            column: column.to_string(),
+            data_type,
+            filter: RwLock::new(BloomFilter::new(0, DEFAULT_FALSE_POSITIVE_RATE)),
+            writes: AtomicU64::new(0),
+            built_at_write: AtomicU64::new(0),
+            skipped_scans: AtomicU64::new(0),
+        };
+        bloom.rebuild(&self.ctx.state()).await?;
+        self.replace_table(self.table_ref(table).table(), Arc::new(bloom))?;
+        Ok(())
+    }
+
+    /// 去掉 Bloom 过滤器，恢复原来的表
+    pub async fn drop_bloom_filter(&self, table: &str) -> Result<()> {
+        let _guard = self.lock_table(table).await;
+        let provider = self.ctx.table_provider(self.table_ref(table)).await?;
+        let Some(bloom) = provider.as_any().downcast_ref::<BloomFilteredTable>() else {
+            return Err(anyhow::anyhow!("Table {} has no bloom filter", table));
+        };
+        self.replace_table(self.table_ref(table).table(), bloom.inner.clone())?;
+        Ok(())
+    }
+
+    /// 重建所有过期的过滤器，返回重建的表
+    pub async fn rebuild_bloom_filters(&self) -> Result<Vec<String>> {
+        let state = self.ctx.state();
+        let mut rebuilt = Vec::new();
+        for (table, bloom) in self.bloom_tables().await? {
+            let bloom = bloom.as_any().downcast_ref::<BloomFilteredTable>().unwrap();
+            if bloom.is_stale() {
+                bloom.rebuild(&state).await?;
+                rebuilt.push(table);
+            }
+        }
+        Ok(rebuilt)
+    }
+
+    /// 所有 Bloom 过滤器的状态和占用的内存
+    pub async fn bloom_filters(&self) -> Result<Vec<BloomFilterInfo>> {
+        let mut infos = Vec::new();
+        for (table, bloom) in self.bloom_tables().await? {
+            let bloom = bloom.as_any().downcast_ref::<BloomFilteredTable>().unwrap();
+            let filter = bloom.filter.read().unwrap();
+            infos.push(BloomFilterInfo {
+                table,
+                column: bloom.column.clone(),
+                items: filter.items,
+                memory_bytes: filter.memory_bytes(),
+                stale: bloom.is_stale(),
+                skipped_scans: bloom.skipped_scans.load(Ordering::Relaxed),
+            });
+        }
+        Ok(infos)
+    }
+
+    async fn bloom_tables(&self) -> Result<Vec<(String, Arc<dyn TableProvider>)>> {
+        let schema = self.default_schema()?;
+        let mut names = schema.table_names();
+        names.sort();
+        let mut tables = Vec::new();
+        for name in names {
+            let Some(provider) = schema.table(&name).await? else {
+                continue;
+            };
+            if provider
+                .as_any()
+                .downcast_ref::<BloomFilteredTable>()
+                .is_some()
+            {
+                tables.push((name, provider));
+            }
+        }
+        Ok(tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            filter.insert(format!("key-{}", i).as_bytes());
+        }
+        assert!((0..1000).all(|i| filter.might_contain(format!("key-{}", i).as_bytes())));
+        let false_positives = (0..10000)
+            .filter(|i| filter.might_contain(format!("other-{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[tokio::test]
+    async fn test_point_lookup() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+        db.create_bloom_filter("t", "id").await?;
+        let count = |sql: &'static str| {
+            let db = &db;
+            async move { Ok::<_, anyhow::Error>(db.query(sql).await?.count().await?) }
+        };
+
+        assert_eq!(count("SELECT * FROM t WHERE id = 1").await?, 1);
+        assert_eq!(count("SELECT name FROM t WHERE id = 999").await?, 0);
+        assert_eq!(db.bloom_filters().await?[0].skipped_scans, 1);
+
+        // 过期之后照常扫描，不会漏掉新写入的行
+        db.execute("INSERT INTO t VALUES (999, 'z')").await?;
+        assert!(db.bloom_filters().await?[0].stale);
+        assert_eq!(count("SELECT * FROM t WHERE id = 999").await?, 1);
+        assert_eq!(db.rebuild_bloom_filters().await?, vec!["t".to_string()]);
+
+        let info = &db.bloom_filters().await?[0];
+        assert_eq!((info.items, info.stale), (3, false));
+        assert!(info.memory_bytes > 0);
+        assert_eq!(count("SELECT * FROM t WHERE id = 999").await?, 1);
+        assert_eq!(count("SELECT * FROM t WHERE 12345 = id").await?, 0);
+        assert_eq!(db.bloom_filters().await?[0].skipped_scans, 2);
+
+        db.drop_bloom_filter("t").await?;
+        assert!(db.bloom_filters().await?.is_empty());
+        assert_eq!(count("SELECT * FROM t").await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_keeps_bloom_filter() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+        db.create_bloom_filter("t", "id").await?;
+
+        // upsert 整表重写之后仍然带着过滤器，并且用新的数据重建过
+        let batch = db.query("SELECT 3::BIGINT AS id, 'c' AS name").await?;
+        let batch = batch.collect().await?.remove(0);
+        let batch = batch.with_schema(db.table_schema("t").await?)?;
+        db.upsert("t", &["id"], batch).await?;
+        let info = &db.bloom_filters().await?[0];
+        assert_eq!((info.items, info.stale), (3, false));
+        assert_eq!(
+            db.query("SELECT * FROM t WHERE id = 3")
+                .await?
+                .count()
+                .await?,
+            1
+        );
+        Ok(())
+    }
+}
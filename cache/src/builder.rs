@@ -0,0 +1,154 @@
+use crate::placement::TempPlacement;
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::execution::disk_manager::DiskManagerConfig;
+use datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use datafusion::prelude::{SessionConfig, SessionContext};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// 构造 DB 时调整内嵌的 DataFusion，没有设置的选项使用 DataFusion 的默认值
+#[derive(Debug, Clone)]
+pub struct DBBuilder<V: Serialize + DeserializeOwned + Send + Sync> {
+    id: String,
+    batch_size: Option<usize>,
+    target_partitions: Option<usize>,
+    // 查询执行可以使用的内存上限，超过时可以落盘的算子落盘，其他算子报错
+    memory_limit: Option<usize>,
+    spill_dir: Option<PathBuf>,
+    default_catalog: Option<(String, String)>,
+    information_schema: bool,
+    _phantom: PhantomData<V>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn builder(id: &str) -> DBBuilder<V> {
+        DBBuilder {
+            id: id.to_string(),
+            batch_size: None,
+            target_partitions: None,
+            memory_limit: None,
+            spill_dir: None,
+            default_catalog: None,
+            information_schema: false,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DBBuilder<V> {
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size);
+        self
+    }
+
+    pub fn target_partitions(mut self, partitions: usize) -> Self {
+        self.target_partitions = Some(partitions);
+        self
+    }
+
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// 落盘目录，同时作为 TempPlacement 的本地目录
+    pub fn spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spill_dir = Some(dir.into());
+        self
+    }
+
+    /// 表注册到的默认 catalog 和 schema，默认是 datafusion.public
+    pub fn default_catalog(mut self, catalog: &str, schema: &str) -> Self {
+        self.default_catalog = Some((catalog.to_string(), schema.to_string()));
+        self
+    }
+
+    pub fn information_schema(mut self, enabled: bool) -> Self {
+        self.information_schema = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<DB<V>> {
+        if self.batch_size == Some(0) || self.target_partitions == Some(0) {
+            return Err(anyhow::anyhow!(
+                "batch_size and target_partitions must be greater than 0"
+            ));
+        }
+        let mut config = SessionConfig::new().with_information_schema(self.information_schema);
+        if let Some(batch_size) = self.batch_size {
+            config = config.with_batch_size(batch_size);
+        }
+        if let Some(partitions) = self.target_partitions {
+            config = config.with_target_partitions(partitions);
+        }
+        if let Some((catalog, schema)) = &self.default_catalog {
+            config = config.with_default_catalog_and_schema(catalog, schema);
+        }
+
+        let mut runtime = RuntimeEnvBuilder::new();
+        if let Some(limit) = self.memory_limit {
+            runtime = runtime.with_memory_limit(limit, 1.0);
+        }
+        if let Some(dir) = &self.spill_dir {
+            std::fs::create_dir_all(dir)?;
+            runtime = runtime.with_disk_manager(DiskManagerConfig::NewSpecified(vec![dir.clone()]));
+        }
+
+        let ctx = SessionContext::new_with_config_rt(config, runtime.build_arc()?);
+        let db = DB::with_context(&self.id, ctx);
+        if let Some(dir) = self.spill_dir {
+            db.set_temp_placement(TempPlacement {
+                local_dir: Some(dir),
+                ..TempPlacement::default()
+            });
+        }
+        Ok(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::AsArray;
+    use datafusion::arrow::datatypes::Int64Type;
+
+    #[tokio::test]
+    async fn test_builder() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = DB::<()>::builder("test_db")
+            .batch_size(128)
+            .target_partitions(2)
+            .memory_limit(64 * 1024 * 1024)
+            .spill_dir(dir.path().join("spill"))
+            .default_catalog("cache", "main")
+            .information_schema(true)
+            .build()?;
+
+        let state = db.ctx.state();
+        let options = state.config().options();
+        assert_eq!(options.execution.batch_size, 128);
+        assert_eq!(options.execution.target_partitions, 2);
+        assert_eq!(
+            db.temp_placement().local_dir,
+            Some(dir.path().join("spill"))
+        );
+
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2)").await?;
+        let batches = db
+            .query_to_batches("SELECT count(*) FROM cache.main.t")
+            .await?;
+        assert_eq!(batches[0].column(0).as_primitive::<Int64Type>().value(0), 2);
+        let tables = db
+            .query_to_batches(
+                "SELECT table_name FROM information_schema.tables WHERE table_name = 't'",
+            )
+            .await?;
+        assert_eq!(tables[0].num_rows(), 1);
+
+        assert!(DB::<()>::builder("test_db").batch_size(0).build().is_err());
+        Ok(())
+    }
+}
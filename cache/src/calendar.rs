@@ -0,0 +1,398 @@
+use crate::finance::MemoryTableReader;
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use datafusion::arrow::array::{
+    ArrayRef, AsArray, BooleanArray, Date32Array, Int32Array, StringArray,
+};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Date32Type, Field, Int64Type, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::TableProvider;
+use datafusion::common::{exec_err, plan_err, Result, ScalarValue};
+use datafusion::datasource::function::TableFunctionImpl;
+use datafusion::datasource::MemTable;
+use datafusion::logical_expr::{ColumnarValue, ScalarUDF, ScalarUDFImpl, Signature, Volatility};
+use datafusion::prelude::{Expr, SessionContext};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+// generate_date_dim 最多生成的天数，避免写错年份生成一张巨大的表
+const MAX_DATE_DIM_DAYS: i64 = 366 * 200;
+
+pub(crate) fn register_calendar_functions(ctx: &SessionContext) {
+    let tables = MemoryTableReader::new(ctx);
+    ctx.register_udtf("generate_date_dim", Arc::new(GenerateDateDim));
+    ctx.register_udf(ScalarUDF::from(IsBusinessDay {
+        signature: Signature::user_defined(Volatility::Stable),
+        tables: tables.clone(),
+    }));
+    ctx.register_udf(ScalarUDF::from(AddBusinessDays {
+        signature: Signature::user_defined(Volatility::Stable),
+        tables,
+    }));
+}
+
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+fn to_date(days: i32) -> NaiveDate {
+    epoch() + Duration::days(days as i64)
+}
+
+fn to_days(date: NaiveDate) -> i32 {
+    (date - epoch()).num_days() as i32
+}
+
+/// 不是周末也不在节假日列表中
+pub fn is_business_day(date: NaiveDate, holidays: &HashSet<NaiveDate>) -> bool {
+    !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !holidays.contains(&date)
+}
+
+/// 加上 n 个工作日，n 为负数时往前数，n 为 0 时原样返回
+pub fn add_business_days(date: NaiveDate, n: i64, holidays: &HashSet<NaiveDate>) -> NaiveDate {
+    let step = Duration::days(n.signum());
+    let mut date = date;
+    for _ in 0..n.unsigned_abs() {
+        date += step;
+        while !is_business_day(date, holidays) {
+            date += step;
+        }
+    }
+    date
+}
+
+/// generate_date_dim(start, end)：start 到 end（包含）每天一行的日期维度表
+#[derive(Debug)]
+struct GenerateDateDim;
+
+// 参数可以是 '2024-01-01' 或者 DATE '2024-01-01'
+fn date_arg(expr: &Expr) -> Result<NaiveDate> {
+    match expr {
+        Expr::Literal(ScalarValue::Utf8(Some(s)))
+        | Expr::Literal(ScalarValue::LargeUtf8(Some(s))) => {
+            match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+                Ok(date) => Ok(date),
+                Err(e) => plan_err!("invalid date {}: {}", s, e),
+            }
+        }
+        Expr::Literal(ScalarValue::Date32(Some(days))) => Ok(to_date(*days)),
+        Expr::Cast(cast) => date_arg(&cast.expr),
+        other => plan_err!("generate_date_dim expects date literals, got {}", other),
+    }
+}
+
+impl TableFunctionImpl for GenerateDateDim {
+    fn call(&self, args: &[Expr]) -> Result<Arc<dyn TableProvider>> {
+        let [start, end] = args else {
+            return plan_err!("generate_date_dim expects 2 arguments");
+        };
+        let (start, end) = (date_arg(start)?, date_arg(end)?);
+        let days = (end - start).num_days() + 1;
+        if days > MAX_DATE_DIM_DAYS {
+            return plan_err!("generate_date_dim range is too large: {} days", days);
+        }
+        let dates: Vec<NaiveDate> = (0..days.max(0))
+            .map(|i| start + Duration::days(i))
+            .collect();
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("date", DataType::Date32, false),
+            Field::new("year", DataType::Int32, false),
+            Field::new("quarter", DataType::Int32, false),
+            Field::new("month", DataType::Int32, false),
+            Field::new("day", DataType::Int32, false),
+            // ISO 星期，周一为 1
+            Field::new("day_of_week", DataType::Int32, false),
+            Field::new("day_of_year", DataType::Int32, false),
+            Field::new("iso_week", DataType::Int32, false),
+            Field::new("day_name", DataType::Utf8, false),
+            Field::new("is_weekend", DataType::Boolean, false),
+            Field::new("is_month_end", DataType::Boolean, false),
+        ]));
+        let int_column = |f: fn(&NaiveDate) -> i32| -> ArrayRef {
+            Arc::new(Int32Array::from_iter_values(dates.iter().map(f)))
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Date32Array::from_iter_values(
+                dates.iter().map(|d| to_days(*d)),
+            )),
+            int_column(|d| d.year()),
+            int_column(|d| (d.month0() / 3 + 1) as i32),
+            int_column(|d| d.month() as i32),
+            int_column(|d| d.day() as i32),
+            int_column(|d| d.weekday().number_from_monday() as i32),
+            int_column(|d| d.ordinal() as i32),
+            int_column(|d| d.iso_week().week() as i32),
+            Arc::new(StringArray::from_iter_values(
+                dates.iter().map(|d| d.format("%A").to_string()),
+            )),
+            Arc::new(BooleanArray::from_iter(dates.iter().map(|d| {
+                Some(matches!(d.weekday(), Weekday::Sat | Weekday::Sun))
+            }))),
+            Arc::new(BooleanArray::from_iter(
+                dates
+                    .iter()
+                    .map(|d| Some((*d + Duration::days(1)).day() == 1)),
+            )),
+        ];
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        Ok(Arc::new(MemTable::try_new(schema, vec![vec![batch]])?))
+    }
+}
+
+// 读取节假日表的 date 列
+fn load_holidays(tables: &MemoryTableReader, table: &str) -> Result<HashSet<NaiveDate>> {
+    let mut holidays = HashSet::new();
+    for batch in tables.read(table)? {
+        let Some(column) = batch.column_by_name("date") else {
+            return exec_err!("holiday table {} is missing column date", table);
+        };
+        let column = cast(column, &DataType::Date32)?;
+        holidays.extend(
+            column
+                .as_primitive::<Date32Type>()
+                .iter()
+                .flatten()
+                .map(to_date),
+        );
+    }
+    Ok(holidays)
+}
+
+fn to_arrays(args: &[ColumnarValue], number_rows: usize) -> Result<Vec<ArrayRef>> {
+    args.iter()
+        .map(|arg| arg.clone().into_array(number_rows))
+        .collect()
+}
+
+// 每一行对应的节假日，没有传节假日表时只排除周末
+fn holidays_per_row<'a>(
+    tables: &MemoryTableReader,
+    names: Option<&'a ArrayRef>,
+    loaded: &'a mut HashMap<String, HashSet<NaiveDate>>,
+    number_rows: usize,
+) -> Result<Vec<Option<&'a HashSet<NaiveDate>>>> {
+    static NO_HOLIDAYS: std::sync::OnceLock<HashSet<NaiveDate>> = std::sync::OnceLock::new();
+    let no_holidays = NO_HOLIDAYS.get_or_init(HashSet::new);
+    let Some(names) = names else {
+        return Ok(vec![Some(no_holidays); number_rows]);
+    };
+    let names = names.as_string::<i32>();
+    for name in names.iter().flatten() {
+        if !loaded.contains_key(name) {
+            loaded.insert(name.to_string(), load_holidays(tables, name)?);
+        }
+    }
+    Ok(names
+        .iter()
+        .map(|name| name.map(|name| &loaded[name]))
+        .collect())
+}
+
+fn coerce_date_args(
+    name: &str,
+    arg_types: &[DataType],
+    middle: &[DataType],
+) -> Result<Vec<DataType>> {
+    let required = 1 + middle.len();
+    if arg_types.len() != required && arg_types.len() != required + 1 {
+        return plan_err!(
+            "{} expects {} or {} arguments",
+            name,
+            required,
+            required + 1
+        );
+    }
+    let mut types = vec![DataType::Date32];
+    types.extend_from_slice(middle);
+    if arg_types.len() > required {
+        types.push(DataType::Utf8);
+    }
+    Ok(types)
+}
+
+/// is_business_day(date [, holiday_table])
+#[derive(Debug)]
+struct IsBusinessDay {
+    signature: Signature,
+    tables: MemoryTableReader,
+}
+
+impl ScalarUDFImpl for IsBusinessDay {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "is_business_day"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        coerce_date_args(self.name(), arg_types, &[])
+    }
+
+    fn invoke_batch(&self, args: &[ColumnarValue], number_rows: usize) -> Result<ColumnarValue> {
+        let arrays = to_arrays(args, number_rows)?;
+        let mut loaded = HashMap::new();
+        let holidays = holidays_per_row(&self.tables, arrays.get(1), &mut loaded, number_rows)?;
+        let dates = arrays[0].as_primitive::<Date32Type>();
+        let result: BooleanArray = dates
+            .iter()
+            .zip(holidays)
+            .map(|(date, holidays)| Some(is_business_day(to_date(date?), holidays?)))
+            .collect();
+        Ok(ColumnarValue::Array(Arc::new(result)))
+    }
+}
+
+/// add_business_days(date, n [, holiday_table])
+#[derive(Debug)]
+struct AddBusinessDays {
+    signature: Signature,
+    tables: MemoryTableReader,
+}
+
+impl ScalarUDFImpl for AddBusinessDays {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "add_business_days"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Date32)
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        coerce_date_args(self.name(), arg_types, &[DataType::Int64])
+    }
+
+    fn invoke_batch(&self, args: &[ColumnarValue], number_rows: usize) -> Result<ColumnarValue> {
+        let arrays = to_arrays(args, number_rows)?;
+        let mut loaded = HashMap::new();
+        let holidays = holidays_per_row(&self.tables, arrays.get(2), &mut loaded, number_rows)?;
+        let dates = arrays[0].as_primitive::<Date32Type>();
+        let offsets = arrays[1].as_primitive::<Int64Type>();
+        let result: Date32Array = dates
+            .iter()
+            .zip(offsets.iter())
+            .zip(holidays)
+            .map(|((date, n), holidays)| {
+                Some(to_days(add_business_days(to_date(date?), n?, holidays?)))
+            })
+            .collect();
+        Ok(ColumnarValue::Array(Arc::new(result)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pool::DB;
+    use datafusion::arrow::util::display::array_value_to_string;
+
+    async fn values(db: &DB<()>, sql: &str) -> anyhow::Result<Vec<String>> {
+        let batches = db.query_to_batches(sql).await?;
+        let mut values = Vec::new();
+        for batch in batches {
+            for i in 0..batch.num_rows() {
+                values.push(array_value_to_string(batch.column(0), i)?);
+            }
+        }
+        Ok(values)
+    }
+
+    #[tokio::test]
+    async fn test_generate_date_dim() -> anyhow::Result<()> {
+        let db = DB::<()>::new("test_db");
+        assert_eq!(
+            values(
+                &db,
+                "SELECT count(*) FROM generate_date_dim('2024-01-01', '2024-12-31')"
+            )
+            .await?,
+            vec!["366"]
+        );
+        assert_eq!(
+            values(
+                &db,
+                "SELECT day_name FROM generate_date_dim(DATE '2024-02-28', DATE '2024-03-01') WHERE is_month_end"
+            )
+            .await?,
+            vec!["Thursday"]
+        );
+        assert!(db
+            .query_to_batches("SELECT * FROM generate_date_dim('2024-01-01', '9999-01-01')")
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_business_days() -> anyhow::Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE holidays (date DATE, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO holidays VALUES (DATE '2024-12-25', 'Christmas')")
+            .await?;
+
+        // 2024-12-21 是周六
+        assert_eq!(
+            values(
+                &db,
+                "SELECT is_business_day(d) FROM (VALUES (DATE '2024-12-20'), (DATE '2024-12-21')) AS t(d)"
+            )
+            .await?,
+            vec!["true", "false"]
+        );
+        assert_eq!(
+            values(&db, "SELECT is_business_day(DATE '2024-12-25', 'holidays')").await?,
+            vec!["false"]
+        );
+        assert_eq!(
+            values(&db, "SELECT add_business_days(DATE '2024-12-24', 1)").await?,
+            vec!["2024-12-25"]
+        );
+        assert_eq!(
+            values(
+                &db,
+                "SELECT add_business_days(DATE '2024-12-24', 1, 'holidays')"
+            )
+            .await?,
+            vec!["2024-12-26"]
+        );
+        assert_eq!(
+            values(
+                &db,
+                "SELECT add_business_days(DATE '2024-12-23', -1, 'holidays')"
+            )
+            .await?,
+            vec!["2024-12-20"]
+        );
+
+        // 和日期维度表一起用
+        assert_eq!(
+            values(
+                &db,
+                "SELECT count(*) FROM generate_date_dim('2024-12-01', '2024-12-31') WHERE is_business_day(date, 'holidays')"
+            )
+            .await?,
+            vec!["21"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use crate::job::{run_until, CancellationToken};
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 单次查询的超时和标签，带标签的查询可以用 DB::cancel 取消
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    pub timeout: Option<Duration>,
+    pub tag: Option<String>,
+}
+
+impl QueryOptions {
+    pub fn timeout(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            tag: None,
+        }
+    }
+
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+}
+
+// 正在执行的带标签的查询，同一个标签可以有多个查询
+#[derive(Debug, Default)]
+pub(crate) struct RunningQueries {
+    next_id: u64,
+    tagged: HashMap<String, HashMap<u64, CancellationToken>>,
+}
+
+// 查询结束（包括 future 被 drop）时注销
+struct Registration<'a, V: Serialize + DeserializeOwned + Send + Sync> {
+    db: &'a DB<V>,
+    tag: String,
+    id: u64,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> Drop for Registration<'_, V> {
+    fn drop(&mut self) {
+        let mut running = self.db.running_queries.lock().unwrap();
+        if let Some(queries) = running.tagged.get_mut(&self.tag) {
+            queries.remove(&self.id);
+            if queries.is_empty() {
+                running.tagged.remove(&self.tag);
+            }
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 和 query_to_batches 一样执行查询，超时或者被 cancel 时停止执行计划
+    ///
+    /// 中断时返回的错误可以 downcast 成 JobInterrupted
+    pub async fn query_with_options(
+        &self,
+        sql: &str,
+        options: QueryOptions,
+    ) -> Result<Vec<RecordBatch>> {
+        let token = CancellationToken::new();
+        let _registration = options.tag.map(|tag| {
+            let mut running = self.running_queries.lock().unwrap();
+            running.next_id += 1;
+            let id = running.next_id;
+            running
+                .tagged
+                .entry(tag.clone())
+                .or_default()
+                .insert(id, token.clone());
+            Registration { db: self, tag, id }
+        });
+        let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+        run_until(&token, deadline, self.run(sql)).await
+    }
+
+    /// 取消所有带这个标签、正在执行的查询，返回取消的数量
+    pub fn cancel(&self, tag: &str) -> usize {
+        let running = self.running_queries.lock().unwrap();
+        let Some(queries) = running.tagged.get(tag) else {
+            return 0;
+        };
+        for token in queries.values() {
+            token.cancel();
+        }
+        queries.len()
+    }
+
+    /// 正在执行的查询的标签，按名字排序
+    pub fn running_query_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self
+            .running_queries
+            .lock()
+            .unwrap()
+            .tagged
+            .keys()
+            .cloned()
+            .collect();
+        tags.sort();
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::JobInterrupted;
+    use std::sync::Arc;
+
+    // 足够慢的查询；用 sum 而不是 count，count 可能直接从统计信息得到
+    const SLOW_QUERY: &str = "SELECT sum(a.value * b.value) \
+        FROM generate_series(1, 100000000) a CROSS JOIN generate_series(1, 1000) b";
+
+    #[tokio::test]
+    async fn test_query_timeout() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        let err = db
+            .query_with_options(SLOW_QUERY, QueryOptions::timeout(Duration::from_millis(50)))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<JobInterrupted>(),
+            Some(&JobInterrupted::DeadlineExceeded)
+        );
+        assert_eq!(
+            db.query_with_options("SELECT 1", QueryOptions::timeout(Duration::from_secs(10)))
+                .await?[0]
+                .num_rows(),
+            1
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_by_tag() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+        let running = tokio::spawn({
+            let db = db.clone();
+            async move {
+                db.query_with_options(SLOW_QUERY, QueryOptions::default().with_tag("report"))
+                    .await
+            }
+        });
+        while db.running_query_tags().is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(db.running_query_tags(), vec!["report"]);
+        assert_eq!(db.cancel("missing"), 0);
+        assert_eq!(db.cancel("report"), 1);
+
+        let err = running.await?.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<JobInterrupted>(),
+            Some(&JobInterrupted::Cancelled)
+        );
+        assert!(db.running_query_tags().is_empty());
+        Ok(())
+    }
+}
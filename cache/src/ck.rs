@@ -1,3 +1,5 @@
+#[cfg(feature = "clickhouse")]
+use crate::ck_client::{ClickHouseClient, QueryHints};
 use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
 use async_trait::async_trait;
 use datafusion::catalog::Session;
@@ -9,26 +11,110 @@ use datafusion::execution::TaskContext;
 use datafusion::logical_expr::Expr;
 use datafusion::physical_expr::EquivalenceProperties;
 use datafusion::physical_expr::PhysicalSortRequirement;
+#[cfg(feature = "clickhouse")]
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
 use datafusion::physical_plan::ExecutionMode;
 use datafusion::physical_plan::Partitioning;
 use datafusion::physical_plan::{
     DisplayAs, Distribution, ExecutionPlan, PlanProperties, SendableRecordBatchStream,
 };
+#[cfg(feature = "clickhouse")]
+use futures::TryStreamExt;
 use std::any::Any;
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct ClickHouseTableProvider {
     // ClickHouse 连接信息等
+    #[cfg(feature = "clickhouse")]
+    remote: Option<RemoteTable>,
 }
+
+/// ClickHouse 中的表，扫描时带上这个表的 SETTINGS
+#[cfg(feature = "clickhouse")]
+#[derive(Debug, Clone)]
+struct RemoteTable {
+    client: ClickHouseClient,
+    // 已经加好引号的 `database`.`table`
+    table: String,
+    schema: SchemaRef,
+    hints: QueryHints,
+}
+
+// 扫描时发给 ClickHouse 的查询
+#[cfg(feature = "clickhouse")]
+#[derive(Debug, Clone)]
+struct RemoteQuery {
+    client: ClickHouseClient,
+    sql: String,
+    hints: QueryHints,
+}
+
 impl ClickHouseTableProvider {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            #[cfg(feature = "clickhouse")]
+            remote: None,
+        }
+    }
+
+    /// 读取 ClickHouse 中 table 的表，每次扫描都带上 hints 中的 SETTINGS
+    #[cfg(feature = "clickhouse")]
+    pub(crate) fn remote(
+        client: ClickHouseClient,
+        table: String,
+        schema: SchemaRef,
+        hints: QueryHints,
+    ) -> Self {
+        Self {
+            remote: Some(RemoteTable {
+                client,
+                table,
+                schema,
+                hints,
+            }),
+        }
     }
 
     pub async fn create_physical_plan(&self, schema: SchemaRef) -> Result<Arc<dyn ExecutionPlan>> {
         Ok(Arc::new(ClickHouseExecutionPlan::new(schema, self.clone())))
     }
+
+    // 只查询需要的列，没有过滤条件时 DataFusion 传下来的 limit 也交给 ClickHouse
+    #[cfg(feature = "clickhouse")]
+    fn remote_plan(
+        &self,
+        remote: &RemoteTable,
+        projection: Option<&Vec<usize>>,
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let schema = match projection {
+            Some(projection) => Arc::new(remote.schema.project(projection)?),
+            None => remote.schema.clone(),
+        };
+        let columns = if schema.fields().is_empty() {
+            "1".to_string()
+        } else {
+            schema
+                .fields()
+                .iter()
+                .map(|field| format!("`{}`", field.name().replace('`', "\\`")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut sql = format!("SELECT {} FROM {}", columns, remote.table);
+        if let Some(limit) = limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        let mut plan = ClickHouseExecutionPlan::new(schema, self.clone());
+        plan.properties = plan.properties.with_execution_mode(ExecutionMode::Bounded);
+        plan.query = Some(RemoteQuery {
+            client: remote.client.clone(),
+            sql,
+            hints: remote.hints.clone(),
+        });
+        Ok(Arc::new(plan))
+    }
 }
 #[async_trait]
 impl TableProvider for ClickHouseTableProvider {
@@ -47,11 +133,19 @@ impl TableProvider for ClickHouseTableProvider {
         _filters: &[Expr],
         _limit: Option<usize>,
     ) -> Result<Arc<dyn ExecutionPlan>> {
+        #[cfg(feature = "clickhouse")]
+        if let Some(remote) = &self.remote {
+            return self.remote_plan(remote, _projection, _limit);
+        }
         return self.create_physical_plan(self.schema()).await;
     }
 
     // TODO 通过 cache pool 统一 schema
     fn schema(&self) -> SchemaRef {
+        #[cfg(feature = "clickhouse")]
+        if let Some(remote) = &self.remote {
+            return remote.schema.clone();
+        }
         // 创建字段列表
         let fields = vec![
             Field::new("id", DataType::Int32, false),
@@ -73,6 +167,8 @@ struct ClickHouseExecutionPlan {
     schema: SchemaRef,
     properties: PlanProperties,
     db: ClickHouseTableProvider,
+    #[cfg(feature = "clickhouse")]
+    query: Option<RemoteQuery>,
 }
 
 impl ClickHouseExecutionPlan {
@@ -93,6 +189,8 @@ impl ClickHouseExecutionPlan {
             schema,
             properties,
             db,
+            #[cfg(feature = "clickhouse")]
+            query: None,
         }
     }
 }
@@ -126,8 +224,26 @@ impl ExecutionPlan for ClickHouseExecutionPlan {
         _partition: usize,
         _context: Arc<TaskContext>,
     ) -> Result<SendableRecordBatchStream> {
-        // 实现查询执行逻辑
-        todo!("Implement query execution")
+        #[cfg(feature = "clickhouse")]
+        if let Some(query) = self.query.clone() {
+            let stream = futures::stream::once(async move {
+                let (_, batches) = query
+                    .client
+                    .query(&query.sql, &query.hints)
+                    .await
+                    .map_err(|e| DataFusionError::External(e.into()))?;
+                Ok::<_, DataFusionError>(futures::stream::iter(batches.into_iter().map(Ok)))
+            })
+            .try_flatten();
+            return Ok(Box::pin(RecordBatchStreamAdapter::new(
+                self.schema.clone(),
+                stream,
+            )));
+        }
+        // 没有连接的表还不能查询
+        Err(DataFusionError::NotImplemented(
+            "ClickHouse table without a connection".to_string(),
+        ))
     }
 
     fn required_input_distribution(&self) -> Vec<Distribution> {
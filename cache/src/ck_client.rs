@@ -0,0 +1,775 @@
+use crate::ck::ClickHouseTableProvider;
+use crate::config::ClickHouseConfig;
+use crate::events::CacheEvent;
+use crate::pool::DB;
+use crate::sync_dlq::SyncDeadLetter;
+use crate::workload::fingerprint_normalized;
+use anyhow::{Context, Result};
+use arrow_schema::SchemaRef;
+use datafusion::arrow::array::ArrayRef;
+use datafusion::arrow::compute::concat_batches;
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::json::LineDelimitedWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::physical_plan::collect;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// 第一次重试前等待的时间，之后每次翻倍，最多等待 MAX_RETRY_BACKOFF
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// 目标表的引擎，决定写入时需要的设置
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableEngine {
+    // Distributed 表，由接收写入的节点转发到各个 shard
+    Distributed,
+    // Replicated*MergeTree，insert_deduplication_token 在副本间生效
+    Replicated,
+    Other(String),
+}
+
+impl TableEngine {
+    fn parse(engine: &str) -> Self {
+        let engine = engine.trim();
+        if engine == "Distributed" {
+            TableEngine::Distributed
+        } else if engine.starts_with("Replicated") {
+            TableEngine::Replicated
+        } else {
+            TableEngine::Other(engine.to_string())
+        }
+    }
+}
+
+/// 单次查询附带的 ClickHouse SETTINGS，用于限制缓存刷新时远端的资源占用
+/// 和 ClickHouseConfig::settings 同名时覆盖配置里的值
+#[derive(Debug, Clone, Default)]
+pub struct QueryHints {
+    settings: BTreeMap<String, String>,
+}
+
+impl QueryHints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_setting(mut self, name: &str, value: impl ToString) -> Self {
+        self.settings.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn max_threads(self, threads: usize) -> Self {
+        self.with_setting("max_threads", threads)
+    }
+
+    pub fn max_execution_time(self, timeout: Duration) -> Self {
+        self.with_setting("max_execution_time", timeout.as_secs().max(1))
+    }
+
+    pub fn use_query_cache(self, enabled: bool) -> Self {
+        self.with_setting("use_query_cache", enabled as u8)
+    }
+
+    /// 配置文件中表的 settings
+    pub fn with_settings<'a>(
+        self,
+        settings: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Self {
+        settings
+            .into_iter()
+            .fold(self, |hints, (name, value)| hints.with_setting(name, value))
+    }
+}
+
+/// 通过 HTTP 接口把数据按块写入 ClickHouse
+///
+/// 每块数据是一个 INSERT ... FORMAT JSONEachRow 请求，带上由内容计算的
+/// insert_deduplication_token，重试或者重复同步同一块数据时 ClickHouse 会去重。
+/// 配置了多个节点时轮询使用，请求失败时在下一个节点上重试。
+/// 也可以用 query 从 ClickHouse 读取数据刷新本地表
+#[derive(Debug, Clone)]
+pub struct ClickHouseClient {
+    client: reqwest::Client,
+    config: ClickHouseConfig,
+    next_endpoint: Arc<AtomicUsize>,
+    engines: Arc<Mutex<HashMap<String, TableEngine>>>,
+    dead_letter: Option<Arc<SyncDeadLetter>>,
+    // 每个表已经同步过的 batch，key 是第一列数组的地址，同时持有数组保证地址不会被复用
+    synced: Arc<tokio::sync::Mutex<HashMap<String, HashMap<usize, ArrayRef>>>>,
+}
+
+/// 一次同步的结果
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncReport {
+    pub written: usize,
+    // 暂存到 dead_letter 的行数
+    pub parked: usize,
+}
+
+impl ClickHouseClient {
+    pub fn new(config: ClickHouseConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+            next_endpoint: Arc::new(AtomicUsize::new(0)),
+            engines: Arc::new(Mutex::new(HashMap::new())),
+            dead_letter: None,
+            synced: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 重试后仍然失败的数据块暂存到 dead_letter，继续写后面的数据，之后用 replay_parked 重放
+    pub fn with_dead_letter(mut self, dead_letter: SyncDeadLetter) -> Self {
+        self.dead_letter = Some(Arc::new(dead_letter));
+        self
+    }
+
+    /// 按块写入所有行；配置了 dead_letter 时失败的块会被暂存而不是返回错误
+    pub async fn write_batches(&self, table: &str, batches: &[RecordBatch]) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+        let Some(first) = batches.first() else {
+            return Ok(report);
+        };
+        let all = concat_batches(&first.schema(), batches)?;
+        let block_size = self.config.block_size.max(1);
+        let engine = self.table_engine(table).await;
+
+        let mut offset = 0;
+        while offset < all.num_rows() {
+            let len = block_size.min(all.num_rows() - offset);
+            let block = all.slice(offset, len);
+            let result = match &engine {
+                Ok(engine) => self.insert_block(table, engine, &block).await,
+                Err(e) => Err(anyhow::anyhow!("{:#}", e)),
+            };
+            match (result, &self.dead_letter) {
+                (Ok(()), _) => report.written += len,
+                (Err(e), Some(dead_letter)) => {
+                    dead_letter.park(table, &block, &format!("{:#}", e))?;
+                    report.parked += len;
+                }
+                (Err(e), None) => return Err(e),
+            }
+            offset += len;
+        }
+        Ok(report)
+    }
+
+    /// 只写入 batches 中之前没有同步过的 batch，batches 是表当前的全部内容
+    ///
+    /// 写入失败时不记录这次的 batch，下次同步重新发送，已经写入的块由去重 token 去掉
+    async fn write_unsynced(&self, table: &str, batches: Vec<RecordBatch>) -> Result<SyncReport> {
+        let mut synced = self.synced.lock().await;
+        let previous = synced.remove(table).unwrap_or_default();
+        let unsynced: Vec<RecordBatch> = batches
+            .iter()
+            .filter(|batch| batch_key(batch).is_some_and(|(key, _)| !previous.contains_key(&key)))
+            .cloned()
+            .collect();
+        let report = self.write_batches(table, &unsynced).await;
+        // 只保留表中还存在的 batch，被重写掉的数据不会一直占用内存
+        let current = match &report {
+            Ok(_) => batches.iter().filter_map(batch_key).collect(),
+            Err(_) => previous,
+        };
+        synced.insert(table.to_string(), current);
+        report
+    }
+
+    /// 重放 dead_letter 中暂存的数据块，成功的从 dead_letter 中删除
+    /// 块的去重 token 和第一次写入时相同，重复写入会被 ClickHouse 去掉
+    pub async fn replay_parked(&self) -> Result<SyncReport> {
+        let dead_letter = self
+            .dead_letter
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No dead letter configured"))?;
+        let mut report = SyncReport::default();
+        for parked in dead_letter.parked()? {
+            let block = dead_letter.load(&parked)?;
+            let result = match self.table_engine(&parked.table).await {
+                Ok(engine) => self.insert_block(&parked.table, &engine, &block).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    dead_letter.remove(&parked)?;
+                    report.written += parked.rows;
+                }
+                Err(_) => report.parked += parked.rows,
+            }
+        }
+        Ok(report)
+    }
+
+    /// 执行查询，以 ArrowStream 格式读取结果
+    pub async fn query(
+        &self,
+        sql: &str,
+        hints: &QueryHints,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+        let mut params = vec![("query", format!("{} FORMAT ArrowStream", sql))];
+        params.extend(
+            hints
+                .settings
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone())),
+        );
+        let output = self.execute(&params, String::new()).await?;
+        let reader = StreamReader::try_new(Cursor::new(output), None)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok((schema, batches))
+    }
+
+    // 加好引号的 `database`.`table`
+    pub(crate) fn qualified_table(&self, table: &str) -> String {
+        format!("`{}`.`{}`", self.config.database, table)
+    }
+
+    /// 查询 system.tables 得到目标表的引擎，结果会缓存
+    pub async fn table_engine(&self, table: &str) -> Result<TableEngine> {
+        if let Some(engine) = self.engines.lock().unwrap().get(table) {
+            return Ok(engine.clone());
+        }
+        let sql = format!(
+            "SELECT engine FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT TabSeparated",
+            self.config.database.replace('\'', "\\'"),
+            table.replace('\'', "\\'")
+        );
+        let output = self
+            .execute(&[("query", sql)], String::new())
+            .await
+            .with_context(|| format!("get engine of {}", table))?;
+        if output.iter().all(u8::is_ascii_whitespace) {
+            return Err(anyhow::anyhow!(
+                "table {}.{} not found in ClickHouse",
+                self.config.database,
+                table
+            ));
+        }
+        let engine = TableEngine::parse(&String::from_utf8_lossy(&output));
+        self.engines
+            .lock()
+            .unwrap()
+            .insert(table.to_string(), engine.clone());
+        Ok(engine)
+    }
+
+    async fn insert_block(
+        &self,
+        table: &str,
+        engine: &TableEngine,
+        block: &RecordBatch,
+    ) -> Result<()> {
+        let mut writer = LineDelimitedWriter::new(Vec::new());
+        writer.write_batches(&[block])?;
+        writer.finish()?;
+        let body = String::from_utf8(writer.into_inner())?;
+        let token = fingerprint_normalized(&format!("{}\n{}", table, body));
+
+        let mut params = vec![
+            (
+                "query",
+                format!(
+                    "INSERT INTO {} FORMAT JSONEachRow",
+                    self.qualified_table(table)
+                ),
+            ),
+            ("insert_deduplication_token", token),
+        ];
+        if self.config.async_insert {
+            params.push(("async_insert", "1".to_string()));
+            params.push(("wait_for_async_insert", "1".to_string()));
+            params.push(("async_insert_deduplicate", "1".to_string()));
+        }
+        if engine == &TableEngine::Distributed {
+            // 等数据写到各个 shard 之后再返回，否则失败时无法重试
+            params.push(("insert_distributed_sync", "1".to_string()));
+        }
+
+        self.execute(&params, body)
+            .await
+            .with_context(|| format!("insert into {}", table))?;
+        Ok(())
+    }
+
+    // 发送请求，可以重试的错误换下一个节点重试
+    // 后面的参数覆盖前面的：配置里的 settings < params
+    async fn execute(&self, params: &[(&str, String)], body: String) -> Result<Vec<u8>> {
+        let mut merged: BTreeMap<&str, String> = self
+            .config
+            .settings
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        merged.extend(params.iter().cloned());
+        let params: Vec<_> = merged.into_iter().collect();
+
+        let mut attempt = 0;
+        loop {
+            let endpoint = self.endpoint();
+            match self.send(endpoint, &params, body.clone()).await {
+                Ok(text) => return Ok(text),
+                Err(e) if e.retryable && attempt < self.config.max_retries => {
+                    tokio::time::sleep(retry_backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e.error)
+                        .with_context(|| format!("failed after {} attempts", attempt + 1))
+                }
+            }
+        }
+    }
+
+    fn endpoint(&self) -> &str {
+        let count = 1 + self.config.replicas.len();
+        match self.next_endpoint.fetch_add(1, Ordering::Relaxed) % count {
+            0 => &self.config.url,
+            i => &self.config.replicas[i - 1],
+        }
+    }
+
+    async fn send(
+        &self,
+        endpoint: &str,
+        params: &[(&str, String)],
+        body: String,
+    ) -> Result<Vec<u8>, SendError> {
+        let mut request = self.client.post(endpoint).query(params).body(body);
+        if let Some(user) = &self.config.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.config.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+
+        let response = request.send().await.map_err(|e| SendError {
+            retryable: true,
+            error: e.into(),
+        })?;
+        let status = response.status();
+        let output = response
+            .bytes()
+            .await
+            .map(|b| b.to_vec())
+            .unwrap_or_default();
+        if status.is_success() {
+            return Ok(output);
+        }
+        let text = String::from_utf8_lossy(&output);
+        Err(SendError {
+            // 4xx 一般是 SQL 或数据的问题，重试也不会成功
+            retryable: status.is_server_error(),
+            error: anyhow::anyhow!("ClickHouse {} returned {}: {}", endpoint, status, text),
+        })
+    }
+}
+
+struct SendError {
+    retryable: bool,
+    error: anyhow::Error,
+}
+
+// batch 的标识：第一列数组的地址，表中的 batch 被查询、追加或者 copy-on-write 时不会变
+fn batch_key(batch: &RecordBatch) -> Option<(usize, ArrayRef)> {
+    let column = batch.columns().first()?;
+    Some((Arc::as_ptr(column) as *const () as usize, column.clone()))
+}
+
+// 第 attempt 次重试前等待的时间，max_retries 很大时也不会溢出
+fn retry_backoff(attempt: usize) -> Duration {
+    u32::try_from(attempt)
+        .ok()
+        .and_then(|attempt| 2u32.checked_pow(attempt))
+        .and_then(|factor| RETRY_BACKOFF.checked_mul(factor))
+        .map_or(MAX_RETRY_BACKOFF, |backoff| backoff.min(MAX_RETRY_BACKOFF))
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 把表中还没有同步过的数据写入 ClickHouse 中的同名表，第一次同步写入全部数据
+    ///
+    /// client 按 batch 记录已经同步过的数据，之后只发送新追加的 batch。
+    /// upsert、truncate 等整表重写之后表中都是新的 batch，下一次同步会重新发送整个表，
+    /// 目标表需要能去掉重复的行（比如 ReplacingMergeTree）；外部表每次读取的都是新的 batch，同样每次全部发送
+    pub async fn sync_to_clickhouse(
+        &self,
+        table: &str,
+        client: &ClickHouseClient,
+    ) -> Result<SyncReport> {
+        let provider = self
+            .ctx
+            .table_provider(self.table_ref(table))
+            .await
+            .map_err(|_| anyhow::anyhow!("Table {} not found", table))?;
+        // 直接扫描表，拿到表中原样的 batch，查询计划可能会合并或者切分 batch
+        let plan = provider.scan(&self.ctx.state(), None, &[], None).await?;
+        let batches = collect(plan, self.ctx.task_ctx()).await?;
+        let report = client.write_unsynced(table, batches).await;
+        match &report {
+            Ok(report) if report.parked > 0 => self.emit(CacheEvent::SyncFailed {
+                table: table.to_string(),
+                error: format!("{} rows parked in dead letter", report.parked),
+            }),
+            Err(e) => self.emit(CacheEvent::SyncFailed {
+                table: table.to_string(),
+                error: format!("{:#}", e),
+            }),
+            Ok(_) => {}
+        }
+        report
+    }
+
+    /// 把 ClickHouse 中的 remote_table 注册成本地表 name，查询时直接读取远端，不缓存数据
+    ///
+    /// hints 是这个表的 SETTINGS，每次扫描都带上，比如用 max_threads 限制远端的资源占用；
+    /// 注册只在内存中，重启后需要重新注册
+    pub async fn register_clickhouse_table(
+        &self,
+        name: &str,
+        remote_table: &str,
+        client: &ClickHouseClient,
+        hints: QueryHints,
+    ) -> Result<()> {
+        let table = client.qualified_table(remote_table);
+        let (schema, _) = client
+            .query(&format!("SELECT * FROM {} LIMIT 0", table), &hints)
+            .await?;
+        let provider = ClickHouseTableProvider::remote(client.clone(), table, schema, hints);
+        self.replace_table(self.table_ref(name).table(), Arc::new(provider))?;
+        Ok(())
+    }
+
+    /// 用 ClickHouse 查询的结果原子替换本地表的内容，返回行数
+    ///
+    /// 替换时持有表的写锁，开启 WAL 时新内容写入 WAL；约束和索引保留
+    pub async fn refresh_from_clickhouse(
+        &self,
+        table: &str,
+        sql: &str,
+        client: &ClickHouseClient,
+        hints: &QueryHints,
+    ) -> Result<usize> {
+        let (schema, batches) = match client.query(sql, hints).await {
+            Ok(result) => result,
+            Err(e) => {
+                self.emit(CacheEvent::RefreshFailed {
+                    table: table.to_string(),
+                    error: format!("{:#}", e),
+                });
+                return Err(e);
+            }
+        };
+        let rows = batches.iter().map(|b| b.num_rows()).sum();
+        let _guard = self.lock_table(table).await;
+        let base = self.ctx.table_provider(self.table_ref(table)).await.ok();
+        self.rewrite_table(
+            self.table_ref(table).table(),
+            schema,
+            vec![batches],
+            base.as_ref(),
+        )?;
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::ipc::writer::StreamWriter;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+    // 记录收到的 INSERT 请求（请求行, body），前 fail_first 个请求返回 500
+    // 其他请求返回 respond(请求行)
+    async fn mock_clickhouse(
+        fail_first: usize,
+        respond: fn(&str) -> Vec<u8>,
+    ) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            let mut served = 0;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let (head, body) = loop {
+                    let n = socket.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break (head.to_string(), body.to_string());
+                        }
+                    }
+                };
+                let line = head.lines().next().unwrap().to_string();
+                let (status, output) = if served < fail_first {
+                    ("500 Internal Server Error", Vec::new())
+                } else if line.contains("INSERT") {
+                    recorded.lock().unwrap().push((line, body));
+                    ("200 OK", Vec::new())
+                } else {
+                    ("200 OK", respond(&line))
+                };
+                served += 1;
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    output.len()
+                );
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(&output).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn config(url: String, replicas: Vec<String>) -> ClickHouseConfig {
+        ClickHouseConfig {
+            url,
+            replicas,
+            database: "cache".to_string(),
+            user: None,
+            password: None,
+            block_size: 2,
+            async_insert: true,
+            max_retries: 3,
+            settings: HashMap::from([("max_threads".to_string(), "8".to_string())]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_to_clickhouse() -> Result<()> {
+        let (url, requests) = mock_clickhouse(1, |_| b"ReplicatedMergeTree\n".to_vec()).await;
+        let client = ClickHouseClient::new(config(url, vec![]));
+
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')")
+            .await?;
+        assert_eq!(db.sync_to_clickhouse("t", &client).await?.written, 3);
+        assert_eq!(client.table_engine("t").await?, TableEngine::Replicated);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (line, _) = &requests[0];
+        assert!(line.contains("async_insert=1"));
+        assert!(line.contains("insert_deduplication_token="));
+        assert!(!line.contains("insert_distributed_sync"));
+        let rows: usize = requests.iter().map(|(_, body)| body.lines().count()).sum();
+        assert_eq!(rows, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_only_new_rows() -> Result<()> {
+        let (url, requests) = mock_clickhouse(0, |_| b"MergeTree\n".to_vec()).await;
+        let client = ClickHouseClient::new(config(url, vec![]));
+        let rows = |requests: &Requests| -> usize {
+            requests
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|(_, body)| body.lines().count())
+                .sum()
+        };
+
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3)").await?;
+        assert_eq!(db.sync_to_clickhouse("t", &client).await?.written, 3);
+        assert_eq!(rows(&requests), 3);
+
+        db.execute("INSERT INTO t VALUES (4)").await?;
+        assert_eq!(db.sync_to_clickhouse("t", &client).await?.written, 1);
+        assert_eq!(rows(&requests), 1);
+        assert_eq!(db.sync_to_clickhouse("t", &client).await?.written, 0);
+        assert_eq!(rows(&requests), 0);
+
+        // 整表重写之后重新发送整个表
+        db.truncate_table("t").await?;
+        db.execute("INSERT INTO t VALUES (5)").await?;
+        assert_eq!(db.sync_to_clickhouse("t", &client).await?.written, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(0), RETRY_BACKOFF);
+        assert_eq!(retry_backoff(3), RETRY_BACKOFF * 8);
+        assert_eq!(retry_backoff(32), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(usize::MAX), MAX_RETRY_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_replica() -> Result<()> {
+        // 先占用一个端口再释放，连接会被拒绝
+        let down = {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            format!("http://{}/", listener.local_addr()?)
+        };
+        let (url, requests) = mock_clickhouse(0, |_| b"Distributed\n".to_vec()).await;
+        let client = ClickHouseClient::new(config(down, vec![url]));
+
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3), (4), (5)")
+            .await?;
+        assert_eq!(db.sync_to_clickhouse("t", &client).await?.written, 5);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|(line, _)| line.contains("insert_distributed_sync=1")));
+        Ok(())
+    }
+
+    // 把请求行里的 max_threads 作为结果返回
+    fn echo_max_threads(line: &str) -> Vec<u8> {
+        let threads: i64 = line
+            .split(['?', '&', ' '])
+            .find_map(|p| p.strip_prefix("max_threads="))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let batch = RecordBatch::try_from_iter(vec![(
+            "max_threads",
+            Arc::new(Int64Array::from(vec![threads])) as ArrayRef,
+        )])
+        .unwrap();
+        let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_refresh_with_hints() -> Result<()> {
+        let (url, _) = mock_clickhouse(0, echo_max_threads).await;
+        let client = ClickHouseClient::new(config(url, vec![]));
+
+        let db = DB::<()>::new("test_db");
+        let sql = "SELECT * FROM ledger";
+        db.refresh_from_clickhouse("remote", sql, &client, &QueryHints::new())
+            .await?;
+        let batches = db
+            .query_to_batches("SELECT max_threads FROM remote")
+            .await?;
+        let column = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(column.value(0), 8);
+
+        let hints = QueryHints::new()
+            .max_threads(2)
+            .max_execution_time(Duration::from_secs(30));
+        let rows = db
+            .refresh_from_clickhouse("remote", sql, &client, &hints)
+            .await?;
+        assert_eq!(rows, 1);
+        let batches = db
+            .query_to_batches("SELECT max_threads FROM remote")
+            .await?;
+        let column = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(column.value(0), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clickhouse_table_settings() -> Result<()> {
+        let (url, _) = mock_clickhouse(0, echo_max_threads).await;
+        let client = ClickHouseClient::new(config(url, vec![]));
+
+        // 每次扫描都带上表的 SETTINGS
+        let db = DB::<()>::new("test_db");
+        db.register_clickhouse_table(
+            "remote",
+            "ledger",
+            &client,
+            QueryHints::new().max_threads(3),
+        )
+        .await?;
+        for _ in 0..2 {
+            let batches = db
+                .query_to_batches("SELECT max_threads FROM remote")
+                .await?;
+            let column = batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            assert_eq!(column.value(0), 3);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_park_and_replay() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let down = {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            format!("http://{}/", listener.local_addr()?)
+        };
+        let mut unavailable = config(down, vec![]);
+        unavailable.max_retries = 0;
+        let client =
+            ClickHouseClient::new(unavailable).with_dead_letter(SyncDeadLetter::new(dir.path())?);
+
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3)").await?;
+        let report = db.sync_to_clickhouse("t", &client).await?;
+        assert_eq!(
+            report,
+            SyncReport {
+                written: 0,
+                parked: 3
+            }
+        );
+        assert_eq!(client.replay_parked().await?.parked, 3);
+
+        // 上游恢复之后重放
+        let (url, requests) = mock_clickhouse(0, |_| b"MergeTree\n".to_vec()).await;
+        let client = ClickHouseClient::new(config(url, vec![]))
+            .with_dead_letter(SyncDeadLetter::new(dir.path())?);
+        let report = client.replay_parked().await?;
+        assert_eq!(
+            report,
+            SyncReport {
+                written: 3,
+                parked: 0
+            }
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(SyncDeadLetter::new(dir.path())?.parked()?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use crate::ingest::IngestReport;
+use crate::pool::{get_value_with, DB};
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+/// 查询结果和写入数据的行编码方式，每一行编码成 列名 -> 值 的 map，整体是一个数组
+pub trait RowEncoder: Send + Sync {
+    // 比如 HTTP 响应的 Content-Type
+    fn content_type(&self) -> &'static str;
+    fn encode(&self, rows: &[Map<String, Value>]) -> Result<Vec<u8>>;
+    fn decode(&self, payload: &[u8]) -> Result<Vec<Map<String, Value>>>;
+}
+
+pub struct JsonEncoder;
+
+impl RowEncoder for JsonEncoder {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, rows: &[Map<String, Value>]) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(rows)?)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Vec<Map<String, Value>>> {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+pub struct MessagePackEncoder;
+
+impl RowEncoder for MessagePackEncoder {
+    fn content_type(&self) -> &'static str {
+        "application/msgpack"
+    }
+
+    fn encode(&self, rows: &[Map<String, Value>]) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(rows)?)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Vec<Map<String, Value>>> {
+        Ok(rmp_serde::from_slice(payload)?)
+    }
+}
+
+pub struct CborEncoder;
+
+impl RowEncoder for CborEncoder {
+    fn content_type(&self) -> &'static str {
+        "application/cbor"
+    }
+
+    fn encode(&self, rows: &[Map<String, Value>]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(rows, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Vec<Map<String, Value>>> {
+        Ok(ciborium::from_reader(payload)?)
+    }
+}
+
+/// 查询结果转 JSON 时数值的输出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberMode {
+    // 都输出成 JSON 数字，超过 2^53 的整数在 JavaScript 中会丢失精度，DECIMAL 会转成浮点数
+    #[default]
+    Number,
+    // 超过 2^53 的整数和所有 DECIMAL 输出成字符串，不丢失精度
+    UnsafeAsString,
+}
+
+/// query_to_table_json 的数据排列方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableJsonLayout {
+    // rows: 每行一个数组
+    #[default]
+    RowMajor,
+    // data: 每列一个数组
+    ColumnMajor,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: String,
+}
+
+/// 图表和表格组件使用的格式，列名只出现一次，宽表比逐行的 map 小很多
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableJson {
+    pub columns: Vec<ColumnInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<Vec<Vec<Value>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<Vec<Value>>>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 查询结果转成 `{ columns: [{name, type}], rows: [[...]] }`
+    pub async fn query_to_table_json(&self, sql: &str) -> Result<TableJson> {
+        self.query_to_table_json_with(sql, TableJsonLayout::RowMajor, NumberMode::Number)
+            .await
+    }
+
+    pub async fn query_to_table_json_with(
+        &self,
+        sql: &str,
+        layout: TableJsonLayout,
+        numbers: NumberMode,
+    ) -> Result<TableJson> {
+        let batches = self.query_to_batches(sql).await?;
+        let schema = match batches.first() {
+            Some(batch) => batch.schema(),
+            // 没有结果时仍然返回列信息
+            None => Arc::new(
+                self.ctx
+                    .state()
+                    .create_logical_plan(sql)
+                    .await?
+                    .schema()
+                    .as_arrow()
+                    .clone(),
+            ),
+        };
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|f| ColumnInfo {
+                name: f.name().clone(),
+                data_type: f.data_type().to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut values = match layout {
+            TableJsonLayout::RowMajor => Vec::new(),
+            TableJsonLayout::ColumnMajor => vec![Vec::new(); columns.len()],
+        };
+        for batch in &batches {
+            for row_index in 0..batch.num_rows() {
+                let mut row = Vec::with_capacity(columns.len());
+                for (col_index, column) in batch.columns().iter().enumerate() {
+                    let value = get_value_with(column, row_index, numbers)?;
+                    match layout {
+                        TableJsonLayout::RowMajor => row.push(value),
+                        TableJsonLayout::ColumnMajor => values[col_index].push(value),
+                    }
+                }
+                if layout == TableJsonLayout::RowMajor {
+                    values.push(row);
+                }
+            }
+        }
+        Ok(match layout {
+            TableJsonLayout::RowMajor => TableJson {
+                columns,
+                rows: Some(values),
+                data: None,
+            },
+            TableJsonLayout::ColumnMajor => TableJson {
+                columns,
+                rows: None,
+                data: Some(values),
+            },
+        })
+    }
+
+    /// 查询并用指定的编码输出所有行
+    pub async fn query_to_encoded(&self, sql: &str, codec: &dyn RowEncoder) -> Result<Vec<u8>> {
+        let batches = self.query_to_batches(sql).await?;
+        codec.encode(&batches_to_rows(&batches)?)
+    }
+
+    /// query_to_encoded，可以指定大整数和 DECIMAL 的输出方式
+    pub async fn query_to_encoded_with(
+        &self,
+        sql: &str,
+        codec: &dyn RowEncoder,
+        numbers: NumberMode,
+    ) -> Result<Vec<u8>> {
+        let batches = self.query_to_batches(sql).await?;
+        codec.encode(&batches_to_rows_with(&batches, numbers)?)
+    }
+
+    /// 写入 MessagePack/CBOR 等编码的行，和 insert_json_rows 一样，单行失败时进入 dead-letter 表
+    /// 整个 payload 解码失败时返回错误
+    pub async fn insert_rows_encoded(
+        &self,
+        table: &str,
+        codec: &dyn RowEncoder,
+        payload: &[u8],
+    ) -> Result<IngestReport> {
+        let rows = codec
+            .decode(payload)?
+            .into_iter()
+            .map(|row| serde_json::to_string(&row))
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        self.insert_json_rows(table, &rows).await
+    }
+}
+
+pub(crate) fn batches_to_rows(batches: &[RecordBatch]) -> Result<Vec<Map<String, Value>>> {
+    batches_to_rows_with(batches, NumberMode::Number)
+}
+
+pub(crate) fn batches_to_rows_with(
+    batches: &[RecordBatch],
+    numbers: NumberMode,
+) -> Result<Vec<Map<String, Value>>> {
+    let mut rows = Vec::new();
+    for batch in batches {
+        let schema = batch.schema();
+        for row_index in 0..batch.num_rows() {
+            let mut row = Map::new();
+            for (col_index, field) in schema.fields().iter().enumerate() {
+                let value = get_value_with(batch.column(col_index), row_index, numbers)?;
+                row.insert(field.name().clone(), value);
+            }
+            rows.push(row);
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_to_encoded() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+        let sql = "SELECT * FROM t ORDER BY id";
+
+        let json = db.query_to_encoded(sql, &JsonEncoder).await?;
+        let expected: Value = serde_json::from_slice(&json)?;
+        assert_eq!(expected[1]["name"], "b");
+
+        let msgpack = db.query_to_encoded(sql, &MessagePackEncoder).await?;
+        let decoded: Value = rmp_serde::from_slice(&msgpack)?;
+        assert_eq!(decoded, expected);
+
+        let cbor = db.query_to_encoded(sql, &CborEncoder).await?;
+        let decoded: Value = ciborium::from_reader(cbor.as_slice())?;
+        assert_eq!(decoded, expected);
+        assert!(cbor.len() < json.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_to_table_json() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+
+        let table = db
+            .query_to_table_json("SELECT * FROM t ORDER BY id")
+            .await?;
+        assert_eq!(
+            serde_json::to_value(&table)?,
+            serde_json::json!({
+                "columns": [{"name": "id", "type": "Int64"}, {"name": "name", "type": "Utf8"}],
+                "rows": [[1, "a"], [2, "b"]],
+            })
+        );
+
+        let table = db
+            .query_to_table_json_with(
+                "SELECT * FROM t ORDER BY id",
+                TableJsonLayout::ColumnMajor,
+                NumberMode::Number,
+            )
+            .await?;
+        assert_eq!(
+            serde_json::to_value(&table.data)?,
+            serde_json::json!([[1, 2], ["a", "b"]])
+        );
+
+        let table = db
+            .query_to_table_json("SELECT * FROM t WHERE id > 10")
+            .await?;
+        assert_eq!(table.columns.len(), 2);
+        assert_eq!(table.rows, Some(vec![]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_number_mode() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        let sql = "SELECT 9007199254740993 AS big, 42 AS small, CAST('12345678901234567.89' AS DECIMAL(20, 2)) AS amount";
+
+        let json = db.query_to_encoded(sql, &JsonEncoder).await?;
+        let rows: Value = serde_json::from_slice(&json)?;
+        assert!(rows[0]["big"].is_number());
+        assert!(rows[0]["amount"].is_number());
+
+        let json = db
+            .query_to_encoded_with(sql, &JsonEncoder, NumberMode::UnsafeAsString)
+            .await?;
+        let rows: Value = serde_json::from_slice(&json)?;
+        assert_eq!(rows[0]["big"], "9007199254740993");
+        assert_eq!(rows[0]["small"], 42);
+        assert_eq!(rows[0]["amount"], "12345678901234567.89");
+
+        let table = db
+            .query_to_table_json_with(sql, TableJsonLayout::RowMajor, NumberMode::UnsafeAsString)
+            .await?;
+        assert_eq!(table.rows.unwrap()[0][0], "9007199254740993");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_rows_encoded() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        let rows: Vec<Map<String, Value>> = serde_json::from_str(
+            r#"[{"id": 1, "name": "a"}, {"id": "oops", "name": "b"}, {"id": 3, "name": "c"}]"#,
+        )?;
+
+        let report = db
+            .insert_rows_encoded("t", &MessagePackEncoder, &MessagePackEncoder.encode(&rows)?)
+            .await?;
+        assert_eq!(report.inserted, 2);
+        assert_eq!(report.dead_lettered, 1);
+
+        let report = db
+            .insert_rows_encoded("t", &CborEncoder, &CborEncoder.encode(&rows[..1])?)
+            .await?;
+        assert_eq!(report.inserted, 1);
+
+        let batches = db.query_to_batches("SELECT * FROM t").await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+        assert!(db
+            .insert_rows_encoded("t", &CborEncoder, b"not cbor")
+            .await
+            .is_err());
+        Ok(())
+    }
+}
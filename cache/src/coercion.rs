@@ -0,0 +1,406 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow::compute::{can_cast_types, cast, cast_with_options, CastOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::{NaiveDate, NaiveDateTime};
+use datafusion::arrow::array::{
+    new_null_array, Array, ArrayRef, StringArray, TimestampNanosecondArray, UInt64Array,
+};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::dataframe::DataFrame;
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::Statement;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::sync::Arc;
+
+/// 写入时自动执行的类型转换，列名和表一致但类型不同时使用
+///
+/// 只做不会丢失信息的转换，转换失败（比如时间格式都不匹配、超出 Decimal 精度）时整批写入失败
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct CoercionConfig {
+    // 整数转成更宽的整数（无符号可以转成更宽的有符号），Float32 转 Float64
+    #[serde(default)]
+    pub widen_numbers: bool,
+    // 字符串转时间戳时依次尝试的 chrono 格式，按 UTC 解析；为空时不转换字符串
+    #[serde(default)]
+    pub timestamp_formats: Vec<String>,
+    // 浮点数按表的精度和小数位转成 Decimal，多出的小数位四舍五入
+    #[serde(default)]
+    pub float_to_decimal: bool,
+}
+
+impl CoercionConfig {
+    pub fn widening() -> Self {
+        Self {
+            widen_numbers: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_timestamp_format(mut self, format: &str) -> Self {
+        self.timestamp_formats.push(format.to_string());
+        self
+    }
+
+    pub fn with_float_to_decimal(mut self) -> Self {
+        self.float_to_decimal = true;
+        self
+    }
+
+    // 返回 None 表示这个配置不处理从 from 到 to 的转换
+    fn coerce(&self, column: &str, array: &ArrayRef, to: &DataType) -> Result<Option<ArrayRef>> {
+        let from = array.data_type();
+        let strict = CastOptions {
+            safe: false,
+            ..Default::default()
+        };
+        if self.widen_numbers && is_widening(from, to) {
+            return Ok(Some(cast_with_options(array, to, &strict)?));
+        }
+        if self.float_to_decimal
+            && from.is_floating()
+            && matches!(to, DataType::Decimal128(_, _) | DataType::Decimal256(_, _))
+        {
+            return cast_with_options(array, to, &strict)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("Column {}: {}", column, e));
+        }
+        if !self.timestamp_formats.is_empty() && matches!(to, DataType::Timestamp(_, _)) {
+            if let Some(strings) = as_strings(array)? {
+                let parsed = self.parse_timestamps(column, &strings)?;
+                return Ok(Some(cast_with_options(&parsed, to, &strict)?));
+            }
+        }
+        Ok(None)
+    }
+
+    fn parse_timestamps(&self, column: &str, strings: &StringArray) -> Result<ArrayRef> {
+        let mut values = Vec::with_capacity(strings.len());
+        for (row, value) in strings.iter().enumerate() {
+            let Some(value) = value else {
+                values.push(None);
+                continue;
+            };
+            let parsed = self.timestamp_formats.iter().find_map(|format| {
+                NaiveDateTime::parse_from_str(value, format)
+                    .ok()
+                    .or_else(|| {
+                        NaiveDate::parse_from_str(value, format)
+                            .ok()
+                            .and_then(|date| date.and_hms_opt(0, 0, 0))
+                    })
+            });
+            let nanos = parsed
+                .and_then(|t| t.and_utc().timestamp_nanos_opt())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Column {} row {}: {:?} does not match any timestamp format",
+                        column,
+                        row,
+                        value
+                    )
+                })?;
+            values.push(Some(nanos));
+        }
+        Ok(Arc::new(TimestampNanosecondArray::from(values)))
+    }
+}
+
+fn is_widening(from: &DataType, to: &DataType) -> bool {
+    use DataType::*;
+    let width = |t: &DataType| match t {
+        Int8 | UInt8 => 8,
+        Int16 | UInt16 => 16,
+        Int32 | UInt32 => 32,
+        Int64 | UInt64 => 64,
+        _ => 0,
+    };
+    match (from, to) {
+        (Float32, Float64) => true,
+        _ if from.is_signed_integer() && to.is_signed_integer() => width(from) < width(to),
+        _ if from.is_unsigned_integer() && to.is_integer() => width(from) < width(to),
+        _ => false,
+    }
+}
+
+fn as_strings(array: &ArrayRef) -> Result<Option<StringArray>> {
+    if !matches!(
+        array.data_type(),
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View
+    ) {
+        return Ok(None);
+    }
+    let strings = cast(array, &DataType::Utf8)?;
+    Ok(strings.as_any().downcast_ref::<StringArray>().cloned())
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 设置表写入时的自动类型转换，对 insert_batch 和 INSERT 语句都生效
+    pub fn set_coercion(&self, table: &str, config: CoercionConfig) {
+        self.coercions
+            .write()
+            .unwrap()
+            .insert(table.to_string(), config);
+    }
+
+    pub fn coercion(&self, table: &str) -> Option<CoercionConfig> {
+        self.coercions.read().unwrap().get(table).cloned()
+    }
+
+    // 按列名把 batch 中类型和表不同的列转换成表的类型，没有配置或者不需要转换时原样返回
+    pub(crate) fn coerce_batch(
+        &self,
+        table: &str,
+        schema: &Schema,
+        batch: RecordBatch,
+    ) -> Result<RecordBatch> {
+        let Some(config) = self.coercion(table) else {
+            return Ok(batch);
+        };
+        let mut fields = Vec::with_capacity(batch.num_columns());
+        let mut columns = Vec::with_capacity(batch.num_columns());
+        let mut changed = false;
+        for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+            let target = schema.field_with_name(field.name()).ok();
+            let coerced = match target {
+                Some(target) if target.data_type() != field.data_type() => {
+                    config.coerce(field.name(), column, target.data_type())?
+                }
+                _ => None,
+            };
+            match (coerced, target) {
+                (Some(coerced), Some(target)) => {
+                    changed = true;
+                    fields.push(Arc::new(target.clone()));
+                    columns.push(coerced);
+                }
+                _ => {
+                    fields.push(field.clone());
+                    columns.push(column.clone());
+                }
+            }
+        }
+        if !changed {
+            return Ok(batch);
+        }
+        Ok(RecordBatch::try_new(
+            Arc::new(Schema::new(fields)),
+            columns,
+        )?)
+    }
+
+    /// 目标表配置了类型转换时，先执行 INSERT 的查询部分，转换后再写入
+    ///
+    /// 不是 INSERT、表没有配置或者列数对不上时返回 None，按普通的 INSERT 执行
+    pub(crate) async fn insert_coerced(&self, sql: &str) -> Result<Option<DataFrame>> {
+        if self.coercions.read().unwrap().is_empty() {
+            return Ok(None);
+        }
+        let Ok(mut statements) = DFParser::parse_sql(sql) else {
+            return Ok(None);
+        };
+        if statements.len() != 1 {
+            return Ok(None);
+        }
+        let Some(DFStatement::Statement(statement)) = statements.pop_front() else {
+            return Ok(None);
+        };
+        let Statement::Insert(insert) = *statement else {
+            return Ok(None);
+        };
+        let table = insert.table_name.to_string();
+        let Some(source) = insert.source else {
+            return Ok(None);
+        };
+        if self.coercion(&table).is_none() || !self.ctx.table_exist(table.as_str())? {
+            return Ok(None);
+        }
+        let schema = self.ctx.table_provider(table.as_str()).await?.schema();
+
+        // 没有指定列时按表的列顺序对应
+        let targets: Vec<String> = if insert.columns.is_empty() {
+            schema.fields().iter().map(|f| f.name().clone()).collect()
+        } else {
+            insert.columns.iter().map(|c| c.value.clone()).collect()
+        };
+        let plan = self
+            .ctx
+            .state()
+            .statement_to_plan(DFStatement::Statement(Box::new(Statement::Query(source))))
+            .await?;
+        if plan.schema().fields().len() != targets.len()
+            || targets.iter().any(|t| schema.index_of(t).is_err())
+        {
+            return Ok(None);
+        }
+        let batches = self.ctx.execute_logical_plan(plan).await?.collect().await?;
+
+        let mut rows = 0;
+        let mut aligned = Vec::with_capacity(batches.len());
+        for batch in batches {
+            rows += batch.num_rows();
+            let batch = align_to_table(&schema, &targets, batch)?;
+            let batch = self.coerce_batch(&table, &schema, batch)?;
+            aligned.push(cast_remaining(&schema, batch)?);
+        }
+        self.insert_batches(&table, aligned).await?;
+
+        let count_schema = Arc::new(Schema::new(vec![Field::new(
+            "count",
+            DataType::UInt64,
+            false,
+        )]));
+        let count = RecordBatch::try_new(
+            count_schema,
+            vec![Arc::new(UInt64Array::from(vec![rows as u64]))],
+        )?;
+        Ok(Some(self.ctx.read_batch(count)?))
+    }
+}
+
+// 按目标列改名，并用 NULL 补上没有写入的列，类型保持不变
+fn align_to_table(
+    schema: &SchemaRef,
+    targets: &[String],
+    batch: RecordBatch,
+) -> Result<RecordBatch> {
+    let mut fields = Vec::with_capacity(schema.fields().len());
+    let mut columns = Vec::with_capacity(schema.fields().len());
+    for field in schema.fields() {
+        match targets.iter().position(|t| t == field.name()) {
+            Some(index) => {
+                let column = batch.column(index).clone();
+                fields.push(Field::new(
+                    field.name(),
+                    column.data_type().clone(),
+                    field.is_nullable(),
+                ));
+                columns.push(column);
+            }
+            None => {
+                fields.push(field.as_ref().clone());
+                columns.push(new_null_array(field.data_type(), batch.num_rows()));
+            }
+        }
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+// 配置之外的类型差异按 INSERT 原本的规则转换（比如 Int64 -> Int32），不能转换的留给 diagnose_batch 报告
+fn cast_remaining(schema: &SchemaRef, batch: RecordBatch) -> Result<RecordBatch> {
+    let mut fields = Vec::with_capacity(batch.num_columns());
+    let mut columns = Vec::with_capacity(batch.num_columns());
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        if column.data_type() != field.data_type()
+            && can_cast_types(column.data_type(), field.data_type())
+        {
+            fields.push(field.as_ref().clone());
+            columns.push(cast(column, field.data_type())?);
+        } else {
+            fields.push(Field::new(
+                field.name(),
+                column.data_type().clone(),
+                field.is_nullable(),
+            ));
+            columns.push(column.clone());
+        }
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Float64Array, Int32Array};
+
+    #[tokio::test]
+    async fn test_coerce_insert_batch() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE trades (id BIGINT, traded_at TIMESTAMP, price DECIMAL(10, 2))")
+            .await?;
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("traded_at", DataType::Utf8, true),
+            Field::new("price", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("2024/01/02 09:30"), None])),
+                Arc::new(Float64Array::from(vec![Some(10.125), Some(3.1)])),
+            ],
+        )?;
+
+        // 没有配置时按 schema 不一致报错
+        assert!(db.insert_batch("trades", batch.clone()).await.is_err());
+
+        db.set_coercion(
+            "trades",
+            CoercionConfig::widening()
+                .with_timestamp_format("%Y-%m-%d %H:%M:%S")
+                .with_timestamp_format("%Y/%m/%d %H:%M")
+                .with_float_to_decimal(),
+        );
+        db.insert_batch("trades", batch).await?;
+        let batches = db
+            .query_to_batches(
+                "SELECT CAST(traded_at AS VARCHAR), CAST(price AS VARCHAR) FROM trades ORDER BY id",
+            )
+            .await?;
+        let formatted =
+            datafusion::arrow::util::pretty::pretty_format_batches(&batches)?.to_string();
+        assert!(formatted.contains("2024-01-02T09:30:00"));
+        assert!(formatted.contains("10.13"));
+        assert!(formatted.contains("3.10"));
+
+        let bad = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Int64, false),
+                Field::new("traded_at", DataType::Utf8, true),
+                Field::new("price", DataType::Float64, true),
+            ])),
+            vec![
+                Arc::new(datafusion::arrow::array::Int64Array::from(vec![3])),
+                Arc::new(StringArray::from(vec!["yesterday"])),
+                Arc::new(Float64Array::from(vec![1.0])),
+            ],
+        )?;
+        let err = db.insert_batch("trades", bad).await.unwrap_err();
+        assert!(err.to_string().contains("yesterday"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coerce_insert_statement() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE events (id BIGINT, at TIMESTAMP, note VARCHAR)")
+            .await?;
+        db.set_coercion(
+            "events",
+            CoercionConfig::default().with_timestamp_format("%d.%m.%Y"),
+        );
+        db.execute("INSERT INTO events (id, at) VALUES (1, '02.01.2024'), (2, '03.01.2024')")
+            .await?;
+        let batches = db
+            .query_to_batches(
+                "SELECT count(*) FROM events \
+                 WHERE at = TIMESTAMP '2024-01-03 00:00:00' AND note IS NULL",
+            )
+            .await?;
+        let count = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<datafusion::arrow::array::Int64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(count, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,396 @@
+use crate::config::{CodecConfig, CompressionConfig};
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::basic::{Compression, ZstdLevel};
+use datafusion::parquet::file::properties::WriterProperties;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+// 压缩测试最多使用的样本大小
+const MAX_BENCHMARK_SAMPLE_BYTES: usize = 16 * 1024 * 1024;
+
+/// 压缩算法，name 会写在 WAL 记录和备份文件名中，解压时按名字在 CompressionRegistry 中查找
+pub trait CompressionCodec: Debug + Send + Sync {
+    fn name(&self) -> &str;
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    // snapshot 写 Parquet 时使用 Parquet 自带的压缩，没有对应算法的 codec 不能用于 snapshot
+    fn parquet_compression(&self) -> Option<Compression> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCompression;
+
+impl CompressionCodec for NoCompression {
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn parquet_compression(&self) -> Option<Compression> {
+        Some(Compression::UNCOMPRESSED)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4;
+
+impl CompressionCodec for Lz4 {
+    fn name(&self) -> &str {
+        "lz4"
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(lz4_flex::compress_prepend_size(data))
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(lz4_flex::decompress_size_prepended(data)?)
+    }
+
+    fn parquet_compression(&self) -> Option<Compression> {
+        Some(Compression::LZ4_RAW)
+    }
+}
+
+/// 级别越高压缩率越高、CPU 开销越大，解压不需要知道级别
+#[derive(Debug, Clone, Copy)]
+pub struct Zstd {
+    pub level: i32,
+}
+
+impl Default for Zstd {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_ZSTD_LEVEL,
+        }
+    }
+}
+
+impl CompressionCodec for Zstd {
+    fn name(&self) -> &str {
+        "zstd"
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::encode_all(data, self.level)?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::decode_all(data)?)
+    }
+
+    fn parquet_compression(&self) -> Option<Compression> {
+        ZstdLevel::try_new(self.level).ok().map(Compression::ZSTD)
+    }
+}
+
+/// 按名字注册的压缩算法，默认包含 none、lz4 和 zstd
+#[derive(Debug, Clone)]
+pub struct CompressionRegistry {
+    codecs: HashMap<String, Arc<dyn CompressionCodec>>,
+}
+
+impl Default for CompressionRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            codecs: HashMap::new(),
+        };
+        registry.register(Arc::new(NoCompression));
+        registry.register(Arc::new(Lz4));
+        registry.register(Arc::new(Zstd::default()));
+        registry
+    }
+}
+
+impl CompressionRegistry {
+    /// 注册或者替换同名的 codec
+    pub fn register(&mut self, codec: Arc<dyn CompressionCodec>) {
+        self.codecs.insert(codec.name().to_string(), codec);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn CompressionCodec>> {
+        self.codecs.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.codecs.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// 按配置选择 codec，zstd 可以指定级别
+    pub fn resolve(&self, config: &CodecConfig) -> Result<Arc<dyn CompressionCodec>> {
+        if config.codec == "zstd" {
+            let level = config.level.unwrap_or(DEFAULT_ZSTD_LEVEL);
+            if !zstd::compression_level_range().contains(&level) {
+                return Err(anyhow::anyhow!("Invalid zstd level {}", level));
+            }
+            return Ok(Arc::new(Zstd { level }));
+        }
+        if config.level.is_some() {
+            return Err(anyhow::anyhow!(
+                "Codec {} does not take a level",
+                config.codec
+            ));
+        }
+        self.get(&config.codec)
+            .ok_or_else(|| anyhow::anyhow!("Unknown compression codec {}", config.codec))
+    }
+}
+
+/// 使用压缩的产物
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Artifact {
+    // WAL 中 INSERT 和 DDL 记录的 payload
+    Wal,
+    // DB::snapshot 写的 Parquet 文件
+    Snapshot,
+    // backup_to_storage 导出的 Arrow IPC 文件
+    Ipc,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CompressionState {
+    registry: CompressionRegistry,
+    // 没有设置的产物不压缩
+    selected: HashMap<Artifact, Arc<dyn CompressionCodec>>,
+}
+
+/// 一个 codec 在样本数据上的压缩结果
+#[derive(Debug, Clone)]
+pub struct CodecBenchmark {
+    pub codec: String,
+    pub input_bytes: usize,
+    pub output_bytes: usize,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+}
+
+impl CodecBenchmark {
+    /// 压缩后大小占原始大小的比例，越小越好
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            return 1.0;
+        }
+        self.output_bytes as f64 / self.input_bytes as f64
+    }
+}
+
+/// 用样本数据测试每个 codec 的压缩率和耗时，同时检查解压结果和原始数据一致
+pub fn benchmark_codecs(
+    codecs: &[Arc<dyn CompressionCodec>],
+    sample: &[u8],
+) -> Result<Vec<CodecBenchmark>> {
+    let mut results = Vec::new();
+    for codec in codecs {
+        let start = Instant::now();
+        let compressed = codec.compress(sample)?;
+        let compress_time = start.elapsed();
+        let start = Instant::now();
+        let decompressed = codec.decompress(&compressed)?;
+        let decompress_time = start.elapsed();
+        if decompressed != sample {
+            return Err(anyhow::anyhow!(
+                "Codec {} did not round-trip the sample",
+                codec.name()
+            ));
+        }
+        results.push(CodecBenchmark {
+            codec: codec.name().to_string(),
+            input_bytes: sample.len(),
+            output_bytes: compressed.len(),
+            compress_time,
+            decompress_time,
+        });
+    }
+    Ok(results)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 注册自定义 codec，之后可以在 set_compression 中按名字选择
+    pub fn register_compression_codec(&self, codec: Arc<dyn CompressionCodec>) {
+        self.compression.write().unwrap().registry.register(codec);
+    }
+
+    /// 选择产物使用的压缩算法，只影响之后写入的数据
+    ///
+    /// WAL 的压缩在 enable_wal 时确定，已经开启的 WAL 不受影响
+    pub fn set_compression(&self, artifact: Artifact, config: &CodecConfig) -> Result<()> {
+        let mut state = self.compression.write().unwrap();
+        let codec = state.registry.resolve(config)?;
+        if artifact == Artifact::Snapshot && codec.parquet_compression().is_none() {
+            return Err(anyhow::anyhow!(
+                "Codec {} cannot be used for Parquet snapshots",
+                codec.name()
+            ));
+        }
+        state.selected.insert(artifact, codec);
+        Ok(())
+    }
+
+    pub fn set_compression_config(&self, config: &CompressionConfig) -> Result<()> {
+        for (artifact, codec) in [
+            (Artifact::Wal, &config.wal),
+            (Artifact::Snapshot, &config.snapshot),
+            (Artifact::Ipc, &config.ipc),
+        ] {
+            if let Some(codec) = codec {
+                self.set_compression(artifact, codec)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn compression_codec(&self, artifact: Artifact) -> Arc<dyn CompressionCodec> {
+        self.compression
+            .read()
+            .unwrap()
+            .selected
+            .get(&artifact)
+            .cloned()
+            .unwrap_or_else(|| Arc::new(NoCompression))
+    }
+
+    pub(crate) fn compression_registry(&self) -> CompressionRegistry {
+        self.compression.read().unwrap().registry.clone()
+    }
+
+    /// 用当前内存表的数据测试所有可用于这个产物的 codec，结果按压缩后大小排序
+    ///
+    /// WAL 和 IPC 的样本是 Arrow IPC 编码的数据；snapshot 的样本是不压缩的 Parquet 文件，
+    /// 实际写入时 Parquet 按页压缩，压缩率会略有不同
+    pub async fn benchmark_compression(&self, artifact: Artifact) -> Result<Vec<CodecBenchmark>> {
+        let selected = self.compression_codec(artifact);
+        let mut codecs: Vec<Arc<dyn CompressionCodec>> = {
+            let state = self.compression.read().unwrap();
+            state
+                .registry
+                .names()
+                .iter()
+                .filter_map(|name| state.registry.get(name))
+                .filter(|codec| codec.name() != selected.name())
+                .collect()
+        };
+        codecs.push(selected);
+        if artifact == Artifact::Snapshot {
+            codecs.retain(|codec| codec.parquet_compression().is_some());
+        }
+
+        let mut sample = self.compression_sample(artifact).await?;
+        sample.truncate(MAX_BENCHMARK_SAMPLE_BYTES);
+        let mut results = benchmark_codecs(&codecs, &sample)?;
+        results.sort_by_key(|r| r.output_bytes);
+        Ok(results)
+    }
+
+    async fn compression_sample(&self, artifact: Artifact) -> Result<Vec<u8>> {
+        let schema = self.default_schema()?;
+        let mut sample = Vec::new();
+        for name in schema.table_names() {
+            let Some(provider) = schema.table(&name).await? else {
+                continue;
+            };
+            if !is_memory_table(provider.as_ref()) {
+                continue;
+            }
+            let batches = self.ctx.table(name.as_str()).await?.collect().await?;
+            if artifact == Artifact::Snapshot {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::UNCOMPRESSED)
+                    .build();
+                let mut writer =
+                    ArrowWriter::try_new(&mut sample, provider.schema(), Some(properties))?;
+                for batch in &batches {
+                    writer.write(batch)?;
+                }
+                writer.close()?;
+            } else {
+                let mut writer = StreamWriter::try_new(&mut sample, &provider.schema())?;
+                for batch in &batches {
+                    writer.write(batch)?;
+                }
+                writer.finish()?;
+            }
+            if sample.len() >= MAX_BENCHMARK_SAMPLE_BYTES {
+                break;
+            }
+        }
+        Ok(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 只做异或的 codec，用来测试注册和按名字解压
+    #[derive(Debug)]
+    struct Xor;
+
+    impl CompressionCodec for Xor {
+        fn name(&self) -> &str {
+            "xor"
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().map(|b| b ^ 0x5a).collect())
+        }
+
+        fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            self.compress(data)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_codec_selection_and_benchmark() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t AS SELECT value AS id, value % 10 AS bucket FROM generate_series(1, 10000)")
+            .await?;
+
+        assert_eq!(db.compression_codec(Artifact::Wal).name(), "none");
+        db.set_compression(Artifact::Wal, &CodecConfig::zstd(9))?;
+        assert_eq!(db.compression_codec(Artifact::Wal).name(), "zstd");
+        assert!(db
+            .set_compression(Artifact::Ipc, &CodecConfig::new("xor"))
+            .is_err());
+        assert!(db
+            .set_compression(Artifact::Wal, &CodecConfig::zstd(100))
+            .is_err());
+
+        db.register_compression_codec(Arc::new(Xor));
+        db.set_compression(Artifact::Ipc, &CodecConfig::new("xor"))?;
+        assert!(db
+            .set_compression(Artifact::Snapshot, &CodecConfig::new("xor"))
+            .is_err());
+
+        let results = db.benchmark_compression(Artifact::Ipc).await?;
+        let names: Vec<&str> = results.iter().map(|r| r.codec.as_str()).collect();
+        assert_eq!(names.len(), 4);
+        assert!(names.contains(&"xor"));
+        // 结果按压缩后大小排序，不压缩的 codec 在最后
+        assert!(results[0].ratio() < 1.0);
+        assert!(results.last().unwrap().ratio() >= 1.0);
+
+        let snapshot = db.benchmark_compression(Artifact::Snapshot).await?;
+        assert!(snapshot.iter().all(|r| r.codec != "xor"));
+        Ok(())
+    }
+}
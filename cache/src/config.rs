@@ -1,6 +1,10 @@
+use crate::credential::TokenProvider;
+use crate::notify::NotificationRule;
+use crate::ratelimit::RateLimitConfig;
 use config::{Config as ConfigRs, ConfigError, Environment, File};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Debug, Deserialize)]
 pub struct StorageConfig {
@@ -11,11 +15,254 @@ pub struct StorageConfig {
     pub bucket: String,
     // s3/oss/minio 等，暂时用 String，都用小写吧
     pub schema: String,
+    // STS 临时凭证的 session token，S3 和 OSS STS 都用这个
+    pub session_token: Option<String>,
+    // 长期运行的进程需要自动刷新临时凭证，只能在代码里设置
+    #[serde(skip)]
+    pub token_provider: Option<Arc<dyn TokenProvider>>,
+    // bucket 不存在时自动创建（DB::ensure_buckets）
+    #[serde(default)]
+    pub create_bucket_if_missing: bool,
+    // 所有读写都限制在这个前缀下，比如 cache/prod/
+    pub prefix: Option<String>,
+    // 每 GB 读/写的费用，用于 DB::accounting_report 估算成本
+    #[serde(default)]
+    pub cost_per_gb_read: Option<f64>,
+    #[serde(default)]
+    pub cost_per_gb_written: Option<f64>,
+    // 访问限制，避免触发服务端限流
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// 同一份数据的多个存储副本，读的时候主存储慢或者出错会读副本
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageGroupConfig {
+    // storages 中的名字
+    pub primary: String,
+    pub replicas: Vec<String>,
+    // 主存储超过这个时间没有返回就同时读副本
+    #[serde(default = "default_hedge_after_ms")]
+    pub hedge_after_ms: u64,
+}
+
+fn default_hedge_after_ms() -> u64 {
+    200
+}
+
+/// 危险语句的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardAction {
+    Allow,
+    // 需要调用方确认后才执行
+    Confirm,
+    Reject,
+}
+
+/// 服务端入口的危险语句检查，见 DB::check_statement
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuardConfig {
+    #[serde(default = "default_guard_action")]
+    pub drop_table: GuardAction,
+    // 没有 WHERE 的 DELETE 和 TRUNCATE
+    #[serde(default = "default_guard_action")]
+    pub full_table_delete: GuardAction,
+    // 扫描超过 max_unbounded_select_rows 行的表且没有 LIMIT 的 SELECT
+    #[serde(default = "default_guard_action")]
+    pub unbounded_select: GuardAction,
+    #[serde(default = "default_max_unbounded_select_rows")]
+    pub max_unbounded_select_rows: usize,
+    // 这些用户的语句不做检查
+    #[serde(default)]
+    pub trusted_principals: Vec<String>,
+    // 可以通过服务端新增外部表的用户，trusted_principals 也可以
+    #[serde(default)]
+    pub table_admins: Vec<String>,
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        Self {
+            drop_table: default_guard_action(),
+            full_table_delete: default_guard_action(),
+            unbounded_select: default_guard_action(),
+            max_unbounded_select_rows: default_max_unbounded_select_rows(),
+            trusted_principals: Vec::new(),
+            table_admins: Vec::new(),
+        }
+    }
+}
+
+fn default_guard_action() -> GuardAction {
+    GuardAction::Confirm
+}
+
+fn default_max_unbounded_select_rows() -> usize {
+    100_000
+}
+
+/// 同步到 ClickHouse 使用的 HTTP 接口配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClickHouseConfig {
+    // 比如 http://localhost:8123
+    pub url: String,
+    // 同一集群的其他节点，和 url 一起轮询，请求失败时切换到下一个节点
+    #[serde(default)]
+    pub replicas: Vec<String>,
+    #[serde(default = "default_clickhouse_database")]
+    pub database: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    // 每个 INSERT 的行数
+    #[serde(default = "default_clickhouse_block_size")]
+    pub block_size: usize,
+    // 使用 ClickHouse 的 async_insert，由服务端合并小批量写入
+    #[serde(default)]
+    pub async_insert: bool,
+    #[serde(default = "default_clickhouse_max_retries")]
+    pub max_retries: usize,
+    // 每个请求都带上的 SETTINGS，比如 max_threads、max_execution_time，
+    // 单次查询可以用 QueryHints 覆盖
+    #[serde(default)]
+    pub settings: HashMap<String, String>,
+}
+
+fn default_clickhouse_database() -> String {
+    "default".to_string()
+}
+
+fn default_clickhouse_block_size() -> usize {
+    100_000
+}
+
+fn default_clickhouse_max_retries() -> usize {
+    3
+}
+
+/// 在配置文件中声明的表，由 DB::init_from_config 创建
+#[derive(Debug, Clone, Deserialize)]
+pub struct TableConfig {
+    pub name: String,
+    pub source: TableSourceConfig,
+    // 列定义，query/clickhouse 表使用查询结果的 schema，external 表为空时自动推断
+    #[serde(default)]
+    pub columns: Vec<ColumnConfig>,
+    // 用 protobuf message 定义内存表的列，和 columns 二选一
+    pub proto: Option<ProtoSchemaConfig>,
+    #[serde(default)]
+    pub refresh: RefreshPolicyConfig,
+    pub ttl: Option<TtlConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TableSourceConfig {
+    // 空的内存表，由应用写入
+    Memory,
+    // 存储中的文件，比如 location = "minio://demo/users.csv"，format = "csv"
+    External {
+        location: String,
+        format: String,
+    },
+    // 本地 SQL 的结果，比如把外部表加载到内存
+    Query {
+        sql: String,
+    },
+    // ClickHouse 查询的结果，使用 clickhouse 配置中的连接；
+    // settings 是这个表查询时带上的 SETTINGS，覆盖 clickhouse 配置中的同名设置
+    ClickHouse {
+        sql: String,
+        #[serde(default)]
+        settings: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ColumnConfig {
+    pub name: String,
+    // SQL 类型，比如 BIGINT、VARCHAR、DECIMAL(10,2)
+    pub data_type: String,
+    #[serde(default = "default_nullable")]
+    pub nullable: bool,
+}
+
+fn default_nullable() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProtoSchemaConfig {
+    pub file: String,
+    pub message: String,
+}
+
+/// query/clickhouse 表的加载方式
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct RefreshPolicyConfig {
+    // 需要先加载的表，比如事实表依赖维度表
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    pub timeout_secs: Option<u64>,
+    // 刷新用时超过这个时间时发送 SlaBreached 事件
+    pub sla_secs: Option<u64>,
+}
+
+/// 行级 TTL，见 DB::set_row_ttl 和 DB::set_row_ttl_after
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TtlConfig {
+    pub column: String,
+    // 不设置时 column 就是过期时间，设置时过期时间为 column 加上这个时间
+    pub after_secs: Option<u64>,
+}
+
+/// 压缩算法的配置，codec 是注册的名字（none、lz4、zstd 或者自定义的），level 只用于 zstd
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CodecConfig {
+    pub codec: String,
+    pub level: Option<i32>,
+}
+
+impl CodecConfig {
+    pub fn new(codec: &str) -> Self {
+        Self {
+            codec: codec.to_string(),
+            level: None,
+        }
+    }
+
+    pub fn zstd(level: i32) -> Self {
+        Self {
+            codec: "zstd".to_string(),
+            level: Some(level),
+        }
+    }
+}
+
+/// 各个产物使用的压缩算法，不设置时不压缩，见 DB::set_compression
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct CompressionConfig {
+    pub wal: Option<CodecConfig>,
+    pub snapshot: Option<CodecConfig>,
+    pub ipc: Option<CodecConfig>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub storages: HashMap<String, StorageConfig>,
+    // 通过 group://{name}/path 访问
+    #[serde(default)]
+    pub storage_groups: HashMap<String, StorageGroupConfig>,
+    #[serde(default)]
+    pub guard: GuardConfig,
+    pub clickhouse: Option<ClickHouseConfig>,
+    // 事件通知，init_from_config 时启动，见 DB::spawn_notifier
+    #[serde(default)]
+    pub notifications: Vec<NotificationRule>,
+    #[serde(default)]
+    pub tables: Vec<TableConfig>,
+    #[serde(default)]
+    pub compression: CompressionConfig,
 }
 
 impl Config {
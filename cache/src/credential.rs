@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+#[cfg(feature = "storage")]
+use chrono::Duration as ChronoDuration;
+use chrono::{DateTime, Utc};
+#[cfg(feature = "storage")]
+use object_store::{aws::AwsCredential, CredentialProvider};
+use std::fmt::Debug;
+#[cfg(feature = "storage")]
+use std::sync::Arc;
+#[cfg(feature = "storage")]
+use tokio::sync::Mutex;
+
+// 临时凭证在过期前多少秒就开始刷新，避免导出到一半凭证失效
+#[cfg(feature = "storage")]
+const REFRESH_AHEAD_SECS: i64 = 300;
+
+/// STS 临时凭证（S3 session token / OSS STS token 通用）
+#[derive(Debug, Clone)]
+pub struct TemporaryCredential {
+    pub access_key: String,
+    pub access_secret: String,
+    pub session_token: Option<String>,
+    // None 表示不会过期
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// 临时凭证的获取回调，由调用方实现（比如请求 STS AssumeRole）
+#[async_trait]
+pub trait TokenProvider: Debug + Send + Sync {
+    async fn fetch(&self) -> anyhow::Result<TemporaryCredential>;
+}
+
+/// 缓存 TokenProvider 返回的凭证，在快过期时自动刷新
+#[cfg(feature = "storage")]
+#[derive(Debug)]
+pub(crate) struct RefreshingCredentialProvider {
+    provider: Arc<dyn TokenProvider>,
+    cached: Mutex<Option<(Arc<AwsCredential>, Option<DateTime<Utc>>)>>,
+}
+
+#[cfg(feature = "storage")]
+impl RefreshingCredentialProvider {
+    pub(crate) fn new(provider: Arc<dyn TokenProvider>) -> Self {
+        Self {
+            provider,
+            cached: Mutex::new(None),
+        }
+    }
+}
+
+#[cfg(feature = "storage")]
+fn is_fresh(expires_at: Option<DateTime<Utc>>) -> bool {
+    match expires_at {
+        Some(t) => t - ChronoDuration::seconds(REFRESH_AHEAD_SECS) > Utc::now(),
+        None => true,
+    }
+}
+
+#[cfg(feature = "storage")]
+#[async_trait]
+impl CredentialProvider for RefreshingCredentialProvider {
+    type Credential = AwsCredential;
+
+    async fn get_credential(&self) -> object_store::Result<Arc<AwsCredential>> {
+        let mut cached = self.cached.lock().await;
+        if let Some((credential, expires_at)) = cached.as_ref() {
+            if is_fresh(*expires_at) {
+                return Ok(credential.clone());
+            }
+        }
+
+        let fresh = self
+            .provider
+            .fetch()
+            .await
+            .map_err(|e| object_store::Error::Generic {
+                store: "S3",
+                source: e.into(),
+            })?;
+        let credential = Arc::new(AwsCredential {
+            key_id: fresh.access_key,
+            secret_key: fresh.access_secret,
+            token: fresh.session_token,
+        });
+        *cached = Some((credential.clone(), fresh.expires_at));
+        Ok(credential)
+    }
+}
+
+#[cfg(all(test, feature = "storage"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug)]
+    struct CountingProvider {
+        calls: AtomicUsize,
+        ttl_secs: i64,
+    }
+
+    #[async_trait]
+    impl TokenProvider for CountingProvider {
+        async fn fetch(&self) -> anyhow::Result<TemporaryCredential> {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(TemporaryCredential {
+                access_key: format!("ak-{}", n),
+                access_secret: "sk".to_string(),
+                session_token: Some(format!("token-{}", n)),
+                expires_at: Some(Utc::now() + ChronoDuration::seconds(self.ttl_secs)),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_credential_cached_until_expiry() -> anyhow::Result<()> {
+        let provider = Arc::new(CountingProvider {
+            calls: AtomicUsize::new(0),
+            ttl_secs: 3600,
+        });
+        let refreshing = RefreshingCredentialProvider::new(provider.clone());
+
+        let first = refreshing.get_credential().await?;
+        let second = refreshing.get_credential().await?;
+        assert_eq!(first.key_id, "ak-0");
+        assert_eq!(second.token.as_deref(), Some("token-0"));
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_credential_refresh_ahead_of_expiry() -> anyhow::Result<()> {
+        // 有效期比提前刷新窗口还短，每次都要重新获取
+        let provider = Arc::new(CountingProvider {
+            calls: AtomicUsize::new(0),
+            ttl_secs: 60,
+        });
+        let refreshing = RefreshingCredentialProvider::new(provider.clone());
+
+        refreshing.get_credential().await?;
+        let second = refreshing.get_credential().await?;
+        assert_eq!(second.key_id, "ak-1");
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
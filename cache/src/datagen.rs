@@ -0,0 +1,387 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::{DataType, SchemaRef};
+use datafusion::arrow::array::{
+    new_null_array, Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray,
+};
+use datafusion::arrow::compute::{cast, nullif};
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// 单列的生成方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnGen {
+    // 均匀分布，包含两端；日期列是距离 1970-01-01 的天数，时间戳列是对应单位的数值
+    Range { min: f64, max: f64 },
+    Normal { mean: f64, std_dev: f64 },
+    // start, start + step, ...
+    Sequence { start: i64, step: i64 },
+    // 从给定的值里随机选，按列类型转换
+    Choice(Vec<String>),
+    // # 数字，? 小写字母，* 数字或字母，其他字符原样输出
+    Pattern(String),
+}
+
+/// generate_rows 的参数，相同的 seed 和 spec 总是生成相同的数据
+#[derive(Debug, Clone)]
+pub struct GenSpec {
+    pub seed: u64,
+    // 没有配置的列按类型使用默认的生成方式
+    pub columns: HashMap<String, ColumnGen>,
+    // 可空列中 NULL 的比例
+    pub null_ratio: f64,
+    pub batch_size: usize,
+}
+
+impl Default for GenSpec {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            columns: HashMap::new(),
+            null_ratio: 0.0,
+            batch_size: 8192,
+        }
+    }
+}
+
+impl GenSpec {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    pub fn column(mut self, name: &str, gen: ColumnGen) -> Self {
+        self.columns.insert(name.to_string(), gen);
+        self
+    }
+
+    pub fn null_ratio(mut self, ratio: f64) -> Self {
+        self.null_ratio = ratio;
+        self
+    }
+}
+
+// SplitMix64，不依赖外部 crate，保证不同版本生成的数据一致
+struct Rng(u64);
+
+impl Rng {
+    // 每一列使用独立的随机序列，增减其他列不影响这一列的数据
+    fn for_column(seed: u64, column: &str, salt: u64) -> Self {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in column.as_bytes() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Rng(seed ^ hash ^ salt.wrapping_mul(0x9e3779b97f4a7c15))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_f64() * n as f64) as usize
+    }
+
+    fn normal(&mut self, mean: f64, std_dev: f64) -> f64 {
+        // Box-Muller
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        mean + std_dev * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+fn default_gen(data_type: &DataType) -> Option<ColumnGen> {
+    Some(match data_type {
+        DataType::Int8 => ColumnGen::Range {
+            min: -100.0,
+            max: 100.0,
+        },
+        DataType::UInt8 => ColumnGen::Range {
+            min: 0.0,
+            max: 200.0,
+        },
+        t if t.is_integer() || t.is_floating() || matches!(t, DataType::Decimal128(_, _)) => {
+            ColumnGen::Range {
+                min: 0.0,
+                max: 1000.0,
+            }
+        }
+        DataType::Utf8 | DataType::LargeUtf8 => ColumnGen::Pattern("????????".to_string()),
+        // 2020-01-01 起的 5 年
+        DataType::Date32 => ColumnGen::Range {
+            min: 18262.0,
+            max: 20088.0,
+        },
+        DataType::Timestamp(unit, _) => {
+            let per_second = match unit {
+                arrow_schema::TimeUnit::Second => 1.0,
+                arrow_schema::TimeUnit::Millisecond => 1e3,
+                arrow_schema::TimeUnit::Microsecond => 1e6,
+                arrow_schema::TimeUnit::Nanosecond => 1e9,
+            };
+            ColumnGen::Range {
+                min: 1_577_836_800.0 * per_second,
+                max: 1_735_689_600.0 * per_second,
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn pattern(rng: &mut Rng, pattern: &str) -> String {
+    const LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+    const ALNUM: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+    pattern
+        .chars()
+        .map(|c| match c {
+            '#' => (b'0' + rng.below(10) as u8) as char,
+            '?' => LETTERS[rng.below(LETTERS.len())] as char,
+            '*' => ALNUM[rng.below(ALNUM.len())] as char,
+            c => c,
+        })
+        .collect()
+}
+
+// 先生成 Float64/Int64/Utf8 数组，再转换成目标类型
+fn generate_column(
+    gen: &ColumnGen,
+    data_type: &DataType,
+    rng: &mut Rng,
+    offset: usize,
+    rows: usize,
+) -> Result<ArrayRef> {
+    // 整数和日期列取整，避免转换时截断造成分布偏差
+    let integral =
+        data_type.is_integer() || matches!(data_type, DataType::Date32 | DataType::Timestamp(_, _));
+    let raw: ArrayRef = match gen {
+        ColumnGen::Range { min, max } => {
+            let values = (0..rows).map(|_| {
+                if integral {
+                    (min + rng.next_f64() * (max - min + 1.0)).floor().min(*max)
+                } else {
+                    min + rng.next_f64() * (max - min)
+                }
+            });
+            Arc::new(Float64Array::from_iter_values(values))
+        }
+        ColumnGen::Normal { mean, std_dev } => {
+            let values = (0..rows).map(|_| {
+                let value = rng.normal(*mean, *std_dev);
+                if integral {
+                    value.round()
+                } else {
+                    value
+                }
+            });
+            Arc::new(Float64Array::from_iter_values(values))
+        }
+        ColumnGen::Sequence { start, step } => Arc::new(Int64Array::from_iter_values(
+            (offset..offset + rows).map(|i| start + step * i as i64),
+        )),
+        ColumnGen::Choice(values) => {
+            if values.is_empty() {
+                return Err(anyhow::anyhow!("Choice generator needs at least one value"));
+            }
+            Arc::new(StringArray::from_iter_values(
+                (0..rows).map(|_| &values[rng.below(values.len())]),
+            ))
+        }
+        ColumnGen::Pattern(p) => Arc::new(StringArray::from_iter_values(
+            (0..rows).map(|_| pattern(rng, p)),
+        )),
+    };
+    let raw = match (raw.data_type(), data_type) {
+        // 没有 Float64 -> Date32 的直接转换
+        (DataType::Float64 | DataType::Int64, DataType::Date32) => {
+            cast(&cast(&raw, &DataType::Int32)?, data_type)?
+        }
+        (DataType::Float64, DataType::Timestamp(_, _)) => {
+            cast(&cast(&raw, &DataType::Int64)?, data_type)?
+        }
+        _ => cast(&raw, data_type)?,
+    };
+    Ok(raw)
+}
+
+/// 按 schema 生成 n 行数据
+pub fn generate_batches(schema: &SchemaRef, n: usize, spec: &GenSpec) -> Result<Vec<RecordBatch>> {
+    let batch_size = spec.batch_size.max(1);
+    let mut rngs: Vec<(Rng, Rng)> = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            (
+                Rng::for_column(spec.seed, f.name(), 0),
+                Rng::for_column(spec.seed, f.name(), 1),
+            )
+        })
+        .collect();
+
+    let mut batches = Vec::new();
+    let mut offset = 0;
+    while offset < n {
+        let rows = batch_size.min(n - offset);
+        let mut columns = Vec::with_capacity(schema.fields().len());
+        for (field, (rng, null_rng)) in schema.fields().iter().zip(rngs.iter_mut()) {
+            let column = match (spec.columns.get(field.name()), field.data_type()) {
+                (_, DataType::Null) => new_null_array(&DataType::Null, rows),
+                (None, DataType::Boolean) => Arc::new(BooleanArray::from_iter(
+                    (0..rows).map(|_| Some(rng.next_u64() & 1 == 1)),
+                )),
+                (Some(gen), data_type) => generate_column(gen, data_type, rng, offset, rows)?,
+                (None, data_type) => {
+                    let gen = default_gen(data_type).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "no default generator for column {} of type {}",
+                            field.name(),
+                            data_type
+                        )
+                    })?;
+                    generate_column(&gen, data_type, rng, offset, rows)?
+                }
+            };
+            let column = if field.is_nullable() && spec.null_ratio > 0.0 {
+                let mask = BooleanArray::from_iter(
+                    (0..rows).map(|_| Some(null_rng.next_f64() < spec.null_ratio)),
+                );
+                nullif(&column, &mask)?
+            } else {
+                column
+            };
+            columns.push(column);
+        }
+        batches.push(RecordBatch::try_new(schema.clone(), columns)?);
+        offset += rows;
+    }
+    Ok(batches)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 按表的 schema 生成 n 行确定性的测试数据并追加到表里，用于压测
+    pub async fn generate_rows(&self, table: &str, n: usize, spec: GenSpec) -> Result<usize> {
+        let schema = self.ctx.table_provider(table).await?.schema();
+        for name in spec.columns.keys() {
+            if schema.field_with_name(name).is_err() {
+                return Err(anyhow::anyhow!("Column {} not found in {}", name, table));
+            }
+        }
+        let batches = generate_batches(&schema, n, &spec)?;
+        self.insert_batches(table, batches).await?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::{Field, Schema};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    #[test]
+    fn test_generate_batches_deterministic() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let spec = GenSpec::new(42).null_ratio(0.3);
+        let a = generate_batches(&schema, 100, &spec)?;
+        let b = generate_batches(&schema, 100, &spec)?;
+        assert_eq!(
+            pretty_format_batches(&a)?.to_string(),
+            pretty_format_batches(&b)?.to_string()
+        );
+        let c = generate_batches(&schema, 100, &GenSpec::new(7))?;
+        assert_ne!(
+            pretty_format_batches(&a)?.to_string(),
+            pretty_format_batches(&c)?.to_string()
+        );
+        // 非空列不会生成 NULL
+        assert_eq!(a[0].column(0).null_count(), 0);
+        assert!(a[0].column(1).null_count() > 0);
+
+        // 新增一列不影响已有列的数据
+        let wider = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("score", DataType::Float64, false),
+        ]));
+        let d = generate_batches(&wider, 100, &spec)?;
+        assert_eq!(a[0].column(1).as_ref(), d[0].column(1).as_ref());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_generate_rows() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute(
+            "CREATE TABLE trades (id BIGINT NOT NULL, account VARCHAR NOT NULL, side VARCHAR NOT NULL, \
+             qty INT NOT NULL, price DECIMAL(10, 2) NOT NULL, traded_on DATE NOT NULL, ok BOOLEAN)",
+        )
+        .await?;
+        let spec = GenSpec {
+            batch_size: 1000,
+            ..GenSpec::new(1)
+        }
+        .column("id", ColumnGen::Sequence { start: 1, step: 1 })
+        .column("account", ColumnGen::Pattern("ACC-####".to_string()))
+        .column(
+            "side",
+            ColumnGen::Choice(vec!["buy".to_string(), "sell".to_string()]),
+        )
+        .column(
+            "qty",
+            ColumnGen::Range {
+                min: 1.0,
+                max: 10.0,
+            },
+        )
+        .column(
+            "price",
+            ColumnGen::Normal {
+                mean: 100.0,
+                std_dev: 5.0,
+            },
+        );
+        assert_eq!(db.generate_rows("trades", 2500, spec).await?, 2500);
+
+        let batches = db
+            .query_to_batches(
+                "SELECT count(*), min(id), max(id), min(qty), max(qty), count(DISTINCT side), \
+                 sum(CASE WHEN account LIKE 'ACC-____' THEN 1 ELSE 0 END) FROM trades",
+            )
+            .await?;
+        let table = pretty_format_batches(&batches)?.to_string();
+        let values: Vec<&str> = table
+            .lines()
+            .nth(3)
+            .unwrap()
+            .split('|')
+            .map(|v| v.trim())
+            .collect();
+        assert_eq!(values[1..8], ["2500", "1", "2500", "1", "10", "2", "2500"]);
+
+        assert!(db
+            .generate_rows(
+                "trades",
+                1,
+                GenSpec::new(1).column("missing", ColumnGen::Pattern("x".to_string()))
+            )
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use crate::pool::DB;
+use crate::snapshot::snapshot_table_name;
+use crate::workload::fingerprint_normalized;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::TableReference;
+use datafusion::prelude::DataFrame;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// diff_query 每次调用的临时表编号，同时比较同一个查询时不会冲突
+static NEXT_DIFF_ID: AtomicU64 = AtomicU64::new(0);
+
+/// 同一个查询两次结果的差异，或者两张表的差异
+#[derive(Debug, Clone)]
+pub struct QueryDiff {
+    // 用来匹配两次结果中同一行的列
+    pub key_columns: Vec<String>,
+    // 只在当前结果中出现的行
+    pub added: Vec<RecordBatch>,
+    // 只在基线中出现的行
+    pub removed: Vec<RecordBatch>,
+    // key 相同但其他列不同的行，每个非 key 列输出 {col}_baseline 和 {col}_current
+    pub changed: Vec<RecordBatch>,
+}
+
+impl QueryDiff {
+    pub fn is_empty(&self) -> bool {
+        [&self.added, &self.removed, &self.changed]
+            .iter()
+            .all(|batches| batches.iter().all(|b| b.num_rows() == 0))
+    }
+}
+
+// 查询结果快照对应的表名，按 SQL 原文区分，只是字面量不同的查询使用不同的基线
+fn query_result_table(sql: &str) -> String {
+    format!("__query_{}", fingerprint_normalized(sql))
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 把查询当前的结果保存为命名快照，作为之后 diff_query 的基线
+    pub async fn save_query_result(&self, sql: &str, snapshot: &str) -> Result<()> {
+        let name = snapshot_table_name(&query_result_table(sql), snapshot);
+        if self.ctx.table_exist(TableReference::bare(name.as_str()))? {
+            return Err(anyhow::anyhow!("Snapshot {} already exists", name));
+        }
+        let df = self.query(sql).await?;
+        let schema = df.schema().inner().clone();
+        let batches = df.collect().await?;
+        self.register_frozen(&name, schema, batches)
+    }
+
+    /// 比较查询结果和基线快照，current 为 None 时和当前执行结果比较，否则和另一个快照比较
+    ///
+    /// 非数值列作为 key（聚合查询的维度列），没有非数值列时整行比较，只有新增和删除
+    pub async fn diff_query(
+        &self,
+        sql: &str,
+        baseline_snapshot: &str,
+        current: Option<&str>,
+    ) -> Result<QueryDiff> {
+        self.diff_query_by(sql, baseline_snapshot, current, None)
+            .await
+    }
+
+    /// 和 diff_query 一样，但是指定 key 列
+    pub async fn diff_query_by(
+        &self,
+        sql: &str,
+        baseline_snapshot: &str,
+        current: Option<&str>,
+        key_columns: Option<&[&str]>,
+    ) -> Result<QueryDiff> {
+        let table = query_result_table(sql);
+        let baseline = snapshot_table_name(&table, baseline_snapshot);
+        let baseline_df = self
+            .ctx
+            .table(TableReference::bare(baseline.as_str()))
+            .await?;
+        let current_df = match current {
+            Some(snapshot) => {
+                let name = snapshot_table_name(&table, snapshot);
+                self.ctx.table(TableReference::bare(name.as_str())).await?
+            }
+            None => self.query(sql).await?,
+        };
+        if current_df.schema().inner().fields() != baseline_df.schema().inner().fields() {
+            return Err(anyhow::anyhow!(
+                "Result schema changed since snapshot {}",
+                baseline_snapshot
+            ));
+        }
+        self.diff_frames(baseline_df, current_df, key_columns).await
+    }
+
+    /// 比较两张表（或视图）的内容，两边的列名和类型需要一致
+    ///
+    /// current 中多出来的行是 added，baseline 中多出来的行是 removed；
+    /// key_columns 为 None 时和 diff_query 一样用非数值列作为 key
+    pub async fn diff_tables(
+        &self,
+        baseline: &str,
+        current: &str,
+        key_columns: Option<&[&str]>,
+    ) -> Result<QueryDiff> {
+        let baseline_df = self.ctx.table(self.table_ref(baseline)).await?;
+        let current_df = self.ctx.table(self.table_ref(current)).await?;
+        if current_df.schema().inner().fields() != baseline_df.schema().inner().fields() {
+            return Err(anyhow::anyhow!(
+                "Tables {} and {} have different schemas",
+                baseline,
+                current
+            ));
+        }
+        self.diff_frames(baseline_df, current_df, key_columns).await
+    }
+
+    async fn diff_frames(
+        &self,
+        baseline_df: DataFrame,
+        current_df: DataFrame,
+        key_columns: Option<&[&str]>,
+    ) -> Result<QueryDiff> {
+        let schema: SchemaRef = baseline_df.schema().inner().clone();
+        let keys: Vec<String> = match key_columns {
+            Some(keys) => keys.iter().map(|k| k.to_string()).collect(),
+            None => schema
+                .fields()
+                .iter()
+                .filter(|f| !f.data_type().is_numeric())
+                .map(|f| f.name().clone())
+                .collect(),
+        };
+        let values: Vec<String> = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .filter(|name| !keys.contains(name))
+            .collect();
+
+        // 两边的结果注册成临时表，用 SQL 计算差异
+        let id = NEXT_DIFF_ID.fetch_add(1, Ordering::Relaxed);
+        let b = format!("__diff_baseline_{}", id);
+        let c = format!("__diff_current_{}", id);
+        let current_batches = current_df.collect().await?;
+        self.register_frozen(&b, schema.clone(), baseline_df.collect().await?)?;
+        self.register_frozen(&c, schema.clone(), current_batches)?;
+
+        let result = self.compute_diff(&b, &c, &keys, &values).await;
+        self.ctx
+            .deregister_table(TableReference::bare(b.as_str()))?;
+        self.ctx
+            .deregister_table(TableReference::bare(c.as_str()))?;
+        let (added, removed, changed) = result?;
+        Ok(QueryDiff {
+            key_columns: keys,
+            added,
+            removed,
+            changed,
+        })
+    }
+
+    async fn compute_diff(
+        &self,
+        baseline: &str,
+        current: &str,
+        keys: &[String],
+        values: &[String],
+    ) -> Result<(Vec<RecordBatch>, Vec<RecordBatch>, Vec<RecordBatch>)> {
+        let run = |sql: String| async move { self.ctx.sql(&sql).await?.collect().await };
+
+        if keys.is_empty() {
+            let added = run(format!(
+                "SELECT * FROM \"{current}\" EXCEPT ALL SELECT * FROM \"{baseline}\""
+            ))
+            .await?;
+            let removed = run(format!(
+                "SELECT * FROM \"{baseline}\" EXCEPT ALL SELECT * FROM \"{current}\""
+            ))
+            .await?;
+            return Ok((added, removed, Vec::new()));
+        }
+
+        let on = keys
+            .iter()
+            .map(|k| format!("b.\"{k}\" IS NOT DISTINCT FROM c.\"{k}\""))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let added = run(format!(
+            "SELECT c.* FROM \"{current}\" c WHERE NOT EXISTS (SELECT 1 FROM \"{baseline}\" b WHERE {on})"
+        ))
+        .await?;
+        let removed = run(format!(
+            "SELECT b.* FROM \"{baseline}\" b WHERE NOT EXISTS (SELECT 1 FROM \"{current}\" c WHERE {on})"
+        ))
+        .await?;
+        if values.is_empty() {
+            return Ok((added, removed, Vec::new()));
+        }
+
+        let projection = keys
+            .iter()
+            .map(|k| format!("c.\"{k}\""))
+            .chain(values.iter().flat_map(|v| {
+                [
+                    format!("b.\"{v}\" AS \"{v}_baseline\""),
+                    format!("c.\"{v}\" AS \"{v}_current\""),
+                ]
+            }))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let predicate = values
+            .iter()
+            .map(|v| format!("b.\"{v}\" IS DISTINCT FROM c.\"{v}\""))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        let changed = run(format!(
+            "SELECT {projection} FROM \"{baseline}\" b JOIN \"{current}\" c ON {on} WHERE {predicate}"
+        ))
+        .await?;
+        Ok((added, removed, changed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(batches: &[RecordBatch]) -> usize {
+        batches.iter().map(|b| b.num_rows()).sum()
+    }
+
+    #[tokio::test]
+    async fn test_diff_query() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE ledger (currency VARCHAR, amount DOUBLE)")
+            .await?;
+        db.execute("INSERT INTO ledger VALUES ('USD', 10), ('HKD', 5), ('EUR', 1)")
+            .await?;
+        let sql = "SELECT currency, SUM(amount) AS total FROM ledger GROUP BY currency";
+
+        db.save_query_result(sql, "day1").await?;
+        assert!(db.diff_query(sql, "day1", None).await?.is_empty());
+
+        db.execute("INSERT INTO ledger VALUES ('USD', 1), ('JPY', 100)")
+            .await?;
+        let diff = db.diff_query(sql, "day1", None).await?;
+        assert_eq!(diff.key_columns, vec!["currency".to_string()]);
+        assert_eq!(rows(&diff.added), 1);
+        assert_eq!(rows(&diff.changed), 1);
+        assert_eq!(diff.changed[0].schema().field(1).name(), "total_baseline");
+
+        // 两个快照之间比较
+        db.save_query_result(sql, "day2").await?;
+        let diff = db.diff_query(sql, "day1", Some("day2")).await?;
+        assert_eq!(rows(&diff.added), 1);
+        let diff = db.diff_query(sql, "day2", Some("day1")).await?;
+        assert_eq!(rows(&diff.removed), 1);
+        assert!(db.diff_query(sql, "missing", None).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diff_query_keys_on_exact_sql() -> Result<()> {
+        let db = std::sync::Arc::new(DB::<()>::new("test_db"));
+        db.execute("CREATE TABLE t (id BIGINT, v BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1, 1), (2, 2)").await?;
+        let one = "SELECT * FROM t WHERE id = 1";
+        let two = "SELECT * FROM t WHERE id = 2";
+        db.save_query_result(one, "base").await?;
+        // 只是字面量不同的查询有自己的基线
+        assert!(db.diff_query(two, "base", None).await.is_err());
+        db.save_query_result(two, "base").await?;
+
+        // 同时比较同一个查询，临时表不会冲突
+        let diffs: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move { db.diff_query(one, "base", None).await })
+            })
+            .collect();
+        for diff in diffs {
+            assert!(diff.await??.is_empty());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diff_tables() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE ledger (txn_id VARCHAR, amount DOUBLE)")
+            .await?;
+        db.execute("CREATE TABLE bank (txn_id VARCHAR, amount DOUBLE)")
+            .await?;
+        db.execute("INSERT INTO ledger VALUES ('T1', 10), ('T2', 5), ('T3', 1)")
+            .await?;
+        db.execute("INSERT INTO bank VALUES ('T1', 10), ('T2', 6), ('T4', 2)")
+            .await?;
+
+        let diff = db.diff_tables("ledger", "bank", Some(&["txn_id"])).await?;
+        assert_eq!(rows(&diff.added), 1);
+        assert_eq!(rows(&diff.removed), 1);
+        assert_eq!(rows(&diff.changed), 1);
+        assert_eq!(diff.changed[0].schema().field(2).name(), "amount_current");
+
+        db.execute("CREATE TABLE other (txn_id VARCHAR)").await?;
+        assert!(db.diff_tables("ledger", "other", None).await.is_err());
+        Ok(())
+    }
+}
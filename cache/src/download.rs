@@ -0,0 +1,256 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow::csv::WriterBuilder as CsvWriterBuilder;
+use arrow::json::LineDelimitedWriter;
+use arrow_schema::SchemaRef;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// 下载查询结果的格式，对应 HTTP 前端 `/query?format=` 的取值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadFormat {
+    Csv,
+    Parquet,
+    NdJson,
+    Arrow,
+}
+
+impl DownloadFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format.to_ascii_lowercase().as_str() {
+            "csv" => Ok(DownloadFormat::Csv),
+            "parquet" => Ok(DownloadFormat::Parquet),
+            "ndjson" | "jsonl" => Ok(DownloadFormat::NdJson),
+            "arrow" => Ok(DownloadFormat::Arrow),
+            other => Err(anyhow::anyhow!("Unsupported download format {}", other)),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DownloadFormat::Csv => "text/csv; charset=utf-8",
+            DownloadFormat::Parquet => "application/vnd.apache.parquet",
+            DownloadFormat::NdJson => "application/x-ndjson",
+            DownloadFormat::Arrow => "application/vnd.apache.arrow.stream",
+        }
+    }
+
+    // Content-Disposition 中的文件扩展名
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DownloadFormat::Csv => "csv",
+            DownloadFormat::Parquet => "parquet",
+            DownloadFormat::NdJson => "ndjson",
+            DownloadFormat::Arrow => "arrow",
+        }
+    }
+}
+
+/// 按格式编码的查询结果，chunks 每一项可以直接作为 chunked 响应的一块写出
+pub struct ResultDownload {
+    pub format: DownloadFormat,
+    pub content_type: &'static str,
+    pub chunks: BoxStream<'static, Result<Vec<u8>>>,
+}
+
+// 编码器写入共享的缓冲区，每个 batch 写完后把新增的字节取出来发送
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Encoder {
+    // CSV 只在第一块写表头
+    Csv { header: bool },
+    NdJson,
+    Arrow(StreamWriter<SharedBuffer>),
+    // Parquet 的 footer 在最后写出，之前的块是已经完成的 row group
+    Parquet(ArrowWriter<SharedBuffer>),
+}
+
+impl Encoder {
+    fn new(format: DownloadFormat, schema: &SchemaRef, buffer: &SharedBuffer) -> Result<Self> {
+        Ok(match format {
+            DownloadFormat::Csv => Encoder::Csv { header: true },
+            DownloadFormat::NdJson => Encoder::NdJson,
+            DownloadFormat::Arrow => Encoder::Arrow(StreamWriter::try_new(buffer.clone(), schema)?),
+            DownloadFormat::Parquet => {
+                Encoder::Parquet(ArrowWriter::try_new(buffer.clone(), schema.clone(), None)?)
+            }
+        })
+    }
+
+    fn write(&mut self, buffer: &SharedBuffer, batch: &RecordBatch) -> Result<()> {
+        match self {
+            Encoder::Csv { header } => {
+                let mut writer = CsvWriterBuilder::new()
+                    .with_header(*header)
+                    .build(buffer.clone());
+                writer.write(batch)?;
+                *header = false;
+            }
+            Encoder::NdJson => {
+                let mut writer = LineDelimitedWriter::new(buffer.clone());
+                writer.write(batch)?;
+                writer.finish()?;
+            }
+            Encoder::Arrow(writer) => writer.write(batch)?,
+            Encoder::Parquet(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    // 结果为空时 CSV 也要写出表头
+    fn finish(self, buffer: &SharedBuffer, schema: &SchemaRef) -> Result<()> {
+        match self {
+            Encoder::Csv { header: true } => {
+                CsvWriterBuilder::new()
+                    .with_header(true)
+                    .build(buffer.clone())
+                    .write(&RecordBatch::new_empty(schema.clone()))?;
+            }
+            Encoder::Csv { .. } | Encoder::NdJson => {}
+            Encoder::Arrow(mut writer) => writer.finish()?,
+            Encoder::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct DownloadState {
+    stream: Option<SendableRecordBatchStream>,
+    encoder: Option<Encoder>,
+    buffer: SharedBuffer,
+    schema: SchemaRef,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 流式执行查询并按格式编码，不会把整个结果放在内存里，HTTP 前端直接转发 chunks 即可
+    pub async fn query_download(
+        &self,
+        sql: &str,
+        format: DownloadFormat,
+    ) -> Result<ResultDownload> {
+        let stream = self.query(sql).await?.execute_stream().await?;
+        let schema = stream.schema();
+        let buffer = SharedBuffer::default();
+        let state = DownloadState {
+            encoder: Some(Encoder::new(format, &schema, &buffer)?),
+            stream: Some(stream),
+            buffer,
+            schema,
+        };
+
+        let chunks = stream::unfold(state, |mut state| async move {
+            loop {
+                let stream = state.stream.as_mut()?;
+                let result = match stream.next().await {
+                    Some(Ok(batch)) => {
+                        let encoder = state.encoder.as_mut()?;
+                        encoder.write(&state.buffer, &batch)
+                    }
+                    Some(Err(e)) => Err(e.into()),
+                    None => {
+                        state.stream = None;
+                        let encoder = state.encoder.take()?;
+                        encoder.finish(&state.buffer, &state.schema)
+                    }
+                };
+                if let Err(e) = result {
+                    // 出错后结束，已经发出的内容是不完整的
+                    state.stream = None;
+                    state.encoder = None;
+                    return Some((Err(e), state));
+                }
+                let chunk = state.buffer.take();
+                if !chunk.is_empty() {
+                    return Some((Ok(chunk), state));
+                }
+                if state.stream.is_none() {
+                    return None;
+                }
+            }
+        })
+        .boxed();
+
+        Ok(ResultDownload {
+            format,
+            content_type: format.content_type(),
+            chunks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::ipc::reader::StreamReader;
+    use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use futures::TryStreamExt;
+
+    async fn download(db: &DB<()>, sql: &str, format: &str) -> Result<Vec<u8>> {
+        let download = db
+            .query_download(sql, DownloadFormat::parse(format)?)
+            .await?;
+        let chunks: Vec<Vec<u8>> = download.chunks.try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn test_query_download() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')")
+            .await?;
+        let sql = "SELECT * FROM t ORDER BY id";
+
+        let csv = String::from_utf8(download(&db, sql, "csv").await?)?;
+        assert_eq!(csv, "id,name\n1,a\n2,b\n3,c\n");
+        let empty = String::from_utf8(download(&db, "SELECT * FROM t WHERE id > 9", "csv").await?)?;
+        assert_eq!(empty, "id,name\n");
+
+        let ndjson = String::from_utf8(download(&db, sql, "ndjson").await?)?;
+        assert_eq!(ndjson.lines().next(), Some(r#"{"id":1,"name":"a"}"#));
+        assert_eq!(ndjson.lines().count(), 3);
+
+        let arrow = download(&db, sql, "arrow").await?;
+        let rows: usize = StreamReader::try_new(arrow.as_slice(), None)?
+            .map(|b| b.map(|b| b.num_rows()))
+            .sum::<std::result::Result<usize, _>>()?;
+        assert_eq!(rows, 3);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("t.parquet");
+        std::fs::write(&path, download(&db, sql, "parquet").await?)?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path)?)?;
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+
+        assert!(DownloadFormat::parse("xlsx").is_err());
+        assert_eq!(DownloadFormat::Parquet.extension(), "parquet");
+        Ok(())
+    }
+}
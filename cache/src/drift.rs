@@ -0,0 +1,230 @@
+use crate::events::CacheEvent;
+use crate::ident::quote_literal;
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::{DataType, Schema, SchemaRef};
+use chrono::{DateTime, Utc};
+use datafusion::common::ScalarValue;
+use datafusion::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+// 记录所有 schema drift 事件
+pub const SCHEMA_DRIFT_TABLE: &str = "__schema_drift";
+pub(crate) const SCHEMA_DRIFT_DDL: &str =
+    "CREATE TABLE IF NOT EXISTS __schema_drift (source VARCHAR, \
+     table_name VARCHAR, location VARCHAR, expected VARCHAR, actual VARCHAR, policy VARCHAR, \
+     detected_at BIGINT)";
+
+/// 源数据和目标表 schema 不一致时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DriftPolicy {
+    // 报错，文件不会被标记为已处理，修复后会重试
+    #[default]
+    Fail,
+    // 跳过这个文件
+    Skip,
+    // 按目标表的列导入：多出来的列丢弃，缺少的列填 NULL，类型转换成目标类型
+    Project,
+}
+
+/// 两个 schema 之间的差异
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaDrift {
+    // 目标表有，源数据没有
+    pub missing_columns: Vec<String>,
+    // 源数据有，目标表没有
+    pub extra_columns: Vec<String>,
+    // (列名, 目标类型, 源数据类型)
+    pub type_changes: Vec<(String, DataType, DataType)>,
+}
+
+impl SchemaDrift {
+    /// 没有差异时返回 None
+    ///
+    /// 类型只比较大类（数值 / 字符串 / 时间 / 布尔），CSV 推断出来的 Int64 和表里的 INT 不算 drift
+    pub fn detect(expected: &Schema, actual: &Schema) -> Option<Self> {
+        let mut drift = SchemaDrift {
+            missing_columns: vec![],
+            extra_columns: vec![],
+            type_changes: vec![],
+        };
+
+        for field in expected.fields() {
+            match actual.field_with_name(field.name()) {
+                Ok(actual_field) => {
+                    if type_family(field.data_type()) != type_family(actual_field.data_type()) {
+                        drift.type_changes.push((
+                            field.name().clone(),
+                            field.data_type().clone(),
+                            actual_field.data_type().clone(),
+                        ));
+                    }
+                }
+                Err(_) => drift.missing_columns.push(field.name().clone()),
+            }
+        }
+        for field in actual.fields() {
+            if expected.field_with_name(field.name()).is_err() {
+                drift.extra_columns.push(field.name().clone());
+            }
+        }
+
+        if drift.missing_columns.is_empty()
+            && drift.extra_columns.is_empty()
+            && drift.type_changes.is_empty()
+        {
+            None
+        } else {
+            Some(drift)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TypeFamily {
+    Boolean,
+    Numeric,
+    String,
+    Temporal,
+    Other,
+}
+
+fn type_family(data_type: &DataType) -> TypeFamily {
+    match data_type {
+        DataType::Boolean => TypeFamily::Boolean,
+        t if t.is_numeric() => TypeFamily::Numeric,
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => TypeFamily::String,
+        t if t.is_temporal() => TypeFamily::Temporal,
+        _ => TypeFamily::Other,
+    }
+}
+
+/// 一次 schema drift
+#[derive(Debug, Clone)]
+pub struct SchemaDriftEvent {
+    // 数据源，比如 watch 的名字
+    pub source: String,
+    pub table: String,
+    pub location: String,
+    pub expected: SchemaRef,
+    pub actual: SchemaRef,
+    pub drift: SchemaDrift,
+    pub policy: DriftPolicy,
+    pub detected_at: DateTime<Utc>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 记录 drift 事件到 __schema_drift 表，并通知订阅者
+    #[cfg_attr(not(feature = "storage"), allow(dead_code))]
+    pub(crate) async fn record_schema_drift(&self, event: SchemaDriftEvent) -> Result<()> {
+        self.execute(SCHEMA_DRIFT_DDL).await?;
+        self.execute(&format!(
+            "INSERT INTO {} VALUES ({}, {}, {}, {}, {}, '{:?}', {})",
+            SCHEMA_DRIFT_TABLE,
+            quote_literal(&event.source),
+            quote_literal(&event.table),
+            quote_literal(&event.location),
+            quote_literal(&describe_schema(&event.expected)),
+            quote_literal(&describe_schema(&event.actual)),
+            event.policy,
+            event.detected_at.timestamp_millis()
+        ))
+        .await?;
+
+        tracing::warn!(
+            table = %event.table,
+            location = %event.location,
+            drift = ?event.drift,
+            "schema drift detected"
+        );
+        self.emit(CacheEvent::SchemaDrift(event));
+        Ok(())
+    }
+
+    /// 同一个数据源的同一个文件已经按同样的 schema 和处理方式记录过 drift
+    ///
+    /// Fail 时文件不会被标记为已处理，每次扫描都会遇到同样的 drift，只需要记录第一次
+    #[cfg_attr(not(feature = "storage"), allow(dead_code))]
+    pub(crate) async fn schema_drift_recorded(&self, event: &SchemaDriftEvent) -> Result<bool> {
+        self.execute(SCHEMA_DRIFT_DDL).await?;
+        let batches = self
+            .query_to_batches(&format!(
+                "SELECT 1 FROM {} WHERE source = {} AND table_name = {} AND location = {} \
+                 AND expected = {} AND actual = {} AND policy = '{:?}' LIMIT 1",
+                SCHEMA_DRIFT_TABLE,
+                quote_literal(&event.source),
+                quote_literal(&event.table),
+                quote_literal(&event.location),
+                quote_literal(&describe_schema(&event.expected)),
+                quote_literal(&describe_schema(&event.actual)),
+                event.policy
+            ))
+            .await?;
+        Ok(batches.iter().any(|b| b.num_rows() > 0))
+    }
+}
+
+/// 按目标 schema 投影：缺少的列填 NULL，其他列转换成目标类型
+#[cfg_attr(not(feature = "storage"), allow(dead_code))]
+pub(crate) fn project_to_schema(df: DataFrame, target: &Schema) -> Result<DataFrame> {
+    let source = df.schema().clone();
+    let exprs = target
+        .fields()
+        .iter()
+        .map(|field| {
+            let expr = if source.field_with_unqualified_name(field.name()).is_ok() {
+                cast(ident(field.name()), field.data_type().clone())
+            } else {
+                lit(ScalarValue::try_from(field.data_type())?)
+            };
+            Ok(expr.alias(field.name()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(df.select(exprs)?)
+}
+
+#[cfg_attr(not(feature = "storage"), allow(dead_code))]
+fn describe_schema(schema: &Schema) -> String {
+    schema
+        .fields()
+        .iter()
+        .map(|f| format!("{}:{}", f.name(), f.data_type()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::Field;
+
+    #[test]
+    fn test_detect_schema_drift() {
+        let expected = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("amount", DataType::Decimal128(10, 2), true),
+        ]);
+
+        // 同一类型大类不算 drift
+        let same = Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("amount", DataType::Float64, true),
+        ]);
+        assert_eq!(SchemaDrift::detect(&expected, &same), None);
+
+        let drifted = Schema::new(vec![
+            Field::new("id", DataType::Utf8, true),
+            Field::new("amount", DataType::Float64, true),
+            Field::new("currency", DataType::Utf8, true),
+        ]);
+        let drift = SchemaDrift::detect(&expected, &drifted).unwrap();
+        assert_eq!(drift.missing_columns, vec!["name".to_string()]);
+        assert_eq!(drift.extra_columns, vec!["currency".to_string()]);
+        assert_eq!(
+            drift.type_changes,
+            vec![("id".to_string(), DataType::Int32, DataType::Utf8)]
+        );
+    }
+}
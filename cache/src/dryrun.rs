@@ -0,0 +1,288 @@
+use crate::pool::DB;
+use crate::template::PathTemplate;
+use anyhow::{Context, Result};
+use arrow_schema::SchemaRef;
+use chrono::Utc;
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::logical_expr::{DdlStatement, LogicalPlan};
+use datafusion::prelude::DataFrame;
+use datafusion::sql::parser::DFParser;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+/// dry-run 的检查结果，不会写任何数据
+#[derive(Debug, Clone)]
+pub struct DryRunReport {
+    // 语句或者导出目标的简短描述
+    pub target: String,
+    // 结果（查询、CTAS、INSERT、导出）的 schema
+    pub schema: SchemaRef,
+    // 会读取或写入的位置
+    pub location: Option<String>,
+    // 根据统计信息估算的输出行数和字节数，统计信息不可用时为 None
+    pub estimated_rows: Option<usize>,
+    pub estimated_bytes: Option<usize>,
+    // 不会导致失败但需要注意的问题，比如 location 下没有文件
+    pub warnings: Vec<String>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 逐条生成逻辑计划并检查 schema 和 location，但不执行，适合在 CI 中校验脚本
+    ///
+    /// 后面的语句依赖前面语句建的表时会校验失败，因为前面的语句并没有真正执行
+    pub async fn dry_run_sql(&self, script: &str) -> Result<Vec<DryRunReport>> {
+        let state = self.ctx.state();
+        let statements = DFParser::parse_sql(script).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let mut reports = Vec::new();
+        for (i, statement) in statements.into_iter().enumerate() {
+            let target = statement.to_string();
+            let plan = state
+                .statement_to_plan(statement)
+                .await
+                .with_context(|| format!("statement {}: {}", i + 1, target))?;
+            reports.push(
+                self.check_plan(target, &plan)
+                    .await
+                    .with_context(|| format!("statement {}", i + 1))?,
+            );
+        }
+        Ok(reports)
+    }
+
+    /// export_to_storage 的 dry-run：检查存储、格式和目标目录的访问权限，估算输出大小
+    pub async fn dry_run_export(
+        &self,
+        df: DataFrame,
+        storage_name: &str,
+        template: &PathTemplate,
+        format: &str,
+    ) -> Result<DryRunReport> {
+        if !matches!(format.to_lowercase().as_str(), "csv" | "parquet") {
+            return Err(anyhow::anyhow!("Unsupported format: {}", format));
+        }
+        let path = template.render(Utc::now())?;
+        let (store, location) = {
+            let storages = self.registered_storages.read().unwrap();
+            let storage = storages.get(storage_name).context("get storage")?;
+            (
+                storage.store.clone(),
+                format!(
+                    "{}://{}/{}",
+                    storage.config.schema, storage.config.bucket, path
+                ),
+            )
+        };
+
+        // 没有办法在不写入的情况下验证写权限，这里只验证凭证和目录可以访问
+        let parent = path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        let mut warnings = Vec::new();
+        if check_location(store, &Path::from(parent)).await? {
+            warnings.push(format!(
+                "{} already exists and will be overwritten",
+                location
+            ));
+        }
+
+        let schema = df.schema().inner().clone();
+        let (estimated_rows, estimated_bytes) = estimate_output(df).await;
+        Ok(DryRunReport {
+            target: format!("export to {}", location),
+            schema,
+            location: Some(location),
+            estimated_rows,
+            estimated_bytes,
+            warnings,
+        })
+    }
+
+    async fn check_plan(&self, target: String, plan: &LogicalPlan) -> Result<DryRunReport> {
+        let mut report = DryRunReport {
+            target,
+            schema: plan.schema().inner().clone(),
+            location: None,
+            estimated_rows: None,
+            estimated_bytes: None,
+            warnings: Vec::new(),
+        };
+
+        let input = match plan {
+            LogicalPlan::Ddl(DdlStatement::CreateExternalTable(cmd)) => {
+                report.schema = cmd.schema.inner().clone();
+                report.location = Some(cmd.location.clone());
+                let (store, prefix) = self.resolve_location(&cmd.location)?;
+                if !check_location(store, &prefix).await? {
+                    report
+                        .warnings
+                        .push(format!("no files found at {}", cmd.location));
+                }
+                None
+            }
+            LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(cmd)) => Some(cmd.input.clone()),
+            LogicalPlan::Dml(dml) => Some(dml.input.clone()),
+            LogicalPlan::Copy(copy) => {
+                report.location = Some(copy.output_url.clone());
+                let (store, prefix) = self.resolve_location(&copy.output_url)?;
+                check_location(store, &prefix).await?;
+                Some(copy.input.clone())
+            }
+            LogicalPlan::Ddl(_) | LogicalPlan::Statement(_) => None,
+            _ => Some(Arc::new(plan.clone())),
+        };
+
+        if let Some(input) = input {
+            report.schema = input.schema().inner().clone();
+            let df = DataFrame::new(self.ctx.state(), input.as_ref().clone());
+            (report.estimated_rows, report.estimated_bytes) = estimate_output(df).await;
+        }
+        Ok(report)
+    }
+
+    // location 对应的 object store 和路径，存储没有注册时报错
+    fn resolve_location(&self, location: &str) -> Result<(Arc<dyn ObjectStore>, Path)> {
+        let url = ListingTableUrl::parse(location)?;
+        let store = self
+            .ctx
+            .runtime_env()
+            .object_store(url.object_store())
+            .with_context(|| format!("no storage registered for {}", location))?;
+        Ok((store, url.prefix().clone()))
+    }
+}
+
+// 检查路径是否可以访问（凭证、bucket 是否正确），返回路径下是否已经有对象
+async fn check_location(store: Arc<dyn ObjectStore>, path: &Path) -> Result<bool> {
+    if store.head(path).await.is_ok() {
+        return Ok(true);
+    }
+    let prefix = if path.as_ref().is_empty() {
+        None
+    } else {
+        Some(path)
+    };
+    match store.list(prefix).next().await {
+        Some(Ok(_)) => Ok(true),
+        Some(Err(e)) => Err(anyhow::anyhow!("cannot access {}: {}", path, e)),
+        None => Ok(false),
+    }
+}
+
+// 根据物理计划的统计信息估算（行数, 字节数）
+async fn estimate_output(df: DataFrame) -> (Option<usize>, Option<usize>) {
+    let Ok(plan) = df.create_physical_plan().await else {
+        return (None, None);
+    };
+    match plan.statistics() {
+        Ok(stats) => (
+            stats.num_rows.get_value().copied(),
+            stats.total_byte_size.get_value().copied(),
+        ),
+        Err(_) => (None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StorageConfig;
+    use crate::pool::StorageEntry;
+    use object_store::aws::AwsCredential;
+    use object_store::local::LocalFileSystem;
+    use object_store::StaticCredentialProvider;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_dry_run_sql() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+
+        let reports = db
+            .dry_run_sql(
+                "CREATE TABLE t2 AS SELECT id FROM t; INSERT INTO t VALUES (3, 'c'); DROP TABLE t",
+            )
+            .await?;
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].schema.fields().len(), 1);
+        assert_eq!(reports[0].estimated_rows, Some(2));
+
+        // 什么都没有执行
+        let batches = db.query_to_batches("SELECT * FROM t").await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        assert!(db.query_to_batches("SELECT * FROM t2").await.is_err());
+
+        let err = db
+            .dry_run_sql("SELECT id FROM t; SELECT missing FROM t")
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("statement 2"));
+
+        let dir = tempdir()?;
+        let reports = db
+            .dry_run_sql(&format!(
+                "CREATE EXTERNAL TABLE e STORED AS CSV LOCATION '{}/'",
+                dir.path().to_string_lossy()
+            ))
+            .await?;
+        assert_eq!(reports[0].warnings.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_export() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        let config = StorageConfig {
+            access_key: String::new(),
+            access_secret: String::new(),
+            endpoint: None,
+            region: String::new(),
+            bucket: String::new(),
+            schema: "file".to_string(),
+            session_token: None,
+            token_provider: None,
+            create_bucket_if_missing: false,
+            prefix: None,
+            cost_per_gb_read: None,
+            cost_per_gb_written: None,
+            rate_limit: None,
+        };
+        let credentials = Arc::new(StaticCredentialProvider::new(AwsCredential {
+            key_id: String::new(),
+            secret_key: String::new(),
+            token: None,
+        }));
+        db.registered_storages.write().unwrap().insert(
+            "local".to_string(),
+            StorageEntry {
+                store: Arc::new(LocalFileSystem::new()),
+                credentials,
+                config,
+                counters: Default::default(),
+            },
+        );
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3)").await?;
+
+        let path = format!("{}/out.csv", dir.path().to_string_lossy());
+        let template = PathTemplate::new(path.trim_start_matches('/'));
+        let df = db.query("SELECT * FROM t").await?;
+        let report = db
+            .dry_run_export(df.clone(), "local", &template, "csv")
+            .await?;
+        assert_eq!(report.estimated_rows, Some(3));
+        assert!(report.warnings.is_empty());
+        assert!(!dir.path().join("out.csv").exists());
+
+        assert!(db
+            .dry_run_export(df, "local", &template, "xlsx")
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use datafusion::arrow::compute::concat_batches;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::Write;
+use std::sync::Arc;
+
+/// 带 ETag 的查询结果
+#[derive(Debug, Clone)]
+pub enum EtagResult {
+    // 结果和 If-None-Match 一致，不需要再传输
+    NotModified {
+        etag: String,
+    },
+    Modified {
+        etag: String,
+        batches: Vec<RecordBatch>,
+    },
+}
+
+impl EtagResult {
+    pub fn etag(&self) -> &str {
+        match self {
+            EtagResult::NotModified { etag } | EtagResult::Modified { etag, .. } => etag,
+        }
+    }
+}
+
+// FNV-1a，和 workload::fingerprint 一样，结果不依赖进程和 Rust 版本
+struct Fnv1a(u64);
+
+impl Write for Fnv1a {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 根据 schema 和数据计算 ETag（带引号，可以直接放在 HTTP 头里）
+/// 先合并成一个 batch，结果相同但分批方式不同时 ETag 也相同
+pub fn result_etag(schema: &SchemaRef, batches: &[RecordBatch]) -> Result<String> {
+    let mut hasher = Fnv1a(0xcbf29ce484222325);
+    {
+        let mut writer = StreamWriter::try_new(&mut hasher, schema)?;
+        writer.write(&concat_batches(schema, batches)?)?;
+        writer.finish()?;
+    }
+    Ok(format!("\"{:016x}\"", hasher.0))
+}
+
+// If-None-Match 可能是多个 ETag，也可能是弱 ETag 或者 *
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 执行查询并计算结果的 ETag，和 if_none_match 一致时不返回数据
+    /// 查询仍然会执行，节省的是传输和序列化，适合频繁轮询的看板
+    pub async fn query_with_etag(
+        &self,
+        sql: &str,
+        if_none_match: Option<&str>,
+    ) -> Result<EtagResult> {
+        let batches = self.query_to_batches(sql).await?;
+        let schema = match batches.first() {
+            Some(batch) => batch.schema(),
+            None => Arc::new(
+                self.ctx
+                    .state()
+                    .create_logical_plan(sql)
+                    .await?
+                    .schema()
+                    .as_arrow()
+                    .clone(),
+            ),
+        };
+        let etag = result_etag(&schema, &batches)?;
+        if if_none_match.is_some_and(|tags| etag_matches(tags, &etag)) {
+            return Ok(EtagResult::NotModified { etag });
+        }
+        Ok(EtagResult::Modified { etag, batches })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_with_etag() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2)").await?;
+        let sql = "SELECT * FROM t ORDER BY id";
+
+        let first = db.query_with_etag(sql, None).await?;
+        assert!(matches!(first, EtagResult::Modified { .. }));
+        let etag = first.etag().to_string();
+        let again = db.query_with_etag(sql, Some(&etag)).await?;
+        assert!(matches!(again, EtagResult::NotModified { .. }));
+        assert!(matches!(
+            db.query_with_etag(sql, Some(&format!("\"other\", W/{}", etag)))
+                .await?,
+            EtagResult::NotModified { .. }
+        ));
+
+        db.execute("INSERT INTO t VALUES (3)").await?;
+        let changed = db.query_with_etag(sql, Some(&etag)).await?;
+        assert!(matches!(changed, EtagResult::Modified { .. }));
+        assert_ne!(changed.etag(), etag);
+
+        // 分批方式不影响 ETag
+        let batches = db.query_to_batches(sql).await?;
+        let schema = batches[0].schema();
+        let split: Vec<RecordBatch> = (0..3).map(|i| batches[0].slice(i, 1)).collect();
+        assert_eq!(result_etag(&schema, &split)?, changed.etag());
+        Ok(())
+    }
+}
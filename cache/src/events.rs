@@ -0,0 +1,55 @@
+use crate::drift::SchemaDriftEvent;
+use crate::pool::DB;
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// 订阅者处理不过来时最多保留的事件数，超过后旧事件会被丢弃（Lagged）
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// DB 内部产生的事件，通过 DB::subscribe 订阅
+#[derive(Debug, Clone)]
+pub enum CacheEvent {
+    SchemaDrift(SchemaDriftEvent),
+    // 从上游刷新表失败
+    RefreshFailed {
+        table: String,
+        error: String,
+    },
+    // 同步到下游失败，包括数据块被暂存到 dead letter
+    SyncFailed {
+        table: String,
+        error: String,
+    },
+    ExportCompleted {
+        storage: String,
+        location: String,
+    },
+    // 刷新成功但用时超过了 RefreshTask 的 SLA
+    SlaBreached {
+        table: String,
+        elapsed: Duration,
+        sla: Duration,
+    },
+    // 超出内存预算触发淘汰，或者语句被 statement guard 拒绝（table 为空）
+    QuotaExceeded {
+        table: String,
+        message: String,
+    },
+    // spawn_job 启动的后台任务失败或者超过截止时间，job 是 JobOptions 中的名字
+    JobFailed {
+        job: String,
+        error: String,
+    },
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn subscribe(&self) -> broadcast::Receiver<CacheEvent> {
+        self.events.subscribe()
+    }
+
+    // 没有订阅者时发送会失败，直接忽略
+    pub(crate) fn emit(&self, event: CacheEvent) {
+        let _ = self.events.send(event);
+    }
+}
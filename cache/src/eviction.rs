@@ -0,0 +1,458 @@
+use crate::events::CacheEvent;
+use crate::ident::quote_ident;
+use crate::job::{JobHandle, JobOptions};
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::logical_expr::LogicalPlan;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 超出内存预算时释放内存的方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvictionPolicy {
+    // 删除最久没有被查询的整张表，只能用于 DB 级别的预算
+    LruTable,
+    // 按时间列删除最旧的行，保留最新的数据
+    OldestRows {
+        column: String,
+    },
+    // 导出成 Parquet 后用外部表替换，表数据还在但变成只读
+    #[cfg(feature = "storage")]
+    Spill {
+        storage: String,
+        prefix: String,
+    },
+}
+
+/// 内存预算，bytes 和 rows 都设置时任意一个超出都会触发淘汰
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryBudget {
+    pub max_bytes: Option<usize>,
+    pub max_rows: Option<usize>,
+    pub policy: EvictionPolicy,
+}
+
+impl MemoryBudget {
+    pub fn bytes(max_bytes: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            max_bytes: Some(max_bytes),
+            max_rows: None,
+            policy,
+        }
+    }
+
+    pub fn rows(max_rows: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            max_bytes: None,
+            max_rows: Some(max_rows),
+            policy,
+        }
+    }
+
+    // 超出预算的字节数和行数
+    fn excess(&self, usage: TableMemory) -> (usize, usize) {
+        (
+            self.max_bytes
+                .map_or(0, |max| usage.bytes.saturating_sub(max)),
+            self.max_rows
+                .map_or(0, |max| usage.rows.saturating_sub(max)),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct BudgetState {
+    db: Option<MemoryBudget>,
+    tables: HashMap<String, MemoryBudget>,
+    // 表最近一次被查询的时间，LRU 淘汰时使用
+    last_access: HashMap<String, Instant>,
+}
+
+/// 内存表占用的内存（按 Arrow 数组的大小估算）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableMemory {
+    pub rows: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvictionAction {
+    DroppedTable,
+    RemovedRows { rows: usize },
+    Spilled { location: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    pub table: String,
+    pub action: EvictionAction,
+    pub freed_bytes: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvictionReport {
+    pub evictions: Vec<Eviction>,
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 设置整个 DB 的内存预算，None 表示不限制；在 enforce_memory_budget 时生效
+    pub fn set_memory_budget(&self, budget: Option<MemoryBudget>) {
+        self.memory_budget.write().unwrap().db = budget;
+    }
+
+    /// 设置单个表的内存预算，先于 DB 级别的预算检查
+    pub fn set_table_memory_budget(&self, table: &str, budget: Option<MemoryBudget>) -> Result<()> {
+        let mut state = self.memory_budget.write().unwrap();
+        match budget {
+            Some(budget) => {
+                if budget.policy == EvictionPolicy::LruTable {
+                    return Err(anyhow::anyhow!(
+                        "LRU eviction only applies to the DB memory budget"
+                    ));
+                }
+                state.tables.insert(table.to_string(), budget);
+            }
+            None => {
+                state.tables.remove(table);
+            }
+        }
+        Ok(())
+    }
+
+    /// 默认 schema 下每个内存表占用的内存，不包括 __ 开头的内部表
+    pub async fn memory_usage(&self) -> Result<BTreeMap<String, TableMemory>> {
+        let schema = self.default_schema()?;
+        let mut usage = BTreeMap::new();
+        for name in schema.table_names() {
+            if name.starts_with("__") {
+                continue;
+            }
+            let Some(provider) = schema.table(&name).await? else {
+                continue;
+            };
+            if !is_memory_table(provider.as_ref()) {
+                continue;
+            }
+            let batches = self.ctx.read_table(provider)?.collect().await?;
+            usage.insert(
+                name,
+                TableMemory {
+                    rows: batches.iter().map(|b| b.num_rows()).sum(),
+                    bytes: batches.iter().map(|b| b.get_array_memory_size()).sum(),
+                },
+            );
+        }
+        Ok(usage)
+    }
+
+    /// 按预算淘汰数据：先检查每个表自己的预算，再按 LRU 顺序淘汰直到 DB 不超出预算
+    ///
+    /// 和 reap_expired_rows 一样，淘汰持有表的写锁，同时进行的写入会等淘汰完成后再执行
+    pub async fn enforce_memory_budget(&self) -> Result<EvictionReport> {
+        let mut usage = self.memory_usage().await?;
+        let mut report = EvictionReport {
+            bytes_before: usage.values().map(|u| u.bytes).sum(),
+            ..Default::default()
+        };
+
+        let table_budgets = self.memory_budget.read().unwrap().tables.clone();
+        for (table, budget) in table_budgets {
+            let Some(current) = usage.get(&table).copied() else {
+                continue;
+            };
+            let (bytes, rows) = budget.excess(current);
+            if bytes == 0 && rows == 0 {
+                continue;
+            }
+            let eviction = self
+                .evict(&table, current, bytes, rows, &budget.policy)
+                .await?;
+            shrink(&mut usage, &eviction, current);
+            self.emit(quota_exceeded(&eviction));
+            report.evictions.push(eviction);
+        }
+
+        let db_budget = self.memory_budget.read().unwrap().db.clone();
+        if let Some(budget) = db_budget {
+            for table in self.lru_order(&usage) {
+                let total = usage
+                    .values()
+                    .fold(TableMemory::default(), |acc, u| TableMemory {
+                        rows: acc.rows + u.rows,
+                        bytes: acc.bytes + u.bytes,
+                    });
+                let (bytes, rows) = budget.excess(total);
+                if bytes == 0 && rows == 0 {
+                    break;
+                }
+                let current = usage[&table];
+                if let EvictionPolicy::OldestRows { column } = &budget.policy {
+                    // 没有时间列的表不参与按行淘汰
+                    let schema = self.ctx.table_provider(table.as_str()).await?.schema();
+                    if schema.field_with_name(column).is_err() {
+                        continue;
+                    }
+                }
+                let eviction = self
+                    .evict(&table, current, bytes, rows, &budget.policy)
+                    .await?;
+                shrink(&mut usage, &eviction, current);
+                self.emit(quota_exceeded(&eviction));
+                report.evictions.push(eviction);
+            }
+        }
+
+        report.bytes_after = usage.values().map(|u| u.bytes).sum();
+        Ok(report)
+    }
+
+    // 释放表中至少 bytes 字节、rows 行
+    async fn evict(
+        &self,
+        table: &str,
+        current: TableMemory,
+        bytes: usize,
+        rows: usize,
+        policy: &EvictionPolicy,
+    ) -> Result<Eviction> {
+        match policy {
+            EvictionPolicy::LruTable => {
+                self.drop_table(table).await?;
+                Ok(Eviction {
+                    table: table.to_string(),
+                    action: EvictionAction::DroppedTable,
+                    freed_bytes: current.bytes,
+                })
+            }
+            EvictionPolicy::OldestRows { column } => {
+                let bytes_per_row = current.bytes.div_ceil(current.rows.max(1)).max(1);
+                let remove = rows.max(bytes.div_ceil(bytes_per_row)).min(current.rows);
+                let keep = current.rows - remove;
+                let _guard = self.lock_table(table).await;
+                let provider = self.ctx.table_provider(table).await?;
+                let batches = self
+                    .ctx
+                    .sql(&format!(
+                        "SELECT * FROM {} ORDER BY {} DESC NULLS LAST LIMIT {}",
+                        self.quote_table(table),
+                        quote_ident(column),
+                        keep
+                    ))
+                    .await?
+                    .collect()
+                    .await?;
+                let freed = current
+                    .bytes
+                    .saturating_sub(batches.iter().map(|b| b.get_array_memory_size()).sum());
+                self.rewrite_table(table, provider.schema(), vec![batches], Some(&provider))?;
+                Ok(Eviction {
+                    table: table.to_string(),
+                    action: EvictionAction::RemovedRows { rows: remove },
+                    freed_bytes: freed,
+                })
+            }
+            #[cfg(feature = "storage")]
+            EvictionPolicy::Spill { storage, prefix } => {
+                let location = self.spill_table(table, storage, prefix).await?;
+                Ok(Eviction {
+                    table: table.to_string(),
+                    action: EvictionAction::Spilled { location },
+                    freed_bytes: current.bytes,
+                })
+            }
+        }
+    }
+
+    #[cfg(feature = "storage")]
+    async fn spill_table(&self, table: &str, storage: &str, prefix: &str) -> Result<String> {
+        use datafusion::dataframe::DataFrameWriteOptions;
+        use datafusion::datasource::file_format::parquet::ParquetFormat;
+        use datafusion::datasource::listing::{
+            ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+        };
+
+        let path = format!("{}/{}.parquet", prefix.trim_matches('/'), table);
+        let location = self.storage_location(storage, &path)?;
+        let _guard = self.lock_table(table).await;
+        let provider = self.ctx.table_provider(table).await?;
+        let schema = provider.schema();
+        self.ctx
+            .read_table(provider)?
+            .write_parquet(
+                &location,
+                DataFrameWriteOptions::new().with_single_file_output(true),
+                None,
+            )
+            .await?;
+
+        let config = ListingTableConfig::new(ListingTableUrl::parse(&location)?)
+            .with_listing_options(ListingOptions::new(Arc::new(ParquetFormat::default())))
+            .with_schema(schema);
+        self.replace_table(
+            self.table_ref(table).table(),
+            Arc::new(ListingTable::try_new(config)?),
+        )?;
+        Ok(location)
+    }
+
+    // 最久没有被查询的表排在前面，从来没有被查询过的表最先淘汰
+    fn lru_order(&self, usage: &BTreeMap<String, TableMemory>) -> Vec<String> {
+        let state = self.memory_budget.read().unwrap();
+        let mut tables: Vec<(Option<Instant>, String)> = usage
+            .keys()
+            .map(|table| (state.last_access.get(table).copied(), table.clone()))
+            .collect();
+        tables.sort();
+        tables.into_iter().map(|(_, table)| table).collect()
+    }
+
+    // 记录查询中扫描的表，用于 LRU 淘汰
+    pub(crate) fn record_table_access(&self, plan: &LogicalPlan) {
+        let mut tables = Vec::new();
+        let _ = plan.apply(|node| {
+            if let LogicalPlan::TableScan(scan) = node {
+                tables.push(scan.table_name.table().to_string());
+            }
+            Ok(TreeNodeRecursion::Continue)
+        });
+        if tables.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut state = self.memory_budget.write().unwrap();
+        for table in tables {
+            state.last_access.insert(table, now);
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync + 'static> DB<V> {
+    /// 后台按 sync_interval 定期执行 enforce_memory_budget，直到任务被取消
+    pub fn eviction_job(self: &Arc<Self>, options: JobOptions) -> JobHandle<()> {
+        self.spawn_job(options, |db, token| async move {
+            loop {
+                if let Err(e) = db.enforce_memory_budget().await {
+                    tracing::warn!(error = %e, "enforce memory budget failed");
+                }
+                tokio::time::sleep(db.sync_interval()).await;
+                if token.is_cancelled() {
+                    return Ok(());
+                }
+            }
+        })
+    }
+}
+
+// 淘汰后更新内存占用，删除或导出的表不再占用内存
+fn shrink(usage: &mut BTreeMap<String, TableMemory>, eviction: &Eviction, current: TableMemory) {
+    match eviction.action {
+        EvictionAction::RemovedRows { rows } => {
+            usage.insert(
+                eviction.table.clone(),
+                TableMemory {
+                    rows: current.rows - rows,
+                    bytes: current.bytes - eviction.freed_bytes,
+                },
+            );
+        }
+        _ => {
+            usage.remove(&eviction.table);
+        }
+    }
+}
+
+// 淘汰时发送的事件
+fn quota_exceeded(eviction: &Eviction) -> CacheEvent {
+    let action = match &eviction.action {
+        EvictionAction::DroppedTable => "dropped table".to_string(),
+        EvictionAction::RemovedRows { rows } => format!("removed {} rows", rows),
+        EvictionAction::Spilled { location } => format!("spilled to {}", location),
+    };
+    CacheEvent::QuotaExceeded {
+        table: eviction.table.clone(),
+        message: format!(
+            "memory budget exceeded, {}, freed {} bytes",
+            action, eviction.freed_bytes
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn rows(db: &DB<()>, table: &str) -> Result<usize> {
+        Ok(db.ctx.table(table).await?.count().await?)
+    }
+
+    #[tokio::test]
+    async fn test_table_budget_oldest_rows() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE ticks (ts BIGINT, price BIGINT)")
+            .await?;
+        db.execute("INSERT INTO ticks VALUES (1, 10), (2, 20), (3, 30), (4, 40)")
+            .await?;
+        assert!(db
+            .set_table_memory_budget(
+                "ticks",
+                Some(MemoryBudget::rows(2, EvictionPolicy::LruTable))
+            )
+            .is_err());
+        db.set_table_memory_budget(
+            "ticks",
+            Some(MemoryBudget::rows(
+                2,
+                EvictionPolicy::OldestRows {
+                    column: "ts".to_string(),
+                },
+            )),
+        )?;
+
+        let report = db.enforce_memory_budget().await?;
+        assert_eq!(
+            report.evictions[0].action,
+            EvictionAction::RemovedRows { rows: 2 }
+        );
+        let batches = db.query_to_batches("SELECT min(ts) FROM ticks").await?;
+        assert_eq!(
+            batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<datafusion::arrow::array::Int64Array>()
+                .unwrap()
+                .value(0),
+            3
+        );
+        assert!(db.enforce_memory_budget().await?.evictions.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_db_budget_lru() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        for table in ["a", "b", "c"] {
+            db.execute(&format!("CREATE TABLE {} (id BIGINT)", table))
+                .await?;
+            db.execute(&format!("INSERT INTO {} VALUES (1), (2)", table))
+                .await?;
+        }
+        db.query_to_batches("SELECT * FROM a").await?;
+        db.query_to_batches("SELECT * FROM c").await?;
+        db.query_to_batches("SELECT * FROM a").await?;
+
+        db.set_memory_budget(Some(MemoryBudget::rows(3, EvictionPolicy::LruTable)));
+        let report = db.enforce_memory_budget().await?;
+        // b 没有被查询过，c 比 a 更早被查询
+        let evicted: Vec<&str> = report.evictions.iter().map(|e| e.table.as_str()).collect();
+        assert_eq!(evicted, vec!["b", "c"]);
+        assert_eq!(rows(&db, "a").await?, 2);
+        assert!(!db.ctx.table_exist("b")?);
+        Ok(())
+    }
+}
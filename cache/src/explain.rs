@@ -0,0 +1,125 @@
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::physical_plan::display::DisplayableExecutionPlan;
+use datafusion::physical_plan::{displayable, ExecutionPlan};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// explain 的结果，三个计划都是缩进格式的文本
+#[derive(Debug, Clone)]
+pub struct ExplainReport {
+    pub logical_plan: String,
+    // 经过优化器之后的逻辑计划
+    pub optimized_plan: String,
+    pub physical_plan: String,
+}
+
+/// 物理计划中一个节点的执行指标，多个分区的值已经合并
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorMetrics {
+    // 在计划树中的深度，根节点是 0
+    pub depth: usize,
+    pub operator: String,
+    pub output_rows: Option<usize>,
+    pub elapsed_compute: Option<Duration>,
+    pub spill_count: Option<usize>,
+    pub spilled_bytes: Option<usize>,
+}
+
+/// explain_analyze 的结果，operators 按计划树先序排列
+#[derive(Debug, Clone)]
+pub struct AnalyzeReport {
+    // 带 metrics 的物理计划
+    pub physical_plan: String,
+    pub operators: Vec<OperatorMetrics>,
+    pub output_rows: usize,
+    pub duration: Duration,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 返回查询的逻辑计划、优化后的逻辑计划和物理计划，不执行查询
+    pub async fn explain(&self, sql: &str) -> Result<ExplainReport> {
+        let state = self.ctx.state();
+        let logical = state.create_logical_plan(sql).await?;
+        let optimized = state.optimize(&logical)?;
+        let physical = state.create_physical_plan(&logical).await?;
+        Ok(ExplainReport {
+            logical_plan: logical.display_indent().to_string(),
+            optimized_plan: optimized.display_indent().to_string(),
+            physical_plan: displayable(physical.as_ref()).indent(true).to_string(),
+        })
+    }
+
+    /// 执行查询并返回各个节点的执行指标，查询会计入 workload 和最近查询
+    pub async fn explain_analyze(&self, sql: &str) -> Result<AnalyzeReport> {
+        let start = Instant::now();
+        let (batches, plan) = self.run_with_plan(sql).await?;
+        let duration = start.elapsed();
+        let mut operators = Vec::new();
+        collect_metrics(&plan, 0, &mut operators);
+        Ok(AnalyzeReport {
+            physical_plan: DisplayableExecutionPlan::with_metrics(plan.as_ref())
+                .indent(true)
+                .to_string(),
+            operators,
+            output_rows: batches.iter().map(|b| b.num_rows()).sum(),
+            duration,
+        })
+    }
+}
+
+fn collect_metrics(
+    plan: &Arc<dyn ExecutionPlan>,
+    depth: usize,
+    operators: &mut Vec<OperatorMetrics>,
+) {
+    let metrics = plan.metrics().map(|m| m.aggregate_by_name());
+    operators.push(OperatorMetrics {
+        depth,
+        operator: plan.name().to_string(),
+        output_rows: metrics.as_ref().and_then(|m| m.output_rows()),
+        elapsed_compute: metrics
+            .as_ref()
+            .and_then(|m| m.elapsed_compute())
+            .map(|nanos| Duration::from_nanos(nanos as u64)),
+        spill_count: metrics.as_ref().and_then(|m| m.spill_count()),
+        spilled_bytes: metrics.as_ref().and_then(|m| m.spilled_bytes()),
+    });
+    for child in plan.children() {
+        collect_metrics(child, depth + 1, operators);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_explain() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, v BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1, 10), (2, 20), (3, 30)")
+            .await?;
+        let sql = "SELECT id, v * 2 FROM t WHERE v > 15";
+
+        let report = db.explain(sql).await?;
+        assert!(report.logical_plan.contains("Filter"));
+        assert!(report.optimized_plan.contains("TableScan: t"));
+        assert!(report.physical_plan.contains("FilterExec"));
+        assert!(db.explain("SELECT * FROM missing").await.is_err());
+
+        let analyzed = db.explain_analyze(sql).await?;
+        assert_eq!(analyzed.output_rows, 2);
+        assert!(analyzed.physical_plan.contains("output_rows"));
+        assert_eq!(analyzed.operators[0].depth, 0);
+        let filter = analyzed
+            .operators
+            .iter()
+            .find(|o| o.operator == "FilterExec")
+            .unwrap();
+        assert_eq!(filter.output_rows, Some(2));
+        assert!(filter.elapsed_compute.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use crate::events::CacheEvent;
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::array::{Array, AsArray};
+use datafusion::arrow::datatypes::UInt64Type;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::prelude::DataFrame;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+
+// 本地导出在 ExportCompleted 事件中的存储名
+pub const LOCAL_STORAGE: &str = "local";
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 和 export_to_storage 一样，但是写到本地文件，不需要配置对象存储，返回写入的行数
+    ///
+    /// format 支持 csv、parquet、json（每行一个 JSON 对象），父目录不存在时会创建
+    pub async fn export_to_file(
+        &self,
+        df: DataFrame,
+        path: impl AsRef<Path>,
+        format: &str,
+    ) -> Result<usize> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let location = std::path::absolute(path)?.to_string_lossy().to_string();
+        let options = DataFrameWriteOptions::new().with_single_file_output(true);
+
+        let counts = match format.to_lowercase().as_str() {
+            "csv" => df.write_csv(&location, options, None).await?,
+            "parquet" => df.write_parquet(&location, options, None).await?,
+            "json" | "ndjson" => df.write_json(&location, options, None).await?,
+            _ => return Err(anyhow::anyhow!("Unsupported format: {}", format)),
+        };
+        self.emit(CacheEvent::ExportCompleted {
+            storage: LOCAL_STORAGE.to_string(),
+            location,
+        });
+        Ok(written_rows(&counts))
+    }
+}
+
+// 写入返回一行 count
+fn written_rows(counts: &[RecordBatch]) -> usize {
+    counts
+        .iter()
+        .filter(|b| b.num_columns() > 0 && b.column(0).is_valid(0))
+        .map(|b| b.column(0).as_primitive::<UInt64Type>().value(0) as usize)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_export_to_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+
+        for format in ["csv", "parquet", "json"] {
+            let path = dir.path().join("out").join(format!("t.{}", format));
+            let df = db.query("SELECT * FROM t").await?;
+            assert_eq!(db.export_to_file(df, &path, format).await?, 2);
+            assert!(path.is_file());
+        }
+        let read = db
+            .ctx
+            .read_parquet(
+                dir.path().join("out/t.parquet").to_str().unwrap(),
+                Default::default(),
+            )
+            .await?;
+        assert_eq!(read.count().await?, 2);
+
+        let df = db.query("SELECT * FROM t").await?;
+        assert!(db
+            .export_to_file(df, dir.path().join("t.xml"), "xml")
+            .await
+            .is_err());
+        Ok(())
+    }
+}
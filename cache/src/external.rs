@@ -0,0 +1,220 @@
+use crate::ident::quote_literal;
+use crate::location::reject_control_chars;
+use crate::pool::DB;
+use anyhow::Result;
+use chrono::Utc;
+use datafusion::arrow::array::{Array, StringArray};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::logical_expr::{CreateExternalTable, DdlStatement, LogicalPlan};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const EXTERNAL_TABLES_TABLE: &str = "__external_tables";
+
+// OPTIONS 中指定存储的 key，LOCATION 是存储中的路径
+const STORAGE_OPTION: &str = "storage";
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 执行服务端（Flight/HTTP）提交的 `CREATE EXTERNAL TABLE`，成功后保存定义，
+    /// 重启后用 restore_external_tables 重新创建，不需要重新部署
+    ///
+    /// 定义只有开启 WAL（enable_wal）时才会持久化：建表语句和 __external_tables 的写入都记录在 WAL 中，
+    /// recovery 时重新创建；没有开启 WAL 时重启后定义会丢失
+    ///
+    /// principal 需要在 table_admins 或 trusted_principals 中；
+    /// `OPTIONS ('storage' 'minio')` 表示 LOCATION 是存储 minio 中的路径
+    pub async fn create_external_table_from_frontend(
+        &self,
+        sql: &str,
+        principal: Option<&str>,
+    ) -> Result<String> {
+        let allowed = {
+            let guard = self.guard.read().unwrap();
+            principal.is_some_and(|p| {
+                guard
+                    .table_admins
+                    .iter()
+                    .chain(&guard.trusted_principals)
+                    .any(|admin| admin == p)
+            })
+        };
+        if !allowed {
+            return Err(anyhow::anyhow!(
+                "{} is not allowed to create external tables",
+                principal.unwrap_or("anonymous")
+            ));
+        }
+
+        let name = self.create_external_table(sql).await?;
+        self.run_migrations().await?;
+        self.execute(&format!(
+            "INSERT INTO {} VALUES ({}, {}, {}, {})",
+            EXTERNAL_TABLES_TABLE,
+            quote_literal(&name),
+            quote_literal(sql),
+            quote_literal(principal.unwrap_or_default()),
+            Utc::now().timestamp_millis()
+        ))
+        .await?;
+        Ok(name)
+    }
+
+    /// 重新创建保存的外部表，已经存在的表跳过，返回创建的表
+    pub async fn restore_external_tables(&self) -> Result<Vec<String>> {
+        if !self.ctx.table_exist(EXTERNAL_TABLES_TABLE)? {
+            return Ok(Vec::new());
+        }
+        let batches = self
+            .query_to_batches(&format!(
+                "SELECT name, statement FROM {} ORDER BY created_at",
+                EXTERNAL_TABLES_TABLE
+            ))
+            .await?;
+        let mut restored = Vec::new();
+        for batch in batches {
+            let names = string_column(&batch, 0)?;
+            let statements = string_column(&batch, 1)?;
+            for row in 0..batch.num_rows() {
+                if self.ctx.table_exist(names.value(row))? {
+                    continue;
+                }
+                restored.push(self.create_external_table(statements.value(row)).await?);
+            }
+        }
+        Ok(restored)
+    }
+
+    // 只接受单条 CREATE EXTERNAL TABLE，通过 execute_logged 执行，WAL 中记录原始语句
+    async fn create_external_table(&self, sql: &str) -> Result<String> {
+        let plan = self
+            .ctx
+            .state()
+            .create_logical_plan(sql)
+            .await
+            .map_err(|e| anyhow::anyhow!("Query error: {}", e))?;
+        let LogicalPlan::Ddl(DdlStatement::CreateExternalTable(cmd)) = plan else {
+            return Err(anyhow::anyhow!("Only CREATE EXTERNAL TABLE is allowed"));
+        };
+        let cmd = self.resolve_external_table(cmd)?;
+        let name = cmd.name.table().to_string();
+        self.execute_logged(
+            sql,
+            LogicalPlan::Ddl(DdlStatement::CreateExternalTable(cmd)),
+        )
+        .await?;
+        Ok(name)
+    }
+
+    // 把 storage 选项换成存储中的完整地址，recovery 重放建表语句时也需要
+    pub(crate) fn resolve_external_table(
+        &self,
+        mut cmd: CreateExternalTable,
+    ) -> Result<CreateExternalTable> {
+        if let Some(storage) = cmd.options.remove(STORAGE_OPTION) {
+            cmd.location = self.storage_path(&storage, &cmd.location)?;
+        }
+        reject_control_chars(&cmd.location, "location")?;
+        Ok(cmd)
+    }
+
+    #[cfg(feature = "storage")]
+    fn storage_path(&self, storage: &str, path: &str) -> Result<String> {
+        self.storage_location(storage, path)
+    }
+
+    #[cfg(not(feature = "storage"))]
+    fn storage_path(&self, storage: &str, _path: &str) -> Result<String> {
+        Err(anyhow::anyhow!(
+            "Storage {} requested but the storage feature is disabled",
+            storage
+        ))
+    }
+}
+
+fn string_column(batch: &RecordBatch, index: usize) -> Result<&StringArray> {
+    batch
+        .column(index)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .filter(|column| column.null_count() == 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid {} table", EXTERNAL_TABLES_TABLE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GuardConfig;
+    use crate::wal::WalConfig;
+
+    #[tokio::test]
+    async fn test_create_external_table_from_frontend() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let csv = dir.path().join("prices.csv");
+        std::fs::write(&csv, "symbol,price\nAAPL,100\nMSFT,200\n")?;
+        let sql = format!(
+            "CREATE EXTERNAL TABLE prices (symbol VARCHAR, price BIGINT) \
+             STORED AS CSV LOCATION '{}' OPTIONS ('format.has_header' 'true')",
+            csv.display()
+        );
+
+        let db = DB::<()>::new("test_db");
+        db.set_statement_guard(GuardConfig {
+            table_admins: vec!["analyst".to_string()],
+            ..Default::default()
+        });
+        assert!(db
+            .create_external_table_from_frontend(&sql, Some("guest"))
+            .await
+            .is_err());
+        assert!(db
+            .create_external_table_from_frontend("DROP TABLE x", Some("analyst"))
+            .await
+            .is_err());
+        assert_eq!(
+            db.create_external_table_from_frontend(&sql, Some("analyst"))
+                .await?,
+            "prices"
+        );
+        assert_eq!(db.ctx.table("prices").await?.count().await?, 2);
+
+        // 模拟重启：内部表从备份恢复，外部表需要重新创建
+        db.ctx.deregister_table("prices")?;
+        assert_eq!(db.restore_external_tables().await?, vec!["prices"]);
+        assert!(db.restore_external_tables().await?.is_empty());
+        assert_eq!(db.ctx.table("prices").await?.count().await?, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_external_table_survives_recovery() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let csv = dir.path().join("prices.csv");
+        std::fs::write(&csv, "symbol,price\nAAPL,100\nMSFT,200\n")?;
+        let sql = format!(
+            "CREATE EXTERNAL TABLE prices (symbol VARCHAR, price BIGINT) \
+             STORED AS CSV LOCATION '{}' OPTIONS ('format.has_header' 'true')",
+            csv.display()
+        );
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(WalConfig::new(dir.path().join("wal")))?;
+        db.set_statement_guard(GuardConfig {
+            table_admins: vec!["analyst".to_string()],
+            ..Default::default()
+        });
+        db.create_external_table_from_frontend(&sql, Some("analyst"))
+            .await?;
+        // 同名的表创建失败，不会写入 WAL
+        assert!(db
+            .create_external_table_from_frontend(&sql, Some("analyst"))
+            .await
+            .is_err());
+        drop(db);
+
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(WalConfig::new(dir.path().join("wal")))?;
+        let report = db.recovery().await?;
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(db.ctx.table("prices").await?.count().await?, 2);
+        assert!(db.restore_external_tables().await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,530 @@
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use datafusion::arrow::array::{
+    Array, ArrayRef, AsArray, Decimal128Array, Float64Array, Int32Array, StringArray,
+};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Decimal128Type, Float64Type, Int64Type};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::CatalogProviderList;
+use datafusion::common::{exec_err, plan_err, Result, TableReference};
+use datafusion::execution::TaskContext;
+use datafusion::logical_expr::{ColumnarValue, ScalarUDF, ScalarUDFImpl, Signature, Volatility};
+use datafusion::physical_plan::collect;
+use datafusion::prelude::SessionContext;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// 汇率统一换算成这个精度
+const RATE_SCALE: i8 = 10;
+
+// ISO 4217 中小数位不是 2 的币种
+const MINOR_UNITS: &[(&str, i32)] = &[
+    ("BIF", 0),
+    ("CLP", 0),
+    ("DJF", 0),
+    ("GNF", 0),
+    ("ISK", 0),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("UGX", 0),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+    ("BHD", 3),
+    ("IQD", 3),
+    ("JOD", 3),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("OMR", 3),
+    ("TND", 3),
+    ("CLF", 4),
+    ("UYW", 4),
+];
+
+// 常见的 2 位小数币种，不在列表中的代码返回 NULL
+const TWO_DIGIT_CURRENCIES: &[&str] = &[
+    "AED", "ARS", "AUD", "BDT", "BGN", "BRL", "CAD", "CHF", "CNY", "COP", "CZK", "DKK", "EGP",
+    "EUR", "GBP", "HKD", "HUF", "IDR", "ILS", "INR", "KES", "LKR", "MAD", "MXN", "MYR", "NGN",
+    "NOK", "NZD", "PEN", "PHP", "PKR", "PLN", "QAR", "RON", "RUB", "SAR", "SEK", "SGD", "THB",
+    "TRY", "TWD", "UAH", "USD", "ZAR",
+];
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 注册 round_bankers、fx_convert、currency_minor_units，DB::new 时自动调用
+    pub fn register_finance_functions(&self) {
+        register_finance_functions(&self.ctx);
+    }
+}
+
+pub(crate) fn register_finance_functions(ctx: &SessionContext) {
+    ctx.register_udf(ScalarUDF::from(RoundBankers::new()));
+    ctx.register_udf(ScalarUDF::from(CurrencyMinorUnits::new()));
+    ctx.register_udf(ScalarUDF::from(FxConvert {
+        signature: Signature::user_defined(Volatility::Volatile),
+        tables: MemoryTableReader::new(ctx),
+    }));
+}
+
+/// 除以 10^digits，按银行家舍入（四舍六入五取偶）
+pub fn div_round_half_even(value: i128, digits: u32) -> Option<i128> {
+    if digits == 0 {
+        return Some(value);
+    }
+    if digits > 38 {
+        return Some(0);
+    }
+    let divisor = 10i128.pow(digits);
+    let (q, r) = (value / divisor, value % divisor);
+    let twice = r.unsigned_abs() * 2;
+    let round_away = twice > divisor as u128 || (twice == divisor as u128 && q % 2 != 0);
+    if round_away {
+        q.checked_add(value.signum())
+    } else {
+        Some(q)
+    }
+}
+
+// 数值参数：decimal 保持原类型，其他转换成 DOUBLE
+fn coerce_amount(data_type: &DataType) -> Result<DataType> {
+    match data_type {
+        DataType::Decimal128(_, _) => Ok(data_type.clone()),
+        t if t.is_numeric() || t.is_null() => Ok(DataType::Float64),
+        t => plan_err!("expected a numeric argument, got {}", t),
+    }
+}
+
+fn to_arrays(args: &[ColumnarValue], number_rows: usize) -> Result<Vec<ArrayRef>> {
+    args.iter()
+        .map(|arg| arg.clone().into_array(number_rows))
+        .collect()
+}
+
+/// round_bankers(x, scale)：保留 scale 位小数，尾数正好是 5 时舍入到偶数，结果类型和 x 相同
+#[derive(Debug)]
+struct RoundBankers {
+    signature: Signature,
+}
+
+impl RoundBankers {
+    fn new() -> Self {
+        Self {
+            signature: Signature::user_defined(Volatility::Immutable),
+        }
+    }
+}
+
+impl ScalarUDFImpl for RoundBankers {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "round_bankers"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        Ok(arg_types[0].clone())
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        if arg_types.len() != 2 {
+            return plan_err!("round_bankers expects 2 arguments");
+        }
+        Ok(vec![coerce_amount(&arg_types[0])?, DataType::Int64])
+    }
+
+    fn invoke_batch(&self, args: &[ColumnarValue], number_rows: usize) -> Result<ColumnarValue> {
+        let arrays = to_arrays(args, number_rows)?;
+        let digits = arrays[1].as_primitive::<Int64Type>();
+        let result: ArrayRef = match arrays[0].data_type() {
+            DataType::Decimal128(precision, scale) => {
+                let values = arrays[0].as_primitive::<Decimal128Type>();
+                let rounded = values
+                    .iter()
+                    .zip(digits.iter())
+                    .map(|(value, digits)| match (value, digits) {
+                        (Some(value), Some(digits)) => {
+                            let drop = (*scale as i64 - digits).max(0) as u32;
+                            if drop > 38 {
+                                return Ok(Some(0));
+                            }
+                            div_round_half_even(value, drop)
+                                .and_then(|q| q.checked_mul(10i128.pow(drop)))
+                                .map(Some)
+                                .ok_or_else(|| {
+                                    datafusion::error::DataFusionError::Execution(
+                                        "round_bankers overflow".to_string(),
+                                    )
+                                })
+                        }
+                        _ => Ok(None),
+                    })
+                    .collect::<Result<Decimal128Array>>()?;
+                Arc::new(rounded.with_precision_and_scale(*precision, *scale)?)
+            }
+            _ => {
+                let values = arrays[0].as_primitive::<Float64Type>();
+                Arc::new(
+                    values
+                        .iter()
+                        .zip(digits.iter())
+                        .map(|(value, digits)| {
+                            let factor = 10f64.powi(digits? as i32);
+                            Some((value? * factor).round_ties_even() / factor)
+                        })
+                        .collect::<Float64Array>(),
+                )
+            }
+        };
+        Ok(ColumnarValue::Array(result))
+    }
+}
+
+/// currency_minor_units(code)：ISO 4217 币种的小数位数，未知币种返回 NULL
+#[derive(Debug)]
+struct CurrencyMinorUnits {
+    signature: Signature,
+}
+
+impl CurrencyMinorUnits {
+    fn new() -> Self {
+        Self {
+            signature: Signature::exact(vec![DataType::Utf8], Volatility::Immutable),
+        }
+    }
+}
+
+pub fn currency_minor_units(code: &str) -> Option<i32> {
+    let code = code.trim().to_uppercase();
+    if let Some((_, units)) = MINOR_UNITS.iter().find(|(c, _)| *c == code) {
+        return Some(*units);
+    }
+    TWO_DIGIT_CURRENCIES.contains(&code.as_str()).then_some(2)
+}
+
+impl ScalarUDFImpl for CurrencyMinorUnits {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "currency_minor_units"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Int32)
+    }
+
+    fn invoke_batch(&self, args: &[ColumnarValue], number_rows: usize) -> Result<ColumnarValue> {
+        let arrays = to_arrays(args, number_rows)?;
+        let codes = arrays[0].as_string::<i32>();
+        let units: Int32Array = codes
+            .iter()
+            .map(|code| code.and_then(currency_minor_units))
+            .collect();
+        Ok(ColumnarValue::Array(Arc::new(units)))
+    }
+}
+
+/// fx_convert(amount, from, to, rate_table)：按汇率表换算金额
+///
+/// 汇率表需要是内存表，包含 from_currency、to_currency、rate 三列，
+/// 只有反方向的汇率时取倒数，找不到汇率时返回 NULL。
+/// decimal 金额的结果保持原来的小数位数，按银行家舍入
+#[derive(Debug)]
+struct FxConvert {
+    signature: Signature,
+    tables: MemoryTableReader,
+}
+
+/// 在 UDF 中同步读取内存表，比如汇率表和节假日表
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryTableReader {
+    catalogs: Arc<dyn CatalogProviderList>,
+    default_catalog: String,
+    default_schema: String,
+}
+
+impl MemoryTableReader {
+    pub(crate) fn new(ctx: &SessionContext) -> Self {
+        let state = ctx.state();
+        let options = &state.config().options().catalog;
+        Self {
+            catalogs: state.catalog_list().clone(),
+            default_catalog: options.default_catalog.clone(),
+            default_schema: options.default_schema.clone(),
+        }
+    }
+
+    pub(crate) fn read(&self, table: &str) -> Result<Vec<RecordBatch>> {
+        let reference =
+            TableReference::from(table).resolve(&self.default_catalog, &self.default_schema);
+        let Some(schema) = self
+            .catalogs
+            .catalog(&reference.catalog)
+            .and_then(|catalog| catalog.schema(&reference.schema))
+        else {
+            return exec_err!("table {} not found", table);
+        };
+        // 内存表的 scan 不涉及 IO，可以在这里同步等待
+        futures::executor::block_on(async {
+            let Some(provider) = schema.table(&reference.table).await? else {
+                return exec_err!("table {} not found", table);
+            };
+            if !is_memory_table(provider.as_ref()) {
+                return exec_err!("table {} must be an in-memory table", table);
+            }
+            let ctx = SessionContext::new();
+            let plan = provider.scan(&ctx.state(), None, &[], None).await?;
+            collect(plan, Arc::new(TaskContext::default())).await
+        })
+    }
+}
+
+// (from, to) -> 按 RATE_SCALE 缩放的汇率
+type Rates = HashMap<(String, String), i128>;
+
+impl FxConvert {
+    fn load_rates(&self, table: &str) -> Result<Rates> {
+        let mut rates = Rates::new();
+        for batch in self.tables.read(table)? {
+            read_rates(&batch, &mut rates)?;
+        }
+        Ok(rates)
+    }
+}
+
+fn read_rates(batch: &RecordBatch, rates: &mut Rates) -> Result<()> {
+    let column = |name: &str| {
+        batch.column_by_name(name).cloned().ok_or_else(|| {
+            datafusion::error::DataFusionError::Execution(format!(
+                "rate table is missing column {}",
+                name
+            ))
+        })
+    };
+    let from = cast(&column("from_currency")?, &DataType::Utf8)?;
+    let to = cast(&column("to_currency")?, &DataType::Utf8)?;
+    let rate = cast(&column("rate")?, &DataType::Decimal128(38, RATE_SCALE))?;
+    let (from, to) = (from.as_string::<i32>(), to.as_string::<i32>());
+    let rate = rate.as_primitive::<Decimal128Type>();
+    for i in 0..batch.num_rows() {
+        if from.is_null(i) || to.is_null(i) || rate.is_null(i) {
+            continue;
+        }
+        rates.insert(
+            (from.value(i).to_uppercase(), to.value(i).to_uppercase()),
+            rate.value(i),
+        );
+    }
+    Ok(())
+}
+
+fn find_rate(rates: &Rates, from: &str, to: &str) -> Option<i128> {
+    let (from, to) = (from.to_uppercase(), to.to_uppercase());
+    if from == to {
+        return Some(10i128.pow(RATE_SCALE as u32));
+    }
+    if let Some(rate) = rates.get(&(from.clone(), to.clone())) {
+        return Some(*rate);
+    }
+    // 反方向的汇率取倒数
+    let inverse = *rates.get(&(to, from))?;
+    if inverse == 0 {
+        return None;
+    }
+    let one = 10i128.pow(2 * RATE_SCALE as u32);
+    let q = one / inverse;
+    let r = one % inverse;
+    let twice = r * 2;
+    Some(if twice > inverse || (twice == inverse && q % 2 != 0) {
+        q + 1
+    } else {
+        q
+    })
+}
+
+impl ScalarUDFImpl for FxConvert {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "fx_convert"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        Ok(match &arg_types[0] {
+            DataType::Decimal128(_, scale) => DataType::Decimal128(38, *scale),
+            _ => DataType::Float64,
+        })
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        if arg_types.len() != 4 {
+            return plan_err!("fx_convert expects 4 arguments");
+        }
+        Ok(vec![
+            coerce_amount(&arg_types[0])?,
+            DataType::Utf8,
+            DataType::Utf8,
+            DataType::Utf8,
+        ])
+    }
+
+    fn invoke_batch(&self, args: &[ColumnarValue], number_rows: usize) -> Result<ColumnarValue> {
+        let arrays = to_arrays(args, number_rows)?;
+        let from = arrays[1].as_string::<i32>();
+        let to = arrays[2].as_string::<i32>();
+        let tables: &StringArray = arrays[3].as_string::<i32>();
+
+        let mut loaded: HashMap<&str, Rates> = HashMap::new();
+        let mut rates = Vec::with_capacity(number_rows);
+        for i in 0..number_rows {
+            if from.is_null(i) || to.is_null(i) || tables.is_null(i) {
+                rates.push(None);
+                continue;
+            }
+            let table = tables.value(i);
+            if !loaded.contains_key(table) {
+                loaded.insert(table, self.load_rates(table)?);
+            }
+            rates.push(find_rate(&loaded[table], from.value(i), to.value(i)));
+        }
+
+        let result: ArrayRef = match arrays[0].data_type() {
+            DataType::Decimal128(_, scale) => {
+                let amounts = arrays[0].as_primitive::<Decimal128Type>();
+                let converted = amounts
+                    .iter()
+                    .zip(rates)
+                    .map(|(amount, rate)| match (amount, rate) {
+                        (Some(amount), Some(rate)) => amount
+                            .checked_mul(rate)
+                            .and_then(|v| div_round_half_even(v, RATE_SCALE as u32))
+                            .map(Some)
+                            .ok_or_else(|| {
+                                datafusion::error::DataFusionError::Execution(
+                                    "fx_convert overflow".to_string(),
+                                )
+                            }),
+                        _ => Ok(None),
+                    })
+                    .collect::<Result<Decimal128Array>>()?;
+                Arc::new(converted.with_precision_and_scale(38, *scale)?)
+            }
+            _ => {
+                let amounts = arrays[0].as_primitive::<Float64Type>();
+                let factor = 10f64.powi(RATE_SCALE as i32);
+                Arc::new(
+                    amounts
+                        .iter()
+                        .zip(rates)
+                        .map(|(amount, rate)| Some(amount? * rate? as f64 / factor))
+                        .collect::<Float64Array>(),
+                )
+            }
+        };
+        Ok(ColumnarValue::Array(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::util::display::array_value_to_string;
+
+    async fn values(db: &DB<()>, sql: &str) -> anyhow::Result<Vec<String>> {
+        let batches = db.query_to_batches(sql).await?;
+        let mut values = Vec::new();
+        for batch in batches {
+            for i in 0..batch.num_rows() {
+                values.push(array_value_to_string(batch.column(0), i)?);
+            }
+        }
+        Ok(values)
+    }
+
+    #[test]
+    fn test_div_round_half_even() {
+        assert_eq!(div_round_half_even(125, 1), Some(12));
+        assert_eq!(div_round_half_even(135, 1), Some(14));
+        assert_eq!(div_round_half_even(-125, 1), Some(-12));
+        assert_eq!(div_round_half_even(-126, 1), Some(-13));
+        assert_eq!(div_round_half_even(124, 1), Some(12));
+    }
+
+    #[tokio::test]
+    async fn test_finance_functions() -> anyhow::Result<()> {
+        let db = DB::<()>::new("test_db");
+        assert_eq!(
+            values(
+                &db,
+                "SELECT round_bankers(CAST(v AS DECIMAL(10, 3)), 2) FROM (VALUES ('2.345'), ('2.355'), ('-2.345'), (NULL)) AS t(v)"
+            )
+            .await?,
+            vec!["2.340", "2.360", "-2.340", ""]
+        );
+        assert_eq!(
+            values(&db, "SELECT round_bankers(2.5, 0)").await?,
+            vec!["2.0"]
+        );
+        assert_eq!(
+            values(
+                &db,
+                "SELECT currency_minor_units(c) FROM (VALUES ('usd'), ('JPY'), ('KWD'), ('XXX')) AS t(c)"
+            )
+            .await?,
+            vec!["2", "0", "3", ""]
+        );
+
+        db.execute("CREATE TABLE rates (from_currency VARCHAR, to_currency VARCHAR, rate DOUBLE)")
+            .await?;
+        db.execute("INSERT INTO rates VALUES ('USD', 'EUR', 0.9), ('EUR', 'JPY', 160)")
+            .await?;
+        db.execute("CREATE TABLE ledger (amount DECIMAL(18, 2), currency VARCHAR)")
+            .await?;
+        db.execute(
+            "INSERT INTO ledger VALUES (CAST('100.05' AS DECIMAL(18, 2)), 'USD'), (CAST('10' AS DECIMAL(18, 2)), 'JPY'), (CAST('1' AS DECIMAL(18, 2)), 'GBP')",
+        )
+        .await?;
+        assert_eq!(
+            values(
+                &db,
+                "SELECT fx_convert(amount, currency, 'EUR', 'rates') FROM ledger"
+            )
+            .await?,
+            vec!["90.04", "0.06", ""]
+        );
+        assert_eq!(
+            values(&db, "SELECT fx_convert(2.5, 'usd', 'eur', 'rates')").await?,
+            vec!["2.25"]
+        );
+        assert!(
+            values(&db, "SELECT fx_convert(1.0, 'USD', 'EUR', 'missing')")
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::{GuardAction, GuardConfig};
+use crate::events::CacheEvent;
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::datasource::source_as_provider;
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::{ObjectType, Statement};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// 语句检查的结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuardVerdict {
+    Allow,
+    // 需要确认，附带原因
+    Confirm(String),
+    Reject(String),
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn set_statement_guard(&self, config: GuardConfig) {
+        *self.guard.write().unwrap() = config;
+    }
+
+    /// 检查服务端提交的语句，多条语句时返回最严格的结果
+    /// principal 在 trusted_principals 中时直接放行
+    pub async fn check_statement(
+        &self,
+        sql: &str,
+        principal: Option<&str>,
+    ) -> Result<GuardVerdict> {
+        let config = self.guard.read().unwrap().clone();
+        if principal.is_some_and(|p| config.trusted_principals.iter().any(|t| t == p)) {
+            return Ok(GuardVerdict::Allow);
+        }
+
+        let statements = DFParser::parse_sql(sql).map_err(|e| anyhow::anyhow!("{}", e))?;
+        let mut verdict = GuardVerdict::Allow;
+        for statement in statements {
+            let (action, reason) = match &statement {
+                DFStatement::Statement(s) => match s.as_ref() {
+                    Statement::Drop {
+                        object_type: ObjectType::Table,
+                        names,
+                        ..
+                    } => (
+                        config.drop_table,
+                        format!("DROP TABLE {}", join_names(names)),
+                    ),
+                    Statement::Delete(delete) if delete.selection.is_none() => {
+                        (config.full_table_delete, "DELETE without WHERE".to_string())
+                    }
+                    Statement::Truncate { table_names, .. } => (
+                        config.full_table_delete,
+                        format!(
+                            "TRUNCATE {}",
+                            table_names
+                                .iter()
+                                .map(|t| t.name.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    ),
+                    Statement::Query(query) if query.limit.is_none() && query.fetch.is_none() => {
+                        let plan = self.ctx.state().statement_to_plan(statement).await?;
+                        match self
+                            .largest_scanned_table(&plan, config.max_unbounded_select_rows)
+                            .await?
+                        {
+                            Some((table, rows)) => (
+                                config.unbounded_select,
+                                format!("SELECT without LIMIT on {} ({} rows)", table, rows),
+                            ),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            verdict = stricter(verdict, action, reason);
+        }
+        Ok(verdict)
+    }
+
+    /// 检查通过后执行；需要确认的语句只有 confirmed 为 true 时才执行
+    /// 被拒绝的语句发送 QuotaExceeded 事件
+    pub async fn execute_guarded(
+        &self,
+        sql: &str,
+        principal: Option<&str>,
+        confirmed: bool,
+    ) -> Result<Vec<RecordBatch>> {
+        match self.check_statement(sql, principal).await? {
+            GuardVerdict::Reject(reason) => {
+                self.emit(CacheEvent::QuotaExceeded {
+                    table: String::new(),
+                    message: format!("statement rejected: {}", reason),
+                });
+                Err(anyhow::anyhow!("Statement rejected: {}", reason))
+            }
+            GuardVerdict::Confirm(reason) if !confirmed => Err(anyhow::anyhow!(
+                "Statement requires confirmation: {}",
+                reason
+            )),
+            _ => self.query_to_batches(sql).await,
+        }
+    }
+
+    // 计划中扫描的行数超过 threshold 的最大的表
+    async fn largest_scanned_table(
+        &self,
+        plan: &LogicalPlan,
+        threshold: usize,
+    ) -> Result<Option<(String, usize)>> {
+        let mut scans = Vec::new();
+        plan.apply(|node| {
+            if let LogicalPlan::TableScan(scan) = node {
+                scans.push((scan.table_name.to_string(), scan.source.clone()));
+            }
+            Ok(TreeNodeRecursion::Continue)
+        })?;
+
+        let state = self.ctx.state();
+        let mut largest: Option<(String, usize)> = None;
+        for (table, source) in scans {
+            let provider = source_as_provider(&source)?;
+            let rows = provider
+                .scan(&state, None, &[], None)
+                .await?
+                .statistics()?
+                .num_rows
+                .get_value()
+                .copied()
+                .unwrap_or(0);
+            if rows > threshold && largest.as_ref().map_or(true, |(_, r)| rows > *r) {
+                largest = Some((table, rows));
+            }
+        }
+        Ok(largest)
+    }
+}
+
+fn join_names<T: ToString>(names: &[T]) -> String {
+    names
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn stricter(current: GuardVerdict, action: GuardAction, reason: String) -> GuardVerdict {
+    match (current, action) {
+        (GuardVerdict::Reject(r), _) => GuardVerdict::Reject(r),
+        (_, GuardAction::Reject) => GuardVerdict::Reject(reason),
+        (GuardVerdict::Confirm(r), _) => GuardVerdict::Confirm(r),
+        (_, GuardAction::Confirm) => GuardVerdict::Confirm(reason),
+        (GuardVerdict::Allow, GuardAction::Allow) => GuardVerdict::Allow,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_statement() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3)").await?;
+        db.set_statement_guard(GuardConfig {
+            drop_table: GuardAction::Reject,
+            max_unbounded_select_rows: 2,
+            trusted_principals: vec!["admin".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            db.check_statement("SELECT * FROM t LIMIT 10", None).await?,
+            GuardVerdict::Allow
+        );
+        assert!(matches!(
+            db.check_statement("SELECT * FROM t", None).await?,
+            GuardVerdict::Confirm(_)
+        ));
+        assert!(matches!(
+            db.check_statement("DELETE FROM t", None).await?,
+            GuardVerdict::Confirm(_)
+        ));
+        assert!(matches!(
+            db.check_statement("SELECT 1; DROP TABLE t", None).await?,
+            GuardVerdict::Reject(_)
+        ));
+        assert_eq!(
+            db.check_statement("DROP TABLE t", Some("admin")).await?,
+            GuardVerdict::Allow
+        );
+
+        assert!(db
+            .execute_guarded("SELECT * FROM t", None, false)
+            .await
+            .is_err());
+        let batches = db.execute_guarded("SELECT * FROM t", None, true).await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+        let mut events = db.subscribe();
+        assert!(db
+            .execute_guarded("DROP TABLE t", None, true)
+            .await
+            .is_err());
+        assert!(matches!(
+            events.try_recv()?,
+            CacheEvent::QuotaExceeded { .. }
+        ));
+        Ok(())
+    }
+}
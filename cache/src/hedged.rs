@@ -0,0 +1,198 @@
+use async_trait::async_trait;
+use futures::stream::{BoxStream, FuturesUnordered};
+use futures::{Future, StreamExt};
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOpts,
+    PutOptions, PutPayload, PutResult, Result,
+};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 同一份数据在多个存储中有副本时（比如 OSS 主存储 + S3 备份）的读取封装
+///
+/// 读请求先发给主存储，超过 hedge_after 还没返回或者出错时，再向下一个副本发起请求，
+/// 哪个先成功就用哪个；写请求只发给主存储
+#[derive(Debug)]
+pub struct HedgedStore {
+    // 第一个是主存储
+    stores: Vec<Arc<dyn ObjectStore>>,
+    hedge_after: Duration,
+}
+
+impl HedgedStore {
+    pub fn new(
+        primary: Arc<dyn ObjectStore>,
+        replicas: Vec<Arc<dyn ObjectStore>>,
+        hedge_after: Duration,
+    ) -> Self {
+        let mut stores = vec![primary];
+        stores.extend(replicas);
+        Self {
+            stores,
+            hedge_after,
+        }
+    }
+
+    fn primary(&self) -> &Arc<dyn ObjectStore> {
+        &self.stores[0]
+    }
+
+    async fn hedged<T, F, Fut>(&self, op: F) -> Result<T>
+    where
+        F: Fn(Arc<dyn ObjectStore>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut pending = FuturesUnordered::new();
+        pending.push(op(self.stores[0].clone()));
+        let mut next = 1;
+        let mut last_error = None;
+
+        loop {
+            let can_hedge = next < self.stores.len();
+            tokio::select! {
+                result = pending.next() => match result {
+                    Some(Ok(value)) => return Ok(value),
+                    Some(Err(e)) => {
+                        last_error = Some(e);
+                        // 出错时立即尝试下一个副本
+                        if can_hedge {
+                            pending.push(op(self.stores[next].clone()));
+                            next += 1;
+                        } else if pending.is_empty() {
+                            return Err(last_error.unwrap());
+                        }
+                    }
+                    None => return Err(last_error.unwrap()),
+                },
+                _ = tokio::time::sleep(self.hedge_after), if can_hedge => {
+                    pending.push(op(self.stores[next].clone()));
+                    next += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Display for HedgedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HedgedStore(")?;
+        for (i, store) in self.stores.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", store)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[async_trait]
+impl ObjectStore for HedgedStore {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        self.primary().put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        self.primary().put_multipart_opts(location, opts).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.hedged(|store| {
+            let options = options.clone();
+            async move { store.get_opts(location, options).await }
+        })
+        .await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.primary().delete(location).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.primary().list(prefix)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.hedged(|store| async move { store.list_with_delimiter(prefix).await })
+            .await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.primary().copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.primary().copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use object_store::throttle::{ThrottleConfig, ThrottledStore};
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_hedged_read_when_primary_slow() -> anyhow::Result<()> {
+        let path = Path::from("data/a.csv");
+        let primary = InMemory::new();
+        primary.put(&path, PutPayload::from("primary")).await?;
+        let primary = ThrottledStore::new(
+            primary,
+            ThrottleConfig {
+                wait_get_per_call: Duration::from_secs(5),
+                ..Default::default()
+            },
+        );
+        let replica = InMemory::new();
+        replica.put(&path, PutPayload::from("replica")).await?;
+
+        let store = HedgedStore::new(
+            Arc::new(primary),
+            vec![Arc::new(replica)],
+            Duration::from_millis(20),
+        );
+        let start = Instant::now();
+        let bytes = store.get(&path).await?.bytes().await?;
+        assert_eq!(bytes.as_ref(), b"replica");
+        assert!(start.elapsed() < Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fallback_when_primary_fails() -> anyhow::Result<()> {
+        let path = Path::from("data/a.csv");
+        let replica = InMemory::new();
+        replica.put(&path, PutPayload::from("replica")).await?;
+
+        let store = HedgedStore::new(
+            Arc::new(InMemory::new()),
+            vec![Arc::new(replica)],
+            Duration::from_secs(10),
+        );
+        let bytes = store.get(&path).await?.bytes().await?;
+        assert_eq!(bytes.as_ref(), b"replica");
+
+        // 所有副本都失败时返回最后一个错误
+        let missing = store.get(&Path::from("data/missing.csv")).await;
+        assert!(matches!(missing, Err(object_store::Error::NotFound { .. })));
+
+        // 写只会写到主存储
+        store.put(&path, PutPayload::from("new")).await?;
+        let bytes = store.primary().get(&path).await?.bytes().await?;
+        assert_eq!(bytes.as_ref(), b"new");
+        Ok(())
+    }
+}
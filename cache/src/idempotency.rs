@@ -0,0 +1,246 @@
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::array::BooleanArray;
+use datafusion::arrow::compute::filter_record_batch;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::array_value_to_string;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// 表的幂等键配置，Kafka 重放、HTTP 重试时相同的键只会写入一次
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdempotencyConfig {
+    // 行级别的幂等键列，为 None 时只按请求级别的键去重
+    pub column: Option<String>,
+    // 键在这段时间内有效，超过之后相同的键会再次写入
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    // 最多记住的键数量，超过后淘汰最早的键
+    #[serde(default = "default_max_keys")]
+    pub max_keys: usize,
+    // 记住的键大约占用的内存（字节），超过后同样淘汰最早的键
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+fn default_window_secs() -> u64 {
+    3600
+}
+
+fn default_max_keys() -> usize {
+    1_000_000
+}
+
+fn default_max_bytes() -> usize {
+    256 * 1024 * 1024
+}
+
+// 一个键在 seen 和 order 中各存一份
+fn key_bytes(key: &str) -> usize {
+    2 * (key.len() + std::mem::size_of::<String>() + std::mem::size_of::<Instant>())
+}
+
+// 见过的幂等键，按写入顺序淘汰
+#[derive(Debug)]
+pub(crate) struct DedupLedger {
+    config: IdempotencyConfig,
+    seen: HashMap<String, Instant>,
+    order: VecDeque<(String, Instant)>,
+    // order 中所有键的 key_bytes 之和
+    bytes: usize,
+    // 已经预留、正在写入的请求级别幂等键
+    pending: HashSet<String>,
+}
+
+impl DedupLedger {
+    fn new(config: IdempotencyConfig) -> Self {
+        Self {
+            config,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            bytes: 0,
+            pending: HashSet::new(),
+        }
+    }
+
+    // 键在窗口内见过
+    fn contains(&mut self, key: &str, now: Instant) -> bool {
+        self.evict(now, 0);
+        self.seen.contains_key(key)
+    }
+
+    // 键没有见过也没有正在写入时预留，预留成功返回 true
+    fn reserve(&mut self, key: &str, now: Instant) -> bool {
+        if self.contains(key, now) || self.pending.contains(key) {
+            return false;
+        }
+        self.pending.insert(key.to_string());
+        true
+    }
+
+    // 写入成功后记录键，已经记录过时什么都不做
+    fn record(&mut self, key: String, now: Instant) {
+        self.pending.remove(&key);
+        let bytes = key_bytes(&key);
+        self.evict(now, bytes);
+        if self.seen.contains_key(&key) {
+            return;
+        }
+        self.seen.insert(key.clone(), now);
+        self.order.push_back((key, now));
+        self.bytes += bytes;
+    }
+
+    // 淘汰过期的键，并且给 incoming 字节的新键腾出位置
+    fn evict(&mut self, now: Instant, incoming: usize) {
+        let window = Duration::from_secs(self.config.window_secs);
+        while let Some((key, at)) = self.order.front() {
+            let within_budget = self.order.len() < self.config.max_keys.max(1)
+                && self.bytes + incoming <= self.config.max_bytes;
+            if now.duration_since(*at) < window && within_budget {
+                break;
+            }
+            // 同一个键过期后可能被重新记录，只删除和队列中时间一致的
+            if self.seen.get(key) == Some(at) {
+                self.seen.remove(key);
+            }
+            self.bytes -= key_bytes(key);
+            self.order.pop_front();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.seen.len()
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn set_idempotency(&self, table: &str, config: IdempotencyConfig) {
+        self.dedup
+            .write()
+            .unwrap()
+            .insert(table.to_string(), DedupLedger::new(config));
+    }
+
+    /// 当前记住的幂等键数量
+    pub fn idempotency_keys(&self, table: &str) -> usize {
+        self.dedup
+            .read()
+            .unwrap()
+            .get(table)
+            .map_or(0, |ledger| ledger.len())
+    }
+
+    // 预留请求级别的幂等键（比如 HTTP 的 Idempotency-Key 头），键已经写入过或者另一个请求
+    // 正在用这个键写入时返回 false；没有配置时总是返回 true
+    pub(crate) fn reserve_request_key(&self, table: &str, key: &str) -> bool {
+        match self.dedup.write().unwrap().get_mut(table) {
+            Some(ledger) => ledger.reserve(&format!("request:{}", key), Instant::now()),
+            None => true,
+        }
+    }
+
+    // 写入失败时释放预留的键，之后的重试可以再次写入
+    pub(crate) fn release_request_key(&self, table: &str, key: &str) {
+        if let Some(ledger) = self.dedup.write().unwrap().get_mut(table) {
+            ledger.pending.remove(&format!("request:{}", key));
+        }
+    }
+
+    // 请求写入成功后记录请求级别的幂等键
+    pub(crate) fn record_request_key(&self, table: &str, key: &str) {
+        self.record_dedup_keys(table, vec![format!("request:{}", key)]);
+    }
+
+    // 去掉幂等键列已经见过的行和 batch 内重复的行，返回去重后的数据、重复的行数和新的键
+    //
+    // 新的键不会马上记录，写入成功后再通过 record_dedup_keys 记录，写入失败时重试不会被当成重复
+    pub(crate) fn dedup_rows(
+        &self,
+        table: &str,
+        batch: RecordBatch,
+    ) -> Result<(RecordBatch, usize, Vec<String>)> {
+        let mut dedup = self.dedup.write().unwrap();
+        let Some(ledger) = dedup.get_mut(table) else {
+            return Ok((batch, 0, Vec::new()));
+        };
+        let Some(column) = ledger.config.column.clone() else {
+            return Ok((batch, 0, Vec::new()));
+        };
+        let keys = batch
+            .column_by_name(&column)
+            .ok_or_else(|| anyhow::anyhow!("Idempotency key column {} not found", column))?;
+
+        let now = Instant::now();
+        let mut fresh = HashSet::new();
+        let mut keep = Vec::with_capacity(batch.num_rows());
+        for i in 0..batch.num_rows() {
+            let key = format!("row:{}", array_value_to_string(keys, i)?);
+            keep.push(!ledger.contains(&key, now) && fresh.insert(key));
+        }
+        let duplicates = keep.iter().filter(|k| !**k).count();
+        let fresh = fresh.into_iter().collect();
+        if duplicates == 0 {
+            return Ok((batch, 0, fresh));
+        }
+        Ok((
+            filter_record_batch(&batch, &BooleanArray::from(keep))?,
+            duplicates,
+            fresh,
+        ))
+    }
+
+    // 记录已经写入成功的幂等键
+    pub(crate) fn record_dedup_keys(&self, table: &str, keys: Vec<String>) {
+        if let Some(ledger) = self.dedup.write().unwrap().get_mut(table) {
+            let now = Instant::now();
+            for key in keys {
+                ledger.record(key, now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_eviction() {
+        let mut ledger = DedupLedger::new(IdempotencyConfig {
+            column: None,
+            window_secs: 10,
+            max_keys: 2,
+            max_bytes: default_max_bytes(),
+        });
+        let now = Instant::now();
+        assert!(!ledger.contains("a", now));
+        ledger.record("a".to_string(), now);
+        assert!(ledger.contains("a", now));
+        ledger.record("b".to_string(), now);
+        // 超过 max_keys，最早的 a 被淘汰
+        ledger.record("c".to_string(), now);
+        assert_eq!(ledger.len(), 2);
+        assert!(!ledger.contains("a", now));
+        // 超过窗口
+        assert!(!ledger.contains("b", now + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_ledger_memory_budget() {
+        let mut ledger = DedupLedger::new(IdempotencyConfig {
+            column: None,
+            window_secs: 10,
+            max_keys: 100,
+            max_bytes: 2 * key_bytes("key-0"),
+        });
+        let now = Instant::now();
+        for i in 0..3 {
+            ledger.record(format!("key-{}", i), now);
+        }
+        assert_eq!(ledger.len(), 2);
+        assert!(!ledger.contains("key-0", now));
+        assert!(ledger.contains("key-2", now));
+    }
+}
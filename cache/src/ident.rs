@@ -0,0 +1,142 @@
+use crate::pool::DB;
+use datafusion::common::TableReference;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::parser::Parser;
+use datafusion::sql::sqlparser::tokenizer::Token;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// 标识符的大小写规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdentifierCase {
+    // DataFusion 默认行为：没有加引号的标识符转成小写
+    #[default]
+    Normalize,
+    // 按原样匹配，Users 和 users 是两张表
+    Sensitive,
+}
+
+/// 给标识符加双引号，内部的双引号转义，生成 SQL 时表名和列名都应该经过这里
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// 生成 SQL 字符串字面量
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+// 按 SQL 的规则拆分 a.b.c，不是合法的多段标识符（比如带 - 的名字）时返回 None
+fn parse_parts(name: &str) -> Option<Vec<(String, bool)>> {
+    let dialect = GenericDialect {};
+    let mut parser = Parser::new(&dialect).try_with_sql(name).ok()?;
+    let idents = parser.parse_multipart_identifier().ok()?;
+    if parser.peek_token().token != Token::EOF || idents.is_empty() || idents.len() > 3 {
+        return None;
+    }
+    Some(
+        idents
+            .into_iter()
+            .map(|ident| (ident.value, ident.quote_style.is_some()))
+            .collect(),
+    )
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn identifier_case(&self) -> IdentifierCase {
+        let state = self.ctx.state();
+        if state
+            .config()
+            .options()
+            .sql_parser
+            .enable_ident_normalization
+        {
+            IdentifierCase::Normalize
+        } else {
+            IdentifierCase::Sensitive
+        }
+    }
+
+    /// 修改用户 SQL 和内部生成的 SQL 中标识符的大小写规则
+    pub fn set_identifier_case(&self, case: IdentifierCase) {
+        self.ctx
+            .state_ref()
+            .write()
+            .config_mut()
+            .options_mut()
+            .sql_parser
+            .enable_ident_normalization = case == IdentifierCase::Normalize;
+    }
+
+    /// 按当前的大小写规则解析 API 参数中的表名
+    ///
+    /// 没有引号的部分在 Normalize 模式下转成小写，和 SQL 中直接写表名的效果一样；
+    /// 不是合法标识符的名字（比如配置里带 - 的名字）按原样使用
+    pub fn table_ref(&self, name: &str) -> TableReference {
+        let Some(parts) = parse_parts(name) else {
+            return TableReference::bare(name);
+        };
+        let normalize = self.identifier_case() == IdentifierCase::Normalize;
+        let mut parts: Vec<String> = parts
+            .into_iter()
+            .map(|(part, quoted)| {
+                if normalize && !quoted {
+                    part.to_lowercase()
+                } else {
+                    part
+                }
+            })
+            .collect();
+        match parts.len() {
+            1 => TableReference::bare(parts.remove(0)),
+            2 => TableReference::partial(parts.remove(0), parts.remove(0)),
+            _ => TableReference::full(parts.remove(0), parts.remove(0), parts.remove(0)),
+        }
+    }
+
+    /// 表名加上引号之后的 SQL 片段，可以直接拼到内部生成的 SQL 中
+    pub fn quote_table(&self, name: &str) -> String {
+        self.table_ref(name)
+            .to_vec()
+            .iter()
+            .map(|part| quote_ident(part))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote_ident("my-table"), "\"my-table\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+        assert_eq!(quote_literal("O'Brien"), "'O''Brien'");
+    }
+
+    #[tokio::test]
+    async fn test_table_ref() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        assert_eq!(db.quote_table("Users"), "\"users\"");
+        assert_eq!(db.quote_table("\"Users\""), "\"Users\"");
+        assert_eq!(db.quote_table("system.Workload"), "\"system\".\"workload\"");
+        assert_eq!(db.quote_table("ledger-2024"), "\"ledger-2024\"");
+
+        db.set_identifier_case(IdentifierCase::Sensitive);
+        assert_eq!(db.identifier_case(), IdentifierCase::Sensitive);
+        assert_eq!(db.quote_table("Users"), "\"Users\"");
+
+        db.execute("CREATE TABLE Users (Id BIGINT)").await?;
+        db.execute("CREATE TABLE users (id BIGINT)").await?;
+        db.execute("INSERT INTO Users VALUES (1), (2)").await?;
+        let count = db
+            .query(&format!("SELECT * FROM {}", db.quote_table("Users")))
+            .await?
+            .count()
+            .await?;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+}
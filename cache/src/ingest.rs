@@ -0,0 +1,334 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow::json::ReaderBuilder;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::Utc;
+use datafusion::arrow::array::{ArrayRef, BooleanArray, Int64Array, StringArray};
+use datafusion::arrow::compute::{concat_batches, filter_record_batch};
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// 一次导入的结果
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IngestReport {
+    pub inserted: usize,
+    // 解析或约束检查失败、进入 dead-letter 表的行数
+    pub dead_lettered: usize,
+    // 幂等键已经写入过、被跳过的行数
+    pub duplicates: usize,
+}
+
+/// 每个表对应的 dead-letter 表名
+pub fn dlq_table_name(table: &str) -> String {
+    format!("{}__dlq", table)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 按行导入 JSON，失败的行写入 `{table}__dlq`（错误原因 + 原始内容），不会让整批失败
+    pub async fn insert_json_rows(&self, table: &str, rows: &[String]) -> Result<IngestReport> {
+        let (report, dead) = self.ingest_json_rows(table, rows).await?;
+        if !dead.is_empty() {
+            self.write_dead_letters(table, dead).await?;
+        }
+        Ok(report)
+    }
+
+    // 写入能解析的行，返回无法解析的行（错误原因，原始内容），由调用方写入 dead-letter 表
+    async fn ingest_json_rows(
+        &self,
+        table: &str,
+        rows: &[String],
+    ) -> Result<(IngestReport, Vec<(String, String)>)> {
+        let schema = self.ctx.table_provider(table).await?.schema();
+
+        let mut good = Vec::new();
+        let mut dead = Vec::new();
+        for row in rows {
+            match decode_json_row(schema.clone(), row) {
+                Ok(batch) => good.push(batch),
+                Err(e) => dead.push((e.to_string(), row.clone())),
+            }
+        }
+
+        let mut report = IngestReport {
+            inserted: good.len(),
+            dead_lettered: dead.len(),
+            duplicates: 0,
+        };
+        if !good.is_empty() {
+            // 持有表的写锁，同时写入的相同键只有一个能通过去重
+            let _guard = self.lock_table(table).await;
+            let (batch, duplicates, keys) =
+                self.dedup_rows(table, concat_batches(&schema, &good)?)?;
+            report.inserted -= duplicates;
+            report.duplicates = duplicates;
+            if batch.num_rows() > 0 {
+                self.append_locked(table, vec![batch]).await?;
+            }
+            self.record_dedup_keys(table, keys);
+        }
+        Ok((report, dead))
+    }
+
+    /// 带请求级别幂等键的 insert_json_rows，相同 key 的重试请求整批跳过
+    ///
+    /// 相同 key 的请求正在写入时，其他请求同样按重复跳过；写入失败后 key 会被释放，可以重试
+    pub async fn insert_json_rows_idempotent(
+        &self,
+        table: &str,
+        key: &str,
+        rows: &[String],
+    ) -> Result<IngestReport> {
+        // 先预留键，同时到达的重试只有一个能写入
+        if !self.reserve_request_key(table, key) {
+            return Ok(IngestReport {
+                duplicates: rows.len(),
+                ..Default::default()
+            });
+        }
+        match self.insert_json_rows(table, rows).await {
+            Ok(report) => {
+                self.record_request_key(table, key);
+                Ok(report)
+            }
+            Err(e) => {
+                self.release_request_key(table, key);
+                Err(e)
+            }
+        }
+    }
+
+    /// 修复表结构或上游数据后，重新导入 dead-letter 表里的行，仍然失败的行会留在 dead-letter 表
+    pub async fn reprocess_dlq(&self, table: &str) -> Result<IngestReport> {
+        let dlq = dlq_table_name(table);
+        if !self.ctx.table_exist(dlq.as_str())? {
+            return Ok(IngestReport::default());
+        }
+
+        let batches = self
+            .query_to_batches(&format!(
+                "SELECT reason, payload, failed_at FROM {} ORDER BY failed_at",
+                self.quote_table(&dlq)
+            ))
+            .await?;
+        let processed = dead_letter_rows(&batches)?;
+        let payloads: Vec<String> = processed.iter().map(|(_, p, _)| p.clone()).collect();
+        let (report, dead) = self.ingest_json_rows(table, &payloads).await?;
+
+        // 重新导入成功后，用一次替换去掉处理过的行并写回仍然失败的行；
+        // 处理期间新进入 dead-letter 表的行会保留
+        let _guard = self.lock_table(&dlq).await;
+        let provider = self.ctx.table_provider(self.table_ref(&dlq)).await?;
+        let mut pending: HashMap<(String, String, i64), usize> = HashMap::new();
+        for row in processed {
+            *pending.entry(row).or_default() += 1;
+        }
+        let mut kept = Vec::new();
+        for batch in self.ctx.read_table(provider.clone())?.collect().await? {
+            let keep: BooleanArray = dead_letter_rows(std::slice::from_ref(&batch))?
+                .into_iter()
+                .map(|row| match pending.get_mut(&row) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        Some(false)
+                    }
+                    _ => Some(true),
+                })
+                .collect();
+            kept.push(filter_record_batch(&batch, &keep)?);
+        }
+        if !dead.is_empty() {
+            kept.push(dead_letter_batch(dead)?);
+        }
+        self.rewrite_table(&dlq, provider.schema(), vec![kept], Some(&provider))?;
+        Ok(report)
+    }
+
+    async fn write_dead_letters(&self, table: &str, dead: Vec<(String, String)>) -> Result<()> {
+        let dlq = dlq_table_name(table);
+        self.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (reason VARCHAR, payload VARCHAR, failed_at BIGINT)",
+            self.quote_table(&dlq)
+        ))
+        .await?;
+        self.append_batches(&dlq, vec![dead_letter_batch(dead)?])
+            .await
+    }
+}
+
+fn dead_letter_batch(dead: Vec<(String, String)>) -> Result<RecordBatch> {
+    let now = Utc::now().timestamp_millis();
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("reason", DataType::Utf8, true),
+        Field::new("payload", DataType::Utf8, true),
+        Field::new("failed_at", DataType::Int64, true),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(dead.iter().map(|(r, _)| r))),
+        Arc::new(StringArray::from_iter_values(dead.iter().map(|(_, p)| p))),
+        Arc::new(Int64Array::from(vec![now; dead.len()])),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+// dead-letter 表的 (reason, payload, failed_at)
+fn dead_letter_rows(batches: &[RecordBatch]) -> Result<Vec<(String, String, i64)>> {
+    let mut rows = Vec::new();
+    for batch in batches {
+        let string_column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+                .ok_or_else(|| anyhow::anyhow!("{} column should be utf8", name))
+        };
+        let reasons = string_column("reason")?;
+        let payloads = string_column("payload")?;
+        let failed_at = batch
+            .column_by_name("failed_at")
+            .and_then(|c| c.as_any().downcast_ref::<Int64Array>())
+            .ok_or_else(|| anyhow::anyhow!("failed_at column should be bigint"))?;
+        for i in 0..batch.num_rows() {
+            rows.push((
+                reasons.value(i).to_string(),
+                payloads.value(i).to_string(),
+                failed_at.value(i),
+            ));
+        }
+    }
+    Ok(rows)
+}
+
+// 单独解码一行，这样一行失败不会影响其他行
+fn decode_json_row(schema: SchemaRef, row: &str) -> Result<RecordBatch> {
+    let mut decoder = ReaderBuilder::new(schema).build_decoder()?;
+    decoder.decode(row.as_bytes())?;
+    let batch = decoder
+        .flush()?
+        .ok_or_else(|| anyhow::anyhow!("Empty row"))?;
+    if batch.num_rows() != 1 {
+        return Err(anyhow::anyhow!(
+            "Expected exactly one row, got {}",
+            batch.num_rows()
+        ));
+    }
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idempotency::IdempotencyConfig;
+
+    #[tokio::test]
+    async fn test_insert_json_rows_with_dlq() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE users (id BIGINT NOT NULL, name VARCHAR)")
+            .await?;
+
+        let rows = vec![
+            r#"{"id": 1, "name": "Alice"}"#.to_string(),
+            r#"{"id": "abc", "name": "Bob"}"#.to_string(),
+            r#"{"id": 3, "name": "#.to_string(),
+            r#"{"id": 4, "name": "David"}"#.to_string(),
+        ];
+        let report = db.insert_json_rows("users", &rows).await?;
+        assert_eq!(
+            report,
+            IngestReport {
+                inserted: 2,
+                dead_lettered: 2,
+                duplicates: 0,
+            }
+        );
+
+        let count = db.query("SELECT * FROM users").await?.count().await?;
+        assert_eq!(count, 2);
+        let dlq_count = db.query("SELECT * FROM users__dlq").await?.count().await?;
+        assert_eq!(dlq_count, 2);
+
+        // 仍然无法解析的行会留在 dead-letter 表
+        let report = db.reprocess_dlq("users").await?;
+        assert_eq!(report.inserted, 0);
+        assert_eq!(report.dead_lettered, 2);
+        let dlq_count = db.query("SELECT * FROM users__dlq").await?.count().await?;
+        assert_eq!(dlq_count, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_insert() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE events (id VARCHAR NOT NULL, v BIGINT)")
+            .await?;
+        db.set_idempotency(
+            "events",
+            IdempotencyConfig {
+                column: Some("id".to_string()),
+                window_secs: 60,
+                max_keys: 100,
+                max_bytes: 1024 * 1024,
+            },
+        );
+
+        let rows = vec![
+            r#"{"id": "a", "v": 1}"#.to_string(),
+            r#"{"id": "b", "v": 2}"#.to_string(),
+            r#"{"id": "a", "v": 1}"#.to_string(),
+        ];
+        let report = db.insert_json_rows("events", &rows).await?;
+        assert_eq!((report.inserted, report.duplicates), (2, 1));
+        // Kafka 重放
+        let report = db.insert_json_rows("events", &rows[1..]).await?;
+        assert_eq!((report.inserted, report.duplicates), (0, 2));
+
+        let rows = vec![r#"{"id": "c", "v": 3}"#.to_string()];
+        db.insert_json_rows_idempotent("events", "req-1", &rows)
+            .await?;
+        let report = db
+            .insert_json_rows_idempotent("events", "req-1", &rows)
+            .await?;
+        assert_eq!(report.duplicates, 1);
+        assert_eq!(db.query("SELECT * FROM events").await?.count().await?, 3);
+        assert_eq!(db.idempotency_keys("events"), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_idempotent_requests() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE events (id VARCHAR, v BIGINT)")
+            .await?;
+        // 只按请求级别的键去重
+        let config = IdempotencyConfig {
+            column: None,
+            window_secs: 60,
+            max_keys: 100,
+            max_bytes: 1024 * 1024,
+        };
+        db.set_idempotency("events", config.clone());
+
+        let rows = vec![r#"{"id": "a", "v": 1}"#.to_string()];
+        let (first, second) = tokio::join!(
+            db.insert_json_rows_idempotent("events", "req-1", &rows),
+            db.insert_json_rows_idempotent("events", "req-1", &rows)
+        );
+        assert_eq!(first?.inserted + second?.inserted, 1);
+        assert_eq!(db.query("SELECT * FROM events").await?.count().await?, 1);
+
+        // 写入失败时释放键，重试可以写入
+        db.set_idempotency("missing", config);
+        assert!(db
+            .insert_json_rows_idempotent("missing", "req-2", &rows)
+            .await
+            .is_err());
+        db.execute("CREATE TABLE missing (id VARCHAR, v BIGINT)")
+            .await?;
+        let report = db
+            .insert_json_rows_idempotent("missing", "req-2", &rows)
+            .await?;
+        assert_eq!(report.inserted, 1);
+        Ok(())
+    }
+}
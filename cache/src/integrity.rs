@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// 快照、备份或者 WAL 的内容和写入时的校验和不一致
+///
+/// 通过 anyhow 返回，调用方可以用 `err.downcast_ref::<IntegrityError>()` 区分数据损坏和其他错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityError {
+    // 损坏的文件：WAL segment 的路径或者存储中的对象路径
+    pub segment: String,
+    // WAL 中第一条损坏记录的偏移，之前的记录都是完整的
+    pub offset: Option<u64>,
+    pub reason: String,
+}
+
+impl IntegrityError {
+    pub fn new(segment: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            segment: segment.into(),
+            offset: None,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn at(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(
+                f,
+                "{} is corrupt at offset {}: {}",
+                self.segment, offset, self.reason
+            ),
+            None => write!(f, "{} is corrupt: {}", self.segment, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32C (Castagnoli)，和 iSCSI、ext4 等使用的一样，其他工具可以直接校验
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    crc32c_append(0, bytes)
+}
+
+/// 在之前数据的 CRC32C 上接着计算，结果和对拼接后的数据计算 crc32c 一样
+pub(crate) fn crc32c_append(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in bytes {
+        crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub(crate) fn verify_crc32c(
+    segment: &str,
+    bytes: &[u8],
+    expected: u32,
+) -> std::result::Result<(), IntegrityError> {
+    let actual = crc32c(bytes);
+    if actual != expected {
+        return Err(IntegrityError::new(
+            segment,
+            format!(
+                "CRC32C mismatch, expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+        assert_eq!(crc32c_append(crc32c(b"1234"), b"56789"), 0xe3069283);
+        assert!(verify_crc32c("t.arrow", b"123456789", 0xe3069283).is_ok());
+        let err = verify_crc32c("t.arrow", b"123456780", 0xe3069283).unwrap_err();
+        assert_eq!(err.segment, "t.arrow");
+        assert!(err.to_string().starts_with("t.arrow is corrupt"));
+    }
+}
@@ -0,0 +1,480 @@
+use crate::pool::DB;
+use crate::swap::IndexedTable;
+use anyhow::Result;
+use arrow_schema::{DataType, SchemaRef};
+use async_trait::async_trait;
+use datafusion::arrow::array::{Array, StringArray, UInt32Array};
+use datafusion::arrow::compute::take_record_batch;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::common::{Constraints, ScalarValue};
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::dml::InsertOp;
+use datafusion::logical_expr::expr::InList;
+use datafusion::logical_expr::{BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_plan::insert::{DataSink, DataSinkExec};
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::metrics::MetricsSet;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan};
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Default)]
+struct IndexState {
+    batches: Vec<RecordBatch>,
+    // 每个索引列：值 -> (batch 下标, 行号)
+    postings: Vec<HashMap<String, Vec<(usize, u32)>>>,
+}
+
+#[derive(Debug)]
+struct IndexData {
+    schema: SchemaRef,
+    // 建了索引的 Utf8 列
+    columns: Vec<usize>,
+    state: RwLock<IndexState>,
+    // 转换之前表上的约束
+    constraints: Option<Constraints>,
+}
+
+impl IndexData {
+    fn new(schema: SchemaRef, columns: Vec<usize>, constraints: Option<Constraints>) -> Self {
+        let postings = vec![HashMap::new(); columns.len()];
+        Self {
+            schema,
+            columns,
+            state: RwLock::new(IndexState {
+                batches: Vec::new(),
+                postings,
+            }),
+            constraints,
+        }
+    }
+
+    fn append_all(&self, batches: impl IntoIterator<Item = RecordBatch>) {
+        let mut state = self.state.write().unwrap();
+        for batch in batches.into_iter().filter(|b| b.num_rows() > 0) {
+            self.append(&mut state, batch);
+        }
+    }
+
+    fn append(&self, state: &mut IndexState, batch: RecordBatch) {
+        let batch_index = state.batches.len();
+        for (postings, column) in state.postings.iter_mut().zip(&self.columns) {
+            let values = batch
+                .column(*column)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .expect("indexed column should be utf8");
+            for row in 0..values.len() {
+                if values.is_valid(row) {
+                    postings
+                        .entry(values.value(row).to_string())
+                        .or_default()
+                        .push((batch_index, row as u32));
+                }
+            }
+        }
+        state.batches.push(batch);
+    }
+
+    // 索引列上的等值或 IN 条件，返回 (索引列的位置, 值)
+    fn lookup_keys(&self, filter: &Expr) -> Option<(usize, Vec<String>)> {
+        // 短的 IN 列表会被优化器改写成 OR 连接的等值条件
+        if let Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        }) = filter
+        {
+            let (position, mut keys) = self.lookup_keys(left)?;
+            let (other, more) = self.lookup_keys(right)?;
+            if position != other {
+                return None;
+            }
+            keys.extend(more);
+            return Some((position, keys));
+        }
+        let (column, values) = match filter {
+            Expr::BinaryExpr(BinaryExpr {
+                left,
+                op: Operator::Eq,
+                right,
+            }) => match (left.as_ref(), right.as_ref()) {
+                (Expr::Column(c), Expr::Literal(v)) | (Expr::Literal(v), Expr::Column(c)) => {
+                    (c, vec![v])
+                }
+                _ => return None,
+            },
+            Expr::InList(InList {
+                expr,
+                list,
+                negated: false,
+            }) => {
+                let Expr::Column(c) = expr.as_ref() else {
+                    return None;
+                };
+                let values = list
+                    .iter()
+                    .map(|e| match e {
+                        Expr::Literal(v) => Some(v),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                (c, values)
+            }
+            _ => return None,
+        };
+        let position = self
+            .columns
+            .iter()
+            .position(|i| self.schema.field(*i).name() == &column.name)?;
+        let keys = values
+            .into_iter()
+            .filter_map(|v| match v {
+                ScalarValue::Utf8(Some(s)) => Some(Some(s.clone())),
+                // NULL 不会等于任何值
+                ScalarValue::Utf8(None) => None,
+                _ => Some(None),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some((position, keys))
+    }
+}
+
+/// 在指定 Utf8 列上维护倒排索引的追加表，等值和 IN 查询只读取命中的行
+/// 适合 serial_no 这类高基数的标识列
+#[derive(Debug)]
+pub struct InvertedIndexTable {
+    data: Arc<IndexData>,
+    indexed_scans: AtomicU64,
+}
+
+/// 倒排索引的统计
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvertedIndexStats {
+    // 索引列 -> 不同值的个数
+    pub distinct_keys: BTreeMap<String, usize>,
+    // 通过索引完成的扫描次数
+    pub indexed_scans: u64,
+}
+
+#[async_trait]
+impl TableProvider for InvertedIndexTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.data.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn constraints(&self) -> Option<&Constraints> {
+        self.data.constraints.as_ref()
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> datafusion::error::Result<Vec<TableProviderFilterPushDown>> {
+        Ok(vec![TableProviderFilterPushDown::Inexact; filters.len()])
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        let state = self.data.state.read().unwrap();
+        // 多个条件可以用索引时选命中行最少的
+        let hits = filters
+            .iter()
+            .filter_map(|f| self.data.lookup_keys(f))
+            .map(|(position, keys)| {
+                let mut hits: Vec<(usize, u32)> = keys
+                    .iter()
+                    .filter_map(|k| state.postings[position].get(k))
+                    .flatten()
+                    .copied()
+                    .collect();
+                hits.sort_unstable();
+                hits.dedup();
+                hits
+            })
+            .min_by_key(|hits| hits.len());
+
+        let batches = match hits {
+            Some(hits) => {
+                self.indexed_scans.fetch_add(1, Ordering::Relaxed);
+                let mut rows: BTreeMap<usize, Vec<u32>> = BTreeMap::new();
+                for (batch, row) in hits {
+                    rows.entry(batch).or_default().push(row);
+                }
+                rows.into_iter()
+                    .map(|(batch, rows)| {
+                        take_record_batch(&state.batches[batch], &UInt32Array::from(rows))
+                    })
+                    .collect::<std::result::Result<Vec<_>, _>>()?
+            }
+            None => state.batches.clone(),
+        };
+        Ok(Arc::new(MemoryExec::try_new(
+            &[batches],
+            self.schema(),
+            projection.cloned(),
+        )?))
+    }
+
+    async fn insert_into(
+        &self,
+        _state: &dyn Session,
+        input: Arc<dyn ExecutionPlan>,
+        insert_op: InsertOp,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        if insert_op == InsertOp::Replace {
+            return Err(datafusion::error::DataFusionError::NotImplemented(
+                "REPLACE is not supported on indexed tables".to_string(),
+            ));
+        }
+        let sink = IndexSink {
+            data: self.data.clone(),
+            overwrite: insert_op == InsertOp::Overwrite,
+        };
+        Ok(Arc::new(DataSinkExec::new(
+            input,
+            Arc::new(sink),
+            self.schema(),
+            None,
+        )))
+    }
+}
+
+#[derive(Debug)]
+struct IndexSink {
+    data: Arc<IndexData>,
+    overwrite: bool,
+}
+
+impl DisplayAs for IndexSink {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "InvertedIndexSink")
+    }
+}
+
+#[async_trait]
+impl DataSink for IndexSink {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        None
+    }
+
+    async fn write_all(
+        &self,
+        mut data: SendableRecordBatchStream,
+        _context: &Arc<TaskContext>,
+    ) -> datafusion::error::Result<u64> {
+        let mut batches = Vec::new();
+        while let Some(batch) = data.next().await.transpose()? {
+            if batch.num_rows() > 0 {
+                batches.push(batch);
+            }
+        }
+        let rows = batches.iter().map(|b| b.num_rows() as u64).sum();
+        let mut state = self.data.state.write().unwrap();
+        if self.overwrite {
+            *state = IndexState {
+                batches: Vec::new(),
+                postings: vec![HashMap::new(); self.data.columns.len()],
+            };
+        }
+        for batch in batches {
+            self.data.append(&mut state, batch);
+        }
+        Ok(rows)
+    }
+}
+
+impl IndexedTable for InvertedIndexTable {
+    fn in_memory(&self) -> bool {
+        true
+    }
+
+    // 索引列按名字对应到新的 schema，重写可能增加列（比如 _row_id）
+    fn reindex(
+        &self,
+        schema: SchemaRef,
+        partitions: Vec<Vec<RecordBatch>>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        let columns = self
+            .data
+            .columns
+            .iter()
+            .map(|i| schema.index_of(self.data.schema.field(*i).name()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let data = IndexData::new(schema, columns, self.data.constraints.clone());
+        data.append_all(partitions.into_iter().flatten());
+        Ok(Arc::new(InvertedIndexTable {
+            data: Arc::new(data),
+            indexed_scans: AtomicU64::new(self.indexed_scans.load(Ordering::Relaxed)),
+        }))
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 把表转换成在 columns（必须是 VARCHAR）上带倒排索引的表，之后的写入也会更新索引
+    ///
+    /// 原表的约束会保留；转换在表的写锁里完成，不会丢失同时进行的写入
+    pub async fn create_inverted_index(&self, table: &str, columns: &[&str]) -> Result<()> {
+        let _guard = self.lock_table(table).await;
+        let provider = self.ctx.table_provider(self.table_ref(table)).await?;
+        let schema = provider.schema();
+        let mut indexed = Vec::new();
+        for column in columns {
+            let index = schema.index_of(column)?;
+            if schema.field(index).data_type() != &DataType::Utf8 {
+                return Err(anyhow::anyhow!(
+                    "Inverted index only supports VARCHAR columns, {} is {}",
+                    column,
+                    schema.field(index).data_type()
+                ));
+            }
+            indexed.push(index);
+        }
+        let data = Arc::new(IndexData::new(
+            schema.clone(),
+            indexed,
+            provider.constraints().cloned(),
+        ));
+
+        let plan = provider.scan(&self.ctx.state(), None, &[], None).await?;
+        let batches = datafusion::physical_plan::collect(plan, self.ctx.task_ctx()).await?;
+        data.append_all(batches);
+
+        self.replace_table(
+            self.table_ref(table).table(),
+            Arc::new(InvertedIndexTable {
+                data,
+                indexed_scans: AtomicU64::new(0),
+            }),
+        )?;
+        Ok(())
+    }
+
+    pub async fn inverted_index_stats(&self, table: &str) -> Result<InvertedIndexStats> {
+        let provider = self.ctx.table_provider(table).await?;
+        let indexed = provider
+            .as_any()
+            .downcast_ref::<InvertedIndexTable>()
+            .ok_or_else(|| anyhow::anyhow!("Table {} has no inverted index", table))?;
+        let state = indexed.data.state.read().unwrap();
+        let stats = InvertedIndexStats {
+            distinct_keys: indexed
+                .data
+                .columns
+                .iter()
+                .zip(&state.postings)
+                .map(|(i, postings)| (indexed.data.schema.field(*i).name().clone(), postings.len()))
+                .collect(),
+            indexed_scans: indexed.indexed_scans.load(Ordering::Relaxed),
+        };
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_inverted_index() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE devices (serial_no VARCHAR, status VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO devices VALUES ('SN-1', 'ok'), ('SN-2', 'ok'), (NULL, 'lost')")
+            .await?;
+        assert!(db
+            .create_inverted_index("devices", &["serial_no", "missing"])
+            .await
+            .is_err());
+        db.create_inverted_index("devices", &["serial_no"]).await?;
+        db.execute("INSERT INTO devices VALUES ('SN-3', 'ok'), ('SN-1', 'repaired')")
+            .await?;
+
+        let count = |sql: &'static str| {
+            let db = &db;
+            async move { Ok::<_, anyhow::Error>(db.query(sql).await?.count().await?) }
+        };
+        assert_eq!(
+            count("SELECT * FROM devices WHERE serial_no = 'SN-1'").await?,
+            2
+        );
+        assert_eq!(
+            count("SELECT * FROM devices WHERE serial_no IN ('SN-2', 'SN-3', 'SN-9')").await?,
+            2
+        );
+        assert_eq!(
+            count("SELECT * FROM devices WHERE serial_no = 'SN-1' AND status = 'ok'").await?,
+            1
+        );
+        assert_eq!(
+            count("SELECT * FROM devices WHERE serial_no = 'none'").await?,
+            0
+        );
+        assert_eq!(
+            count("SELECT * FROM devices WHERE status = 'lost'").await?,
+            1
+        );
+
+        let stats = db.inverted_index_stats("devices").await?;
+        assert_eq!(stats.distinct_keys.get("serial_no"), Some(&3));
+        assert_eq!(stats.indexed_scans, 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inverted_index_survives_rewrite() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE devices (serial_no VARCHAR PRIMARY KEY, status VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO devices VALUES ('SN-1', 'ok'), ('SN-2', 'ok')")
+            .await?;
+        db.create_inverted_index("devices", &["serial_no"]).await?;
+        let provider = db.ctx.table_provider("devices").await?;
+        assert!(provider.constraints().is_some_and(|c| !c.is_empty()));
+
+        // upsert 整表重写之后用新的数据重建索引，约束也还在
+        let batch = db
+            .query("SELECT 'SN-2' AS serial_no, 'lost' AS status")
+            .await?;
+        let batch = batch.collect().await?.remove(0);
+        db.upsert(
+            "devices",
+            &["serial_no"],
+            batch.with_schema(provider.schema())?,
+        )
+        .await?;
+        let lost = db
+            .query("SELECT * FROM devices WHERE serial_no = 'SN-2' AND status = 'lost'")
+            .await?;
+        assert_eq!(lost.count().await?, 1);
+        let stats = db.inverted_index_stats("devices").await?;
+        assert_eq!(stats.distinct_keys.get("serial_no"), Some(&2));
+        assert_eq!(stats.indexed_scans, 1);
+        let provider = db.ctx.table_provider("devices").await?;
+        assert!(provider.constraints().is_some_and(|c| !c.is_empty()));
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use bytes::Bytes;
+use datafusion::arrow::ipc::reader::{FileReader, StreamReader};
+use datafusion::arrow::ipc::writer::{FileWriter, StreamWriter};
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::Cursor;
+
+// IPC 文件格式以这个魔数开头，流格式没有
+const ARROW_MAGIC: &[u8] = b"ARROW1";
+
+/// Arrow IPC 的两种格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpcFormat {
+    // 可以边收边解码，适合网络传输
+    #[default]
+    Stream,
+    // 末尾带索引，可以随机读取某个 batch，适合写成文件
+    File,
+}
+
+impl IpcFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            IpcFormat::Stream => "application/vnd.apache.arrow.stream",
+            IpcFormat::File => "application/vnd.apache.arrow.file",
+        }
+    }
+}
+
+/// 把 batch 编码成 Arrow IPC，没有 batch 时只包含 schema
+pub fn encode_ipc(schema: &SchemaRef, batches: &[RecordBatch], format: IpcFormat) -> Result<Bytes> {
+    let mut buf = Vec::new();
+    match format {
+        IpcFormat::Stream => {
+            let mut writer = StreamWriter::try_new(&mut buf, schema)?;
+            for batch in batches {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+        }
+        IpcFormat::File => {
+            let mut writer = FileWriter::try_new(&mut buf, schema)?;
+            for batch in batches {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(Bytes::from(buf))
+}
+
+/// 解码 query_to_ipc 的结果，根据开头的魔数区分流格式和文件格式
+pub fn decode_ipc(bytes: &[u8]) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    if bytes.starts_with(ARROW_MAGIC) {
+        let reader = FileReader::try_new(Cursor::new(bytes), None)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        return Ok((schema, batches));
+    }
+    let reader = StreamReader::try_new(bytes, None)?;
+    let schema = reader.schema();
+    let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok((schema, batches))
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 执行查询，结果编码成 Arrow IPC 流格式，不需要逐行转换成 JSON
+    pub async fn query_to_ipc(&self, sql: &str) -> Result<Bytes> {
+        self.query_to_ipc_with(sql, IpcFormat::Stream).await
+    }
+
+    pub async fn query_to_ipc_with(&self, sql: &str, format: IpcFormat) -> Result<Bytes> {
+        let df = self.query(sql).await?;
+        let schema = df.schema().inner().clone();
+        let batches = df.collect().await?;
+        encode_ipc(&schema, &batches, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_to_ipc() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+
+        for format in [IpcFormat::Stream, IpcFormat::File] {
+            let bytes = db.query_to_ipc_with("SELECT * FROM t", format).await?;
+            let (schema, batches) = decode_ipc(&bytes)?;
+            assert_eq!(schema.field(1).name(), "name");
+            assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        }
+
+        // 空结果也带 schema
+        let bytes = db.query_to_ipc("SELECT id FROM t WHERE id > 10").await?;
+        let (schema, batches) = decode_ipc(&bytes)?;
+        assert_eq!(schema.fields().len(), 1);
+        assert!(batches.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+#[cfg(feature = "clickhouse")]
+use crate::ck_client::{ClickHouseClient, SyncReport};
+use crate::events::CacheEvent;
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+pub use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    DeadlineExceeded,
+}
+
+/// 任务被取消或者超时，可以从返回的 anyhow::Error 中 downcast 出来
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobInterrupted {
+    Cancelled,
+    DeadlineExceeded,
+}
+
+impl fmt::Display for JobInterrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobInterrupted::Cancelled => write!(f, "job cancelled"),
+            JobInterrupted::DeadlineExceeded => write!(f, "job deadline exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for JobInterrupted {}
+
+/// 后台任务的名字、截止时间和取消信号
+///
+/// 传入的 token 作为父 token：取消它会取消任务，取消任务不会影响它
+#[derive(Debug, Clone, Default)]
+pub struct JobOptions {
+    // JobFailed 事件中的任务名，不设置时为 "job"
+    pub name: Option<String>,
+    pub deadline: Option<Instant>,
+    pub token: Option<CancellationToken>,
+}
+
+impl JobOptions {
+    pub fn timeout(timeout: Duration) -> Self {
+        Self {
+            name: None,
+            deadline: Some(Instant::now() + timeout),
+            token: None,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_token(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+}
+
+/// 后台任务的句柄，drop 不会取消任务
+pub struct JobHandle<T> {
+    token: CancellationToken,
+    status: Arc<Mutex<JobStatus>>,
+    handle: JoinHandle<Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn status(&self) -> JobStatus {
+        *self.status.lock().unwrap()
+    }
+
+    /// 任务使用的 token，可以传给子任务，任务取消时子任务一起取消
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// 等待任务结束
+    pub async fn wait(self) -> Result<T> {
+        self.handle
+            .await
+            .map_err(|e| anyhow::anyhow!("Job panicked: {}", e))?
+    }
+}
+
+/// 在取消或者截止时间之前运行 future
+///
+/// 中断时 future 会被 drop，DataFusion 的 stream 和进行中的 object store 请求随之停止
+pub async fn run_until<T>(
+    token: &CancellationToken,
+    deadline: Option<Instant>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let expired = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        biased;
+        _ = token.cancelled() => Err(JobInterrupted::Cancelled.into()),
+        _ = expired => Err(JobInterrupted::DeadlineExceeded.into()),
+        result = fut => result,
+    }
+}
+
+fn final_status<T>(result: &Result<T>) -> JobStatus {
+    match result {
+        Ok(_) => JobStatus::Succeeded,
+        Err(e) => match e.downcast_ref::<JobInterrupted>() {
+            Some(JobInterrupted::Cancelled) => JobStatus::Cancelled,
+            Some(JobInterrupted::DeadlineExceeded) => JobStatus::DeadlineExceeded,
+            None => JobStatus::Failed,
+        },
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync + 'static> DB<V> {
+    /// 在后台运行任务，f 收到的 token 可以继续传给子任务
+    /// 任务失败或者超过截止时间时发送 JobFailed 事件，主动取消不发送
+    pub fn spawn_job<T, F, Fut>(self: &Arc<Self>, options: JobOptions, f: F) -> JobHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(Arc<Self>, CancellationToken) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+    {
+        let token = options
+            .token
+            .map(|parent| parent.child_token())
+            .unwrap_or_default();
+        let status = Arc::new(Mutex::new(JobStatus::Running));
+        let fut = f(self.clone(), token.clone());
+
+        let (job_token, job_status) = (token.clone(), status.clone());
+        let events = self.events.clone();
+        let name = options.name.unwrap_or_else(|| "job".to_string());
+        let handle = tokio::spawn(async move {
+            let result = run_until(&job_token, options.deadline, fut).await;
+            let final_status = final_status(&result);
+            *job_status.lock().unwrap() = final_status;
+            if let Err(e) = &result {
+                if final_status != JobStatus::Cancelled {
+                    let _ = events.send(CacheEvent::JobFailed {
+                        job: name,
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+            result
+        });
+        JobHandle {
+            token,
+            status,
+            handle,
+        }
+    }
+
+    /// 后台创建快照，见 DB::freeze
+    pub fn freeze_job(
+        self: &Arc<Self>,
+        table: &str,
+        snapshot: &str,
+        options: JobOptions,
+    ) -> JobHandle<()> {
+        let (table, snapshot) = (table.to_string(), snapshot.to_string());
+        self.spawn_job(options, |db, _| async move {
+            db.freeze(&table, &snapshot).await
+        })
+    }
+
+    /// 后台批量写入，返回写入的行数
+    ///
+    /// 按 batch 逐个写入，中断时已经写入的 batch 不会回滚
+    pub fn bulk_load_job(
+        self: &Arc<Self>,
+        table: &str,
+        batches: Vec<RecordBatch>,
+        options: JobOptions,
+    ) -> JobHandle<usize> {
+        let table = table.to_string();
+        self.spawn_job(options, |db, token| async move {
+            let mut rows = 0;
+            for batch in batches {
+                if token.is_cancelled() {
+                    return Err(JobInterrupted::Cancelled.into());
+                }
+                rows += batch.num_rows();
+                db.insert_batch(&table, batch).await?;
+            }
+            Ok(rows)
+        })
+    }
+
+    /// 后台同步到 ClickHouse，见 DB::sync_to_clickhouse
+    #[cfg(feature = "clickhouse")]
+    pub fn sync_job(
+        self: &Arc<Self>,
+        table: &str,
+        client: ClickHouseClient,
+        options: JobOptions,
+    ) -> JobHandle<SyncReport> {
+        let table = table.to_string();
+        self.spawn_job(options, |db, _| async move {
+            db.sync_to_clickhouse(&table, &client).await
+        })
+    }
+}
+
+impl DB<()> {
+    /// 后台执行查询并导出到存储，返回实际写入的路径
+    #[cfg(feature = "storage")]
+    pub fn export_job(
+        self: &Arc<Self>,
+        sql: &str,
+        storage: &str,
+        path: &str,
+        format: &str,
+        options: JobOptions,
+    ) -> JobHandle<String> {
+        let (sql, storage, path, format) = (
+            sql.to_string(),
+            storage.to_string(),
+            path.to_string(),
+            format.to_string(),
+        );
+        self.spawn_job(options, |db, _| async move {
+            let df = db.query(&sql).await?;
+            db.export_to_storage(df, &storage, &path, &format).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::arrow::array::Int64Array;
+
+    fn batches(n: usize) -> Vec<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        (0..n)
+            .map(|i| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![Arc::new(Int64Array::from(vec![i as i64; 10]))],
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_bulk_load_job() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+        db.execute("CREATE TABLE t (id BIGINT NOT NULL)").await?;
+        let job = db.bulk_load_job("t", batches(3), JobOptions::default());
+        assert_eq!(job.wait().await?, 30);
+
+        let job = db.freeze_job("t", "v1", JobOptions::timeout(Duration::from_secs(10)));
+        job.wait().await?;
+        assert_eq!(db.snapshots("t")?, vec!["v1"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_deadline() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+
+        let job = db.spawn_job(JobOptions::default(), |_, _| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        assert_eq!(job.status(), JobStatus::Running);
+        job.cancel();
+        let status = job.status.clone();
+        let err = job.wait().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<JobInterrupted>(),
+            Some(&JobInterrupted::Cancelled)
+        );
+        assert_eq!(*status.lock().unwrap(), JobStatus::Cancelled);
+
+        let job = db.spawn_job(
+            JobOptions::timeout(Duration::from_millis(20)),
+            |_, _| async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            },
+        );
+        let err = job.wait().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<JobInterrupted>(),
+            Some(&JobInterrupted::DeadlineExceeded)
+        );
+
+        // 取消父 token 时子任务一起取消
+        let parent = CancellationToken::new();
+        let job = db.spawn_job(
+            JobOptions::default().with_token(parent.clone()),
+            |db, _| async move { db.query_to_batches("SELECT 1").await },
+        );
+        let child = db.spawn_job(
+            JobOptions::default().with_token(job.token()),
+            |_, _| async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            },
+        );
+        job.wait().await?;
+        parent.cancel();
+        assert!(child.wait().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_job_failed_event() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+        let mut events = db.subscribe();
+
+        let job = db.bulk_load_job(
+            "missing",
+            batches(1),
+            JobOptions::default().with_name("load missing"),
+        );
+        assert!(job.wait().await.is_err());
+        match events.try_recv()? {
+            CacheEvent::JobFailed { job, .. } => assert_eq!(job, "load missing"),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        // 主动取消不发送事件
+        let job = db.spawn_job(JobOptions::default(), |_, _| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        job.cancel();
+        assert!(job.wait().await.is_err());
+        assert!(events.try_recv().is_err());
+        Ok(())
+    }
+}
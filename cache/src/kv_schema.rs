@@ -1,15 +1,940 @@
-use crate::ck::ClickHouseTableProvider;
+use crate::codec::batches_to_rows;
+use crate::ident::{quote_ident, quote_literal};
+use crate::job::{JobHandle, JobOptions};
+use crate::pool::DB;
+use crate::swap::is_memory_table;
 use anyhow::{Ok, Result};
-use datafusion::arrow::array::{ArrayRef, BooleanArray, Int64Array, StringArray};
-use datafusion::arrow::datatypes::{DataType, Field, SchemaRef};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::{Array, ArrayRef, AsArray, BooleanArray, Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Int64Type, Schema, SchemaRef};
 use datafusion::arrow::record_batch::RecordBatch;
-use datafusion::datasource::TableProvider;
+use datafusion::functions_aggregate::expr_fn::max;
+use datafusion::logical_expr::Literal;
 use datafusion::prelude::*;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::de::{Deserializer, Visitor};
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 
 pub struct KVSchema {
     sch: SchemaRef,
+    table: String,
+    // 按这一列查找值
+    key_column: String,
+}
+
+impl KVSchema {
+    pub fn new(sch: SchemaRef, table: &str, key_column: &str) -> Self {
+        Self {
+            sch,
+            table: table.to_string(),
+            key_column: key_column.to_string(),
+        }
+    }
+
+    /// 按 key 读取完整的值
+    pub async fn get<T, K>(&self, db: &DB<T>, key: K) -> Result<Option<T>>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync,
+        K: Literal,
+    {
+        let columns: Vec<String> = self.sch.fields().iter().map(|f| f.name().clone()).collect();
+        self.lookup(db, key, &columns).await
+    }
+
+    /// 只读取 P 中的字段，P 是值类型的一部分字段组成的结构体
+    /// 热点查询不需要的大字段不会被读取和反序列化
+    pub async fn get_partial<P, T, K>(&self, db: &DB<T>, key: K) -> Result<Option<P>>
+    where
+        P: DeserializeOwned,
+        T: Serialize + DeserializeOwned + Send + Sync,
+        K: Literal,
+    {
+        let fields = struct_fields::<P>()?;
+        let columns: Vec<String> = fields
+            .iter()
+            .filter(|f| self.sch.field_with_name(f).is_ok())
+            .map(|f| f.to_string())
+            .collect();
+        self.lookup(db, key, &columns).await
+    }
+
+    async fn lookup<P, T, K>(&self, db: &DB<T>, key: K, columns: &[String]) -> Result<Option<P>>
+    where
+        P: DeserializeOwned,
+        T: Serialize + DeserializeOwned + Send + Sync,
+        K: Literal,
+    {
+        let columns: Vec<&str> = columns.iter().map(|c| c.as_str()).collect();
+        let batches = db
+            .ctx
+            .table(self.table.as_str())
+            .await?
+            .filter(col(self.key_column.as_str()).eq(lit(key)))?
+            .select_columns(&columns)?
+            .limit(0, Some(1))?
+            .collect()
+            .await?;
+        match batches_to_rows(&batches)?.into_iter().next() {
+            Some(row) => Ok(Some(serde_json::from_value(Value::Object(row))?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// KvStore 表的列
+pub const KV_KEY_COLUMN: &str = "key";
+pub const KV_VALUE_COLUMN: &str = "value";
+// 写入时间，UNIX 时间戳（微秒），同一个 KvStore 内严格递增
+pub const KV_TS_COLUMN: &str = "ts";
+// 过期时间，UNIX 时间戳（微秒），NULL 表示不过期
+pub const KV_EXPIRES_COLUMN: &str = "expires_at";
+// 删除标记，删除也是追加一行，compact 时和被覆盖的旧值一起清理
+pub const KV_TOMBSTONE_COLUMN: &str = "is_deleted";
+
+/// 基于 Arrow 表的 key/value 存储，K 和 V 都通过 serde 编码
+///
+/// 表只追加：put 和 delete 各写入一行，读取时取 key 最新的一行，最新一行是删除标记或者已经过期时视为不存在。
+/// 字符串 key 按原样保存，其他类型的 key 保存为 JSON；value 保存为 JSON。
+/// 旧版本在 compact 之前都保留在表中，可以用 get_at 读取某个时间点的值
+pub struct KvStore<K, V: Serialize + DeserializeOwned + Send + Sync> {
+    db: Arc<DB<V>>,
+    table: String,
+    state: Arc<KvTableState>,
+    _key: PhantomData<fn() -> K>,
+}
+
+// 表的时钟和历史版本的状态，保存在 DB 中，同一个表上打开的所有 KvStore 共享
+#[derive(Debug)]
+pub(crate) struct KvTableState {
+    // 最近一次写入的时间戳，None 表示还没有从表中读取
+    clock: RwLock<Option<i64>>,
+    // compact 和 purge_expired 保留的历史版本时长（微秒）
+    retention: AtomicU64,
+    // 早于这个时间戳的历史版本已经被清理
+    horizon: AtomicI64,
+}
+
+impl Default for KvTableState {
+    fn default() -> Self {
+        Self {
+            clock: RwLock::new(None),
+            retention: AtomicU64::new(0),
+            horizon: AtomicI64::new(i64::MIN),
+        }
+    }
+}
+
+// kv_schema 的所有列，按顺序
+const KV_COLUMNS: [&str; 5] = [
+    KV_KEY_COLUMN,
+    KV_VALUE_COLUMN,
+    KV_TS_COLUMN,
+    KV_EXPIRES_COLUMN,
+    KV_TOMBSTONE_COLUMN,
+];
+
+pub fn kv_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new(KV_KEY_COLUMN, DataType::Utf8, false),
+        Field::new(KV_VALUE_COLUMN, DataType::Utf8, true),
+        Field::new(KV_TS_COLUMN, DataType::Int64, false),
+        Field::new(KV_EXPIRES_COLUMN, DataType::Int64, true),
+        Field::new(KV_TOMBSTONE_COLUMN, DataType::Boolean, false),
+    ]))
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 打开表 table 上的 KvStore，表不存在时创建
+    pub async fn kv_store<K: Serialize + DeserializeOwned>(
+        self: &Arc<Self>,
+        table: &str,
+    ) -> Result<KvStore<K, V>> {
+        KvStore::open(self.clone(), table).await
+    }
+}
+
+impl<K, V> KvStore<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned + Send + Sync,
+{
+    pub async fn open(db: Arc<DB<V>>, table: &str) -> Result<Self> {
+        let columns = kv_schema()
+            .fields()
+            .iter()
+            .map(|f| {
+                let ty = match f.data_type() {
+                    DataType::Utf8 => "VARCHAR",
+                    DataType::Int64 => "BIGINT",
+                    _ => "BOOLEAN",
+                };
+                let null = if f.is_nullable() { "" } else { " NOT NULL" };
+                format!("{} {}{}", quote_ident(f.name()), ty, null)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        db.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            db.quote_table(table),
+            columns
+        ))
+        .await?;
+        let schema = db.table_schema(table).await?;
+        if schema.fields() != kv_schema().fields() {
+            return Err(anyhow::anyhow!(
+                "Table {} exists but is not a key/value table",
+                table
+            ));
+        }
+
+        let state = db
+            .kv_tables
+            .lock()
+            .unwrap()
+            .entry(db.table_ref(table).table().to_string())
+            .or_default()
+            .clone();
+        let store = Self {
+            db,
+            table: table.to_string(),
+            state,
+            _key: PhantomData,
+        };
+        let mut clock = store.state.clock.write().await;
+        if clock.is_none() {
+            let last = store
+                .frame()
+                .await?
+                .aggregate(vec![], vec![max(col(KV_TS_COLUMN))])?
+                .collect()
+                .await?;
+            let ts = last
+                .first()
+                .map(|batch| batch.column(0).as_primitive::<Int64Type>())
+                .filter(|ts| ts.is_valid(0))
+                .map_or(0, |ts| ts.value(0));
+            *clock = Some(ts);
+        }
+        drop(clock);
+        Ok(store)
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// 保留多长时间内的历史版本，compact 和 purge_expired 不会删除 get_at 读取这段时间需要的版本
+    ///
+    /// 默认为 0，compact 后只能读取当前的值；对同一个表上打开的所有 KvStore 生效
+    pub fn set_retention(&self, retention: Duration) {
+        self.state
+            .retention
+            .store(retention.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_micros(self.state.retention.load(Ordering::Relaxed))
+    }
+
+    pub async fn put(&self, key: &K, value: &V) -> Result<()> {
+        let value = serde_json::to_string(value)?;
+        self.append(&encode_key(key)?, Some(value), None).await
+    }
+
+    /// 写入 ttl 后过期的值，过期后 get/scan 视为不存在，purge_expired 时从表中删除
+    pub async fn put_with_ttl(&self, key: &K, value: &V, ttl: Duration) -> Result<()> {
+        let value = serde_json::to_string(value)?;
+        self.append(&encode_key(key)?, Some(value), Some(ttl)).await
+    }
+
+    pub async fn get(&self, key: &K) -> Result<Option<V>> {
+        let key = encode_key(key)?;
+        let batches = self
+            .frame()
+            .await?
+            .filter(col(KV_KEY_COLUMN).eq(lit(key)))?
+            .sort(vec![col(KV_TS_COLUMN).sort(false, false)])?
+            .limit(0, Some(1))?
+            .collect()
+            .await?;
+        let Some(row) = batches_to_rows(&batches)?.into_iter().next() else {
+            return Ok(None);
+        };
+        if let Some(expires_at) = row.get(KV_EXPIRES_COLUMN).and_then(|v| v.as_i64()) {
+            if expires_at <= now_micros()? {
+                return Ok(None);
+            }
+        }
+        match row.get(KV_VALUE_COLUMN) {
+            Some(Value::String(value))
+                if row.get(KV_TOMBSTONE_COLUMN) != Some(&Value::Bool(true)) =>
+            {
+                Ok(Some(serde_json::from_str(value)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// key 在 timestamp 时的值，和当时调用 get 的结果一样，同一个时间点读取多个 key 看到的是一致的状态
+    ///
+    /// timestamp 早于已经被清理的历史时返回错误，见 set_retention
+    pub async fn get_at(&self, key: &K, timestamp: DateTime<Utc>) -> Result<Option<V>> {
+        let as_of = timestamp.timestamp_micros();
+        let horizon = self.state.horizon.load(Ordering::Relaxed);
+        if as_of < horizon {
+            return Err(anyhow::anyhow!(
+                "Versions of {} before {} have been compacted",
+                self.table,
+                DateTime::from_timestamp_millis(horizon / 1000).unwrap_or_default()
+            ));
+        }
+        let predicate = format!(
+            "{} = {}",
+            quote_ident(KV_KEY_COLUMN),
+            quote_literal(&encode_key(key)?)
+        );
+        let batches = self
+            .db
+            .ctx
+            .sql(&self.latest_sql(&[KV_VALUE_COLUMN], &[predicate], Some(as_of))?)
+            .await?
+            .collect()
+            .await?;
+        match batches.iter().find(|b| b.num_rows() > 0) {
+            Some(batch) => {
+                let value = batch.column(0).as_string::<i32>().value(0);
+                Ok(Some(serde_json::from_str(value)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn exists(&self, key: &K) -> Result<bool> {
+        Ok(self.get(key).await?.is_some())
+    }
+
+    /// 写入删除标记，返回删除前 key 是否存在
+    pub async fn delete(&self, key: &K) -> Result<bool> {
+        if !self.exists(key).await? {
+            return Ok(false);
+        }
+        self.append(&encode_key(key)?, None, None).await?;
+        Ok(true)
+    }
+
+    /// 删除最新一行在 retention 之前就已经过期的 key 的所有行，返回删除的行数；和 compact 一样整表重写
+    pub async fn purge_expired(&self) -> Result<usize> {
+        let key = quote_ident(KV_KEY_COLUMN);
+        let expires = quote_ident(KV_EXPIRES_COLUMN);
+        let table = self.db.quote_table(&self.table);
+        let cutoff = self.cutoff()?;
+        let sql = format!(
+            "SELECT * FROM {table} WHERE {key} NOT IN (\
+             SELECT {key} FROM (\
+             SELECT {key}, {expires}, \
+             row_number() OVER (PARTITION BY {key} ORDER BY {ts} DESC) AS _rn \
+             FROM {table}) \
+             WHERE _rn = 1 AND {expires} <= {cutoff})",
+            ts = quote_ident(KV_TS_COLUMN),
+        );
+        self.rewrite(&sql, cutoff).await
+    }
+
+    /// 删除被覆盖的旧值、删除标记和已经过期的 key，返回删除的行数
+    ///
+    /// retention 内的版本和 retention 开始时每个 key 的值会保留，retention 为 0 时每个 key 只剩最新的一行。
+    /// 整表重写，只支持内存表；期间写入这个表的操作会等待重写完成
+    pub async fn compact(&self) -> Result<usize> {
+        let cutoff = self.cutoff()?;
+        let sql = format!(
+            "{} UNION ALL SELECT {} FROM {} WHERE {} > {}",
+            self.latest_sql(&KV_COLUMNS, &[], Some(cutoff))?,
+            quote_columns(&KV_COLUMNS),
+            self.db.quote_table(&self.table),
+            quote_ident(KV_TS_COLUMN),
+            cutoff
+        );
+        self.rewrite(&sql, cutoff).await
+    }
+
+    // 早于这个时间戳的历史版本可以清理
+    fn cutoff(&self) -> Result<i64> {
+        Ok(now_micros()? - self.state.retention.load(Ordering::Relaxed) as i64)
+    }
+
+    // 用 sql 的结果整表替换，返回删除的行数；删除了行时 cutoff 之前的历史不再完整
+    //
+    // 和 append 一样先拿时钟再拿表的写锁，重写的结果写入 WAL
+    async fn rewrite(&self, sql: &str, cutoff: i64) -> Result<usize> {
+        let _clock = self.state.clock.write().await;
+        let _guard = self.db.lock_table(&self.table).await;
+        let table_ref = self.db.table_ref(&self.table);
+        let provider = self.db.ctx.table_provider(table_ref.clone()).await?;
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Table {} is not an in-memory table",
+                self.table
+            ));
+        }
+        let before = self.frame().await?.count().await?;
+        let kept = self.db.ctx.sql(sql).await?.collect().await?;
+        let after: usize = kept.iter().map(|b| b.num_rows()).sum();
+        if after == before {
+            return Ok(0);
+        }
+        self.db.rewrite_table(
+            table_ref.table(),
+            provider.schema(),
+            vec![kept],
+            Some(&provider),
+        )?;
+        self.state.horizon.fetch_max(cutoff, Ordering::Relaxed);
+        Ok(before - after)
+    }
+
+    /// 一次查询读取多个 key，结果和 keys 一一对应，不存在或者已删除的 key 为 None
+    pub async fn multi_get(&self, keys: &[K]) -> Result<Vec<Option<V>>> {
+        let encoded = keys.iter().map(encode_key).collect::<Result<Vec<_>>>()?;
+        if encoded.is_empty() {
+            return Ok(Vec::new());
+        }
+        let list = encoded
+            .iter()
+            .map(|key| quote_literal(key))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let predicate = format!("{} IN ({})", quote_ident(KV_KEY_COLUMN), list);
+        let batches = self
+            .db
+            .ctx
+            .sql(&self.latest_sql(&[KV_KEY_COLUMN, KV_VALUE_COLUMN], &[predicate], None)?)
+            .await?
+            .collect()
+            .await?;
+
+        let mut found = HashMap::new();
+        for batch in &batches {
+            let keys = batch.column(0).as_string::<i32>();
+            let values = batch.column(1).as_string::<i32>();
+            for i in 0..batch.num_rows() {
+                found.insert(keys.value(i), values.value(i));
+            }
+        }
+        encoded
+            .iter()
+            .map(|key| match found.get(key.as_str()) {
+                Some(value) => Ok(Some(serde_json::from_str(value)?)),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    // 满足 predicates 的 key 在 as_of 时（None 为当前）的最新一行的 columns，已删除和已过期的 key 不返回
+    //
+    // 条件放在内层查询，可以下推到表扫描
+    fn latest_sql(
+        &self,
+        columns: &[&str],
+        predicates: &[String],
+        as_of: Option<i64>,
+    ) -> Result<String> {
+        let mut predicates = predicates.to_vec();
+        if let Some(as_of) = as_of {
+            predicates.push(format!("{} <= {}", quote_ident(KV_TS_COLUMN), as_of));
+        }
+        let filter = if predicates.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", predicates.join(" AND "))
+        };
+        let key = quote_ident(KV_KEY_COLUMN);
+        let deleted = quote_ident(KV_TOMBSTONE_COLUMN);
+        let expires = quote_ident(KV_EXPIRES_COLUMN);
+        Ok(format!(
+            "SELECT {columns} FROM (\
+             SELECT {all}, \
+             row_number() OVER (PARTITION BY {key} ORDER BY {ts} DESC) AS _rn \
+             FROM {table}{filter}) \
+             WHERE _rn = 1 AND NOT {deleted} AND ({expires} IS NULL OR {expires} > {now})",
+            columns = quote_columns(columns),
+            all = quote_columns(&KV_COLUMNS),
+            ts = quote_ident(KV_TS_COLUMN),
+            table = self.db.quote_table(&self.table),
+            now = match as_of {
+                Some(as_of) => as_of,
+                None => now_micros()?,
+            },
+        ))
+    }
+
+    async fn frame(&self) -> Result<DataFrame> {
+        Ok(self.db.ctx.table(self.db.table_ref(&self.table)).await?)
+    }
+
+    // value 为 None 时写入删除标记
+    async fn append(&self, key: &str, value: Option<String>, ttl: Option<Duration>) -> Result<()> {
+        // 持有锁直到写入完成，时间戳的顺序和写入顺序一致
+        let mut clock = self.state.clock.write().await;
+        let ts = now_micros()?.max(clock.unwrap_or(0) + 1);
+        let expires_at = ttl.map(|ttl| ts.saturating_add(ttl.as_micros() as i64));
+        let deleted = value.is_none();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec![key])),
+            Arc::new(StringArray::from(vec![value])),
+            Arc::new(Int64Array::from(vec![ts])),
+            Arc::new(Int64Array::from(vec![expires_at])),
+            Arc::new(BooleanArray::from(vec![deleted])),
+        ];
+        self.db
+            .insert_batch(&self.table, RecordBatch::try_new(kv_schema(), columns)?)
+            .await?;
+        *clock = Some(ts);
+        Ok(())
+    }
+}
+
+impl<K, V> KvStore<K, V>
+where
+    K: Serialize + DeserializeOwned + Send + 'static,
+    V: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// key 以 prefix 开头的所有 (key, value)，按 key 排序，已删除的 key 不会出现
+    pub async fn scan_prefix(&self, prefix: &str) -> Result<BoxStream<'static, Result<(K, V)>>> {
+        let mut predicates = Vec::new();
+        if !prefix.is_empty() {
+            predicates.push(format!(
+                "{} >= {}",
+                quote_ident(KV_KEY_COLUMN),
+                quote_literal(prefix)
+            ));
+        }
+        if let Some(end) = prefix_end(prefix) {
+            predicates.push(format!(
+                "{} < {}",
+                quote_ident(KV_KEY_COLUMN),
+                quote_literal(&end)
+            ));
+        }
+        self.scan(predicates).await
+    }
+
+    /// key 在 range 内的所有 (key, value)，按 key 排序
+    ///
+    /// 比较的是保存的 key：字符串 key 按字符串顺序，其他类型按 JSON 文本的顺序（数字 10 排在 9 之前）
+    pub async fn scan_range(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> Result<BoxStream<'static, Result<(K, V)>>> {
+        let mut predicates = Vec::new();
+        for (bound, inclusive, exclusive) in [
+            (range.start_bound(), ">=", ">"),
+            (range.end_bound(), "<=", "<"),
+        ] {
+            let (op, key) = match bound {
+                Bound::Included(key) => (inclusive, key),
+                Bound::Excluded(key) => (exclusive, key),
+                Bound::Unbounded => continue,
+            };
+            predicates.push(format!(
+                "{} {} {}",
+                quote_ident(KV_KEY_COLUMN),
+                op,
+                quote_literal(&encode_key(key)?)
+            ));
+        }
+        self.scan(predicates).await
+    }
+
+    /// 后台按 sync_interval 定期执行 purge_expired，直到任务被取消
+    ///
+    /// 单次清理失败不会结束任务，下一轮会重试
+    pub fn purge_expired_job(self: &Arc<Self>, options: JobOptions) -> JobHandle<()> {
+        let store = self.clone();
+        self.db.spawn_job(options, |db, token| async move {
+            loop {
+                if let Err(e) = store.purge_expired().await {
+                    tracing::warn!(table = %store.table, error = %e, "purge expired keys failed");
+                }
+                tokio::time::sleep(db.sync_interval()).await;
+                if token.is_cancelled() {
+                    return Ok(());
+                }
+            }
+        })
+    }
+
+    async fn scan(&self, predicates: Vec<String>) -> Result<BoxStream<'static, Result<(K, V)>>> {
+        let sql = format!(
+            "{} ORDER BY {}",
+            self.latest_sql(&[KV_KEY_COLUMN, KV_VALUE_COLUMN], &predicates, None)?,
+            quote_ident(KV_KEY_COLUMN)
+        );
+        let batches = self.db.ctx.sql(&sql).await?.execute_stream().await?;
+        Ok(Box::pin(batches.flat_map(|batch| {
+            let entries = match batch {
+                std::result::Result::Ok(batch) => decode_entries::<K, V>(&batch),
+                Err(e) => vec![Err(e.into())],
+            };
+            stream::iter(entries)
+        })))
+    }
+}
+
+fn decode_entries<K: DeserializeOwned, V: DeserializeOwned>(
+    batch: &RecordBatch,
+) -> Vec<Result<(K, V)>> {
+    let keys = batch.column(0).as_string::<i32>();
+    let values = batch.column(1).as_string::<i32>();
+    (0..batch.num_rows())
+        .map(|i| {
+            let key = decode_key(keys.value(i))?;
+            let value = serde_json::from_str(values.value(i))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn quote_columns(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .map(|c| quote_ident(c))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn now_micros() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as i64)
+}
+
+// prefix 之后第一个不以 prefix 开头的字符串，不存在时返回 None
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+// 字符串 key 按原样保存，前缀和范围查询按字符串顺序
+pub(crate) fn encode_key<K: Serialize>(key: &K) -> Result<String> {
+    match serde_json::to_value(key)? {
+        Value::String(key) => Ok(key),
+        other => Ok(other.to_string()),
+    }
+}
+
+// encode_key 的逆操作：K 是字符串类型时按原样读取，否则按 JSON 解析
+pub(crate) fn decode_key<K: DeserializeOwned>(key: &str) -> Result<K> {
+    match serde_json::from_value(Value::String(key.to_string())) {
+        std::result::Result::Ok(key) => Ok(key),
+        Err(_) => Ok(serde_json::from_str(key)?),
+    }
+}
+
+// 通过 serde 拿到结构体的字段名：反序列化时 deserialize_struct 会带上所有字段
+fn struct_fields<P: DeserializeOwned>() -> Result<&'static [&'static str]> {
+    struct FieldsDeserializer<'a>(&'a mut Option<&'static [&'static str]>);
+
+    impl<'de> Deserializer<'de> for FieldsDeserializer<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<W: Visitor<'de>>(self, _visitor: W) -> Result<W::Value, Self::Error> {
+            Err(serde::de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<W: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: W,
+        ) -> Result<W::Value, Self::Error> {
+            *self.0 = Some(fields);
+            Err(serde::de::Error::custom("fields collected"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields = None;
+    let _ = P::deserialize(FieldsDeserializer(&mut fields));
+    fields.ok_or_else(|| anyhow::anyhow!("{} is not a struct", std::any::type_name::<P>()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Profile {
+        id: i64,
+        name: String,
+        avatar: String,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct ProfileName {
+        id: i64,
+        name: String,
+    }
+
+    #[tokio::test]
+    async fn test_get_partial() -> Result<()> {
+        let db = DB::<Profile>::new("test_db");
+        db.execute("CREATE TABLE profiles (id BIGINT, name VARCHAR, avatar VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO profiles VALUES (1, 'Alice', 'large blob'), (2, 'Bob', 'blob')")
+            .await?;
+        let schema = db.ctx.table("profiles").await?.schema().inner().clone();
+        let kv = KVSchema::new(schema, "profiles", "id");
+
+        assert_eq!(
+            kv.get(&db, 2i64).await?,
+            Some(Profile {
+                id: 2,
+                name: "Bob".to_string(),
+                avatar: "blob".to_string(),
+            })
+        );
+        assert_eq!(
+            kv.get_partial::<ProfileName, _, _>(&db, 1i64).await?,
+            Some(ProfileName {
+                id: 1,
+                name: "Alice".to_string(),
+            })
+        );
+        assert_eq!(kv.get_partial::<ProfileName, _, _>(&db, 3i64).await?, None);
+        assert!(kv.get_partial::<i64, _, _>(&db, 1i64).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv_store() -> Result<()> {
+        let db = Arc::new(DB::<Profile>::new("test_db"));
+        let store = db.kv_store::<String>("profiles").await?;
+        let alice = Profile {
+            id: 1,
+            name: "Alice".to_string(),
+            avatar: "a.png".to_string(),
+        };
+        let key = "user:1".to_string();
+        assert_eq!(store.get(&key).await?, None);
+        store.put(&key, &alice).await?;
+        assert_eq!(store.get(&key).await?, Some(alice));
+
+        let renamed = Profile {
+            id: 1,
+            name: "Alicia".to_string(),
+            avatar: "a.png".to_string(),
+        };
+        store.put(&key, &renamed).await?;
+        assert_eq!(store.get(&key).await?, Some(renamed));
+        assert!(store.exists(&key).await?);
+
+        assert!(store.delete(&key).await?);
+        assert!(!store.delete(&key).await?);
+        assert!(!store.exists(&key).await?);
+        // 两次写入和删除标记都保留在表中
+        assert_eq!(db.ctx.table("profiles").await?.count().await?, 3);
+
+        // 重新打开后时间戳继续递增
+        let reopened = db.kv_store::<String>("profiles").await?;
+        reopened.put(&key, &renamed_again()).await?;
+        assert_eq!(store.get(&key).await?, Some(renamed_again()));
+
+        db.execute("CREATE TABLE other (id BIGINT)").await?;
+        assert!(db.kv_store::<String>("other").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv_scans() -> Result<()> {
+        use futures::TryStreamExt;
+
+        let db = Arc::new(DB::<i64>::new("test_db"));
+        let store = db.kv_store::<String>("counters").await?;
+        for (key, value) in [
+            ("user:1", 1),
+            ("user:2", 2),
+            ("user:10", 10),
+            ("order:1", 100),
+        ] {
+            store.put(&key.to_string(), &value).await?;
+        }
+        store.put(&"user:1".to_string(), &11).await?;
+        store.delete(&"user:2".to_string()).await?;
+
+        let users: Vec<(String, i64)> = store.scan_prefix("user:").await?.try_collect().await?;
+        assert_eq!(
+            users,
+            vec![("user:1".to_string(), 11), ("user:10".to_string(), 10)]
+        );
+        let range: Vec<(String, i64)> = store
+            .scan_range("order:".to_string().."user:10".to_string())
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(
+            range,
+            vec![("order:1".to_string(), 100), ("user:1".to_string(), 11)]
+        );
+        assert_eq!(store.scan_prefix("").await?.count().await, 3);
+
+        // 非字符串 key 按 JSON 解析
+        let numbers = db.kv_store::<i64>("numbers").await?;
+        numbers.put(&7, &49).await?;
+        let all: Vec<(i64, i64)> = numbers.scan_range(..).await?.try_collect().await?;
+        assert_eq!(all, vec![(7, 49)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_get() -> Result<()> {
+        let db = Arc::new(DB::<i64>::new("test_db"));
+        let store = db.kv_store::<String>("counters").await?;
+        let keys: Vec<String> = ["a", "b", "c", "a"].iter().map(|k| k.to_string()).collect();
+        assert_eq!(store.multi_get(&keys).await?, vec![None; 4]);
+
+        store.put(&keys[0], &1).await?;
+        store.put(&keys[0], &2).await?;
+        store.put(&keys[1], &3).await?;
+        store.put(&keys[2], &4).await?;
+        store.delete(&keys[2]).await?;
+        assert_eq!(
+            store.multi_get(&keys).await?,
+            vec![Some(2), Some(3), None, Some(2)]
+        );
+        assert!(store.multi_get(&[]).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv_ttl() -> Result<()> {
+        let db = Arc::new(DB::<i64>::new("test_db"));
+        let store = db.kv_store::<String>("sessions").await?;
+        let (short, long) = ("short".to_string(), "long".to_string());
+        store.put(&short, &1).await?;
+        store
+            .put_with_ttl(&short, &2, Duration::from_millis(1))
+            .await?;
+        store
+            .put_with_ttl(&long, &3, Duration::from_secs(3600))
+            .await?;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        // 过期的值覆盖了之前的值，整个 key 视为不存在
+        assert_eq!(store.get(&short).await?, None);
+        assert!(!store.delete(&short).await?);
+        assert_eq!(store.get(&long).await?, Some(3));
+        assert_eq!(
+            store.multi_get(&[short.clone(), long.clone()]).await?,
+            vec![None, Some(3)]
+        );
+        assert_eq!(store.scan_prefix("").await?.count().await, 1);
+
+        assert_eq!(store.purge_expired().await?, 2);
+        assert_eq!(store.purge_expired().await?, 0);
+        assert_eq!(db.ctx.table("sessions").await?.count().await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv_compact() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = Arc::new(DB::<i64>::new("test_db"));
+        let wal = db.enable_wal(crate::wal::WalConfig::new(dir.path()))?;
+        let store = db.kv_store::<String>("counters").await?;
+        let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+        store.put(&a, &1).await?;
+        store.put(&a, &2).await?;
+        store.put(&b, &1).await?;
+        store.delete(&b).await?;
+        store.put(&c, &1).await?;
+        assert_eq!(db.ctx.table("counters").await?.count().await?, 5);
+
+        // a 的旧值、b 的值和删除标记被清理，重写的结果写入 WAL
+        assert_eq!(store.compact().await?, 3);
+        assert_eq!(store.compact().await?, 0);
+        let records = wal.read_all()?;
+        let Some(crate::wal::WalRecord::Replace { table, batches, .. }) = records.last() else {
+            panic!("expected replace, got {:?}", records.last());
+        };
+        assert_eq!(table, "counters");
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        assert_eq!(
+            store.multi_get(&[a, b, c]).await?,
+            vec![Some(2), None, Some(1)]
+        );
+        assert_eq!(
+            db.table_schema("counters").await?.fields(),
+            kv_schema().fields()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv_get_at() -> Result<()> {
+        let db = Arc::new(DB::<i64>::new("test_db"));
+        let store = db.kv_store::<String>("counters").await?;
+        let key = "a".to_string();
+        let pause = || tokio::time::sleep(Duration::from_millis(5));
+
+        let before = Utc::now();
+        pause().await;
+        store.put(&key, &1).await?;
+        pause().await;
+        let first = Utc::now();
+        pause().await;
+        store.put(&key, &2).await?;
+        pause().await;
+        let second = Utc::now();
+        pause().await;
+        store.delete(&key).await?;
+
+        assert_eq!(store.get_at(&key, before).await?, None);
+        assert_eq!(store.get_at(&key, first).await?, Some(1));
+        assert_eq!(store.get_at(&key, second).await?, Some(2));
+        assert_eq!(store.get_at(&key, Utc::now()).await?, None);
+
+        // retention 内的版本不会被清理
+        store.set_retention(Duration::from_secs(3600));
+        assert_eq!(store.compact().await?, 0);
+        assert_eq!(store.get_at(&key, first).await?, Some(1));
+
+        store.set_retention(Duration::ZERO);
+        assert_eq!(store.compact().await?, 3);
+        assert!(store.get_at(&key, first).await.is_err());
+        assert_eq!(store.get_at(&key, Utc::now()).await?, None);
+
+        // 同一个表上的其他 KvStore 共享 retention 和已经清理的历史
+        let other = db.kv_store::<String>("counters").await?;
+        assert!(other.get_at(&key, first).await.is_err());
+        other.set_retention(Duration::from_secs(60));
+        assert_eq!(store.retention(), Duration::from_secs(60));
+        Ok(())
+    }
+
+    fn renamed_again() -> Profile {
+        Profile {
+            id: 1,
+            name: "Ally".to_string(),
+            avatar: "b.png".to_string(),
+        }
+    }
 }
@@ -1,9 +1,95 @@
+pub mod accounting;
+pub mod adaptive;
+pub mod advisor;
+pub mod backpressure;
+#[cfg(feature = "storage")]
+pub mod backup;
+pub mod bloom;
+pub mod builder;
+pub mod calendar;
+pub mod cancel;
 mod ck;
+#[cfg(feature = "clickhouse")]
+pub mod ck_client;
+pub mod codec;
+pub mod coercion;
+pub mod compression;
 pub mod config;
+pub mod credential;
+pub mod datagen;
+pub mod diff;
+pub mod download;
+pub mod drift;
+#[cfg(feature = "storage")]
+pub mod dryrun;
+pub mod etag;
+pub mod events;
+pub mod eviction;
+pub mod explain;
+pub mod export;
+pub mod external;
+pub mod finance;
+pub mod guard;
+pub mod hedged;
+pub mod idempotency;
+pub mod ident;
+pub mod ingest;
+pub mod integrity;
+pub mod inverted;
+pub mod ipc;
+pub mod job;
 pub mod kv_schema;
+pub mod lineage;
+pub mod live;
+pub mod location;
+pub mod metadata;
+pub mod middleware;
+pub mod migration;
+pub mod mismatch;
+pub mod notify;
+pub mod pagination;
+pub mod placement;
+pub mod plan_cache;
+pub mod plugin;
 pub mod pool;
+pub mod preview;
+pub mod procedure;
+pub mod provenance;
+pub mod pruning;
+pub mod ratelimit;
+pub mod recent;
+pub mod recovery;
+pub mod refresh;
+pub mod repartition;
+pub mod row_id;
+#[cfg(feature = "proto")]
 pub mod schema;
+pub mod session;
+pub mod shm;
+pub mod snapshot;
+pub mod startup;
+#[cfg(feature = "storage")]
 pub mod storage;
+mod swap;
+#[cfg(feature = "clickhouse")]
+pub mod sync_dlq;
+pub mod system;
+pub mod template;
+pub mod time_travel;
+pub mod topology;
+pub mod transaction;
+pub mod ttl;
+pub mod udf;
+pub mod upsert;
+#[cfg(feature = "storage")]
+pub mod verify;
+pub mod wal;
+pub mod warnings;
+#[cfg(feature = "storage")]
+pub mod watcher;
+pub mod watermark;
+pub mod workload;
+pub mod zonemap;
 #[cfg(test)]
 mod tests {
     use super::*;
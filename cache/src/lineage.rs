@@ -0,0 +1,235 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::{DataType, Field, Schema};
+use datafusion::arrow::array::{ArrayRef, StringArray};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::logical_expr::{DdlStatement, DmlStatement, LogicalPlan, WriteOp};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+pub const LINEAGE_TABLE: &str = "column_lineage";
+
+/// 派生表某一列的来源列
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ColumnLineage {
+    pub table: String,
+    pub column: String,
+    pub source_table: String,
+    pub source_column: String,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 表的列级血缘，只记录 INSERT ... SELECT 和 CREATE TABLE ... AS SELECT
+    pub fn lineage(&self, table: &str) -> Vec<ColumnLineage> {
+        self.lineage
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|l| l.table == table)
+            .cloned()
+            .collect()
+    }
+
+    // 语句执行成功之后记录 plan_lineage 的结果，并刷新 system.column_lineage
+    pub(crate) fn record_lineage(&self, lineage: BTreeSet<ColumnLineage>) -> Result<()> {
+        if lineage.is_empty() {
+            return Ok(());
+        }
+
+        let all = {
+            let mut recorded = self.lineage.write().unwrap();
+            recorded.extend(lineage);
+            recorded.iter().cloned().collect::<Vec<_>>()
+        };
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("column_name", DataType::Utf8, false),
+            Field::new("source_table", DataType::Utf8, false),
+            Field::new("source_column", DataType::Utf8, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(all.iter().map(|l| &l.table))),
+            Arc::new(StringArray::from_iter_values(all.iter().map(|l| &l.column))),
+            Arc::new(StringArray::from_iter_values(
+                all.iter().map(|l| &l.source_table),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                all.iter().map(|l| &l.source_column),
+            )),
+        ];
+        self.refresh_system_table(LINEAGE_TABLE, RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+/// 从逻辑计划中解析出派生表每一列的来源列
+pub fn plan_lineage(plan: &LogicalPlan) -> BTreeSet<ColumnLineage> {
+    let (table, input) = match plan {
+        LogicalPlan::Dml(DmlStatement {
+            table_name,
+            op: WriteOp::Insert(_),
+            input,
+            ..
+        }) => (table_name.to_string(), input),
+        LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(create)) => {
+            (create.name.to_string(), &create.input)
+        }
+        _ => return BTreeSet::new(),
+    };
+
+    let mut lineage = BTreeSet::new();
+    for (i, field) in input.schema().fields().iter().enumerate() {
+        for (source_table, source_column) in column_sources(input, i) {
+            lineage.insert(ColumnLineage {
+                table: table.clone(),
+                column: field.name().clone(),
+                source_table,
+                source_column,
+            });
+        }
+    }
+    lineage
+}
+
+// 递归查找 plan 第 index 个输出列来自哪些表的哪些列
+fn column_sources(plan: &LogicalPlan, index: usize) -> BTreeSet<(String, String)> {
+    match plan {
+        LogicalPlan::TableScan(scan) => {
+            let mut sources = BTreeSet::new();
+            let field = scan.projected_schema.field(index);
+            sources.insert((scan.table_name.to_string(), field.name().clone()));
+            sources
+        }
+        LogicalPlan::Projection(projection) => {
+            expr_sources(&projection.expr[index], &projection.input)
+        }
+        LogicalPlan::Aggregate(aggregate) => {
+            let group_len = aggregate.group_expr.len();
+            let expr = if index < group_len {
+                aggregate.group_expr.get(index)
+            } else {
+                aggregate.aggr_expr.get(index - group_len)
+            };
+            expr.map(|e| expr_sources(e, &aggregate.input))
+                .unwrap_or_default()
+        }
+        LogicalPlan::Window(window) => {
+            let input_len = window.input.schema().fields().len();
+            if index < input_len {
+                column_sources(&window.input, index)
+            } else {
+                window
+                    .window_expr
+                    .get(index - input_len)
+                    .map(|e| expr_sources(e, &window.input))
+                    .unwrap_or_default()
+            }
+        }
+        LogicalPlan::Join(join) => {
+            let left_len = join.left.schema().fields().len();
+            if index < left_len {
+                column_sources(&join.left, index)
+            } else {
+                column_sources(&join.right, index - left_len)
+            }
+        }
+        LogicalPlan::Union(union) => union
+            .inputs
+            .iter()
+            .flat_map(|input| column_sources(input, index))
+            .collect(),
+        // 不改变列的节点，直接透传
+        LogicalPlan::Filter(_)
+        | LogicalPlan::Sort(_)
+        | LogicalPlan::Limit(_)
+        | LogicalPlan::SubqueryAlias(_)
+        | LogicalPlan::Distinct(_)
+        | LogicalPlan::Repartition(_) => plan
+            .inputs()
+            .first()
+            .map(|input| column_sources(input, index))
+            .unwrap_or_default(),
+        _ => BTreeSet::new(),
+    }
+}
+
+fn expr_sources(
+    expr: &datafusion::logical_expr::Expr,
+    input: &LogicalPlan,
+) -> BTreeSet<(String, String)> {
+    expr.column_refs()
+        .into_iter()
+        .filter_map(|column| input.schema().index_of_column(column).ok())
+        .flat_map(|i| column_sources(input, i))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lineage_of(
+        table: &str,
+        column: &str,
+        source_table: &str,
+        source_column: &str,
+    ) -> ColumnLineage {
+        ColumnLineage {
+            table: table.to_string(),
+            column: column.to_string(),
+            source_table: source_table.to_string(),
+            source_column: source_column.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lineage_for_derived_tables() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE orders (id BIGINT, user_id BIGINT, amount DOUBLE)")
+            .await?;
+        db.execute("CREATE TABLE users (id BIGINT, name VARCHAR)")
+            .await?;
+
+        db.execute(
+            "CREATE TABLE user_totals AS \
+             SELECT u.name, SUM(o.amount) * 2 AS doubled \
+             FROM orders o JOIN users u ON o.user_id = u.id \
+             GROUP BY u.name",
+        )
+        .await?;
+        assert_eq!(
+            db.lineage("user_totals"),
+            vec![
+                lineage_of("user_totals", "doubled", "orders", "amount"),
+                lineage_of("user_totals", "name", "users", "name"),
+            ]
+        );
+
+        db.execute("CREATE TABLE big_orders (id BIGINT, amount DOUBLE)")
+            .await?;
+        db.execute("INSERT INTO big_orders SELECT id, amount FROM orders WHERE amount > 100")
+            .await?;
+        assert_eq!(
+            db.lineage("big_orders"),
+            vec![
+                lineage_of("big_orders", "amount", "orders", "amount"),
+                lineage_of("big_orders", "id", "orders", "id"),
+            ]
+        );
+
+        // 执行失败的语句不记录血缘
+        let sql = "CREATE TABLE users AS SELECT user_id FROM orders";
+        assert!(db.execute(sql).await.is_err());
+        assert!(db.execute_prepared(sql, vec![]).await.is_err());
+        assert!(db.lineage("users").is_empty());
+
+        let count = db
+            .query("SELECT * FROM system.column_lineage")
+            .await?
+            .count()
+            .await?;
+        assert_eq!(count, 4);
+        Ok(())
+    }
+}
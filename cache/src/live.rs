@@ -0,0 +1,270 @@
+use crate::codec::batches_to_rows;
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::logical_expr::LogicalPlan;
+use futures::stream::{self, BoxStream};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// 订阅者处理不过来时最多保留的表变更通知数
+pub(crate) const TABLE_CHANGE_CAPACITY: usize = 1024;
+
+/// 表被写入的通知，每次写入一条
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableChange {
+    pub table: String,
+    pub version: u64,
+}
+
+/// live_query 重新执行查询的时机
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveTrigger {
+    // 固定间隔重新执行
+    Interval(Duration),
+    // 查询读取的表被写入后重新执行，短时间内的多次写入合并成一次
+    OnChange,
+}
+
+/// 查询结果的一次变化，第一次是全部结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveUpdate<T> {
+    // 执行查询时 DB 的版本号
+    pub version: u64,
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
+}
+
+struct LiveState<V: Serialize + DeserializeOwned + Send + Sync> {
+    db: Arc<DB<V>>,
+    sql: String,
+    trigger: LiveTrigger,
+    changes: broadcast::Receiver<TableChange>,
+    // 查询读取的表，第一次执行后确定
+    tables: HashSet<String>,
+    // 上一次的结果，key 是行的 JSON
+    previous: Option<Vec<(String, Value)>>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync + 'static> DB<V> {
+    /// 订阅所有表的写入通知
+    pub fn subscribe_table_changes(&self) -> broadcast::Receiver<TableChange> {
+        self.table_changes.subscribe()
+    }
+
+    /// 持续执行只读查询，结果变化时输出新增和删除的行，结果没有变化时不输出
+    ///
+    /// 行按整行比较，值改变的行表现为删除旧行、新增新行；
+    /// OnChange 只能感知通过 DB 的写入，直接通过 ctx 的写入需要用 Interval
+    pub fn live_query<T: DeserializeOwned + Send + 'static>(
+        self: &Arc<Self>,
+        sql: &str,
+        trigger: LiveTrigger,
+    ) -> BoxStream<'static, Result<LiveUpdate<T>>> {
+        let state = LiveState {
+            db: self.clone(),
+            sql: sql.to_string(),
+            trigger,
+            // 先订阅再执行，执行期间的写入不会漏掉
+            changes: self.subscribe_table_changes(),
+            tables: HashSet::new(),
+            previous: None,
+        };
+        Box::pin(stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+            loop {
+                if state.previous.is_some() && !state.wait().await {
+                    return None;
+                }
+                match state.evaluate::<T>().await {
+                    Ok(Some(update)) => return Some((Ok(update), Some(state))),
+                    Ok(None) => continue,
+                    // 出错后结束订阅
+                    Err(e) => return Some((Err(e), None)),
+                }
+            }
+        }))
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> LiveState<V> {
+    // 等到需要重新执行，返回 false 表示 DB 已经不会再有写入通知
+    async fn wait(&mut self) -> bool {
+        match self.trigger {
+            LiveTrigger::Interval(interval) => {
+                tokio::time::sleep(interval).await;
+                true
+            }
+            LiveTrigger::OnChange => loop {
+                match self.changes.recv().await {
+                    Ok(change) if self.tables.contains(&change.table) => {
+                        // 合并已经到达的通知
+                        while self.changes.try_recv().is_ok() {}
+                        return true;
+                    }
+                    Ok(_) => continue,
+                    // 漏掉了通知，不知道是否有相关的写入，重新执行一次
+                    Err(RecvError::Lagged(_)) => return true,
+                    Err(RecvError::Closed) => return false,
+                }
+            },
+        }
+    }
+
+    async fn evaluate<T: DeserializeOwned>(&mut self) -> Result<Option<LiveUpdate<T>>> {
+        let version = self.db.current_version();
+        let plan = self.db.ctx.state().create_logical_plan(&self.sql).await?;
+        if matches!(
+            plan,
+            LogicalPlan::Dml(_) | LogicalPlan::Ddl(_) | LogicalPlan::Copy(_)
+        ) {
+            return Err(anyhow::anyhow!("Live queries must be read-only"));
+        }
+        self.tables.clear();
+        plan.apply(|node| {
+            if let LogicalPlan::TableScan(scan) = node {
+                if let Some(key) = self.db.version_key(&scan.table_name) {
+                    self.tables.insert(key);
+                }
+            }
+            Ok(TreeNodeRecursion::Continue)
+        })?;
+        let batches = self
+            .db
+            .ctx
+            .execute_logical_plan(plan)
+            .await?
+            .collect()
+            .await?;
+        let current = batches_to_rows(&batches)?
+            .into_iter()
+            .map(|row| {
+                let row = Value::Object(row);
+                (row.to_string(), row)
+            })
+            .collect::<Vec<_>>();
+
+        let (added, removed) = match &self.previous {
+            Some(previous) => diff_rows(previous, &current),
+            None => (
+                current.iter().map(|(_, row)| row.clone()).collect(),
+                Vec::new(),
+            ),
+        };
+        let first = self.previous.is_none();
+        self.previous = Some(current);
+        if !first && added.is_empty() && removed.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(LiveUpdate {
+            version,
+            added: decode(added)?,
+            removed: decode(removed)?,
+        }))
+    }
+}
+
+// 按多重集合比较，重复的行也能正确处理
+fn diff_rows(
+    previous: &[(String, Value)],
+    current: &[(String, Value)],
+) -> (Vec<Value>, Vec<Value>) {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for (key, _) in previous {
+        *counts.entry(key).or_default() += 1;
+    }
+    let mut added = Vec::new();
+    for (key, row) in current {
+        match counts.get_mut(key.as_str()) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => added.push(row.clone()),
+        }
+    }
+    let mut removed = Vec::new();
+    for (key, row) in previous {
+        if let Some(count) = counts.get_mut(key.as_str()).filter(|c| **c > 0) {
+            *count -= 1;
+            removed.push(row.clone());
+        }
+    }
+    (added, removed)
+}
+
+fn decode<T: DeserializeOwned>(rows: Vec<Value>) -> Result<Vec<T>> {
+    Ok(rows
+        .into_iter()
+        .map(serde_json::from_value)
+        .collect::<std::result::Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Deserialize)]
+    struct Total {
+        region: String,
+        total: i64,
+    }
+
+    #[tokio::test]
+    async fn test_live_query() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+        db.execute("CREATE TABLE sales (region VARCHAR, amount BIGINT)")
+            .await?;
+        db.execute("CREATE TABLE other (id BIGINT)").await?;
+        db.execute("INSERT INTO sales VALUES ('eu', 10)").await?;
+
+        let mut updates = db.live_query::<Total>(
+            "SELECT region, sum(amount) AS total FROM sales GROUP BY region",
+            LiveTrigger::OnChange,
+        );
+        let first = updates.next().await.unwrap()?;
+        assert_eq!(
+            first.added,
+            vec![Total {
+                region: "eu".into(),
+                total: 10
+            }]
+        );
+        assert!(first.removed.is_empty());
+
+        // 其他表的写入不会触发
+        db.execute("INSERT INTO other VALUES (1)").await?;
+        db.execute("INSERT INTO sales VALUES ('eu', 5), ('us', 1)")
+            .await?;
+        let update = tokio::time::timeout(Duration::from_secs(5), updates.next())
+            .await?
+            .unwrap()?;
+        assert_eq!(
+            update.removed,
+            vec![Total {
+                region: "eu".into(),
+                total: 10
+            }]
+        );
+        let mut added = update.added;
+        added.sort_by(|a, b| a.region.cmp(&b.region));
+        assert_eq!(
+            added,
+            vec![
+                Total {
+                    region: "eu".into(),
+                    total: 15
+                },
+                Total {
+                    region: "us".into(),
+                    total: 1
+                },
+            ]
+        );
+        assert_eq!(update.version, db.current_version());
+        Ok(())
+    }
+}
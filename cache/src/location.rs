@@ -0,0 +1,154 @@
+use crate::ident::quote_literal;
+use anyhow::{bail, Result};
+
+/// 拼接 {scheme}://{bucket}/{path} 形式的存储路径，build 时统一校验
+///
+/// path 中的 . 和 .. 段、控制字符都会被拒绝，多余的 / 会被合并
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageLocation {
+    scheme: String,
+    bucket: String,
+    segments: Vec<String>,
+}
+
+impl StorageLocation {
+    pub fn new(scheme: &str, bucket: &str) -> Self {
+        Self {
+            scheme: scheme.to_string(),
+            bucket: bucket.to_string(),
+            segments: Vec::new(),
+        }
+    }
+
+    /// 追加路径，可以调用多次
+    pub fn path(mut self, path: &str) -> Self {
+        self.segments.extend(
+            path.split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        );
+        self
+    }
+
+    /// 只包含 scheme 和 bucket，用于注册 object store
+    pub fn root(&self) -> Result<String> {
+        validate_scheme(&self.scheme)?;
+        // file:// 没有 bucket，路径直接从根目录开始
+        if !(self.scheme == "file" && self.bucket.is_empty()) {
+            validate_bucket(&self.bucket)?;
+        }
+        Ok(format!("{}://{}", self.scheme, self.bucket))
+    }
+
+    pub fn build(&self) -> Result<String> {
+        let root = self.root()?;
+        for segment in &self.segments {
+            validate_segment(segment)?;
+        }
+        if self.segments.is_empty() {
+            return Ok(root);
+        }
+        Ok(format!("{}/{}", root, self.segments.join("/")))
+    }
+
+    /// 作为 SQL 字符串字面量使用，比如 SELECT * FROM '{location}'
+    pub fn to_sql(&self) -> Result<String> {
+        Ok(quote_literal(&self.build()?))
+    }
+}
+
+/// 存储名和存储组名只允许字母、数字、_、-、.，存储组名会作为 url 的 host
+pub fn validate_storage_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("storage name is empty");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')))
+    {
+        bail!("invalid character {:?} in storage name {:?}", c, name);
+    }
+    Ok(())
+}
+
+/// 拒绝控制字符（换行、\0 等），生成 SQL 或日志时会造成格式问题
+pub fn reject_control_chars(value: &str, what: &str) -> Result<()> {
+    if value.chars().any(char::is_control) {
+        bail!("{} contains control characters: {:?}", what, value);
+    }
+    Ok(())
+}
+
+fn validate_scheme(scheme: &str) -> Result<()> {
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '+' | '-' | '.'));
+    if !valid {
+        bail!("invalid storage scheme {:?}", scheme);
+    }
+    Ok(())
+}
+
+fn validate_bucket(bucket: &str) -> Result<()> {
+    if bucket.is_empty() {
+        bail!("bucket is empty");
+    }
+    reject_control_chars(bucket, "bucket")?;
+    if let Some(c) = bucket
+        .chars()
+        .find(|c| c.is_whitespace() || matches!(c, '/' | '\\' | '?' | '#' | '@' | ':' | '\'' | '"'))
+    {
+        bail!("invalid character {:?} in bucket {:?}", c, bucket);
+    }
+    Ok(())
+}
+
+fn validate_segment(segment: &str) -> Result<()> {
+    reject_control_chars(segment, "path")?;
+    if segment == "." || segment == ".." {
+        bail!("relative path segment {:?} is not allowed", segment);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_location() -> Result<()> {
+        let location = StorageLocation::new("s3", "data")
+            .path("/exports//2024/")
+            .path("a.csv");
+        assert_eq!(location.build()?, "s3://data/exports/2024/a.csv");
+        assert_eq!(location.root()?, "s3://data");
+        assert_eq!(
+            StorageLocation::new("file", "").path("tmp/a.csv").build()?,
+            "file:///tmp/a.csv"
+        );
+
+        let quoted = StorageLocation::new("s3", "data").path("it's.csv");
+        assert_eq!(quoted.to_sql()?, "'s3://data/it''s.csv'");
+
+        assert!(StorageLocation::new("s3", "data")
+            .path("../x")
+            .build()
+            .is_err());
+        assert!(StorageLocation::new("s3", "data")
+            .path("a\nb")
+            .build()
+            .is_err());
+        assert!(StorageLocation::new("s3", "da/ta").build().is_err());
+        assert!(StorageLocation::new("S3 ", "data").build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_storage_name() {
+        assert!(validate_storage_name("minio-1").is_ok());
+        assert!(validate_storage_name("").is_err());
+        assert!(validate_storage_name("a/b").is_err());
+        assert!(validate_storage_name("a'b").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::pool::DB;
+use crate::wal::WalRecord;
+use anyhow::Result;
+use arrow_schema::{DataType, Field, Schema};
+use datafusion::arrow::array::{ArrayRef, StringArray};
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub const TABLE_METADATA_TABLE: &str = "table_metadata";
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 给表附加自定义的 key/value 信息，比如负责人、来源系统、刷新 SLA
+    /// 可以通过 system.table_metadata 查询
+    ///
+    /// 开启 WAL 时修改会写入 WAL，重启后 recovery 恢复；备份和 snapshot 也会带上表的自定义信息
+    pub fn set_table_meta(&self, table: &str, key: &str, value: &str) -> Result<()> {
+        if !self.ctx.table_exist(table)? {
+            return Err(anyhow::anyhow!("Table {} not found", table));
+        }
+        self.update_table_meta(table, |meta| {
+            meta.insert(key.to_string(), value.to_string());
+        })
+    }
+
+    pub fn get_table_meta(&self, table: &str, key: &str) -> Option<String> {
+        self.table_meta
+            .read()
+            .unwrap()
+            .get(table)
+            .and_then(|meta| meta.get(key))
+            .cloned()
+    }
+
+    /// 表的全部自定义信息
+    pub fn table_meta(&self, table: &str) -> BTreeMap<String, String> {
+        self.table_meta
+            .read()
+            .unwrap()
+            .get(table)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn remove_table_meta(&self, table: &str, key: &str) -> Result<Option<String>> {
+        self.update_table_meta(table, |meta| meta.remove(key))
+    }
+
+    pub(crate) fn clear_table_meta(&self, table: &str) -> Result<()> {
+        self.put_table_meta(table, BTreeMap::new())
+    }
+
+    /// 用 meta 替换表的全部自定义信息，空表示清除
+    pub(crate) fn put_table_meta(&self, table: &str, meta: BTreeMap<String, String>) -> Result<()> {
+        self.update_table_meta(table, |current| *current = meta)
+    }
+
+    // 修改表的自定义信息，有变化时先写入 WAL 再生效
+    fn update_table_meta<R>(
+        &self,
+        table: &str,
+        update: impl FnOnce(&mut BTreeMap<String, String>) -> R,
+    ) -> Result<R> {
+        let result = {
+            let mut all = self.table_meta.write().unwrap();
+            let current = all.get(table).cloned().unwrap_or_default();
+            let mut meta = current.clone();
+            let result = update(&mut meta);
+            if meta == current {
+                return Ok(result);
+            }
+            if let Some(wal) = self.wal() {
+                wal.append(&WalRecord::Meta {
+                    table: table.to_string(),
+                    meta: meta.clone(),
+                })?;
+            }
+            set_meta(&mut all, table, meta);
+            result
+        };
+        self.refresh_table_meta()?;
+        Ok(result)
+    }
+
+    // 不写 WAL 的 put_table_meta，WAL 重放时直接调用
+    pub(crate) fn apply_table_meta(
+        &self,
+        table: &str,
+        meta: BTreeMap<String, String>,
+    ) -> Result<()> {
+        set_meta(&mut self.table_meta.write().unwrap(), table, meta);
+        self.refresh_table_meta()
+    }
+
+    fn refresh_table_meta(&self) -> Result<()> {
+        let rows: Vec<(String, String, String)> = self
+            .table_meta
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|(table, meta)| {
+                meta.iter()
+                    .map(move |(key, value)| (table.clone(), key.clone(), value.clone()))
+            })
+            .collect();
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Utf8, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.0))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.1))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.2))),
+        ];
+        self.refresh_system_table(TABLE_METADATA_TABLE, RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+fn set_meta(
+    all: &mut BTreeMap<String, BTreeMap<String, String>>,
+    table: &str,
+    meta: BTreeMap<String, String>,
+) {
+    if meta.is_empty() {
+        all.remove(table);
+    } else {
+        all.insert(table.to_string(), meta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_table_meta() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE ledger (id BIGINT)").await?;
+
+        db.set_table_meta("ledger", "owner", "finance")?;
+        db.set_table_meta("ledger", "refresh_sla", "1h")?;
+        db.set_table_meta("ledger", "owner", "treasury")?;
+        assert!(db.set_table_meta("missing", "owner", "x").is_err());
+
+        assert_eq!(
+            db.get_table_meta("ledger", "owner").as_deref(),
+            Some("treasury")
+        );
+        assert_eq!(db.table_meta("ledger").len(), 2);
+
+        let batches = db
+            .query_to_batches(
+                "SELECT value FROM system.table_metadata WHERE table_name = 'ledger' AND key = 'refresh_sla'",
+            )
+            .await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+        assert_eq!(
+            db.remove_table_meta("ledger", "owner")?.as_deref(),
+            Some("treasury")
+        );
+        assert_eq!(db.get_table_meta("ledger", "owner"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_table_meta_survives_restart() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        {
+            let db = DB::<()>::new("test_db");
+            db.enable_wal(crate::wal::WalConfig::new(dir.path()))?;
+            db.execute("CREATE TABLE ledger (id BIGINT)").await?;
+            db.execute("CREATE TABLE gone (id BIGINT)").await?;
+            db.set_table_meta("ledger", "owner", "finance")?;
+            db.set_table_meta("ledger", "refresh_sla", "1h")?;
+            db.remove_table_meta("ledger", "refresh_sla")?;
+            db.set_table_meta("gone", "owner", "finance")?;
+            db.drop_table("gone").await?;
+        }
+
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(crate::wal::WalConfig::new(dir.path()))?;
+        db.recovery().await?;
+        assert_eq!(
+            db.table_meta("ledger"),
+            BTreeMap::from([("owner".to_string(), "finance".to_string())])
+        );
+        assert!(db.table_meta("gone").is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use crate::pool::DB;
+use object_store::ObjectStore;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// 注册存储时套在 ObjectStore 外面的中间件，比如加自定义的认证 header、记录请求日志、
+/// 测试时注入错误
+///
+/// 按添加的顺序应用，先添加的在里层；只对之后注册的存储生效
+pub trait StoreMiddleware: Debug + Send + Sync {
+    fn wrap(&self, storage: &str, store: Arc<dyn ObjectStore>) -> Arc<dyn ObjectStore>;
+}
+
+impl DB<()> {
+    pub fn add_store_middleware(&self, middleware: Arc<dyn StoreMiddleware>) {
+        self.store_middleware.write().unwrap().push(middleware);
+    }
+
+    #[cfg_attr(not(feature = "storage"), allow(dead_code))]
+    pub(crate) fn apply_store_middleware(
+        &self,
+        storage: &str,
+        store: Arc<dyn ObjectStore>,
+    ) -> Arc<dyn ObjectStore> {
+        self.store_middleware
+            .read()
+            .unwrap()
+            .iter()
+            .fold(store, |store, middleware| middleware.wrap(storage, store))
+    }
+}
+
+#[cfg(all(test, feature = "storage"))]
+mod tests {
+    use super::*;
+    use crate::config::{Config, StorageConfig};
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use object_store::PutPayload;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // 把请求转到内存存储，并记录是哪个存储注册时调用的
+    #[derive(Debug, Default)]
+    struct InMemoryMiddleware {
+        wrapped: Mutex<Vec<String>>,
+    }
+
+    impl StoreMiddleware for InMemoryMiddleware {
+        fn wrap(&self, storage: &str, _store: Arc<dyn ObjectStore>) -> Arc<dyn ObjectStore> {
+            self.wrapped.lock().unwrap().push(storage.to_string());
+            Arc::new(InMemory::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_middleware() -> anyhow::Result<()> {
+        let db = DB::<()>::new("test_db");
+        let middleware = Arc::new(InMemoryMiddleware::default());
+        db.add_store_middleware(middleware.clone());
+
+        let storage = StorageConfig {
+            access_key: "ak".to_string(),
+            access_secret: "sk".to_string(),
+            endpoint: Some("http://127.0.0.1:9".to_string()),
+            region: "us-east-1".to_string(),
+            bucket: "demo".to_string(),
+            schema: "s3".to_string(),
+            session_token: None,
+            token_provider: None,
+            create_bucket_if_missing: false,
+            prefix: None,
+            cost_per_gb_read: None,
+            cost_per_gb_written: None,
+            rate_limit: None,
+        };
+        let report = db.init_storages(Config {
+            storages: HashMap::from([("s3".to_string(), storage)]),
+            storage_groups: HashMap::new(),
+            guard: Default::default(),
+            clickhouse: None,
+            notifications: Vec::new(),
+            tables: Vec::new(),
+            compression: Default::default(),
+        })?;
+        assert_eq!(*middleware.wrapped.lock().unwrap(), vec!["s3".to_string()]);
+        assert_eq!(report.storages[0].root, "s3://demo");
+        assert_eq!(
+            report.warnings,
+            vec!["Storage s3 uses plain HTTP endpoint http://127.0.0.1:9".to_string()]
+        );
+
+        // 请求没有发到 endpoint，而是到了中间件返回的内存存储
+        let store = db.registered_storages.read().unwrap()["s3"].store.clone();
+        let path = Path::from("a.csv");
+        store.put(&path, PutPayload::from("id\n1\n")).await?;
+        assert_eq!(store.get(&path).await?.bytes().await?.as_ref(), b"id\n1\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::drift::SCHEMA_DRIFT_DDL;
+use crate::ident::quote_literal;
+use crate::pool::DB;
+use anyhow::Result;
+use chrono::Utc;
+use datafusion::arrow::array::{Array, Int64Array};
+use serde::{de::DeserializeOwned, Serialize};
+
+// 记录已经执行过的迁移，和其它内部表一起备份/恢复
+pub const MIGRATIONS_TABLE: &str = "__migrations";
+const MIGRATIONS_DDL: &str = "CREATE TABLE IF NOT EXISTS __migrations \
+     (scope VARCHAR, version BIGINT, name VARCHAR, applied_at BIGINT)";
+
+// watcher 记录已经导入的对象，没有开启 storage feature 时只会是空表
+pub(crate) const INGESTED_OBJECTS_DDL: &str = "CREATE TABLE IF NOT EXISTS __ingested_objects \
+     (watch VARCHAR, location VARCHAR, size BIGINT, ingested_at BIGINT)";
+
+// 通过服务端新增的外部表定义，重启后由 restore_external_tables 重新创建
+pub(crate) const EXTERNAL_TABLES_DDL: &str = "CREATE TABLE IF NOT EXISTS __external_tables \
+     (name VARCHAR, statement VARCHAR, created_by VARCHAR, created_at BIGINT)";
+
+// cache 内部表使用的 scope
+pub const CACHE_SCOPE: &str = "cache";
+
+/// 一次 schema 变更，version 在同一个 scope 内递增，已经发布的迁移不要修改
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+/// cache 内部表的迁移，新增内部表或修改内部表结构时在末尾追加
+pub const CACHE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create __schema_drift",
+        statements: &[SCHEMA_DRIFT_DDL],
+    },
+    Migration {
+        version: 2,
+        name: "create __ingested_objects",
+        statements: &[INGESTED_OBJECTS_DDL],
+    },
+    Migration {
+        version: 3,
+        name: "create __external_tables",
+        statements: &[EXTERNAL_TABLES_DDL],
+    },
+];
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 创建/升级 cache 内部表，启动时和从备份恢复后调用，返回这次执行的版本
+    pub async fn run_migrations(&self) -> Result<Vec<i64>> {
+        self.run_migrations_with(CACHE_SCOPE, CACHE_MIGRATIONS)
+            .await
+    }
+
+    /// 执行 scope 下还没有执行过的迁移，应用自己的表可以用单独的 scope
+    pub async fn run_migrations_with(
+        &self,
+        scope: &str,
+        migrations: &[Migration],
+    ) -> Result<Vec<i64>> {
+        if let Some(pair) = migrations
+            .windows(2)
+            .find(|pair| pair[0].version >= pair[1].version)
+        {
+            return Err(anyhow::anyhow!(
+                "Migration versions must increase: {} after {}",
+                pair[1].version,
+                pair[0].version
+            ));
+        }
+
+        self.execute(MIGRATIONS_DDL).await?;
+        let current = self.schema_version(scope).await?;
+        let mut applied = Vec::new();
+        for migration in migrations.iter().filter(|m| m.version > current) {
+            for statement in migration.statements {
+                self.execute(statement).await.map_err(|e| {
+                    anyhow::anyhow!(
+                        "Migration {} {} ({}) failed: {}",
+                        scope,
+                        migration.version,
+                        migration.name,
+                        e
+                    )
+                })?;
+            }
+            self.execute(&format!(
+                "INSERT INTO {} VALUES ({}, {}, {}, {})",
+                MIGRATIONS_TABLE,
+                quote_literal(scope),
+                migration.version,
+                quote_literal(migration.name),
+                Utc::now().timestamp_millis()
+            ))
+            .await?;
+            applied.push(migration.version);
+        }
+        Ok(applied)
+    }
+
+    /// scope 下已经执行到的版本，没有执行过时为 0
+    pub async fn schema_version(&self, scope: &str) -> Result<i64> {
+        if !self.ctx.table_exist(MIGRATIONS_TABLE)? {
+            return Ok(0);
+        }
+        let batches = self
+            .query_to_batches(&format!(
+                "SELECT max(version) FROM {} WHERE scope = {}",
+                MIGRATIONS_TABLE,
+                quote_literal(scope)
+            ))
+            .await?;
+        let version = batches
+            .first()
+            .and_then(|batch| batch.column(0).as_any().downcast_ref::<Int64Array>())
+            .filter(|column| !column.is_empty() && column.is_valid(0))
+            .map_or(0, |column| column.value(0));
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_migrations() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        assert_eq!(db.schema_version(CACHE_SCOPE).await?, 0);
+        assert_eq!(db.run_migrations().await?, vec![1, 2, 3]);
+        assert!(db.ctx.table_exist("__schema_drift")?);
+        assert!(db.ctx.table_exist("__ingested_objects")?);
+        assert!(db.ctx.table_exist("__external_tables")?);
+        assert!(db.run_migrations().await?.is_empty());
+        assert_eq!(db.schema_version(CACHE_SCOPE).await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_app_table() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        const V1: &[Migration] = &[Migration {
+            version: 1,
+            name: "create orders",
+            statements: &["CREATE TABLE orders (id BIGINT)"],
+        }];
+        assert_eq!(db.run_migrations_with("app", V1).await?, vec![1]);
+        db.execute("INSERT INTO orders VALUES (1), (2)").await?;
+
+        // 新版本增加一列，已有的数据保留
+        const V2: &[Migration] = &[
+            V1[0],
+            Migration {
+                version: 2,
+                name: "add orders.amount",
+                statements: &["CREATE OR REPLACE TABLE orders AS \
+                     SELECT id, CAST(NULL AS DOUBLE) AS amount FROM orders"],
+            },
+        ];
+        assert_eq!(db.run_migrations_with("app", V2).await?, vec![2]);
+        let count = db
+            .query("SELECT * FROM orders WHERE amount IS NULL")
+            .await?
+            .count()
+            .await?;
+        assert_eq!(count, 2);
+        assert_eq!(db.schema_version("app").await?, 2);
+        assert_eq!(db.schema_version(CACHE_SCOPE).await?, 0);
+
+        let unordered = [V2[1], V2[0]];
+        assert!(db.run_migrations_with("app", &unordered).await.is_err());
+        Ok(())
+    }
+}
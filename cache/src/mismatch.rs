@@ -0,0 +1,367 @@
+use crate::pool::DB;
+use arrow::compute::can_cast_types;
+use arrow_schema::{DataType, Field, FieldRef, Schema, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::Statement;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// 写入数据和目标表不一致的一列
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnMismatch {
+    // 表中有，写入的数据中没有
+    Missing {
+        column: String,
+        data_type: DataType,
+    },
+    // 写入的数据中有，表中没有
+    Extra {
+        column: String,
+        data_type: DataType,
+    },
+    // 类型不同，suggested_cast 是可以用的 CAST 表达式
+    Type {
+        column: String,
+        expected: DataType,
+        actual: DataType,
+        suggested_cast: Option<String>,
+    },
+    // 列都在，但是顺序不同
+    Position {
+        column: String,
+        expected: usize,
+        actual: usize,
+    },
+    // NOT NULL 列中有 NULL
+    Nulls {
+        column: String,
+    },
+}
+
+impl Display for ColumnMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnMismatch::Missing { column, data_type } => {
+                write!(f, "missing column {} ({})", column, data_type)
+            }
+            ColumnMismatch::Extra { column, data_type } => {
+                write!(f, "extra column {} ({}) not in table", column, data_type)
+            }
+            ColumnMismatch::Type {
+                column,
+                expected,
+                actual,
+                suggested_cast,
+            } => {
+                write!(
+                    f,
+                    "column {}: expected {}, got {}",
+                    column, expected, actual
+                )?;
+                match suggested_cast {
+                    Some(cast) => write!(f, "; try {}", cast),
+                    None => write!(f, "; no cast available"),
+                }
+            }
+            ColumnMismatch::Position {
+                column,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "column {} is at position {}, expected {}",
+                column, actual, expected
+            ),
+            ColumnMismatch::Nulls { column } => {
+                write!(
+                    f,
+                    "column {} is NOT NULL but the data contains NULL",
+                    column
+                )
+            }
+        }
+    }
+}
+
+/// 写入的 schema 和目标表不一致，可以从 anyhow::Error 中 downcast 出来
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaMismatch {
+    pub table: String,
+    pub columns: Vec<ColumnMismatch>,
+}
+
+impl Display for SchemaMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Schema mismatch inserting into {}:", self.table)?;
+        for column in &self.columns {
+            write!(f, "\n  {}", column)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SchemaMismatch {}
+
+/// 按列名比较写入的 batch 和表的 schema，batch 的列名、类型和顺序都需要和表一致
+pub fn diagnose_batch(
+    table: &str,
+    expected: &Schema,
+    batch: &RecordBatch,
+) -> Option<SchemaMismatch> {
+    let actual = batch.schema();
+    let mut columns = Vec::new();
+    for (index, field) in expected.fields().iter().enumerate() {
+        let Ok(actual_index) = actual.index_of(field.name()) else {
+            columns.push(ColumnMismatch::Missing {
+                column: field.name().clone(),
+                data_type: field.data_type().clone(),
+            });
+            continue;
+        };
+        let actual_field = actual.field(actual_index);
+        if actual_field.data_type() != field.data_type() {
+            columns.push(type_mismatch(field, actual_field));
+        } else if actual_index != index {
+            columns.push(ColumnMismatch::Position {
+                column: field.name().clone(),
+                expected: index,
+                actual: actual_index,
+            });
+        } else if !field.is_nullable() && batch.column(actual_index).null_count() > 0 {
+            columns.push(ColumnMismatch::Nulls {
+                column: field.name().clone(),
+            });
+        }
+    }
+    for field in actual.fields() {
+        if expected.index_of(field.name()).is_err() {
+            columns.push(ColumnMismatch::Extra {
+                column: field.name().clone(),
+                data_type: field.data_type().clone(),
+            });
+        }
+    }
+    (!columns.is_empty()).then(|| SchemaMismatch {
+        table: table.to_string(),
+        columns,
+    })
+}
+
+// INSERT 按位置对应，能自动转换的类型不算不一致
+fn diagnose_positional(
+    table: &str,
+    expected: &[FieldRef],
+    actual: &Schema,
+) -> Option<SchemaMismatch> {
+    let mut columns = Vec::new();
+    for (index, field) in expected.iter().enumerate() {
+        match actual.fields().get(index) {
+            None => columns.push(ColumnMismatch::Missing {
+                column: field.name().clone(),
+                data_type: field.data_type().clone(),
+            }),
+            Some(actual_field) => {
+                if !can_cast_types(actual_field.data_type(), field.data_type()) {
+                    columns.push(type_mismatch(field, actual_field));
+                }
+            }
+        }
+    }
+    for field in actual.fields().iter().skip(expected.len()) {
+        columns.push(ColumnMismatch::Extra {
+            column: field.name().clone(),
+            data_type: field.data_type().clone(),
+        });
+    }
+    (!columns.is_empty()).then(|| SchemaMismatch {
+        table: table.to_string(),
+        columns,
+    })
+}
+
+// 建议的 CAST 作用在写入的列上，INSERT SELECT 中它的名字可能和表的列不同
+fn type_mismatch(expected: &Field, actual: &Field) -> ColumnMismatch {
+    ColumnMismatch::Type {
+        column: expected.name().clone(),
+        expected: expected.data_type().clone(),
+        actual: actual.data_type().clone(),
+        suggested_cast: suggest_cast(actual.name(), actual.data_type(), expected.data_type()),
+    }
+}
+
+// 能直接转换时用一次 CAST，否则尝试先转成字符串
+fn suggest_cast(column: &str, from: &DataType, to: &DataType) -> Option<String> {
+    if can_cast_types(from, to) {
+        return Some(cast_expr(column, to));
+    }
+    if can_cast_types(from, &DataType::Utf8) && can_cast_types(&DataType::Utf8, to) {
+        return Some(cast_expr(&format!("CAST({} AS VARCHAR)", column), to));
+    }
+    None
+}
+
+fn cast_expr(expr: &str, to: &DataType) -> String {
+    match sql_type(to) {
+        Some(sql) => format!("CAST({} AS {})", expr, sql),
+        None => format!("arrow_cast({}, '{}')", expr, to),
+    }
+}
+
+fn sql_type(data_type: &DataType) -> Option<String> {
+    let sql = match data_type {
+        DataType::Boolean => "BOOLEAN".to_string(),
+        DataType::Int8 => "TINYINT".to_string(),
+        DataType::Int16 => "SMALLINT".to_string(),
+        DataType::Int32 => "INT".to_string(),
+        DataType::Int64 => "BIGINT".to_string(),
+        DataType::UInt8 => "TINYINT UNSIGNED".to_string(),
+        DataType::UInt16 => "SMALLINT UNSIGNED".to_string(),
+        DataType::UInt32 => "INT UNSIGNED".to_string(),
+        DataType::UInt64 => "BIGINT UNSIGNED".to_string(),
+        DataType::Float32 => "REAL".to_string(),
+        DataType::Float64 => "DOUBLE".to_string(),
+        DataType::Utf8 => "VARCHAR".to_string(),
+        DataType::Date32 => "DATE".to_string(),
+        DataType::Timestamp(TimeUnit::Nanosecond, None) => "TIMESTAMP".to_string(),
+        DataType::Decimal128(precision, scale) => format!("DECIMAL({}, {})", precision, scale),
+        _ => return None,
+    };
+    Some(sql)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// INSERT 语句规划失败时检查 SELECT 的结果和表是否一致，不是 INSERT 或者没有发现问题时返回 None
+    pub(crate) async fn diagnose_insert(&self, sql: &str) -> Option<SchemaMismatch> {
+        let mut statements = DFParser::parse_sql(sql).ok()?;
+        if statements.len() != 1 {
+            return None;
+        }
+        let DFStatement::Statement(statement) = statements.pop_front()? else {
+            return None;
+        };
+        let Statement::Insert(insert) = *statement else {
+            return None;
+        };
+        let table = insert.table_name.to_string();
+        let schema = self.ctx.table_provider(table.as_str()).await.ok()?.schema();
+
+        let source = insert.source?;
+        let plan = self
+            .ctx
+            .state()
+            .statement_to_plan(DFStatement::Statement(Box::new(Statement::Query(source))))
+            .await
+            .ok()?;
+        let actual = plan.schema().as_arrow().clone();
+
+        // 指定了列时按列名找到表中的列，没有指定时按表的列顺序
+        let mut expected = Vec::new();
+        let mut unknown = Vec::new();
+        if insert.columns.is_empty() {
+            expected = schema.fields().iter().cloned().collect();
+        } else {
+            for column in &insert.columns {
+                match schema.field_with_name(&column.value) {
+                    Ok(field) => expected.push(std::sync::Arc::new(field.clone())),
+                    Err(_) => unknown.push(column.value.clone()),
+                }
+            }
+        }
+        let mut mismatch =
+            diagnose_positional(&table, &expected, &actual).unwrap_or(SchemaMismatch {
+                table,
+                columns: Vec::new(),
+            });
+        for column in unknown {
+            mismatch.columns.push(ColumnMismatch::Extra {
+                column,
+                data_type: DataType::Null,
+            });
+        }
+        (!mismatch.columns.is_empty()).then_some(mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int32Array, StringArray};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_insert_batch_mismatch() -> anyhow::Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT NOT NULL, name VARCHAR)")
+            .await?;
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("nickname", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from(vec![1])),
+                Arc::new(StringArray::from(vec!["a"])),
+            ],
+        )?;
+        let err = db.insert_batch("t", batch).await.unwrap_err();
+        let mismatch = err.downcast_ref::<SchemaMismatch>().unwrap();
+        assert_eq!(
+            mismatch.columns,
+            vec![
+                ColumnMismatch::Type {
+                    column: "id".to_string(),
+                    expected: DataType::Int64,
+                    actual: DataType::Int32,
+                    suggested_cast: Some("CAST(id AS BIGINT)".to_string()),
+                },
+                ColumnMismatch::Missing {
+                    column: "name".to_string(),
+                    data_type: DataType::Utf8,
+                },
+                ColumnMismatch::Extra {
+                    column: "nickname".to_string(),
+                    data_type: DataType::Utf8,
+                },
+            ]
+        );
+        assert!(err.to_string().contains("try CAST(id AS BIGINT)"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_select_mismatch() -> anyhow::Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, active BOOLEAN)")
+            .await?;
+        db.execute("CREATE TABLE src (id BIGINT, born DATE, extra INT)")
+            .await?;
+
+        let err = db
+            .execute("INSERT INTO t SELECT id, born, extra FROM src")
+            .await
+            .unwrap_err();
+        let mismatch = err.downcast_ref::<SchemaMismatch>().unwrap();
+        assert_eq!(mismatch.table, "t");
+        assert_eq!(mismatch.columns.len(), 2);
+        assert_eq!(
+            mismatch.columns[0],
+            ColumnMismatch::Type {
+                column: "active".to_string(),
+                expected: DataType::Boolean,
+                actual: DataType::Date32,
+                suggested_cast: Some("CAST(CAST(born AS VARCHAR) AS BOOLEAN)".to_string()),
+            }
+        );
+        assert!(matches!(
+            &mismatch.columns[1],
+            ColumnMismatch::Extra { column, .. } if column == "extra"
+        ));
+
+        // 能自动转换的类型照常写入
+        db.execute("INSERT INTO t (id) SELECT extra FROM src")
+            .await?;
+        Ok(())
+    }
+}
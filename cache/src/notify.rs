@@ -0,0 +1,299 @@
+use crate::events::CacheEvent;
+#[cfg(feature = "notify")]
+use crate::pool::DB;
+#[cfg(feature = "notify")]
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+#[cfg(feature = "notify")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "notify")]
+use std::time::Duration;
+#[cfg(feature = "notify")]
+use tokio::sync::broadcast::error::RecvError;
+
+#[cfg(feature = "notify")]
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 可以通知的事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    RefreshFailed,
+    SyncFailed,
+    ExportCompleted,
+    SchemaDrift,
+    SlaBreached,
+    QuotaExceeded,
+    JobFailed,
+}
+
+/// 一条通知规则：匹配的事件 POST 到 webhook
+///
+/// 只支持 webhook，不直接发送邮件；需要邮件通知时把 webhook 指向邮件网关
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotificationRule {
+    pub webhook: String,
+    // 为空时匹配所有事件类型
+    #[serde(default)]
+    pub events: Vec<NotificationKind>,
+    // 为空时匹配所有表，导出事件按存储名匹配，任务失败按任务名匹配
+    #[serde(default)]
+    pub tables: Vec<String>,
+}
+
+/// 发送给 webhook 的 JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub table: String,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+impl NotificationRule {
+    #[cfg_attr(not(feature = "notify"), allow(dead_code))]
+    fn matches(&self, notification: &Notification) -> bool {
+        (self.events.is_empty() || self.events.contains(&notification.kind))
+            && (self.tables.is_empty() || self.tables.contains(&notification.table))
+    }
+}
+
+impl From<&CacheEvent> for Notification {
+    fn from(event: &CacheEvent) -> Self {
+        let (kind, table, message) = match event {
+            CacheEvent::SchemaDrift(drift) => (
+                NotificationKind::SchemaDrift,
+                drift.table.clone(),
+                format!("schema drift in {} from {}", drift.location, drift.source),
+            ),
+            CacheEvent::RefreshFailed { table, error } => (
+                NotificationKind::RefreshFailed,
+                table.clone(),
+                error.clone(),
+            ),
+            CacheEvent::SyncFailed { table, error } => {
+                (NotificationKind::SyncFailed, table.clone(), error.clone())
+            }
+            CacheEvent::ExportCompleted { storage, location } => (
+                NotificationKind::ExportCompleted,
+                storage.clone(),
+                format!("exported to {}", location),
+            ),
+            CacheEvent::SlaBreached {
+                table,
+                elapsed,
+                sla,
+            } => (
+                NotificationKind::SlaBreached,
+                table.clone(),
+                format!("refresh took {:?}, sla is {:?}", elapsed, sla),
+            ),
+            CacheEvent::QuotaExceeded { table, message } => (
+                NotificationKind::QuotaExceeded,
+                table.clone(),
+                message.clone(),
+            ),
+            CacheEvent::JobFailed { job, error } => {
+                (NotificationKind::JobFailed, job.clone(), error.clone())
+            }
+        };
+        Notification {
+            kind,
+            table,
+            message,
+            at: Utc::now(),
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 后台把事件按规则发送到 webhook，直到返回的 JoinHandle 被 abort 或者 DB 被 drop
+    /// 发送失败只打印日志，不会重试
+    pub fn spawn_notifier(&self, rules: Vec<NotificationRule>) -> tokio::task::JoinHandle<()> {
+        let mut events = self.subscribe();
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "notifier lagged behind, events skipped");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let notification = Notification::from(&event);
+                for rule in rules.iter().filter(|r| r.matches(&notification)) {
+                    if let Err(e) = send_webhook(&client, &rule.webhook, &notification).await {
+                        tracing::warn!(webhook = %rule.webhook, error = %e, "notify failed");
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(feature = "notify")]
+async fn send_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    notification: &Notification,
+) -> Result<()> {
+    client
+        .post(url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .json(notification)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "notify"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // 记录收到的请求 body
+    async fn mock_webhook() -> (String, Arc<Mutex<Vec<Notification>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let body = loop {
+                    let n = socket.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break body.to_string();
+                        }
+                    }
+                };
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(serde_json::from_str(&body).unwrap());
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn test_notifier() -> Result<()> {
+        let (url, received) = mock_webhook().await;
+        let db = Arc::new(DB::<()>::new("test_db"));
+        let notifier = db.spawn_notifier(vec![NotificationRule {
+            webhook: url,
+            events: vec![
+                NotificationKind::SyncFailed,
+                NotificationKind::RefreshFailed,
+            ],
+            tables: vec!["orders".to_string()],
+        }]);
+
+        db.emit(CacheEvent::SyncFailed {
+            table: "users".to_string(),
+            error: "timeout".to_string(),
+        });
+        db.emit(CacheEvent::ExportCompleted {
+            storage: "orders".to_string(),
+            location: "s3://bucket/orders.csv".to_string(),
+        });
+        db.emit(CacheEvent::SyncFailed {
+            table: "orders".to_string(),
+            error: "timeout".to_string(),
+        });
+
+        for _ in 0..50 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        notifier.abort();
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].kind, NotificationKind::SyncFailed);
+        assert_eq!(received[0].table, "orders");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notifier_from_config() -> Result<()> {
+        use crate::config::Config;
+        use config::{Config as ConfigRs, File, FileFormat};
+
+        let (url, received) = mock_webhook().await;
+        let toml = format!(
+            r#"
+            storages = {{}}
+
+            [[notifications]]
+            webhook = "{}"
+            events = ["refresh_failed", "sla_breached"]
+
+            [[tables]]
+            name = "broken"
+            source = {{ kind = "query", sql = "SELECT * FROM missing" }}
+
+            [[tables]]
+            name = "late"
+            source = {{ kind = "query", sql = "SELECT 1" }}
+            refresh = {{ sla_secs = 0 }}
+            "#,
+            url
+        );
+        let config: Config = ConfigRs::builder()
+            .add_source(File::from_str(&toml, FileFormat::Toml))
+            .build()?
+            .try_deserialize()?;
+
+        let db = DB::<()>::new("test_db");
+        let report = db.init_from_config(config).await?;
+        assert!(!report.is_ok());
+
+        for _ in 0..50 {
+            if received.lock().unwrap().len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut kinds: Vec<(String, NotificationKind)> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| (n.table.clone(), n.kind))
+            .collect();
+        kinds.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            kinds,
+            vec![
+                ("broken".to_string(), NotificationKind::RefreshFailed),
+                ("late".to_string(), NotificationKind::SlaBreached),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use chrono::Utc;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// 超过这个时间没有读取的游标会被关闭
+pub const CURSOR_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// 一页查询结果，next_cursor 为 None 时已经没有更多数据
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+    pub rows: usize,
+    pub next_cursor: Option<String>,
+}
+
+// 正在读取的查询，流读到一半的 batch 放在 pending 中
+pub(crate) struct QueryCursor {
+    sql: String,
+    schema: SchemaRef,
+    stream: SendableRecordBatchStream,
+    pending: Option<RecordBatch>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct Cursors {
+    open: HashMap<String, QueryCursor>,
+    next_id: u64,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 分页读取查询结果，第一页 cursor 传 None，之后传上一页返回的 next_cursor
+    ///
+    /// 查询只执行一次，后面的页从同一个结果流继续读取；传入游标时 sql 需要和第一页相同。
+    /// 读到最后一页或者超过 CURSOR_IDLE_TIMEOUT 没有读取时游标失效
+    pub async fn query_page(
+        &self,
+        sql: &str,
+        page_size: usize,
+        cursor: Option<&str>,
+    ) -> Result<Page> {
+        if page_size == 0 {
+            return Err(anyhow::anyhow!("page_size must be greater than 0"));
+        }
+        self.expire_cursors();
+        let mut state = match cursor {
+            Some(cursor) => {
+                let mut cursors = self.cursors.lock().unwrap();
+                match cursors.open.get(cursor) {
+                    None => return Err(anyhow::anyhow!("Cursor {} not found or expired", cursor)),
+                    Some(state) if state.sql != sql => {
+                        return Err(anyhow::anyhow!(
+                            "Cursor {} belongs to another query",
+                            cursor
+                        ))
+                    }
+                    // 读取期间从 map 中取出，同一个游标不能同时被两个请求读取
+                    Some(_) => cursors.open.remove(cursor).unwrap(),
+                }
+            }
+            None => {
+                let df = self.query(sql).await?;
+                let schema = df.schema().inner().clone();
+                QueryCursor {
+                    sql: sql.to_string(),
+                    schema,
+                    stream: df.execute_stream().await?,
+                    pending: None,
+                    last_used: Instant::now(),
+                }
+            }
+        };
+
+        let mut batches = Vec::new();
+        let mut rows = 0;
+        while rows < page_size {
+            let batch = match state.pending.take() {
+                Some(batch) => batch,
+                None => match state.stream.next().await {
+                    Some(batch) => batch?,
+                    None => break,
+                },
+            };
+            let take = batch.num_rows().min(page_size - rows);
+            if take < batch.num_rows() {
+                state.pending = Some(batch.slice(take, batch.num_rows() - take));
+            }
+            if take > 0 {
+                batches.push(batch.slice(0, take));
+                rows += take;
+            }
+        }
+
+        // 刚好读满一页时再读一个 batch，确认后面还有没有数据
+        while state.pending.is_none() {
+            match state.stream.next().await {
+                Some(batch) => {
+                    let batch = batch?;
+                    if batch.num_rows() > 0 {
+                        state.pending = Some(batch);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        let schema = state.schema.clone();
+        let next_cursor = match state.pending {
+            Some(_) => {
+                let mut cursors = self.cursors.lock().unwrap();
+                cursors.next_id += 1;
+                let token = format!("{:x}-{:x}", Utc::now().timestamp_micros(), cursors.next_id);
+                state.last_used = Instant::now();
+                cursors.open.insert(token.clone(), state);
+                Some(token)
+            }
+            None => None,
+        };
+        Ok(Page {
+            schema,
+            batches,
+            rows,
+            next_cursor,
+        })
+    }
+
+    /// 提前关闭游标，释放还没有读取的结果，返回游标是否存在
+    pub fn close_cursor(&self, cursor: &str) -> bool {
+        self.cursors.lock().unwrap().open.remove(cursor).is_some()
+    }
+
+    pub fn open_cursors(&self) -> usize {
+        self.cursors.lock().unwrap().open.len()
+    }
+
+    fn expire_cursors(&self) {
+        self.cursors
+            .lock()
+            .unwrap()
+            .open
+            .retain(|_, cursor| cursor.last_used.elapsed() < CURSOR_IDLE_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(page: &Page) -> Vec<i64> {
+        page.batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_any()
+                    .downcast_ref::<datafusion::arrow::array::Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_query_page() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3), (4), (5)")
+            .await?;
+        let sql = "SELECT id FROM t ORDER BY id";
+
+        let first = db.query_page(sql, 2, None).await?;
+        assert_eq!(ids(&first), vec![1, 2]);
+        assert!(db
+            .query_page("SELECT id FROM t", 2, first.next_cursor.as_deref())
+            .await
+            .is_err());
+
+        let second = db.query_page(sql, 2, first.next_cursor.as_deref()).await?;
+        assert_eq!(ids(&second), vec![3, 4]);
+        let last = db.query_page(sql, 2, second.next_cursor.as_deref()).await?;
+        assert_eq!(ids(&last), vec![5]);
+        assert!(last.next_cursor.is_none());
+        assert!(db
+            .query_page(sql, 2, second.next_cursor.as_deref())
+            .await
+            .is_err());
+
+        // 刚好读完时不返回游标
+        let all = db.query_page(sql, 5, None).await?;
+        assert_eq!(all.rows, 5);
+        assert!(all.next_cursor.is_none());
+
+        let page = db.query_page(sql, 1, None).await?;
+        assert_eq!(db.open_cursors(), 1);
+        assert!(db.close_cursor(page.next_cursor.as_deref().unwrap()));
+        assert_eq!(db.open_cursors(), 0);
+        Ok(())
+    }
+}
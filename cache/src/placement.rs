@@ -0,0 +1,371 @@
+use crate::pool::DB;
+use anyhow::Result;
+use chrono::Utc;
+use futures::{StreamExt, TryStreamExt};
+use object_store::local::LocalFileSystem;
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+// 临时目录和对象的名字前缀，清理残留时按这个前缀查找
+const TEMP_PREFIX: &str = "arrow-cache-tmp";
+
+/// 临时数据所在的位置，从快到慢
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempTier {
+    Memory,
+    LocalDisk,
+    Storage,
+}
+
+/// 临时/落盘数据的放置策略，按 内存 -> 本地磁盘 -> 存储 的顺序选择第一个放得下的位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempPlacement {
+    // 内存中临时数据的总量上限，0 表示不使用内存
+    pub memory_max_bytes: usize,
+    // None 时使用系统临时目录
+    pub local_dir: Option<PathBuf>,
+    // 本地磁盘上临时数据的总量上限，None 表示不限制
+    pub local_max_bytes: Option<usize>,
+    // 本地磁盘放不下时使用的存储，数据写到 storage_prefix 下
+    #[cfg(feature = "storage")]
+    pub storage: Option<String>,
+    #[cfg(feature = "storage")]
+    pub storage_prefix: String,
+}
+
+impl Default for TempPlacement {
+    fn default() -> Self {
+        Self {
+            memory_max_bytes: 64 * 1024 * 1024,
+            local_dir: None,
+            local_max_bytes: None,
+            #[cfg(feature = "storage")]
+            storage: None,
+            #[cfg(feature = "storage")]
+            storage_prefix: TEMP_PREFIX.to_string(),
+        }
+    }
+}
+
+impl TempPlacement {
+    fn local_dir(&self) -> PathBuf {
+        self.local_dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TempUsage {
+    memory: AtomicUsize,
+    local: AtomicUsize,
+}
+
+impl TempUsage {
+    fn counter(&self, tier: TempTier) -> Option<&AtomicUsize> {
+        match tier {
+            TempTier::Memory => Some(&self.memory),
+            TempTier::LocalDisk => Some(&self.local),
+            TempTier::Storage => None,
+        }
+    }
+
+    // 不超过上限时占用 size，返回是否成功
+    fn reserve(&self, tier: TempTier, size: usize, max: usize) -> bool {
+        let Some(counter) = self.counter(tier) else {
+            return true;
+        };
+        counter
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(size).filter(|total| *total <= max)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, tier: TempTier, size: usize) {
+        if let Some(counter) = self.counter(tier) {
+            counter.fetch_sub(size, Ordering::SeqCst);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TempState {
+    placement: RwLock<TempPlacement>,
+    usage: Arc<TempUsage>,
+    memory: Arc<InMemory>,
+    next_id: AtomicU64,
+}
+
+/// 一块临时区域，drop 时删除其中的数据并释放占用，任务被取消时也会清理
+pub struct TempArea {
+    tier: TempTier,
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    reserved: usize,
+    usage: Arc<TempUsage>,
+    local: Option<tempfile::TempDir>,
+    cleaned: bool,
+}
+
+impl TempArea {
+    pub fn tier(&self) -> TempTier {
+        self.tier
+    }
+
+    pub fn store(&self) -> Arc<dyn ObjectStore> {
+        self.store.clone()
+    }
+
+    /// 区域中 name 对应的对象路径，用 store() 读写
+    pub fn path(&self, name: &str) -> Path {
+        self.prefix.child(name)
+    }
+
+    /// 本地磁盘上的目录，其他位置为 None
+    pub fn local_dir(&self) -> Option<&std::path::Path> {
+        self.local.as_ref().map(|dir| dir.path())
+    }
+
+    /// 立即删除区域中的数据并等待完成
+    pub async fn cleanup(mut self) -> Result<()> {
+        self.cleaned = true;
+        if let Some(dir) = self.local.take() {
+            dir.close()?;
+        } else {
+            delete_prefix(self.store.as_ref(), &self.prefix).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempArea {
+    fn drop(&mut self) {
+        self.usage.release(self.tier, self.reserved);
+        // 本地目录由 TempDir 删除；内存和存储中的对象需要异步删除
+        if self.cleaned || self.local.is_some() {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let store = self.store.clone();
+            let prefix = self.prefix.clone();
+            handle.spawn(async move {
+                let _ = delete_prefix(store.as_ref(), &prefix).await;
+            });
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn set_temp_placement(&self, placement: TempPlacement) {
+        *self.temp.placement.write().unwrap() = placement;
+    }
+
+    pub fn temp_placement(&self) -> TempPlacement {
+        self.temp.placement.read().unwrap().clone()
+    }
+
+    /// 为大约 size_hint 字节的临时数据选择最快的、放得下的位置
+    ///
+    /// 内存和本地磁盘按已经分配出去的区域计算占用；都放不下并且没有配置存储时返回错误
+    pub fn temp_area(&self, size_hint: usize) -> Result<TempArea> {
+        let placement = self.temp_placement();
+        let usage = self.temp.usage.clone();
+        let id = self.temp.next_id.fetch_add(1, Ordering::SeqCst);
+        let name = format!("{}-{}", Utc::now().timestamp_millis(), id);
+
+        if usage.reserve(TempTier::Memory, size_hint, placement.memory_max_bytes) {
+            return Ok(TempArea {
+                tier: TempTier::Memory,
+                store: self.temp.memory.clone(),
+                prefix: Path::from(format!("{}/{}", TEMP_PREFIX, name)),
+                reserved: size_hint,
+                usage,
+                local: None,
+                cleaned: false,
+            });
+        }
+
+        let local_max = placement.local_max_bytes.unwrap_or(usize::MAX);
+        if usage.reserve(TempTier::LocalDisk, size_hint, local_max) {
+            let dir = placement.local_dir();
+            let created = std::fs::create_dir_all(&dir)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| {
+                    let local = tempfile::Builder::new()
+                        .prefix(&format!("{}-{}-", TEMP_PREFIX, self.id))
+                        .tempdir_in(&dir)?;
+                    let store = LocalFileSystem::new_with_prefix(local.path())?;
+                    Ok((local, store))
+                });
+            let (local, store) = match created {
+                Ok(created) => created,
+                Err(e) => {
+                    usage.release(TempTier::LocalDisk, size_hint);
+                    return Err(e);
+                }
+            };
+            return Ok(TempArea {
+                tier: TempTier::LocalDisk,
+                store: Arc::new(store),
+                prefix: Path::default(),
+                reserved: size_hint,
+                usage,
+                local: Some(local),
+                cleaned: false,
+            });
+        }
+
+        #[cfg(feature = "storage")]
+        if let Some(storage) = &placement.storage {
+            return Ok(TempArea {
+                tier: TempTier::Storage,
+                store: self.storage_store(storage)?,
+                prefix: Path::from(format!(
+                    "{}/{}/{}",
+                    placement.storage_prefix.trim_matches('/'),
+                    self.id,
+                    name
+                )),
+                reserved: 0,
+                usage,
+                local: None,
+                cleaned: false,
+            });
+        }
+
+        Err(anyhow::anyhow!(
+            "No temp location can hold {} bytes",
+            size_hint
+        ))
+    }
+
+    /// 删除上次运行残留的临时数据（进程崩溃时 drop 不会执行），返回删除的目录和对象数
+    ///
+    /// 会删除本地目录和存储中属于这个 DB id 的所有临时数据，需要在创建临时区域之前调用
+    pub async fn cleanup_temp(&self) -> Result<usize> {
+        let placement = self.temp_placement();
+        let mut removed = 0;
+        let dir = placement.local_dir();
+        if dir.exists() {
+            let prefix = format!("{}-{}-", TEMP_PREFIX, self.id);
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with(&prefix)
+                    && entry.file_type()?.is_dir()
+                {
+                    std::fs::remove_dir_all(entry.path())?;
+                    removed += 1;
+                }
+            }
+        }
+
+        #[cfg(feature = "storage")]
+        if let Some(storage) = &placement.storage {
+            let store = self.storage_store(storage)?;
+            let prefix = Path::from(format!(
+                "{}/{}",
+                placement.storage_prefix.trim_matches('/'),
+                self.id
+            ));
+            removed += delete_prefix(store.as_ref(), &prefix).await?;
+        }
+        Ok(removed)
+    }
+
+    // 落盘使用的本地目录，DataFusion 只能落盘到本地磁盘
+    pub(crate) fn spill_dir(&self) -> PathBuf {
+        self.temp.placement.read().unwrap().local_dir()
+    }
+}
+
+async fn delete_prefix(store: &dyn ObjectStore, prefix: &Path) -> Result<usize> {
+    let locations = store
+        .list(Some(prefix))
+        .map_ok(|meta| meta.location)
+        .boxed();
+    let deleted = store
+        .delete_stream(locations)
+        .try_collect::<Vec<_>>()
+        .await?;
+    Ok(deleted.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::PutPayload;
+    use tempfile::tempdir;
+
+    async fn put(area: &TempArea, name: &str) -> Result<()> {
+        area.store()
+            .put(&area.path(name), PutPayload::from_static(b"spilled"))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_temp_area_placement() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        db.set_temp_placement(TempPlacement {
+            memory_max_bytes: 1000,
+            local_dir: Some(dir.path().to_path_buf()),
+            local_max_bytes: Some(10_000),
+            ..Default::default()
+        });
+
+        let small = db.temp_area(600)?;
+        assert_eq!(small.tier(), TempTier::Memory);
+        put(&small, "part-0").await?;
+
+        // 内存放不下时放到本地磁盘
+        let medium = db.temp_area(600)?;
+        assert_eq!(medium.tier(), TempTier::LocalDisk);
+        put(&medium, "part-0").await?;
+        let local = medium.local_dir().unwrap().to_path_buf();
+        assert!(local.join("part-0").exists());
+
+        // 没有配置存储，本地磁盘也放不下
+        assert!(db.temp_area(20_000).is_err());
+
+        drop(medium);
+        assert!(!local.exists());
+        small.cleanup().await?;
+        assert_eq!(db.temp_area(1000)?.tier(), TempTier::Memory);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_temp_after_abort() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        db.set_temp_placement(TempPlacement {
+            memory_max_bytes: 0,
+            local_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        });
+
+        // 任务被取消时 TempArea 被 drop
+        let task = tokio::spawn({
+            let area = db.temp_area(100)?;
+            async move {
+                let _area = area;
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            }
+        });
+        task.abort();
+        let _ = task.await;
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+
+        // 进程崩溃时 drop 不会执行，下次启动时清理
+        std::mem::forget(db.temp_area(100)?);
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        assert_eq!(db.cleanup_temp().await?, 1);
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+        Ok(())
+    }
+}
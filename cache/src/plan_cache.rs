@@ -0,0 +1,307 @@
+use crate::lineage::plan_lineage;
+use crate::pool::DB;
+use crate::workload::fingerprint_normalized;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::common::ScalarValue;
+use datafusion::datasource::{source_as_provider, TableProvider};
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::tokenizer::{Token, Tokenizer};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 缓存的逻辑计划，以及生成计划时引用的表
+#[derive(Clone)]
+pub(crate) struct CachedPlan {
+    sql: String,
+    plan: LogicalPlan,
+    // 表名 -> 生成计划时的 provider，表被替换之后计划失效
+    tables: Vec<(String, Arc<dyn TableProvider>)>,
+    hits: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct PlanCache {
+    entries: HashMap<String, CachedPlan>,
+    hits: u64,
+    misses: u64,
+}
+
+/// 计划缓存的统计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// 持久化到文件的内容，重启后重新生成计划
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedPlan {
+    pub fingerprint: String,
+    pub sql: String,
+}
+
+// 按 token 去掉空白和注释，字符串字面量中的空白不变，字面量不同的 SQL 是不同的计划
+fn plan_fingerprint(sql: &str) -> String {
+    let normalized = match Tokenizer::new(&GenericDialect {}, sql).tokenize() {
+        Ok(tokens) => tokens
+            .iter()
+            .filter(|t| !matches!(t, Token::Whitespace(_)))
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        // 无法切分成 token 的 SQL 按原样作为键
+        Err(_) => sql.to_string(),
+    };
+    fingerprint_normalized(&normalized)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 执行带 $1、$2 参数的查询，相同 SQL 重复执行时复用缓存的逻辑计划，跳过解析和规划
+    pub async fn execute_prepared(
+        &self,
+        sql: &str,
+        params: Vec<ScalarValue>,
+    ) -> Result<Vec<RecordBatch>> {
+        let started_at = Utc::now();
+        let start = Instant::now();
+        let plan = self.prepared_plan(sql).await?;
+        let result = async {
+            let has_params = !params.is_empty();
+            match plan.with_param_values(params)? {
+                // WAL 中的 DDL 按 SQL 重放，不能带参数
+                LogicalPlan::Ddl(_) if has_params => Err(anyhow::anyhow!(
+                    "DDL with parameters cannot be executed as a prepared statement"
+                )),
+                // 写入和 DDL 和 query 一样加表锁、先写入 WAL，成功之后才记录血缘
+                plan @ (LogicalPlan::Dml(_) | LogicalPlan::Ddl(_)) => {
+                    let lineage = plan_lineage(&plan);
+                    let batches = self.execute_logged(sql, plan).await?.collect().await?;
+                    if let Err(e) = self.record_lineage(lineage) {
+                        tracing::warn!(sql = %sql, error = %e, "record lineage failed");
+                    }
+                    Ok(batches)
+                }
+                plan => Ok(self.ctx.execute_logical_plan(plan).await?.collect().await?),
+            }
+        }
+        .await;
+        let rows = match &result {
+            Ok(batches) => batches.iter().map(|b: &RecordBatch| b.num_rows()).sum(),
+            Err(_) => 0,
+        };
+        self.record_workload(sql, start.elapsed(), rows, result.is_ok());
+        self.record_recent_query(sql, started_at, start.elapsed(), &result)?;
+        result
+    }
+
+    pub fn plan_cache_stats(&self) -> PlanCacheStats {
+        let cache = self.plan_cache.read().unwrap();
+        PlanCacheStats {
+            entries: cache.entries.len(),
+            hits: cache.hits,
+            misses: cache.misses,
+        }
+    }
+
+    pub fn clear_plan_cache(&self) {
+        self.plan_cache.write().unwrap().entries.clear();
+    }
+
+    /// 把缓存的 SQL 写到文件，按命中次数倒序
+    ///
+    /// 逻辑计划引用了内存中的表，不能直接序列化，重启后通过 warm_plan_cache 重新规划
+    pub fn save_plan_cache(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let mut entries: Vec<(String, CachedPlan)> = self
+            .plan_cache
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        entries.sort_by(|a, b| b.1.hits.cmp(&a.1.hits).then_with(|| a.0.cmp(&b.0)));
+        let persisted: Vec<PersistedPlan> = entries
+            .into_iter()
+            .map(|(fingerprint, cached)| PersistedPlan {
+                fingerprint,
+                sql: cached.sql,
+            })
+            .collect();
+        std::fs::write(path.as_ref(), serde_json::to_vec_pretty(&persisted)?)
+            .with_context(|| format!("write {}", path.as_ref().display()))?;
+        Ok(persisted.len())
+    }
+
+    /// 读取 save_plan_cache 写的文件并提前生成计划，返回成功预热的数量
+    /// 表已经不存在或者 schema 变化导致规划失败的 SQL 会被跳过
+    pub async fn warm_plan_cache(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let data = std::fs::read(path.as_ref())
+            .with_context(|| format!("read {}", path.as_ref().display()))?;
+        let persisted: Vec<PersistedPlan> = serde_json::from_slice(&data)?;
+        let mut warmed = 0;
+        for entry in persisted {
+            if self.prepared_plan(&entry.sql).await.is_ok() {
+                warmed += 1;
+            }
+        }
+        Ok(warmed)
+    }
+
+    async fn prepared_plan(&self, sql: &str) -> Result<LogicalPlan> {
+        let key = plan_fingerprint(sql);
+        let cached = self.plan_cache.read().unwrap().entries.get(&key).cloned();
+        if let Some(cached) = cached {
+            if self.plan_is_current(&cached).await {
+                let mut cache = self.plan_cache.write().unwrap();
+                cache.hits += 1;
+                if let Some(entry) = cache.entries.get_mut(&key) {
+                    entry.hits += 1;
+                }
+                return Ok(cached.plan);
+            }
+        }
+
+        let plan = self
+            .ctx
+            .state()
+            .create_logical_plan(sql)
+            .await
+            .map_err(|e| anyhow::anyhow!("Query error: {}", e))?;
+        let tables = scanned_tables(&plan)?;
+        let mut cache = self.plan_cache.write().unwrap();
+        cache.misses += 1;
+        cache.entries.insert(
+            key,
+            CachedPlan {
+                sql: sql.to_string(),
+                plan: plan.clone(),
+                tables,
+                hits: 0,
+            },
+        );
+        Ok(plan)
+    }
+
+    // 计划引用的表都没有被删除或替换
+    async fn plan_is_current(&self, cached: &CachedPlan) -> bool {
+        for (table, provider) in &cached.tables {
+            match self.ctx.table_provider(table.as_str()).await {
+                Ok(current) if Arc::ptr_eq(&current, provider) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+fn scanned_tables(plan: &LogicalPlan) -> Result<Vec<(String, Arc<dyn TableProvider>)>> {
+    let mut tables = Vec::new();
+    plan.apply(|node| {
+        if let LogicalPlan::TableScan(scan) = node {
+            if let Ok(provider) = source_as_provider(&scan.source) {
+                tables.push((scan.table_name.to_string(), provider));
+            }
+        }
+        Ok(TreeNodeRecursion::Continue)
+    })?;
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int64Array;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_fingerprint() {
+        assert_eq!(
+            plan_fingerprint("SELECT  *\nFROM t WHERE id = $1"),
+            plan_fingerprint("SELECT * FROM t WHERE id = $1")
+        );
+        // 字符串字面量中的空白不能被合并
+        assert_ne!(
+            plan_fingerprint("SELECT * FROM t WHERE name = 'a  b'"),
+            plan_fingerprint("SELECT * FROM t WHERE name = 'a b'")
+        );
+    }
+
+    fn ids(batches: &[RecordBatch]) -> Vec<i64> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_execute_prepared() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')")
+            .await?;
+
+        let sql = "SELECT id FROM t WHERE id > $1 ORDER BY id";
+        let batches = db
+            .execute_prepared(sql, vec![ScalarValue::Int64(Some(1))])
+            .await?;
+        assert_eq!(ids(&batches), vec![2, 3]);
+        let batches = db
+            .execute_prepared(sql, vec![ScalarValue::Int64(Some(2))])
+            .await?;
+        assert_eq!(ids(&batches), vec![3]);
+        assert_eq!(
+            db.plan_cache_stats(),
+            PlanCacheStats {
+                entries: 1,
+                hits: 1,
+                misses: 1
+            }
+        );
+
+        // 插入的数据对缓存的计划可见
+        db.execute("INSERT INTO t VALUES (4, 'd')").await?;
+        let batches = db
+            .execute_prepared(sql, vec![ScalarValue::Int64(Some(2))])
+            .await?;
+        assert_eq!(ids(&batches), vec![3, 4]);
+
+        // 表被重建之后重新规划
+        db.execute("DROP TABLE t").await?;
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (5, 'e')").await?;
+        let batches = db
+            .execute_prepared(sql, vec![ScalarValue::Int64(Some(2))])
+            .await?;
+        assert_eq!(ids(&batches), vec![5]);
+        assert_eq!(db.plan_cache_stats().misses, 2);
+
+        let dir = tempdir()?;
+        let path = dir.path().join("plans.json");
+        assert_eq!(db.save_plan_cache(&path)?, 1);
+        db.clear_plan_cache();
+        assert_eq!(db.plan_cache_stats().entries, 0);
+        assert_eq!(db.warm_plan_cache(&path).await?, 1);
+        let hits = db.plan_cache_stats().hits;
+        db.execute_prepared(sql, vec![ScalarValue::Int64(Some(0))])
+            .await?;
+        assert_eq!(db.plan_cache_stats().hits, hits + 1);
+        Ok(())
+    }
+}
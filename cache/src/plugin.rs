@@ -0,0 +1,170 @@
+#[cfg(feature = "clickhouse")]
+use crate::ck_client::{ClickHouseClient, QueryHints};
+use crate::pool::DB;
+use anyhow::Result;
+use async_trait::async_trait;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+/// 自定义语句插件，在 SQL 解析之前调用，用来支持 DataFusion 不认识的语句，
+/// 比如 `CACHE TABLE x FROM orders WHERE dt > '2024-05-01'`、`REFRESH VIEW y`
+///
+/// query、execute、query_to_batches 等执行 SQL 的方法都会先交给插件，
+/// 按添加的顺序尝试，第一个返回 Some 的插件处理这条语句
+#[async_trait]
+pub trait StatementPlugin<V = ()>: Send + Sync
+where
+    V: Serialize + DeserializeOwned + Send + Sync,
+{
+    fn name(&self) -> &str;
+
+    // 不认识的语句返回 None，交给下一个插件或者 DataFusion
+    async fn handle(&self, db: &DB<V>, sql: &str) -> Result<Option<Vec<RecordBatch>>>;
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn add_statement_plugin(&self, plugin: Arc<dyn StatementPlugin<V>>) {
+        self.statement_plugins.write().unwrap().push(plugin);
+    }
+
+    /// 和 query_to_batches 相同，插件认领的语句返回插件的结果
+    pub async fn run_statement(&self, sql: &str) -> Result<Vec<RecordBatch>> {
+        self.query_to_batches(sql).await
+    }
+
+    // 交给插件处理，没有插件认领时返回 None，按普通 SQL 执行
+    pub(crate) async fn run_plugins(&self, sql: &str) -> Result<Option<Vec<RecordBatch>>> {
+        let plugins = self.statement_plugins.read().unwrap().clone();
+        for plugin in plugins {
+            if let Some(batches) = plugin.handle(self, sql).await.map_err(|e| {
+                anyhow::anyhow!("Statement plugin {} failed: {:#}", plugin.name(), e)
+            })? {
+                return Ok(Some(batches));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// `CACHE TABLE <table> FROM <clickhouse 表> [WHERE ...]`：从 ClickHouse 拉取数据替换本地表
+#[cfg(feature = "clickhouse")]
+pub struct CacheTablePlugin {
+    client: ClickHouseClient,
+    hints: QueryHints,
+}
+
+#[cfg(feature = "clickhouse")]
+impl CacheTablePlugin {
+    pub fn new(client: ClickHouseClient) -> Self {
+        Self {
+            client,
+            hints: QueryHints::default(),
+        }
+    }
+
+    pub fn with_hints(mut self, hints: QueryHints) -> Self {
+        self.hints = hints;
+        self
+    }
+}
+
+#[cfg(feature = "clickhouse")]
+#[async_trait]
+impl<V: Serialize + DeserializeOwned + Send + Sync> StatementPlugin<V> for CacheTablePlugin {
+    fn name(&self) -> &str {
+        "cache_table"
+    }
+
+    async fn handle(&self, db: &DB<V>, sql: &str) -> Result<Option<Vec<RecordBatch>>> {
+        let Some((table, source)) = parse_cache_table(sql) else {
+            return Ok(None);
+        };
+        db.refresh_from_clickhouse(
+            table,
+            &format!("SELECT * FROM {}", source),
+            &self.client,
+            &self.hints,
+        )
+        .await?;
+        Ok(Some(Vec::new()))
+    }
+}
+
+// 拆出 (本地表名, FROM 后面的部分)
+#[cfg(feature = "clickhouse")]
+fn parse_cache_table(sql: &str) -> Option<(&str, &str)> {
+    let sql = sql.trim().trim_end_matches(';').trim_end();
+    let mut words = sql.splitn(5, char::is_whitespace).filter(|w| !w.is_empty());
+    if !words.next()?.eq_ignore_ascii_case("CACHE") || !words.next()?.eq_ignore_ascii_case("TABLE")
+    {
+        return None;
+    }
+    let rest = sql[sql.to_ascii_uppercase().find("TABLE")? + "TABLE".len()..].trim_start();
+    let (table, rest) = rest.split_once(char::is_whitespace)?;
+    let rest = rest.trim_start();
+    if rest.len() < 4 || !rest[..4].eq_ignore_ascii_case("FROM") {
+        return None;
+    }
+    let source = rest[4..].trim();
+    if source.is_empty() {
+        return None;
+    }
+    Some((table, source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::arrow::array::StringArray;
+
+    struct EchoPlugin;
+
+    #[async_trait]
+    impl StatementPlugin for EchoPlugin {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        async fn handle(&self, _db: &DB<()>, sql: &str) -> Result<Option<Vec<RecordBatch>>> {
+            let Some(text) = sql.strip_prefix("ECHO ") else {
+                return Ok(None);
+            };
+            let schema = Arc::new(Schema::new(vec![Field::new("text", DataType::Utf8, false)]));
+            Ok(Some(vec![RecordBatch::try_new(
+                schema,
+                vec![Arc::new(StringArray::from(vec![text]))],
+            )?]))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_statement_plugin() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        assert!(db.run_statement("ECHO hello").await.is_err());
+
+        db.add_statement_plugin(Arc::new(EchoPlugin));
+        let batches = db.run_statement("ECHO hello").await?;
+        assert_eq!(batches[0].num_rows(), 1);
+        // query、execute 等方法也会先交给插件
+        let batches = db.query("ECHO hello").await?.collect().await?;
+        assert_eq!(batches[0].num_rows(), 1);
+        db.execute("ECHO hello").await?;
+        // 普通 SQL 仍然交给 DataFusion
+        let batches = db.run_statement("SELECT 1").await?;
+        assert_eq!(batches[0].num_rows(), 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "clickhouse")]
+    #[test]
+    fn test_parse_cache_table() {
+        assert_eq!(
+            parse_cache_table("cache table x from clickhouse.orders WHERE dt > '2024-05-01';"),
+            Some(("x", "clickhouse.orders WHERE dt > '2024-05-01'"))
+        );
+        assert_eq!(parse_cache_table("CACHE TABLE x"), None);
+        assert_eq!(parse_cache_table("SELECT * FROM cache"), None);
+    }
+}
@@ -1,67 +1,255 @@
+#[cfg(feature = "storage")]
+use crate::accounting::StorageCounters;
+use crate::accounting::TableUsage;
+use crate::calendar::register_calendar_functions;
+use crate::cancel::RunningQueries;
 use crate::ck::ClickHouseTableProvider;
+use crate::codec::{batches_to_rows, NumberMode};
+use crate::coercion::CoercionConfig;
+use crate::compression::CompressionState;
+use crate::config::GuardConfig;
+#[cfg(feature = "storage")]
 use crate::config::StorageConfig;
+use crate::events::{CacheEvent, EVENT_CHANNEL_CAPACITY};
+use crate::eviction::BudgetState;
+use crate::finance::register_finance_functions;
+use crate::idempotency::DedupLedger;
+use crate::kv_schema::KvTableState;
+use crate::lineage::{plan_lineage, ColumnLineage};
+use crate::live::{TableChange, TABLE_CHANGE_CAPACITY};
+use crate::middleware::StoreMiddleware;
+use crate::mismatch::diagnose_batch;
+use crate::pagination::Cursors;
+use crate::placement::TempState;
+use crate::plan_cache::PlanCache;
+use crate::plugin::StatementPlugin;
+use crate::provenance::ProvenanceLog;
+use crate::recent::RecentQueries;
+use crate::snapshot::FrozenTable;
+use crate::swap::{install_swap_schema, is_memory_table};
+use crate::time_travel::VersionState;
+use crate::wal::{Wal, WalRecord};
+use crate::watermark::WatermarkState;
+use crate::workload::{install_workload_table, rows_scanned, WorkloadStats};
 use anyhow::{Ok, Result};
 use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::Utc;
 use datafusion::arrow::array::{
-    ArrayRef, BooleanArray, Float64Array, Int32Array, Int64Array, StringArray, UInt64Array,
+    new_empty_array, ArrayRef, BooleanArray, Decimal128Array, Float64Array, Int32Array, Int64Array,
+    StringArray, UInt64Array,
 };
 use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::dataframe::DataFrameWriteOptions;
 use datafusion::datasource::TableProvider;
+use datafusion::physical_plan::{collect, ExecutionPlan};
 use datafusion::prelude::*;
+use futures::{Stream, StreamExt};
+#[cfg(feature = "storage")]
+use object_store::aws::AwsCredentialProvider;
+#[cfg(feature = "storage")]
 use object_store::ObjectStore;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 
 const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(30);
 
+#[cfg(feature = "storage")]
 pub struct StorageEntry {
     pub store: Arc<dyn ObjectStore>,
+    pub credentials: AwsCredentialProvider,
     pub config: StorageConfig,
+    // 通过这个存储读写的字节数
+    pub counters: Arc<StorageCounters>,
 }
 
 pub struct DB<V: Serialize + DeserializeOwned + Send + Sync> {
     pub id: String,
     pub ctx: SessionContext,
     _phantom: std::marker::PhantomData<V>,
-    sync_interval: Duration,
+    // 后台循环（比如行级 TTL 清理）的执行间隔
+    pub(crate) sync_interval: RwLock<Duration>,
+    #[cfg(feature = "storage")]
     pub registered_storages: RwLock<HashMap<String, StorageEntry>>,
+    pub(crate) events: broadcast::Sender<CacheEvent>,
+    pub(crate) lineage: RwLock<BTreeSet<ColumnLineage>>,
+    pub(crate) workload: Arc<RwLock<HashMap<String, WorkloadStats>>>,
+    pub(crate) table_usage: RwLock<HashMap<String, TableUsage>>,
+    pub(crate) guard: RwLock<GuardConfig>,
+    pub(crate) table_meta: RwLock<BTreeMap<String, BTreeMap<String, String>>>,
+    pub(crate) store_middleware: RwLock<Vec<Arc<dyn StoreMiddleware>>>,
+    pub(crate) watermarks: RwLock<HashMap<String, WatermarkState>>,
+    pub(crate) dedup: RwLock<HashMap<String, DedupLedger>>,
+    pub(crate) statement_plugins: RwLock<Vec<Arc<dyn StatementPlugin<V>>>>,
+    // 存储过程名 -> Rhai 脚本
+    pub(crate) procedures: RwLock<HashMap<String, String>>,
+    pub(crate) plan_cache: RwLock<PlanCache>,
+    pub(crate) recent_queries: RwLock<RecentQueries>,
+    // 每个表的写锁，见 lock_table
+    pub(crate) table_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    pub(crate) memory_budget: RwLock<BudgetState>,
+    pub(crate) wal: RwLock<Option<Arc<Wal>>>,
+    pub(crate) temp: TempState,
+    // query_page 返回的游标
+    pub(crate) cursors: Mutex<Cursors>,
+    pub(crate) coercions: RwLock<HashMap<String, CoercionConfig>>,
+    pub(crate) running_queries: Mutex<RunningQueries>,
+    pub(crate) compression: RwLock<CompressionState>,
+    pub(crate) versions: RwLock<VersionState>,
+    pub(crate) table_changes: broadcast::Sender<TableChange>,
+    // 开启了行 ID 的表下一个要分配的 ID
+    pub(crate) row_ids: Mutex<HashMap<String, u64>>,
+    pub(crate) provenance: RwLock<ProvenanceLog>,
+    // 每个 KvStore 表的时钟、retention 和已经清理的历史
+    pub(crate) kv_tables: Mutex<HashMap<String, Arc<KvTableState>>>,
 }
 
 impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
     pub fn new(id: &str) -> Self {
+        Self::with_context(id, SessionContext::new())
+    }
+
+    // DB::new 和 DBBuilder 共用，ctx 中还没有注册任何表
+    pub(crate) fn with_context(id: &str, ctx: SessionContext) -> Self {
+        install_swap_schema(&ctx);
+        let workload = Arc::new(RwLock::new(HashMap::new()));
+        install_workload_table(&ctx, workload.clone());
+        register_finance_functions(&ctx);
+        register_calendar_functions(&ctx);
         Self {
             id: id.to_string(),
-            ctx: SessionContext::new(),
+            ctx,
             _phantom: std::marker::PhantomData,
-            sync_interval: DEFAULT_SYNC_INTERVAL,
+            sync_interval: RwLock::new(DEFAULT_SYNC_INTERVAL),
+            #[cfg(feature = "storage")]
             registered_storages: RwLock::new(HashMap::new()),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            lineage: RwLock::new(BTreeSet::new()),
+            workload,
+            table_usage: RwLock::new(HashMap::new()),
+            guard: RwLock::new(GuardConfig::default()),
+            table_meta: RwLock::new(BTreeMap::new()),
+            store_middleware: RwLock::new(Vec::new()),
+            watermarks: RwLock::new(HashMap::new()),
+            dedup: RwLock::new(HashMap::new()),
+            statement_plugins: RwLock::new(Vec::new()),
+            procedures: RwLock::new(HashMap::new()),
+            plan_cache: RwLock::new(PlanCache::default()),
+            recent_queries: RwLock::new(RecentQueries::default()),
+            table_locks: Mutex::new(HashMap::new()),
+            memory_budget: RwLock::new(BudgetState::default()),
+            wal: RwLock::new(None),
+            temp: TempState::default(),
+            cursors: Mutex::new(Cursors::default()),
+            coercions: RwLock::new(HashMap::new()),
+            running_queries: Mutex::new(RunningQueries::default()),
+            compression: RwLock::new(CompressionState::default()),
+            versions: RwLock::new(VersionState::default()),
+            table_changes: broadcast::channel(TABLE_CHANGE_CAPACITY).0,
+            row_ids: Mutex::new(HashMap::new()),
+            provenance: RwLock::new(ProvenanceLog::default()),
+            kv_tables: Mutex::new(HashMap::new()),
         }
     }
 
+    /// 后台循环的执行间隔，默认 30 秒，修改后从下一轮开始生效
+    pub fn set_sync_interval(&self, interval: Duration) {
+        *self.sync_interval.write().unwrap() = interval;
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        *self.sync_interval.read().unwrap()
+    }
+
     // create table
     // use arrow schema & arrow array to create table
     pub async fn create_table(&self, s: SchemaRef) -> Result<()> {
+        let _guard = self.lock_table(&self.id).await;
+        if self.ctx.table_exist(self.table_ref(&self.id))? {
+            return Err(anyhow::anyhow!("The table {} already exists", self.id));
+        }
         let empty_batch = RecordBatch::try_new(s.clone(), create_empty_columns(&s))?;
-        self.ctx.register_batch(&self.id, empty_batch)?;
-        Ok(())
+        self.rewrite_table(&self.id, s, vec![vec![empty_batch]], None)
     }
 
     pub async fn create_table_with_provider(&self, s: SchemaRef) -> Result<()> {
-        let empty_batch = RecordBatch::try_new(s.clone(), create_empty_columns(&s))?;
-        self.ctx.register_batch(&self.id, empty_batch)?;
+        self.create_table(s).await?;
         let provider = Arc::new(ClickHouseTableProvider::new()) as Arc<dyn TableProvider>;
         let _ = self.ctx.read_table(provider)?;
         Ok(())
     }
 
+    /// 默认 schema 下的所有表，按名字排序
+    pub fn list_tables(&self) -> Result<Vec<String>> {
+        let mut tables = self.default_schema()?.table_names();
+        tables.sort();
+        Ok(tables)
+    }
+
+    pub async fn table_schema(&self, table: &str) -> Result<SchemaRef> {
+        let table_ref = self.table_ref(table);
+        match self.ctx.table_provider(table_ref).await {
+            std::result::Result::Ok(provider) => Ok(provider.schema()),
+            Err(_) => Err(anyhow::anyhow!("Table {} not found", table)),
+        }
+    }
+
+    /// 注销表并清理表的自定义信息，表不存在时返回 false
+    ///
+    /// 持有表的写锁，同时进行的写入不会写到已经删除的表里
+    pub async fn drop_table(&self, table: &str) -> Result<bool> {
+        let _guard = self.lock_table(table).await;
+        let table_ref = self.table_ref(table);
+        if !self.ctx.table_exist(table_ref.clone())? {
+            return Ok(false);
+        }
+        if let Some(wal) = self.wal() {
+            wal.append(&WalRecord::Ddl {
+                sql: format!("DROP TABLE IF EXISTS {}", self.quote_table(table)),
+            })?;
+        }
+        if self.ctx.deregister_table(table_ref.clone())?.is_none() {
+            return Ok(false);
+        }
+        if let Some(key) = self.version_key(&table_ref) {
+            self.record_version(&key, None);
+        }
+        self.clear_table_meta(table_ref.table())?;
+        self.clear_provenance(table_ref.table())?;
+        self.kv_tables.lock().unwrap().remove(table_ref.table());
+        Ok(true)
+    }
+
     pub async fn query(&self, sql: &str) -> Result<DataFrame> {
-        self.ctx
-            .sql(sql)
+        if let Some(batches) = self.run_plugins(sql).await? {
+            return Ok(self.ctx.read_batches(batches)?);
+        }
+        if let Some(df) = self.insert_coerced(sql).await? {
+            return Ok(df);
+        }
+        let plan = match self.ctx.state().create_logical_plan(sql).await {
+            std::result::Result::Ok(plan) => plan,
+            Err(e) => {
+                // INSERT 的列和表对不上时给出每一列的问题，而不是 Arrow 的原始错误
+                if let Some(mismatch) = self.diagnose_insert(sql).await {
+                    return Err(mismatch.into());
+                }
+                return Err(anyhow::anyhow!("Query error: {}", e));
+            }
+        };
+        let lineage = plan_lineage(&plan);
+        self.record_table_access(&plan);
+        let df = self
+            .execute_logged(sql, plan)
             .await
-            .map_err(|e| anyhow::anyhow!("Query error: {}", e))
+            .map_err(|e| anyhow::anyhow!("Query error: {}", e))?;
+        if let Err(e) = self.record_lineage(lineage) {
+            tracing::warn!(sql = %sql, error = %e, "record lineage failed");
+        }
+        Ok(df)
     }
 
     pub async fn query_to_schema(&self, sql: &str) -> Result<Vec<V>> {
@@ -89,6 +277,26 @@ impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
         Ok(results)
     }
 
+    /// 和 query_to_schema 一样把结果转换成 V，但按 batch 逐个转换，不会一次性把结果都放在内存里
+    /// 通过 DataFrame 流式执行，不计入 workload 和 accounting 统计
+    pub async fn query_iter(&self, sql: &str) -> Result<impl Stream<Item = Result<V>>> {
+        let stream = self.query(sql).await?.execute_stream().await?;
+        Ok(stream
+            .map(|batch| -> Result<Vec<Result<V>>> {
+                let rows = batches_to_rows(&[batch?])?;
+                Ok(rows
+                    .into_iter()
+                    .map(|row| Ok(serde_json::from_value(Value::Object(row))?))
+                    .collect())
+            })
+            .flat_map(|rows| futures::stream::iter(rows.unwrap_or_else(|e| vec![Err(e)]))))
+    }
+
+    /// query_to_schema 的流式版本，和 query_iter 相同
+    pub async fn query_to_stream(&self, sql: &str) -> Result<impl Stream<Item = Result<V>>> {
+        self.query_iter(sql).await
+    }
+
     pub async fn query_to_json(&self, sql: &str) -> anyhow::Result<serde_json::Value> {
         let batches = self.query_to_batches(sql).await?;
         for batch in batches {
@@ -114,30 +322,193 @@ impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
     }
 
     pub async fn query_to_batches(&self, sql: &str) -> Result<Vec<RecordBatch>> {
+        self.run(sql).await
+    }
+
+    // 执行 SQL 并收集结果，同时记录 workload 统计（延迟、扫描行数）
+    pub(crate) async fn run(&self, sql: &str) -> Result<Vec<RecordBatch>> {
+        self.run_with_plan(sql).await.map(|(batches, _)| batches)
+    }
+
+    // 和 run 一样，同时返回执行过的物理计划，可以从中读取各个节点的 metrics
+    pub(crate) async fn run_with_plan(
+        &self,
+        sql: &str,
+    ) -> Result<(Vec<RecordBatch>, Arc<dyn ExecutionPlan>)> {
+        let started_at = Utc::now();
+        let start = Instant::now();
+        let result = self.run_plan(sql).await;
+        let rows = result
+            .as_ref()
+            .map(|(_, plan)| rows_scanned(plan))
+            .unwrap_or(0);
+        self.record_workload(sql, start.elapsed(), rows, result.is_ok());
+        let (result, plan) = match result {
+            std::result::Result::Ok((batches, plan)) => (Ok(batches), Some(plan)),
+            Err(e) => (Err(e), None),
+        };
+        self.record_recent_query(sql, started_at, start.elapsed(), &result)?;
+        Ok((result?, plan.expect("plan exists when the query succeeded")))
+    }
+
+    async fn run_plan(&self, sql: &str) -> Result<(Vec<RecordBatch>, Arc<dyn ExecutionPlan>)> {
         let df = self.query(sql).await?;
-        df.collect()
+        let logical = df.logical_plan().clone();
+        let plan = df.create_physical_plan().await?;
+        let batches = collect(plan.clone(), self.ctx.task_ctx())
+            .await
+            .map_err(|e| anyhow::anyhow!("Error collecting results: {}", e))?;
+        self.record_table_usage(&logical, &plan);
+        Ok((batches, plan))
+    }
+
+    // 不经过 SQL 解析，直接把 batch 追加到表里；开启 WAL 时先写入 WAL 再追加
+    pub(crate) async fn append_batches(
+        &self,
+        table: &str,
+        batches: Vec<RecordBatch>,
+    ) -> Result<()> {
+        if batches.iter().all(|b| b.num_rows() == 0) {
+            return Ok(());
+        }
+        let _guard = self.lock_table(table).await;
+        self.append_locked(table, batches).await
+    }
+
+    // 调用方已经持有表的写锁
+    pub(crate) async fn append_locked(&self, table: &str, batches: Vec<RecordBatch>) -> Result<()> {
+        let batches = match self.ctx.table_provider(self.table_ref(table)).await {
+            std::result::Result::Ok(provider) => {
+                self.assign_row_ids(table, &provider.schema(), batches)
+                    .await?
+            }
+            Err(_) => return Err(anyhow::anyhow!("Table {} not found", table)),
+        };
+        self.write_append(table, batches, true).await
+    }
+
+    // 不写 WAL 的 append_batches，调用方需要持有表的写锁；WAL 重放时直接调用
+    pub(crate) async fn apply_append(&self, table: &str, batches: Vec<RecordBatch>) -> Result<()> {
+        self.write_append(table, batches, false).await
+    }
+
+    // log 为 true 时在修改表之前写入 WAL，调用方需要持有表的写锁
+    //
+    // 写入 WAL 之后修改表不能被取消，否则 WAL 中有记录但表没有修改：copy-on-write 在写入 WAL
+    // 之前读出旧数据，之后只有同步的替换；原地追加在单独的任务中执行，调用方被 drop 时也会写完
+    pub(crate) async fn write_append(
+        &self,
+        table: &str,
+        batches: Vec<RecordBatch>,
+        log: bool,
+    ) -> Result<()> {
+        let key = self.version_key(&self.table_ref(table));
+        if let Some(key) = &key {
+            if self.time_travel_enabled() {
+                if let Some((provider, rows)) = self.copy_on_write_rows(key, &batches).await? {
+                    if log {
+                        self.log_insert(table, &batches)?;
+                    }
+                    return self.apply_replace(key, provider.schema(), vec![rows], Some(&provider));
+                }
+            }
+        }
+        if log {
+            self.log_insert(table, &batches)?;
+        }
+        let df = self.ctx.read_batches(batches)?;
+        let target = table.to_string();
+        tokio::spawn(async move { df.write_table(&target, DataFrameWriteOptions::new()).await })
             .await
-            .map_err(|e| anyhow::anyhow!("Error collecting results: {}", e))
+            .map_err(|e| anyhow::anyhow!("Append to {} panicked: {}", table, e))??;
+        if let Some(key) = key {
+            let provider = self.ctx.table_provider(self.table_ref(table)).await.ok();
+            self.record_version(&key, provider);
+        }
+        Ok(())
     }
 
     pub async fn insert(&self, sql: &str) -> Result<()> {
         self.execute(sql).await
     }
 
+    /// 把已经是列式的数据直接追加到表里，不需要拼 VALUES 语句
+    /// batch 的列名和类型需要和表一致
+    pub async fn insert_batch(&self, table: &str, batch: RecordBatch) -> Result<()> {
+        self.insert_batches(table, vec![batch]).await
+    }
+
+    /// 把 V 按表的 schema 转换成 Arrow 数组写入，表名为 DB 的 id
+    /// 字段名和列名对应，多出来的字段忽略，缺少的字段写 NULL
+    pub async fn insert_rows(&self, rows: &[V]) -> Result<()> {
+        self.insert_rows_into(&self.id, rows).await
+    }
+
+    pub async fn insert_rows_into(&self, table: &str, rows: &[V]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let schema = self.ctx.table_provider(table).await?.schema();
+        let mut decoder = arrow::json::ReaderBuilder::new(schema).build_decoder()?;
+        decoder.serialize(rows)?;
+        let batch = decoder
+            .flush()?
+            .ok_or_else(|| anyhow::anyhow!("no rows decoded"))?;
+        self.insert_batch(table, batch).await
+    }
+
+    pub async fn insert_batches(&self, table: &str, batches: Vec<RecordBatch>) -> Result<()> {
+        if !self.ctx.table_exist(table)? {
+            return Err(anyhow::anyhow!("Table {} not found", table));
+        }
+        // 和 append_batches 一样在写锁里分配行 ID，之后直接写入，不再重复分配
+        let _guard = self.lock_table(table).await;
+        let schema = self.ctx.table_provider(table).await?.schema();
+        let batches = self.assign_row_ids(table, &schema, batches).await?;
+        let batches = batches
+            .into_iter()
+            .map(|batch| self.coerce_batch(table, &schema, batch))
+            .collect::<Result<Vec<_>>>()?;
+        for batch in &batches {
+            if let Some(mismatch) = diagnose_batch(table, &schema, batch) {
+                return Err(mismatch.into());
+            }
+        }
+        if batches.iter().all(|b| b.num_rows() == 0) {
+            return Ok(());
+        }
+        self.write_append(table, batches, true)
+            .await
+            .map_err(|e| anyhow::anyhow!("Insert into {} error: {}", table, e))
+    }
+
     pub async fn execute(&self, sql: &str) -> Result<()> {
-        self.ctx.sql(sql).await?.collect().await?;
+        self.run(sql).await?;
         Ok(())
     }
 
+    /// 清空表名为 DB id 的表
     pub async fn truncate(&self) -> Result<()> {
-        //let c = self.ctx.write().await;
-        // TODO support truncate
-        Ok(())
+        self.truncate_table(&self.id).await
     }
 
-    pub async fn recovery(&self) -> Result<()> {
-        // TODO recovery from clickhouse/wal
-        Ok(())
+    /// 用同样 schema 的空表原子替换原表，约束和索引保留
+    /// 已经开始执行的查询继续读旧数据，之后的查询读到空表，不会出现表不存在的情况
+    ///
+    /// 只支持内存表，外部表和 freeze 创建的只读快照返回错误
+    pub async fn truncate_table(&self, table: &str) -> Result<()> {
+        let _guard = self.lock_table(table).await;
+        let provider = match self.ctx.table_provider(self.table_ref(table)).await {
+            std::result::Result::Ok(provider) => provider,
+            Err(_) => return Err(anyhow::anyhow!("Table {} not found", table)),
+        };
+        if provider.as_any().is::<FrozenTable>() {
+            return Err(anyhow::anyhow!("Table {} is a read-only snapshot", table));
+        }
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!("Table {} is not a memory table", table));
+        }
+        self.rewrite_table(table, provider.schema(), vec![vec![]], Some(&provider))
     }
 }
 
@@ -145,25 +516,22 @@ fn create_empty_columns(schema: &SchemaRef) -> Vec<ArrayRef> {
     schema
         .fields()
         .iter()
-        .map(|field| match field.data_type() {
-            DataType::Boolean => {
-                Arc::new(BooleanArray::from(Vec::<Option<bool>>::new())) as ArrayRef
-            }
-            DataType::Int32 => Arc::new(Int32Array::from(Vec::<Option<i32>>::new())) as ArrayRef,
-            DataType::Int64 => Arc::new(Int64Array::from(Vec::<Option<i64>>::new())) as ArrayRef,
-            DataType::UInt64 => Arc::new(UInt64Array::from(Vec::<Option<u64>>::new())) as ArrayRef,
-            DataType::Float64 => {
-                Arc::new(Float64Array::from(Vec::<Option<f64>>::new())) as ArrayRef
-            }
-
-            DataType::Utf8 => Arc::new(StringArray::from(Vec::<Option<&str>>::new())) as ArrayRef,
-            // 可以根据需要添加更多数据类型的处理
-            _ => panic!("Unsupported data type: {:?}", field.data_type()),
-        })
+        .map(|field| new_empty_array(field.data_type()))
         .collect()
 }
 
-fn get_value_at(column: &ArrayRef, index: usize) -> Result<Value> {
+pub(crate) fn get_value_at(column: &ArrayRef, index: usize) -> Result<Value> {
+    get_value_with(column, index, NumberMode::Number)
+}
+
+// JavaScript 中可以精确表示的最大整数
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+pub(crate) fn get_value_with(column: &ArrayRef, index: usize, mode: NumberMode) -> Result<Value> {
+    let as_string = mode == NumberMode::UnsafeAsString;
+    if column.is_null(index) {
+        return Ok(Value::Null);
+    }
     Ok(match column.data_type() {
         DataType::Boolean => Value::Bool(
             column
@@ -180,22 +548,47 @@ fn get_value_at(column: &ArrayRef, index: usize) -> Result<Value> {
                 .value(index)
                 .into(),
         ),
-        DataType::Int64 => Value::Number(
-            column
+        DataType::Int64 => {
+            let value = column
                 .as_any()
                 .downcast_ref::<Int64Array>()
                 .unwrap()
-                .value(index)
-                .into(),
-        ),
-        DataType::UInt64 => Value::Number(
-            column
+                .value(index);
+            if as_string && value.unsigned_abs() > MAX_SAFE_INTEGER {
+                Value::String(value.to_string())
+            } else {
+                Value::Number(value.into())
+            }
+        }
+        DataType::UInt64 => {
+            let value = column
                 .as_any()
                 .downcast_ref::<UInt64Array>()
                 .unwrap()
-                .value(index)
-                .into(),
-        ),
+                .value(index);
+            if as_string && value > MAX_SAFE_INTEGER {
+                Value::String(value.to_string())
+            } else {
+                Value::Number(value.into())
+            }
+        }
+        DataType::Decimal128(_, _) => {
+            let value = column
+                .as_any()
+                .downcast_ref::<Decimal128Array>()
+                .unwrap()
+                .value_as_string(index);
+            if as_string {
+                Value::String(value)
+            } else {
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number)
+                    .unwrap_or(Value::Null)
+            }
+        }
         DataType::Float64 => {
             let float_val = column
                 .as_any()
@@ -380,6 +773,111 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_query_iter() -> Result<()> {
+        let db = DB::<TestUser>::new("test_db");
+        db.execute("CREATE TABLE test_users (id BIGINT, name VARCHAR, age INT)")
+            .await?;
+        db.execute("INSERT INTO test_users VALUES (1, 'Alice', 30), (2, 'Bob', 25)")
+            .await?;
+
+        let users: Vec<TestUser> = db
+            .query_iter("SELECT * FROM test_users ORDER BY id")
+            .await?
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_>>()?;
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[1].name, "Bob");
+
+        let first = Box::pin(
+            db.query_to_stream("SELECT * FROM test_users ORDER BY id")
+                .await?,
+        )
+        .next()
+        .await
+        .unwrap()?;
+        assert_eq!(first.name, "Alice");
+
+        // 类型不匹配的行返回错误，不影响其他行
+        let mut rows = Box::pin(
+            db.query_iter("SELECT id, name, 'old' AS age FROM test_users")
+                .await?,
+        );
+        assert!(rows.next().await.unwrap().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_batch() -> Result<()> {
+        let db = DB::<TestUser>::new("test_db");
+        db.execute("CREATE TABLE test_users (id BIGINT, name VARCHAR, age INT)")
+            .await?;
+
+        let schema = db.ctx.table_provider("test_users").await?.schema();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec!["Alice", "Bob"])),
+                Arc::new(Int32Array::from(vec![30, 25])),
+            ],
+        )?;
+        db.insert_batch("test_users", batch.clone()).await?;
+        db.insert_batches("test_users", vec![batch.clone(), batch.clone()])
+            .await?;
+        let count = db.query("SELECT * FROM test_users").await?.count().await?;
+        assert_eq!(count, 6);
+
+        assert!(db.insert_batch("missing", batch).await.is_err());
+        let other = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Utf8, false)])),
+            vec![Arc::new(StringArray::from(vec!["1"]))],
+        )?;
+        assert!(db.insert_batch("test_users", other).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_rows() -> Result<()> {
+        let db = DB::<TestUser>::new("test_users");
+        db.execute("CREATE TABLE test_users (id BIGINT, name VARCHAR, age INT, email VARCHAR)")
+            .await?;
+        let users = vec![
+            TestUser {
+                id: 1,
+                name: "Alice".to_string(),
+                age: 30,
+            },
+            TestUser {
+                id: 2,
+                name: "Bob".to_string(),
+                age: 25,
+            },
+        ];
+        db.insert_rows(&users).await?;
+        db.insert_rows(&[]).await?;
+
+        let loaded: Vec<TestUser> = db
+            .query_to_schema("SELECT id, name, age FROM test_users ORDER BY id")
+            .await?;
+        assert_eq!(loaded, users);
+        let count = db
+            .query("SELECT * FROM test_users WHERE email IS NULL")
+            .await?
+            .count()
+            .await?;
+        assert_eq!(count, 2);
+
+        db.execute(
+            "CREATE TABLE strict_users (id BIGINT, name VARCHAR, age INT, email VARCHAR NOT NULL)",
+        )
+        .await?;
+        assert!(db.insert_rows_into("strict_users", &users).await.is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_create_table_with_provider() -> Result<()> {
         let db = DB::<TestUser>::new("test_db");
@@ -393,6 +891,99 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_table_introspection() -> Result<()> {
+        let db = DB::<TestUser>::new("test_db");
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("c1", DataType::Int32, false),
+            Field::new("c2", DataType::Utf8, false),
+        ]));
+        db.create_table(schema.clone()).await?;
+        db.execute("CREATE TABLE other (id BIGINT)").await?;
+        assert_eq!(db.list_tables()?, vec!["other", "test_db"]);
+        assert_eq!(db.table_schema("test_db").await?.fields(), schema.fields());
+        assert!(db.table_schema("missing").await.is_err());
+
+        db.set_table_meta("other", "owner", "finance")?;
+        assert!(db.drop_table("other").await?);
+        assert!(!db.drop_table("other").await?);
+        assert_eq!(db.list_tables()?, vec!["test_db"]);
+        assert!(db.table_meta("other").is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncate() -> Result<()> {
+        let db = DB::<TestUser>::new("test_db");
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("c1", DataType::Int32, false),
+            Field::new("c2", DataType::Utf8, false),
+        ]));
+        db.create_table(schema.clone()).await?;
+        db.execute("INSERT INTO test_db VALUES (1, 'a'), (2, 'b')")
+            .await?;
+
+        // 查询和清空并发执行，查询只会看到旧数据或空表
+        let db = Arc::new(db);
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        let rows = db.query("SELECT * FROM test_db").await?.count().await?;
+                        assert!(rows == 0 || rows == 2);
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for _ in 0..20 {
+            db.truncate().await?;
+            db.execute("INSERT INTO test_db VALUES (1, 'a'), (2, 'b')")
+                .await?;
+        }
+        for reader in readers {
+            reader.await??;
+        }
+
+        db.truncate().await?;
+        assert_eq!(db.query("SELECT * FROM test_db").await?.count().await?, 0);
+        assert_eq!(db.table_schema("test_db").await?.fields(), schema.fields());
+        db.execute("INSERT INTO test_db VALUES (3, 'c')").await?;
+        assert_eq!(db.query("SELECT * FROM test_db").await?.count().await?, 1);
+        assert!(db.truncate_table("missing").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncate_rejects_read_only_tables() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("t.csv"), "id\n1\n")?;
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+        db.execute(&format!(
+            "CREATE EXTERNAL TABLE ext STORED AS CSV LOCATION '{}' OPTIONS ('format.has_header' 'true')",
+            dir.path().join("t.csv").display()
+        ))
+        .await?;
+        db.freeze("t", "s1").await?;
+        db.create_bloom_filter("t", "name").await?;
+
+        assert!(db.truncate_table("ext").await.is_err());
+        assert!(db.truncate_table("\"t@s1\"").await.is_err());
+        assert_eq!(db.ctx.table("\"t@s1\"").await?.count().await?, 2);
+
+        // 建了索引的表清空后仍然带着索引
+        db.truncate_table("t").await?;
+        assert_eq!(db.ctx.table("t").await?.count().await?, 0);
+        let provider = db.ctx.table_provider("t").await?;
+        assert!(crate::swap::indexed(provider.as_ref()).is_some());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn context_with_threads() -> Result<()> {
         let db = DB::<TestUser>::new("test_db");
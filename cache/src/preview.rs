@@ -0,0 +1,109 @@
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::listing::{ListingTable, ListingTableConfig, ListingTableUrl};
+use datafusion::datasource::TableProvider;
+use futures::{StreamExt, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 返回表的前 n 行，外部表按存储列出文件的顺序逐个读取，够 n 行就停止，
+    /// 不会等列出所有文件再规划整表扫描，适合预览很大的数据集
+    ///
+    /// S3/OSS 按文件名顺序列出，本地文件系统的顺序不固定
+    pub async fn preview(&self, table: &str, n: usize) -> Result<Vec<RecordBatch>> {
+        let provider = self.ctx.table_provider(table).await?;
+        let listing = match provider.as_any().downcast_ref::<ListingTable>() {
+            // 分区表的分区列要从完整路径中解析，直接用 LIMIT
+            Some(listing) if listing.options().table_partition_cols.is_empty() => listing,
+            _ => {
+                let df = self.ctx.table(table).await?.limit(0, Some(n))?;
+                return Ok(df.collect().await?);
+            }
+        };
+
+        let options = listing.options().clone();
+        let mut batches = Vec::new();
+        let mut remaining = n;
+        for table_path in listing.table_paths() {
+            if remaining == 0 {
+                break;
+            }
+            let store = self.ctx.runtime_env().object_store(table_path)?;
+            // 边列出边读取，够了就丢掉剩下的列表
+            let mut files = if table_path.is_collection() {
+                store
+                    .list(Some(table_path.prefix()))
+                    .map_ok(|meta| meta.location)
+                    .try_filter(|location| {
+                        std::future::ready(location.as_ref().ends_with(&options.file_extension))
+                    })
+                    .boxed()
+            } else {
+                futures::stream::once(std::future::ready(Ok(table_path.prefix().clone()))).boxed()
+            };
+
+            while remaining > 0 {
+                let Some(file) = files.try_next().await? else {
+                    break;
+                };
+                let url = ListingTableUrl::parse(format!(
+                    "{}{}",
+                    table_path.object_store().as_str(),
+                    file
+                ))?;
+                let config = ListingTableConfig::new(url)
+                    .with_listing_options(options.clone())
+                    .with_schema(listing.schema());
+                let provider = ListingTable::try_new(config)?;
+                let rows = self
+                    .ctx
+                    .read_table(Arc::new(provider))?
+                    .limit(0, Some(remaining))?
+                    .collect()
+                    .await?;
+                remaining -= rows.iter().map(|b| b.num_rows()).sum::<usize>();
+                batches.extend(rows);
+            }
+        }
+        Ok(batches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn rows(batches: &[RecordBatch]) -> usize {
+        batches.iter().map(|b| b.num_rows()).sum()
+    }
+
+    #[tokio::test]
+    async fn test_preview() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.csv"), "id,name\n1,Alice\n2,Bob\n")?;
+        fs::write(dir.path().join("b.csv"), "id,name\n3,Carol\n")?;
+
+        let db = DB::<()>::new("test_db");
+        db.execute(&format!(
+            "CREATE EXTERNAL TABLE people (id BIGINT, name VARCHAR) STORED AS CSV LOCATION '{}/' \
+             OPTIONS ('format.has_header' 'true')",
+            dir.path().to_string_lossy()
+        ))
+        .await?;
+
+        // 第一个文件就够时只读一个文件
+        assert_eq!(db.preview("people", 1).await?.len(), 1);
+        assert_eq!(rows(&db.preview("people", 1).await?), 1);
+        assert_eq!(rows(&db.preview("people", 3).await?), 3);
+        assert_eq!(rows(&db.preview("people", 10).await?), 3);
+
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3)").await?;
+        assert_eq!(rows(&db.preview("t", 2).await?), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use crate::codec::batches_to_rows;
+use crate::pool::DB;
+use anyhow::Result;
+use rhai::{Dynamic, Engine, EvalAltResult};
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+// 防止脚本死循环，超过后脚本报错
+const MAX_SCRIPT_OPERATIONS: u64 = 1_000_000;
+
+impl DB<()> {
+    /// 注册一个 Rhai 脚本写的存储过程，注册时会检查语法
+    ///
+    /// 脚本中可以调用：
+    /// - `query(sql)`：返回行的数组，每行是一个 map
+    /// - `scalar(sql)`：第一行第一列，没有结果时为 ()
+    /// - `execute(sql)`
+    /// - `export(sql, storage, path, format)`：返回写入的路径
+    pub fn register_procedure(&self, name: &str, script: &str) -> Result<()> {
+        Engine::new()
+            .compile(script)
+            .map_err(|e| anyhow::anyhow!("Invalid procedure {}: {}", name, e))?;
+        self.procedures
+            .write()
+            .unwrap()
+            .insert(name.to_string(), script.to_string());
+        Ok(())
+    }
+
+    pub fn drop_procedure(&self, name: &str) -> bool {
+        self.procedures.write().unwrap().remove(name).is_some()
+    }
+
+    pub fn procedures(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.procedures.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// 执行存储过程，返回脚本最后一个表达式的值
+    pub async fn call_procedure(self: &Arc<Self>, name: &str) -> Result<Value> {
+        let script = self
+            .procedures
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Procedure {} not found", name))?;
+
+        // Rhai 是同步的，在阻塞线程中执行，脚本里的 DB 调用再回到 runtime 上执行
+        let (db, handle) = (self.clone(), Handle::current());
+        let name = name.to_string();
+        tokio::task::spawn_blocking(move || {
+            let engine = procedure_engine(db, handle);
+            let result: Dynamic = engine
+                .eval(&script)
+                .map_err(|e| anyhow::anyhow!("Procedure {} failed: {}", name, e))?;
+            Ok(rhai::serde::from_dynamic(&result)
+                .map_err(|e| anyhow::anyhow!("Procedure {} returned {}", name, e))?)
+        })
+        .await?
+    }
+
+    /// 后台定时执行存储过程，直到返回的 JoinHandle 被 abort
+    pub fn schedule_procedure(
+        self: &Arc<Self>,
+        name: &str,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let db = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = db.call_procedure(&name).await {
+                    tracing::warn!(procedure = %name, error = %e, "procedure failed");
+                }
+            }
+        })
+    }
+}
+
+fn procedure_engine(db: Arc<DB<()>>, handle: Handle) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+
+    let (d, h) = (db.clone(), handle.clone());
+    engine.register_fn(
+        "query",
+        move |sql: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            let rows = block_on(&h, async {
+                batches_to_rows(&d.query_to_batches(sql).await?)
+            })?;
+            rhai::serde::to_dynamic(rows)
+        },
+    );
+
+    let (d, h) = (db.clone(), handle.clone());
+    engine.register_fn(
+        "scalar",
+        move |sql: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            let rows = block_on(&h, async {
+                batches_to_rows(&d.query_to_batches(sql).await?)
+            })?;
+            match rows
+                .into_iter()
+                .next()
+                .and_then(|row| row.into_iter().next())
+            {
+                Some((_, value)) => rhai::serde::to_dynamic(value),
+                None => Ok(Dynamic::UNIT),
+            }
+        },
+    );
+
+    let (d, h) = (db.clone(), handle.clone());
+    engine.register_fn(
+        "execute",
+        move |sql: &str| -> Result<(), Box<EvalAltResult>> { block_on(&h, d.execute(sql)) },
+    );
+
+    #[cfg(feature = "storage")]
+    {
+        let (d, h) = (db, handle);
+        engine.register_fn(
+            "export",
+            move |sql: &str,
+                  storage: &str,
+                  path: &str,
+                  format: &str|
+                  -> Result<String, Box<EvalAltResult>> {
+                block_on(&h, async {
+                    let df = d.query(sql).await?;
+                    d.export_to_storage(df, storage, path, format).await
+                })
+            },
+        );
+    }
+    engine
+}
+
+fn block_on<T>(
+    handle: &Handle,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T, Box<EvalAltResult>> {
+    handle.block_on(fut).map_err(|e| format!("{:#}", e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_call_procedure() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+        db.execute("CREATE TABLE orders (id BIGINT, amount BIGINT)")
+            .await?;
+        db.execute("CREATE TABLE alerts (message VARCHAR)").await?;
+        db.execute("INSERT INTO orders VALUES (1, 10), (2, 300)")
+            .await?;
+
+        db.register_procedure(
+            "check_large_orders",
+            r#"
+                let large = query("SELECT id FROM orders WHERE amount > 100 ORDER BY id");
+                for row in large {
+                    execute(`INSERT INTO alerts VALUES ('large order ${row.id}')`);
+                }
+                scalar("SELECT count(*) FROM alerts")
+            "#,
+        )?;
+        assert_eq!(db.procedures(), vec!["check_large_orders".to_string()]);
+        assert_eq!(db.call_procedure("check_large_orders").await?, 1);
+        assert_eq!(db.call_procedure("check_large_orders").await?, 2);
+
+        assert!(db.register_procedure("broken", "let x = ;").is_err());
+        db.register_procedure("bad_sql", "execute(\"SELECT * FROM missing\")")?;
+        assert!(db.call_procedure("bad_sql").await.is_err());
+        db.register_procedure("forever", "loop {}")?;
+        assert!(db.call_procedure("forever").await.is_err());
+        assert!(db.drop_procedure("forever"));
+        assert!(db.call_procedure("forever").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+use crate::pool::DB;
+use crate::row_id::ROW_ID_COLUMN;
+use anyhow::Result;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::{
+    ArrayRef, AsArray, Int64Array, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use datafusion::arrow::compute::{max, min};
+use datafusion::arrow::datatypes::UInt64Type;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub const PROVENANCE_TABLE: &str = "provenance";
+
+/// 一批数据的来源
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    // 文件路径、Kafka topic/partition 等
+    pub source: String,
+    // Kafka 等消息队列的 offset 范围，包含两端
+    pub offsets: Option<(i64, i64)>,
+    pub ingested_at: DateTime<Utc>,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl Provenance {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            offsets: None,
+            ingested_at: Utc::now(),
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_offsets(mut self, start: i64, end: i64) -> Self {
+        self.offsets = Some((start, end));
+        self
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+/// 写入的一批数据和它的来源，表中 _row_id 在 [first_row_id, last_row_id] 之间的行属于这一批
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvenanceRecord {
+    pub batch_id: u64,
+    pub table: String,
+    pub provenance: Provenance,
+    pub first_row_id: u64,
+    pub last_row_id: u64,
+    pub rows: usize,
+}
+
+/// rollback_source 删除的批次和行数
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollbackReport {
+    pub batches: Vec<ProvenanceRecord>,
+    pub rows: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ProvenanceLog {
+    next_batch_id: u64,
+    pub(crate) records: Vec<ProvenanceRecord>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 带来源信息写入，表会自动开启 _row_id（只支持内存表）
+    ///
+    /// 来源可以通过 system.provenance 查询，和表按 _row_id 关联：
+    /// SELECT t.*, p.source FROM t JOIN system.provenance p
+    ///   ON p.table_name = 't' AND t._row_id BETWEEN p.first_row_id AND p.last_row_id
+    pub async fn append_with_provenance(
+        &self,
+        table: &str,
+        batches: Vec<RecordBatch>,
+        provenance: Provenance,
+    ) -> Result<ProvenanceRecord> {
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        if rows == 0 {
+            return Err(anyhow::anyhow!("Nothing to append to {}", table));
+        }
+        self.enable_row_ids(table).await?;
+        let schema = self.table_schema(table).await?;
+        let batches = self.assign_row_ids(table, &schema, batches).await?;
+
+        let (mut first, mut last) = (u64::MAX, 0);
+        for batch in &batches {
+            let ids = batch
+                .column(batch.schema().index_of(ROW_ID_COLUMN)?)
+                .as_primitive::<UInt64Type>();
+            if let (Some(lo), Some(hi)) = (min(ids), max(ids)) {
+                first = first.min(lo);
+                last = last.max(hi);
+            }
+        }
+        self.insert_batches(table, batches).await?;
+
+        let record = {
+            let mut log = self.provenance.write().unwrap();
+            log.next_batch_id += 1;
+            let record = ProvenanceRecord {
+                batch_id: log.next_batch_id,
+                table: self.table_ref(table).table().to_string(),
+                provenance,
+                first_row_id: first,
+                last_row_id: last,
+                rows,
+            };
+            log.records.push(record.clone());
+            record
+        };
+        self.refresh_provenance()?;
+        Ok(record)
+    }
+
+    /// 表的所有带来源的写入，按写入顺序
+    pub fn provenance(&self, table: &str) -> Vec<ProvenanceRecord> {
+        let table = self.table_ref(table).table().to_string();
+        self.provenance
+            .read()
+            .unwrap()
+            .records
+            .iter()
+            .filter(|r| r.table == table)
+            .cloned()
+            .collect()
+    }
+
+    /// 某一行的来源，不是通过 append_with_provenance 写入的行返回 None
+    pub fn row_provenance(&self, table: &str, row_id: u64) -> Option<ProvenanceRecord> {
+        self.provenance(table)
+            .into_iter()
+            .find(|r| (r.first_row_id..=r.last_row_id).contains(&row_id))
+    }
+
+    /// 一次删除来自 source 的所有批次，比如上游导出的文件有问题，重新导入前先删掉
+    pub async fn rollback_source(&self, table: &str, source: &str) -> Result<RollbackReport> {
+        self.rollback_matching(table, |p| p.source == source).await
+    }
+
+    /// 只删除 source 中 offset 和 [start, end] 有重叠的批次
+    pub async fn rollback_source_offsets(
+        &self,
+        table: &str,
+        source: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<RollbackReport> {
+        self.rollback_matching(table, |p| {
+            p.source == source && p.offsets.is_some_and(|(lo, hi)| lo <= end && start <= hi)
+        })
+        .await
+    }
+
+    async fn rollback_matching(
+        &self,
+        table: &str,
+        matches: impl Fn(&Provenance) -> bool,
+    ) -> Result<RollbackReport> {
+        let batches: Vec<ProvenanceRecord> = self
+            .provenance(table)
+            .into_iter()
+            .filter(|r| matches(&r.provenance))
+            .collect();
+        if batches.is_empty() {
+            return Ok(RollbackReport { batches, rows: 0 });
+        }
+        let ranges: Vec<(u64, u64)> = batches
+            .iter()
+            .map(|r| (r.first_row_id, r.last_row_id))
+            .collect();
+        let rows = self.delete_row_ranges(table, &ranges).await?;
+
+        self.provenance
+            .write()
+            .unwrap()
+            .records
+            .retain(|r| !batches.iter().any(|b| b.batch_id == r.batch_id));
+        self.refresh_provenance()?;
+        Ok(RollbackReport { batches, rows })
+    }
+
+    pub(crate) fn clear_provenance(&self, table: &str) -> Result<()> {
+        let removed = {
+            let mut log = self.provenance.write().unwrap();
+            let before = log.records.len();
+            log.records.retain(|r| r.table != table);
+            before != log.records.len()
+        };
+        if removed {
+            self.refresh_provenance()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn refresh_provenance(&self) -> Result<()> {
+        let records = self.provenance.read().unwrap().records.clone();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("batch_id", DataType::UInt64, false),
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("source", DataType::Utf8, false),
+            Field::new("offset_start", DataType::Int64, true),
+            Field::new("offset_end", DataType::Int64, true),
+            Field::new(
+                "ingested_at",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("first_row_id", DataType::UInt64, false),
+            Field::new("last_row_id", DataType::UInt64, false),
+            Field::new("rows", DataType::UInt64, false),
+            // JSON 对象，没有附加信息时为 NULL
+            Field::new("attributes", DataType::Utf8, true),
+        ]));
+        let attributes = records
+            .iter()
+            .map(|r| {
+                (!r.provenance.attributes.is_empty())
+                    .then(|| serde_json::to_string(&r.provenance.attributes))
+                    .transpose()
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|r| r.batch_id),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                records.iter().map(|r| &r.table),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                records.iter().map(|r| &r.provenance.source),
+            )),
+            Arc::new(Int64Array::from_iter(
+                records.iter().map(|r| r.provenance.offsets.map(|o| o.0)),
+            )),
+            Arc::new(Int64Array::from_iter(
+                records.iter().map(|r| r.provenance.offsets.map(|o| o.1)),
+            )),
+            Arc::new(TimestampMillisecondArray::from_iter_values(
+                records
+                    .iter()
+                    .map(|r| r.provenance.ingested_at.timestamp_millis()),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|r| r.first_row_id),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|r| r.last_row_id),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|r| r.rows as u64),
+            )),
+            Arc::new(StringArray::from_iter(attributes)),
+        ];
+        self.refresh_system_table(PROVENANCE_TABLE, RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_provenance() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE events (name VARCHAR)").await?;
+        db.execute("INSERT INTO events VALUES ('manual')").await?;
+
+        let batch = db
+            .query_to_batches("SELECT 'a' AS name UNION ALL SELECT 'b'")
+            .await?;
+        let first = db
+            .append_with_provenance(
+                "events",
+                batch,
+                Provenance::new("events-topic/0").with_offsets(100, 101),
+            )
+            .await?;
+        assert_eq!((first.first_row_id, first.last_row_id), (1, 2));
+        let batch = db.query_to_batches("SELECT 'c' AS name").await?;
+        db.append_with_provenance(
+            "events",
+            batch,
+            Provenance::new("s3://bucket/day1.csv").with_attribute("loader", "nightly"),
+        )
+        .await?;
+
+        assert_eq!(db.provenance("events").len(), 2);
+        assert_eq!(db.row_provenance("events", 2), Some(first));
+        assert_eq!(db.row_provenance("events", 0), None);
+
+        let batches = db
+            .query_to_batches(
+                "SELECT e.name FROM events e JOIN system.provenance p \
+                 ON p.table_name = 'events' AND e._row_id BETWEEN p.first_row_id AND p.last_row_id \
+                 WHERE p.source = 's3://bucket/day1.csv'",
+            )
+            .await?;
+        assert_eq!(batches[0].column(0).as_string::<i32>().value(0), "c");
+
+        db.drop_table("events").await?;
+        assert!(db.provenance("events").is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rollback_source() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE events (name VARCHAR)").await?;
+        for (source, offset) in [("day1.csv", 0), ("day2.csv", 10), ("day1.csv", 20)] {
+            let batch = db
+                .query_to_batches("SELECT 'x' AS name UNION ALL SELECT 'y'")
+                .await?;
+            db.append_with_provenance(
+                "events",
+                batch,
+                Provenance::new(source).with_offsets(offset, offset + 1),
+            )
+            .await?;
+        }
+
+        let report = db
+            .rollback_source_offsets("events", "day1.csv", 21, 30)
+            .await?;
+        assert_eq!((report.batches.len(), report.rows), (1, 2));
+        let report = db.rollback_source("events", "day1.csv").await?;
+        assert_eq!((report.batches.len(), report.rows), (1, 2));
+        assert_eq!(db.ctx.table("events").await?.count().await?, 2);
+        assert_eq!(db.provenance("events").len(), 1);
+        assert_eq!(db.rollback_source("events", "day1.csv").await?.rows, 0);
+        Ok(())
+    }
+}
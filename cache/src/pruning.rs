@@ -0,0 +1,208 @@
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::physical_plan::ParquetExec;
+use datafusion::physical_plan::ExecutionPlan;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// 一次查询中所有 Parquet 扫描的 IO 统计，来自 ParquetExec 的 metrics
+///
+/// row group 按 min/max 统计信息和 bloom filter 跳过；page index 按页跳过，
+/// DataFusion 只记录跳过的行数，这里的 page_index_* 是这些页中的行数。
+/// 没有可以用来裁剪的过滤条件时，row group 和 page 的计数都是 0
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    pub files: usize,
+    // 实际从存储读取的字节数
+    pub bytes_scanned: usize,
+    pub row_groups_matched_statistics: usize,
+    pub row_groups_pruned_statistics: usize,
+    pub row_groups_matched_bloom_filter: usize,
+    pub row_groups_pruned_bloom_filter: usize,
+    pub page_index_rows_matched: usize,
+    pub page_index_rows_pruned: usize,
+    // 开启 filter pushdown 时解码过程中过滤的行
+    pub pushdown_rows_matched: usize,
+    pub pushdown_rows_pruned: usize,
+}
+
+impl ScanStats {
+    pub fn row_groups_pruned(&self) -> usize {
+        self.row_groups_pruned_statistics + self.row_groups_pruned_bloom_filter
+    }
+
+    // 统计信息保留的 row group 还会再经过 bloom filter
+    pub fn row_groups_read(&self) -> usize {
+        self.row_groups_matched_statistics
+            .saturating_sub(self.row_groups_pruned_bloom_filter)
+    }
+
+    fn add(&mut self, other: &ScanStats) {
+        self.files += other.files;
+        self.bytes_scanned += other.bytes_scanned;
+        self.row_groups_matched_statistics += other.row_groups_matched_statistics;
+        self.row_groups_pruned_statistics += other.row_groups_pruned_statistics;
+        self.row_groups_matched_bloom_filter += other.row_groups_matched_bloom_filter;
+        self.row_groups_pruned_bloom_filter += other.row_groups_pruned_bloom_filter;
+        self.page_index_rows_matched += other.page_index_rows_matched;
+        self.page_index_rows_pruned += other.page_index_rows_pruned;
+        self.pushdown_rows_matched += other.pushdown_rows_matched;
+        self.pushdown_rows_pruned += other.pushdown_rows_pruned;
+    }
+}
+
+/// 查询结果和 Parquet 扫描统计
+#[derive(Debug, Clone)]
+pub struct ScanOutput {
+    pub batches: Vec<RecordBatch>,
+    pub stats: ScanStats,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 和 query_to_batches 一样执行查询，同时返回 Parquet 扫描的 IO 统计，
+    /// 用来确认过滤条件确实跳过了远端 Parquet 文件中的 row group 和 page
+    pub async fn query_with_scan_stats(&self, sql: &str) -> Result<ScanOutput> {
+        let (batches, plan) = self.run_with_plan(sql).await?;
+        Ok(ScanOutput {
+            batches,
+            stats: scan_stats(&plan),
+        })
+    }
+}
+
+/// 汇总已经执行过的物理计划中所有 ParquetExec 的 metrics
+pub fn scan_stats(plan: &Arc<dyn ExecutionPlan>) -> ScanStats {
+    let mut stats = ScanStats::default();
+    if let Some(exec) = plan.as_any().downcast_ref::<ParquetExec>() {
+        let count = |name: &str| {
+            plan.metrics()
+                .and_then(|m| m.sum_by_name(name))
+                .map(|v| v.as_usize())
+                .unwrap_or(0)
+        };
+        stats.add(&ScanStats {
+            // 大文件会按范围拆到多个分区，同一个文件只算一次
+            files: exec
+                .base_config()
+                .file_groups
+                .iter()
+                .flatten()
+                .map(|f| &f.object_meta.location)
+                .collect::<HashSet<_>>()
+                .len(),
+            bytes_scanned: count("bytes_scanned"),
+            row_groups_matched_statistics: count("row_groups_matched_statistics"),
+            row_groups_pruned_statistics: count("row_groups_pruned_statistics"),
+            row_groups_matched_bloom_filter: count("row_groups_matched_bloom_filter"),
+            row_groups_pruned_bloom_filter: count("row_groups_pruned_bloom_filter"),
+            page_index_rows_matched: count("page_index_rows_matched"),
+            page_index_rows_pruned: count("page_index_rows_pruned"),
+            pushdown_rows_matched: count("pushdown_rows_matched"),
+            pushdown_rows_pruned: count("pushdown_rows_pruned"),
+        });
+    }
+    for child in plan.children() {
+        stats.add(&scan_stats(child));
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::parquet::arrow::ArrowWriter;
+    use datafusion::parquet::file::properties::WriterProperties;
+    use datafusion::prelude::ParquetReadOptions;
+    use tempfile::tempdir;
+
+    // 400 行，每 100 行一个 row group，每 10 行一页，id 有序
+    fn write_events(path: &std::path::Path) -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(100)
+            .set_data_page_row_count_limit(10)
+            .set_write_batch_size(10)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(std::fs::File::create(path)?, schema.clone(), Some(props))?;
+        let ids: Vec<i64> = (0..400).collect();
+        let names: Vec<String> = ids.iter().map(|id| format!("user_{:03}", id)).collect();
+        writer.write(&RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(ids)),
+                Arc::new(StringArray::from(names)),
+            ],
+        )?)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    async fn events_db() -> Result<(DB<()>, tempfile::TempDir)> {
+        let dir = tempdir()?;
+        let path = dir.path().join("events.parquet");
+        write_events(&path)?;
+        let db = DB::<()>::new("test_db");
+        db.ctx
+            .register_parquet(
+                "events",
+                path.to_str().unwrap(),
+                ParquetReadOptions::default(),
+            )
+            .await?;
+        Ok((db, dir))
+    }
+
+    #[tokio::test]
+    async fn test_range_predicate_prunes_row_groups_and_pages() -> Result<()> {
+        let (db, _dir) = events_db().await?;
+        let full = db.query_with_scan_stats("SELECT * FROM events").await?;
+        assert_eq!(full.stats.files, 1);
+        assert_eq!(full.stats.row_groups_pruned(), 0);
+
+        let output = db
+            .query_with_scan_stats("SELECT * FROM events WHERE id >= 350")
+            .await?;
+        let rows: usize = output.batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 50);
+        assert_eq!(output.stats.row_groups_pruned_statistics, 3);
+        assert_eq!(output.stats.row_groups_read(), 1);
+        // 剩下的 row group 中 id < 350 的页被 page index 跳过
+        assert!(output.stats.page_index_rows_pruned > 0);
+        assert_eq!(
+            output.stats.page_index_rows_matched + output.stats.page_index_rows_pruned,
+            100
+        );
+        assert!(output.stats.bytes_scanned < full.stats.bytes_scanned);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_point_and_unprunable_predicates() -> Result<()> {
+        let (db, _dir) = events_db().await?;
+        let point = db
+            .query_with_scan_stats("SELECT name FROM events WHERE id = 42")
+            .await?;
+        assert_eq!(point.stats.row_groups_pruned(), 3);
+        assert_eq!(point.stats.row_groups_read(), 1);
+
+        let name = db
+            .query_with_scan_stats("SELECT id FROM events WHERE name = 'user_250'")
+            .await?;
+        assert_eq!(name.stats.row_groups_pruned_statistics, 3);
+
+        // 表达式无法用 min/max 判断，所有 row group 都要读
+        let modulo = db
+            .query_with_scan_stats("SELECT id FROM events WHERE id % 2 = 0")
+            .await?;
+        assert_eq!(modulo.stats.row_groups_pruned(), 0);
+        Ok(())
+    }
+}
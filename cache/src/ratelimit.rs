@@ -0,0 +1,352 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, GetResultPayload, ListResult, MultipartUpload, ObjectMeta, ObjectStore,
+    PutMultipartOpts, PutOptions, PutPayload, PutResult, Result, UploadPart,
+};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// 单个存储的访问限制，没有配置的项不限制
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RateLimitConfig {
+    pub requests_per_second: Option<f64>,
+    pub max_concurrent_requests: Option<usize>,
+    // 读写的总带宽
+    pub bytes_per_second: Option<u64>,
+}
+
+// 按固定速率放行：每次占用一段时间，下一次要等到上一次占用结束
+// 返回这次占用的 (开始, 结束) 时间
+#[derive(Debug)]
+struct Pacer {
+    next_free: Mutex<Instant>,
+}
+
+impl Pacer {
+    fn new() -> Self {
+        Self {
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn reserve(&self, cost: Duration) -> (Instant, Instant) {
+        let mut next_free = self.next_free.lock().unwrap();
+        let start = (*next_free).max(Instant::now());
+        *next_free = start + cost;
+        (start, *next_free)
+    }
+}
+
+// 所有请求共享的限制，分段上传和读取的 body 也要用
+#[derive(Debug)]
+struct Limits {
+    config: RateLimitConfig,
+    requests: Pacer,
+    bandwidth: Pacer,
+    concurrency: Option<Arc<Semaphore>>,
+}
+
+impl Limits {
+    // 等到可以发起下一个请求，返回的 permit 释放之前占用一个并发数
+    async fn start(&self) -> Option<OwnedSemaphorePermit> {
+        if let Some(rps) = self.config.requests_per_second.filter(|r| *r > 0.0) {
+            let (start, _) = self.requests.reserve(Duration::from_secs_f64(1.0 / rps));
+            tokio::time::sleep_until(start).await;
+        }
+        match &self.concurrency {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await.unwrap()),
+            None => None,
+        }
+    }
+
+    async fn request<T, F>(&self, op: F) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>>,
+    {
+        let _permit = self.start().await;
+        op.await
+    }
+
+    async fn transfer(&self, bytes: usize) {
+        if let Some(bps) = self.config.bytes_per_second.filter(|b| *b > 0) {
+            // 按带宽算传输完成的时间
+            let (_, end) = self
+                .bandwidth
+                .reserve(Duration::from_secs_f64(bytes as f64 / bps as f64));
+            tokio::time::sleep_until(end).await;
+        }
+    }
+}
+
+/// 限制请求速率、并发数和带宽的 ObjectStore 封装
+///
+/// 大量并行的 parquet 扫描会触发 OSS 的限流，影响同一账号下的其他业务。
+/// 读取的 body 在读完或者丢弃之前一直占用并发数，分段上传的每个分段都算一次请求
+#[derive(Debug)]
+pub struct RateLimitedStore {
+    inner: Arc<dyn ObjectStore>,
+    limits: Arc<Limits>,
+}
+
+impl RateLimitedStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, config: RateLimitConfig) -> Self {
+        let concurrency = config
+            .max_concurrent_requests
+            .map(|n| Arc::new(Semaphore::new(n.max(1))));
+        Self {
+            inner,
+            limits: Arc::new(Limits {
+                config,
+                requests: Pacer::new(),
+                bandwidth: Pacer::new(),
+                concurrency,
+            }),
+        }
+    }
+
+    async fn request<T, F>(&self, op: F) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>>,
+    {
+        self.limits.request(op).await
+    }
+}
+
+// 边读边按带宽限速，读完或者丢弃时才释放 permit
+fn limited_stream(
+    stream: BoxStream<'static, Result<Bytes>>,
+    limits: Arc<Limits>,
+    permit: Option<OwnedSemaphorePermit>,
+) -> BoxStream<'static, Result<Bytes>> {
+    futures::stream::unfold(
+        (stream, limits, permit),
+        |(mut stream, limits, permit)| async move {
+            let chunk = stream.next().await?;
+            if let Ok(bytes) = &chunk {
+                limits.transfer(bytes.len()).await;
+            }
+            Some((chunk, (stream, limits, permit)))
+        },
+    )
+    .boxed()
+}
+
+// 每个分段和最后的 complete 都按一次请求限制
+#[derive(Debug)]
+struct LimitedUpload {
+    inner: Box<dyn MultipartUpload>,
+    limits: Arc<Limits>,
+}
+
+#[async_trait]
+impl MultipartUpload for LimitedUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        let limits = self.limits.clone();
+        let bytes = data.content_length();
+        let part = self.inner.put_part(data);
+        Box::pin(async move {
+            limits.transfer(bytes).await;
+            limits.request(part).await
+        })
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        self.limits.request(self.inner.complete()).await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.limits.request(self.inner.abort()).await
+    }
+}
+
+impl Display for RateLimitedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RateLimitedStore({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for RateLimitedStore {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        self.limits.transfer(payload.content_length()).await;
+        self.request(self.inner.put_opts(location, payload, opts))
+            .await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let upload = self
+            .request(self.inner.put_multipart_opts(location, opts))
+            .await?;
+        Ok(Box::new(LimitedUpload {
+            inner: upload,
+            limits: self.limits.clone(),
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let head = options.head;
+        let permit = self.limits.start().await;
+        let mut result = self.inner.get_opts(location, options).await?;
+        if head {
+            return Ok(result);
+        }
+        result.payload = match result.payload {
+            GetResultPayload::Stream(stream) => {
+                GetResultPayload::Stream(limited_stream(stream, self.limits.clone(), permit))
+            }
+            // 本地文件直接按整个范围计算带宽
+            payload => {
+                self.limits
+                    .transfer(result.range.end - result.range.start)
+                    .await;
+                payload
+            }
+        };
+        Ok(result)
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.request(self.inner.delete(location)).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        let prefix = prefix.cloned();
+        futures::stream::once(async move {
+            self.request(async { Ok(()) }).await?;
+            Ok(self.inner.list(prefix.as_ref()))
+        })
+        .map(|result: Result<_>| match result {
+            Ok(stream) => stream,
+            Err(e) => futures::stream::once(async { Err(e) }).boxed(),
+        })
+        .flatten()
+        .boxed()
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.request(self.inner.list_with_delimiter(prefix)).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.request(self.inner.copy(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.request(self.inner.copy_if_not_exists(from, to)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use object_store::throttle::{ThrottleConfig, ThrottledStore};
+
+    #[tokio::test]
+    async fn test_requests_per_second() -> anyhow::Result<()> {
+        let store = RateLimitedStore::new(
+            Arc::new(InMemory::new()),
+            RateLimitConfig {
+                requests_per_second: Some(20.0),
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        for i in 0..5 {
+            store
+                .put(&Path::from(format!("{}.csv", i)), PutPayload::from("x"))
+                .await?;
+        }
+        // 第一个请求不用等
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_and_bandwidth() -> anyhow::Result<()> {
+        let path = Path::from("a.csv");
+        let inner = InMemory::new();
+        inner.put(&path, PutPayload::from(vec![0u8; 100])).await?;
+        let inner = ThrottledStore::new(
+            inner,
+            ThrottleConfig {
+                wait_get_per_call: Duration::from_millis(50),
+                ..Default::default()
+            },
+        );
+        let store = RateLimitedStore::new(
+            Arc::new(inner),
+            RateLimitConfig {
+                max_concurrent_requests: Some(1),
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        let (a, b, c) = tokio::join!(store.head(&path), store.head(&path), store.head(&path));
+        a?;
+        b?;
+        c?;
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        let store = RateLimitedStore::new(
+            Arc::new(InMemory::new()),
+            RateLimitConfig {
+                bytes_per_second: Some(1000),
+                ..Default::default()
+            },
+        );
+        store.put(&path, PutPayload::from(vec![0u8; 100])).await?;
+        let start = Instant::now();
+        store.get(&path).await?.bytes().await?;
+        store.get(&path).await?.bytes().await?;
+        // 写入 100 字节之后再读 200 字节，读完要到 0.3s
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multipart_and_body_are_limited() -> anyhow::Result<()> {
+        let store = RateLimitedStore::new(
+            Arc::new(InMemory::new()),
+            RateLimitConfig {
+                max_concurrent_requests: Some(1),
+                bytes_per_second: Some(1000),
+                ..Default::default()
+            },
+        );
+        let path = Path::from("a.csv");
+        let start = Instant::now();
+        let mut upload = store.put_multipart(&path).await?;
+        upload.put_part(PutPayload::from(vec![0u8; 100])).await?;
+        upload.put_part(PutPayload::from(vec![0u8; 100])).await?;
+        upload.complete().await?;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // 没读完的 body 一直占着唯一的并发数
+        let body = store.get(&path).await?;
+        let head = tokio::time::timeout(Duration::from_millis(50), store.head(&path)).await;
+        assert!(head.is_err());
+        let start = Instant::now();
+        assert_eq!(body.bytes().await?.len(), 200);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        store.head(&path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::{
+    ArrayRef, Float64Array, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const RECENT_QUERIES_TABLE: &str = "recent_queries";
+
+// 默认保留最近多少条查询
+pub const DEFAULT_RECENT_QUERIES: usize = 100;
+
+/// 一次查询的执行记录
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentQuery {
+    pub sql: String,
+    pub started_at: DateTime<Utc>,
+    pub latency_ms: f64,
+    // 返回的行数，失败时为 0
+    pub rows: u64,
+    pub error: Option<String>,
+}
+
+impl RecentQuery {
+    pub fn status(&self) -> &'static str {
+        if self.error.is_none() {
+            "ok"
+        } else {
+            "error"
+        }
+    }
+}
+
+/// 最近执行的查询，超过容量时丢弃最早的
+#[derive(Debug)]
+pub(crate) struct RecentQueries {
+    capacity: usize,
+    entries: VecDeque<RecentQuery>,
+}
+
+impl Default for RecentQueries {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_RECENT_QUERIES,
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 最近执行的查询，最早的在前面
+    pub fn recent_queries(&self) -> Vec<RecentQuery> {
+        self.recent_queries
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// 修改保留的条数，为 0 时不再记录
+    pub fn set_recent_queries_capacity(&self, capacity: usize) -> Result<()> {
+        {
+            let mut recent = self.recent_queries.write().unwrap();
+            recent.capacity = capacity;
+            while recent.entries.len() > capacity {
+                recent.entries.pop_front();
+            }
+        }
+        self.refresh_recent_queries()
+    }
+
+    // 记录一次执行，并刷新 system.recent_queries
+    pub(crate) fn record_recent_query(
+        &self,
+        sql: &str,
+        started_at: DateTime<Utc>,
+        elapsed: Duration,
+        result: &Result<Vec<RecordBatch>>,
+    ) -> Result<()> {
+        {
+            let mut recent = self.recent_queries.write().unwrap();
+            if recent.capacity == 0 {
+                return Ok(());
+            }
+            if recent.entries.len() == recent.capacity {
+                recent.entries.pop_front();
+            }
+            let (rows, error) = match result {
+                Ok(batches) => (batches.iter().map(|b| b.num_rows() as u64).sum(), None),
+                Err(e) => (0, Some(e.to_string())),
+            };
+            recent.entries.push_back(RecentQuery {
+                sql: sql.to_string(),
+                started_at,
+                latency_ms: elapsed.as_secs_f64() * 1000.0,
+                rows,
+                error,
+            });
+        }
+        self.refresh_recent_queries()
+    }
+
+    fn refresh_recent_queries(&self) -> Result<()> {
+        let queries = self.recent_queries();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("sql", DataType::Utf8, false),
+            Field::new("status", DataType::Utf8, false),
+            Field::new(
+                "started_at",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("latency_ms", DataType::Float64, false),
+            Field::new("rows", DataType::UInt64, false),
+            Field::new("error", DataType::Utf8, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(
+                queries.iter().map(|q| &q.sql),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                queries.iter().map(|q| q.status()),
+            )),
+            Arc::new(TimestampMillisecondArray::from_iter_values(
+                queries.iter().map(|q| q.started_at.timestamp_millis()),
+            )),
+            Arc::new(Float64Array::from_iter_values(
+                queries.iter().map(|q| q.latency_ms),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                queries.iter().map(|q| q.rows),
+            )),
+            Arc::new(StringArray::from_iter(
+                queries.iter().map(|q| q.error.as_deref()),
+            )),
+        ];
+        self.refresh_system_table(RECENT_QUERIES_TABLE, RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recent_queries() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.set_recent_queries_capacity(3)?;
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2)").await?;
+        db.query_to_batches("SELECT * FROM t").await?;
+        assert!(db.query_to_batches("SELECT missing FROM t").await.is_err());
+
+        let recent = db.recent_queries();
+        assert_eq!(recent.len(), 3);
+        assert_eq!(recent[0].sql, "INSERT INTO t VALUES (1), (2)");
+        assert_eq!(recent[1].rows, 2);
+        assert_eq!(recent[2].status(), "error");
+
+        let batches = db
+            .query_to_batches(
+                "SELECT sql, rows FROM system.recent_queries WHERE status = 'ok' ORDER BY started_at",
+            )
+            .await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        // 查询 system.recent_queries 本身也会被记录
+        assert_eq!(db.recent_queries().len(), 3);
+
+        db.set_recent_queries_capacity(0)?;
+        db.execute("SELECT 1").await?;
+        assert!(db.recent_queries().is_empty());
+        Ok(())
+    }
+}
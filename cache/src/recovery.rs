@@ -0,0 +1,311 @@
+#[cfg(feature = "clickhouse")]
+use crate::ck_client::{ClickHouseClient, QueryHints};
+use crate::integrity::IntegrityError;
+use crate::pool::DB;
+use crate::wal::WalRecord;
+use anyhow::Result;
+#[cfg(feature = "clickhouse")]
+use datafusion::datasource::MemTable;
+use datafusion::logical_expr::{DdlStatement, LogicalPlan};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeSet;
+#[cfg(feature = "clickhouse")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 从 ClickHouse 重新加载的表，WAL 中这些表的写入会被跳过
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryOptions {
+    #[cfg(feature = "clickhouse")]
+    pub clickhouse: Option<ClickHouseClient>,
+    // ClickHouse 中的同名表，只有设置了 clickhouse 时才会加载
+    pub reload_tables: Vec<String>,
+    // WAL 中有记录校验失败时重放到最后一条完整的记录并隔离损坏的部分，否则返回 IntegrityError
+    pub recover_to_last_valid: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecoveryReport {
+    // 重放或者重新加载过的表，按名字排序
+    pub tables: Vec<String>,
+    pub records_replayed: usize,
+    pub rows_replayed: usize,
+    // 从 ClickHouse 加载的行数
+    pub rows_reloaded: usize,
+    // 重放失败的记录，不会中断恢复
+    pub errors: Vec<String>,
+    // 开启 recover_to_last_valid 时遇到的损坏，从这里开始的记录没有重放
+    pub corruption: Option<IntegrityError>,
+    pub duration: Duration,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 启动时重放 WAL 中的 DDL 和写入，需要先 enable_wal，并且在接受写入之前调用
+    ///
+    /// 重放的内容不会再写入 WAL；没有开启 WAL 时只返回空的结果。
+    /// WAL 中有记录校验失败时返回 IntegrityError，不会加载损坏的状态
+    pub async fn recovery(&self) -> Result<RecoveryReport> {
+        self.recovery_with(RecoveryOptions::default()).await
+    }
+
+    pub async fn recovery_with(&self, options: RecoveryOptions) -> Result<RecoveryReport> {
+        let started = Instant::now();
+        let mut report = RecoveryReport::default();
+        let mut tables = BTreeSet::new();
+        #[cfg(feature = "clickhouse")]
+        let reload: BTreeSet<&str> = match options.clickhouse {
+            Some(_) => options.reload_tables.iter().map(|t| t.as_str()).collect(),
+            None => BTreeSet::new(),
+        };
+        #[cfg(not(feature = "clickhouse"))]
+        let reload: BTreeSet<&str> = {
+            if !options.reload_tables.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Reloading tables requires the clickhouse feature"
+                ));
+            }
+            BTreeSet::new()
+        };
+
+        if let Some(wal) = self.wal() {
+            let records = if options.recover_to_last_valid {
+                let (records, corruption) = wal.read_valid()?;
+                if let Some(corruption) = &corruption {
+                    wal.quarantine(corruption)?;
+                }
+                report.corruption = corruption;
+                records
+            } else {
+                wal.read_all()?
+            };
+            for record in records {
+                let result = match &record {
+                    WalRecord::Ddl { sql } => match self.replay_ddl(sql).await {
+                        Ok(_) => Ok(0),
+                        Err(e) => Err(anyhow::anyhow!("Replay {} failed: {}", sql, e)),
+                    },
+                    WalRecord::Insert { table, .. } if reload.contains(table.as_str()) => {
+                        continue;
+                    }
+                    WalRecord::Insert { table, batches } => {
+                        let rows = batches.iter().map(|b| b.num_rows()).sum();
+                        tables.insert(table.clone());
+                        self.apply_append(table, batches.clone())
+                            .await
+                            .map(|_| rows)
+                            .map_err(|e| {
+                                anyhow::anyhow!("Replay insert into {} failed: {}", table, e)
+                            })
+                    }
+                    WalRecord::Replace { table, .. } if reload.contains(table.as_str()) => {
+                        continue;
+                    }
+                    WalRecord::Replace {
+                        table,
+                        schema,
+                        batches,
+                    } => {
+                        let rows = batches.iter().map(|b| b.num_rows()).sum();
+                        tables.insert(table.clone());
+                        let base = self.ctx.table_provider(self.table_ref(table)).await.ok();
+                        self.apply_replace(
+                            table,
+                            schema.clone(),
+                            vec![batches.clone()],
+                            base.as_ref(),
+                        )
+                        .map(|_| rows)
+                        .map_err(|e| anyhow::anyhow!("Replay rewrite of {} failed: {}", table, e))
+                    }
+                    WalRecord::Meta { table, meta } => self
+                        .apply_table_meta(table, meta.clone())
+                        .map(|_| 0)
+                        .map_err(|e| anyhow::anyhow!("Replay metadata of {} failed: {}", table, e)),
+                };
+                match result {
+                    Ok(rows) => {
+                        report.records_replayed += 1;
+                        report.rows_replayed += rows;
+                    }
+                    Err(e) => report.errors.push(e.to_string()),
+                }
+            }
+        }
+
+        #[cfg(feature = "clickhouse")]
+        if let Some(client) = &options.clickhouse {
+            for table in &reload {
+                match self.reload_from_clickhouse(table, client).await {
+                    Ok(rows) => {
+                        report.rows_reloaded += rows;
+                        tables.insert(table.to_string());
+                    }
+                    Err(e) => report
+                        .errors
+                        .push(format!("Reload {} from ClickHouse failed: {}", table, e)),
+                }
+            }
+        }
+
+        report.tables = tables.into_iter().collect();
+        report.duration = started.elapsed();
+        Ok(report)
+    }
+
+    // CREATE EXTERNAL TABLE 的 storage 选项和 create_external_table_from_frontend 一样换成存储中的地址
+    async fn replay_ddl(&self, sql: &str) -> Result<()> {
+        let plan = match self.ctx.state().create_logical_plan(sql).await? {
+            LogicalPlan::Ddl(DdlStatement::CreateExternalTable(cmd)) => LogicalPlan::Ddl(
+                DdlStatement::CreateExternalTable(self.resolve_external_table(cmd)?),
+            ),
+            plan => plan,
+        };
+        self.ctx.execute_logical_plan(plan).await?;
+        Ok(())
+    }
+
+    // 和 refresh_from_clickhouse 一样替换表的内容，但不发送 RefreshFailed 事件
+    #[cfg(feature = "clickhouse")]
+    async fn reload_from_clickhouse(
+        &self,
+        table: &str,
+        client: &ClickHouseClient,
+    ) -> Result<usize> {
+        let (schema, batches) = client
+            .query(
+                &format!("SELECT * FROM {}", self.quote_table(table)),
+                &QueryHints::default(),
+            )
+            .await?;
+        let rows = batches.iter().map(|b| b.num_rows()).sum();
+        let provider = MemTable::try_new(schema, vec![batches])?;
+        self.replace_table(self.table_ref(table).table(), Arc::new(provider))?;
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::WalConfig;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::common::ScalarValue;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_recovery_from_wal() -> Result<()> {
+        let dir = tempdir()?;
+        {
+            let db = DB::<()>::new("test_db");
+            db.enable_wal(WalConfig::new(dir.path()))?;
+            db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+                .await?;
+            db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+                .await?;
+            db.execute("INSERT INTO t VALUES (3, 'c')").await?;
+        }
+
+        // 重启后重放，重放的内容不会再写入 WAL
+        let db = DB::<()>::new("test_db");
+        let wal = db.enable_wal(WalConfig::new(dir.path()))?;
+        let report = db.recovery().await?;
+        assert_eq!(report.tables, vec!["t"]);
+        assert_eq!(report.records_replayed, 3);
+        assert_eq!(report.rows_replayed, 3);
+        assert!(report.errors.is_empty());
+        assert_eq!(db.ctx.table("t").await?.count().await?, 3);
+        assert_eq!(wal.read_all()?.len(), 3);
+
+        // 没有开启 WAL 时没有需要恢复的内容
+        let report = DB::<()>::new("empty").recovery().await?;
+        assert!(report.tables.is_empty());
+        assert_eq!(report.records_replayed, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recovery_replays_rewrites() -> Result<()> {
+        let dir = tempdir()?;
+        {
+            let db = DB::<()>::new("test_db");
+            db.enable_wal(WalConfig::new(dir.path()))?;
+            db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+                .await?;
+            db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+                .await?;
+            db.execute_prepared(
+                "INSERT INTO t VALUES ($1, 'c')",
+                vec![ScalarValue::Int64(Some(3))],
+            )
+            .await?;
+            let batch = RecordBatch::try_new(
+                db.table_schema("t").await?,
+                vec![
+                    Arc::new(Int64Array::from(vec![2])),
+                    Arc::new(StringArray::from(vec!["B"])),
+                ],
+            )?;
+            db.upsert("t", &["id"], batch).await?;
+            db.execute("CREATE TABLE gone (id BIGINT)").await?;
+            db.execute("INSERT INTO gone VALUES (1)").await?;
+            db.truncate_table("gone").await?;
+        }
+
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(WalConfig::new(dir.path()))?;
+        let report = db.recovery().await?;
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.tables, vec!["gone", "t"]);
+        assert_eq!(db.ctx.table("t").await?.count().await?, 3);
+        let upserted = db.ctx.sql("SELECT * FROM t WHERE id = 2 AND name = 'B'");
+        assert_eq!(upserted.await?.count().await?, 1);
+        assert_eq!(db.ctx.table("gone").await?.count().await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recovery_with_corrupt_wal() -> Result<()> {
+        let dir = tempdir()?;
+        {
+            let db = DB::<()>::new("test_db");
+            db.enable_wal(WalConfig::new(dir.path()))?;
+            db.execute("CREATE TABLE t (id BIGINT)").await?;
+            db.execute("INSERT INTO t VALUES (1)").await?;
+            db.execute("INSERT INTO t VALUES (2)").await?;
+        }
+        // 最后一条记录的最后一个字节损坏
+        let segment = std::fs::read_dir(dir.path())?.next().unwrap()?.path();
+        let mut data = std::fs::read(&segment)?;
+        *data.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&segment, &data)?;
+
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(WalConfig::new(dir.path()))?;
+        let err = db.recovery().await.unwrap_err();
+        assert!(err.downcast_ref::<IntegrityError>().is_some());
+        assert!(db.ctx.table("t").await.is_err());
+
+        let report = db
+            .recovery_with(RecoveryOptions {
+                recover_to_last_valid: true,
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(report.records_replayed, 2);
+        assert_eq!(
+            report.corruption.map(|c| c.segment),
+            Some(segment.display().to_string())
+        );
+        assert_eq!(db.ctx.table("t").await?.count().await?, 1);
+
+        // 隔离之后新的写入在下次恢复时不会被损坏的记录挡住
+        db.execute("INSERT INTO t VALUES (3)").await?;
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(WalConfig::new(dir.path()))?;
+        assert_eq!(db.recovery().await?.records_replayed, 3);
+        assert_eq!(db.ctx.table("t").await?.count().await?, 2);
+        Ok(())
+    }
+}
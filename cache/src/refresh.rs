@@ -0,0 +1,423 @@
+#[cfg(feature = "clickhouse")]
+use crate::ck_client::{ClickHouseClient, QueryHints};
+use crate::events::CacheEvent;
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use async_trait::async_trait;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::prelude::SessionContext;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 刷新表时读取新数据的来源
+#[async_trait]
+pub trait RefreshSource: Send + Sync {
+    async fn load(&self, ctx: &SessionContext) -> Result<(SchemaRef, Vec<RecordBatch>)>;
+}
+
+/// 在本地执行 SQL，比如把外部表的数据加载到内存表
+pub struct SqlSource(pub String);
+
+#[async_trait]
+impl RefreshSource for SqlSource {
+    async fn load(&self, ctx: &SessionContext) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+        let df = ctx.sql(&self.0).await?;
+        let schema = df.schema().inner().clone();
+        Ok((schema, df.collect().await?))
+    }
+}
+
+/// 从 ClickHouse 查询，和 refresh_from_clickhouse 一样
+#[cfg(feature = "clickhouse")]
+pub struct ClickHouseSource {
+    pub client: ClickHouseClient,
+    pub sql: String,
+    pub hints: QueryHints,
+}
+
+#[cfg(feature = "clickhouse")]
+#[async_trait]
+impl RefreshSource for ClickHouseSource {
+    async fn load(&self, _ctx: &SessionContext) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+        self.client.query(&self.sql, &self.hints).await
+    }
+}
+
+/// refresh_all 中的一个表
+pub struct RefreshTask {
+    pub table: String,
+    pub source: Arc<dyn RefreshSource>,
+    // 需要先刷新成功的表，比如事实表依赖维度表
+    pub depends_on: Vec<String>,
+    pub timeout: Option<Duration>,
+    // 刷新成功但用时超过 sla 时发送 SlaBreached 事件
+    pub sla: Option<Duration>,
+}
+
+impl RefreshTask {
+    pub fn new(table: &str, source: impl RefreshSource + 'static) -> Self {
+        Self {
+            table: table.to_string(),
+            source: Arc::new(source),
+            depends_on: Vec::new(),
+            timeout: None,
+            sla: None,
+        }
+    }
+
+    pub fn after(mut self, table: &str) -> Self {
+        self.depends_on.push(table.to_string());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_sla(mut self, sla: Duration) -> Self {
+        self.sla = Some(sla);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefreshStatus {
+    Refreshed { rows: usize },
+    Failed { error: String },
+    TimedOut,
+    // 依赖的表没有刷新成功，没有执行
+    Skipped { dependency: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRefresh {
+    pub table: String,
+    pub status: RefreshStatus,
+    pub elapsed: Duration,
+}
+
+/// refresh_all 的结果，按完成的顺序排列
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshReport {
+    pub tables: Vec<TableRefresh>,
+    pub elapsed: Duration,
+}
+
+impl RefreshReport {
+    pub fn is_ok(&self) -> bool {
+        self.tables
+            .iter()
+            .all(|t| matches!(t.status, RefreshStatus::Refreshed { .. }))
+    }
+
+    pub fn refreshed(&self) -> usize {
+        self.tables
+            .iter()
+            .filter(|t| matches!(t.status, RefreshStatus::Refreshed { .. }))
+            .count()
+    }
+
+    pub fn rows(&self) -> usize {
+        self.tables
+            .iter()
+            .map(|t| match t.status {
+                RefreshStatus::Refreshed { rows } => rows,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    pub fn get(&self, table: &str) -> Option<&TableRefresh> {
+        self.tables.iter().find(|t| t.table == table)
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 并发刷新多个表，最多同时执行 parallelism 个，依赖的表刷新成功后才开始
+    ///
+    /// 每个表读取完成后原子替换，单个表失败或超时不会影响没有依赖它的表；
+    /// 依赖不存在或者有环时不会执行任何刷新
+    pub async fn refresh_all(
+        &self,
+        tasks: Vec<RefreshTask>,
+        parallelism: usize,
+    ) -> Result<RefreshReport> {
+        let started = Instant::now();
+        check_dependencies(&tasks)?;
+        let parallelism = parallelism.max(1);
+
+        let mut pending: Vec<RefreshTask> = tasks;
+        let mut succeeded: HashSet<String> = HashSet::new();
+        let mut finished: HashSet<String> = HashSet::new();
+        let mut report = RefreshReport::default();
+        let mut running = FuturesUnordered::new();
+
+        loop {
+            // 依赖失败的表直接跳过，跳过也算完成，后面依赖它的表随之跳过
+            while let Some(index) = pending.iter().position(|task| {
+                task.depends_on
+                    .iter()
+                    .any(|dep| finished.contains(dep) && !succeeded.contains(dep))
+            }) {
+                let task = pending.remove(index);
+                let dependency = task
+                    .depends_on
+                    .iter()
+                    .find(|dep| !succeeded.contains(*dep))
+                    .cloned()
+                    .unwrap_or_default();
+                finished.insert(task.table.clone());
+                report.tables.push(TableRefresh {
+                    table: task.table,
+                    status: RefreshStatus::Skipped { dependency },
+                    elapsed: Duration::ZERO,
+                });
+            }
+
+            while running.len() < parallelism {
+                let Some(index) = pending
+                    .iter()
+                    .position(|task| task.depends_on.iter().all(|dep| succeeded.contains(dep)))
+                else {
+                    break;
+                };
+                let task = pending.remove(index);
+                running.push(self.refresh_one(task));
+            }
+
+            let Some(done) = running.next().await else {
+                break;
+            };
+            if matches!(done.status, RefreshStatus::Refreshed { .. }) {
+                succeeded.insert(done.table.clone());
+            }
+            finished.insert(done.table.clone());
+            report.tables.push(done);
+        }
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    async fn refresh_one(&self, task: RefreshTask) -> TableRefresh {
+        let started = Instant::now();
+        let load = task.source.load(&self.ctx);
+        let loaded = match task.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, load).await {
+                Ok(loaded) => Some(loaded),
+                Err(_) => None,
+            },
+            None => Some(load.await),
+        };
+        let status = match loaded {
+            Some(Ok((schema, batches))) => {
+                match self.replace_with(&task.table, schema, batches).await {
+                    Ok(rows) => RefreshStatus::Refreshed { rows },
+                    Err(e) => RefreshStatus::Failed {
+                        error: format!("{:#}", e),
+                    },
+                }
+            }
+            Some(Err(e)) => RefreshStatus::Failed {
+                error: format!("{:#}", e),
+            },
+            None => RefreshStatus::TimedOut,
+        };
+        match &status {
+            RefreshStatus::Failed { error } => self.emit(CacheEvent::RefreshFailed {
+                table: task.table.clone(),
+                error: error.clone(),
+            }),
+            RefreshStatus::TimedOut => self.emit(CacheEvent::RefreshFailed {
+                table: task.table.clone(),
+                error: "refresh timed out".to_string(),
+            }),
+            _ => {}
+        }
+        let elapsed = started.elapsed();
+        if let (RefreshStatus::Refreshed { .. }, Some(sla)) = (&status, task.sla) {
+            if elapsed > sla {
+                self.emit(CacheEvent::SlaBreached {
+                    table: task.table.clone(),
+                    elapsed,
+                    sla,
+                });
+            }
+        }
+        TableRefresh {
+            table: task.table,
+            status,
+            elapsed,
+        }
+    }
+
+    async fn replace_with(
+        &self,
+        table: &str,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<usize> {
+        let _guard = self.lock_table(table).await;
+        let rows = batches.iter().map(|b| b.num_rows()).sum();
+        self.rewrite_table(table, schema, vec![batches], None)?;
+        Ok(rows)
+    }
+}
+
+// 依赖必须在本次刷新的表中，并且不能有环
+fn check_dependencies(tasks: &[RefreshTask]) -> Result<()> {
+    let deps: HashMap<&str, &[String]> = tasks
+        .iter()
+        .map(|task| (task.table.as_str(), task.depends_on.as_slice()))
+        .collect();
+    if deps.len() != tasks.len() {
+        return Err(anyhow::anyhow!("Duplicate table in refresh tasks"));
+    }
+    for task in tasks {
+        if let Some(dep) = task
+            .depends_on
+            .iter()
+            .find(|dep| !deps.contains_key(dep.as_str()))
+        {
+            return Err(anyhow::anyhow!(
+                "Table {} depends on {} which is not being refreshed",
+                task.table,
+                dep
+            ));
+        }
+    }
+
+    // 反复去掉依赖都已经去掉的表，剩下的就是环
+    let mut remaining: HashSet<&str> = deps.keys().copied().collect();
+    loop {
+        let ready: Vec<&str> = remaining
+            .iter()
+            .copied()
+            .filter(|table| {
+                deps[table]
+                    .iter()
+                    .all(|dep| !remaining.contains(dep.as_str()))
+            })
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        for table in ready {
+            remaining.remove(table);
+        }
+    }
+    if !remaining.is_empty() {
+        let mut cycle: Vec<&str> = remaining.into_iter().collect();
+        cycle.sort();
+        return Err(anyhow::anyhow!(
+            "Circular refresh dependency between {}",
+            cycle.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct SlowSource;
+
+    #[async_trait]
+    impl RefreshSource for SlowSource {
+        async fn load(&self, _ctx: &SessionContext) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Err(anyhow::anyhow!("unreachable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_all() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE src_dim (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO src_dim VALUES (1, 'a'), (2, 'b')")
+            .await?;
+
+        let report = db
+            .refresh_all(
+                vec![
+                    // 事实表依赖 dim，dim 刷新完成后才能 join
+                    RefreshTask::new(
+                        "fact",
+                        SqlSource("SELECT id * 10 AS amount FROM dim".to_string()),
+                    )
+                    .after("dim"),
+                    RefreshTask::new("dim", SqlSource("SELECT * FROM src_dim".to_string())),
+                    RefreshTask::new("broken", SqlSource("SELECT * FROM missing".to_string())),
+                    RefreshTask::new("downstream", SqlSource("SELECT 1".to_string()))
+                        .after("broken"),
+                    RefreshTask::new("slow", SlowSource).with_timeout(Duration::from_millis(20)),
+                ],
+                2,
+            )
+            .await?;
+
+        assert_eq!(report.refreshed(), 2);
+        assert_eq!(report.rows(), 4);
+        assert!(!report.is_ok());
+        assert!(matches!(
+            report.get("broken").unwrap().status,
+            RefreshStatus::Failed { .. }
+        ));
+        assert_eq!(
+            report.get("downstream").unwrap().status,
+            RefreshStatus::Skipped {
+                dependency: "broken".to_string()
+            }
+        );
+        assert_eq!(report.get("slow").unwrap().status, RefreshStatus::TimedOut);
+        assert_eq!(db.ctx.table("fact").await?.count().await?, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_sla() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        let mut events = db.subscribe();
+        let report = db
+            .refresh_all(
+                vec![
+                    RefreshTask::new("fast", SqlSource("SELECT 1".to_string()))
+                        .with_sla(Duration::from_secs(60)),
+                    RefreshTask::new("late", SqlSource("SELECT 1".to_string()))
+                        .with_sla(Duration::ZERO),
+                ],
+                1,
+            )
+            .await?;
+        assert!(report.is_ok());
+        match events.try_recv()? {
+            CacheEvent::SlaBreached { table, sla, .. } => {
+                assert_eq!(table, "late");
+                assert_eq!(sla, Duration::ZERO);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(events.try_recv().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_dependency_errors() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        let cycle = vec![
+            RefreshTask::new("a", SqlSource("SELECT 1".to_string())).after("b"),
+            RefreshTask::new("b", SqlSource("SELECT 1".to_string())).after("a"),
+        ];
+        assert!(db.refresh_all(cycle, 2).await.is_err());
+        let unknown =
+            vec![RefreshTask::new("a", SqlSource("SELECT 1".to_string())).after("missing")];
+        assert!(db.refresh_all(unknown, 2).await.is_err());
+        Ok(())
+    }
+}
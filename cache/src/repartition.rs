@@ -0,0 +1,125 @@
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use datafusion::physical_expr::expressions::col;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::{collect_partitioned, Partitioning};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::sync::Arc;
+
+/// 表的分区方式
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PartitionSpec {
+    pub partitions: usize,
+    // 按这些列 hash 分区，为空时轮询分配
+    #[serde(default)]
+    pub hash_key: Vec<String>,
+}
+
+impl PartitionSpec {
+    pub fn round_robin(partitions: usize) -> Self {
+        Self {
+            partitions,
+            hash_key: Vec::new(),
+        }
+    }
+
+    pub fn hash(partitions: usize, key: &[&str]) -> Self {
+        Self {
+            partitions,
+            hash_key: key.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 按新的分区数或 hash key 重写内存表，返回新的分区数
+    ///
+    /// 重写期间表仍然可以查询，新的分区构建完成后一次性替换；
+    /// 重写持有表的写锁，同时进行的写入会等重写完成后再执行，不会丢失。
+    /// 表的约束和索引会保留，开启 WAL 时重写的结果写入 WAL
+    pub async fn repartition(&self, table: &str, spec: PartitionSpec) -> Result<usize> {
+        if spec.partitions == 0 {
+            return Err(anyhow::anyhow!("Partition count should be positive"));
+        }
+        let _guard = self.lock_table(table).await;
+        let table_ref = self.table_ref(table);
+        let provider = self.ctx.table_provider(table_ref.clone()).await?;
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Repartition is only supported on in-memory tables, {} is not",
+                table
+            ));
+        }
+
+        // 直接构造物理计划，避免优化器合并或去掉重分区
+        let schema = provider.schema();
+        let partitioning = if spec.hash_key.is_empty() {
+            Partitioning::RoundRobinBatch(spec.partitions)
+        } else {
+            let exprs = spec
+                .hash_key
+                .iter()
+                .map(|key| col(key, &schema))
+                .collect::<datafusion::common::Result<Vec<_>>>()?;
+            Partitioning::Hash(exprs, spec.partitions)
+        };
+        let scan = provider.scan(&self.ctx.state(), None, &[], None).await?;
+        let plan = Arc::new(RepartitionExec::try_new(scan, partitioning)?);
+        let partitions = collect_partitioned(plan, self.ctx.task_ctx()).await?;
+
+        let count = partitions.len();
+        self.rewrite_table(table_ref.table(), schema, partitions, Some(&provider))?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn partition_count(db: &DB<()>, table: &str) -> Result<usize> {
+        let provider = db.ctx.table_provider(table).await?;
+        let plan = provider.scan(&db.ctx.state(), None, &[], None).await?;
+        Ok(plan.properties().output_partitioning().partition_count())
+    }
+
+    #[tokio::test]
+    async fn test_repartition() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c'), (4, 'd')")
+            .await?;
+        assert_eq!(partition_count(&db, "t").await?, 1);
+
+        assert_eq!(
+            db.repartition("t", PartitionSpec::hash(4, &["id"])).await?,
+            4
+        );
+        assert_eq!(partition_count(&db, "t").await?, 4);
+        assert_eq!(db.query("SELECT * FROM t").await?.count().await?, 4);
+
+        assert_eq!(db.repartition("t", PartitionSpec::round_robin(2)).await?, 2);
+        assert_eq!(db.query("SELECT * FROM t").await?.count().await?, 4);
+        assert!(db
+            .repartition("t", PartitionSpec::round_robin(0))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_repartition_keeps_constraints() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT PRIMARY KEY, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+        db.repartition("t", PartitionSpec::round_robin(2)).await?;
+        let provider = db.ctx.table_provider("t").await?;
+        assert!(provider.constraints().is_some_and(|c| !c.is_empty()));
+        assert_eq!(db.query("SELECT * FROM t").await?.count().await?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::array::{Array, ArrayRef, AsArray, UInt64Array};
+use datafusion::arrow::datatypes::UInt64Type;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::ScalarValue;
+use datafusion::prelude::{col, lit, Expr};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+/// 行 ID 列，开启后作为表的最后一列
+///
+/// 写入时自动分配，之后不会改变，淘汰、压缩、upsert 等整表重写也会保留
+pub const ROW_ID_COLUMN: &str = "_row_id";
+
+fn has_row_id(schema: &Schema) -> bool {
+    schema
+        .field_with_name(ROW_ID_COLUMN)
+        .is_ok_and(|f| f.data_type() == &DataType::UInt64)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 给内存表加上 _row_id 列，已有的行按顺序分配 ID，表已经有 _row_id 时什么都不做
+    ///
+    /// 开启后 SQL INSERT 需要写出列名（_row_id 留空），insert_batch 等 API 可以不带 _row_id；
+    /// 按 _row_id 修改行可以用 upsert(table, &["_row_id"], batch)
+    pub async fn enable_row_ids(&self, table: &str) -> Result<()> {
+        let _guard = self.lock_table(table).await;
+        let table_ref = self.table_ref(table);
+        let provider = match self.ctx.table_provider(table_ref.clone()).await {
+            Ok(provider) => provider,
+            Err(_) => return Err(anyhow::anyhow!("Table {} not found", table)),
+        };
+        if has_row_id(&provider.schema()) {
+            return Ok(());
+        }
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Row ids are only supported on in-memory tables, {} is not",
+                table
+            ));
+        }
+
+        let mut fields = provider.schema().fields().to_vec();
+        fields.push(Arc::new(Field::new(ROW_ID_COLUMN, DataType::UInt64, true)));
+        let schema = Arc::new(Schema::new_with_metadata(
+            fields,
+            provider.schema().metadata().clone(),
+        ));
+        let mut next = 0;
+        let mut batches = Vec::new();
+        for batch in self.ctx.read_table(provider.clone())?.collect().await? {
+            let ids = UInt64Array::from_iter_values(next..next + batch.num_rows() as u64);
+            next += batch.num_rows() as u64;
+            let mut columns = batch.columns().to_vec();
+            columns.push(Arc::new(ids));
+            batches.push(RecordBatch::try_new(schema.clone(), columns)?);
+        }
+        self.rewrite_table(table_ref.table(), schema, vec![batches], Some(&provider))?;
+        self.row_ids
+            .lock()
+            .unwrap()
+            .insert(table_ref.table().to_string(), next);
+        Ok(())
+    }
+
+    /// 按 _row_id 删除行，返回删除的行数
+    pub async fn delete_rows(&self, table: &str, row_ids: &[u64]) -> Result<usize> {
+        let ids = row_ids
+            .iter()
+            .map(|id| lit(ScalarValue::UInt64(Some(*id))))
+            .collect();
+        self.delete_rows_where(table, col(ROW_ID_COLUMN).in_list(ids, false))
+            .await
+    }
+
+    /// 删除 _row_id 落在任意一个闭区间 [first, last] 内的行，返回删除的行数
+    pub(crate) async fn delete_row_ranges(
+        &self,
+        table: &str,
+        ranges: &[(u64, u64)],
+    ) -> Result<usize> {
+        let Some(predicate) = ranges
+            .iter()
+            .map(|(first, last)| {
+                col(ROW_ID_COLUMN).between(
+                    lit(ScalarValue::UInt64(Some(*first))),
+                    lit(ScalarValue::UInt64(Some(*last))),
+                )
+            })
+            .reduce(|a, b| a.or(b))
+        else {
+            return Ok(0);
+        };
+        self.delete_rows_where(table, predicate).await
+    }
+
+    // 用不满足 predicate 的行重建表，整表替换
+    async fn delete_rows_where(&self, table: &str, predicate: Expr) -> Result<usize> {
+        let _guard = self.lock_table(table).await;
+        let table_ref = self.table_ref(table);
+        let provider = match self.ctx.table_provider(table_ref.clone()).await {
+            Ok(provider) => provider,
+            Err(_) => return Err(anyhow::anyhow!("Table {} not found", table)),
+        };
+        if !has_row_id(&provider.schema()) {
+            return Err(anyhow::anyhow!("Table {} has no row ids", table));
+        }
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!("Table {} is not an in-memory table", table));
+        }
+        let before = self.ctx.read_table(provider.clone())?.count().await?;
+        let kept = self
+            .ctx
+            .read_table(provider.clone())?
+            .filter(predicate.is_not_true())?
+            .collect()
+            .await?;
+        let after: usize = kept.iter().map(|b| b.num_rows()).sum();
+        if after == before {
+            return Ok(0);
+        }
+        self.rewrite_table(
+            table_ref.table(),
+            provider.schema(),
+            vec![kept],
+            Some(&provider),
+        )?;
+        Ok(before - after)
+    }
+
+    // 写入开启了行 ID 的表之前调用：没有 _row_id 列的 batch 补上这一列，为空的 ID 分配新值
+    pub(crate) async fn assign_row_ids(
+        &self,
+        table: &str,
+        schema: &SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<Vec<RecordBatch>> {
+        let Ok(index) = schema.index_of(ROW_ID_COLUMN) else {
+            return Ok(batches);
+        };
+        if !has_row_id(schema) {
+            return Ok(batches);
+        }
+        let missing: usize = batches
+            .iter()
+            .map(|batch| match batch.schema().index_of(ROW_ID_COLUMN) {
+                Ok(i) => batch.column(i).null_count(),
+                Err(_) => batch.num_rows(),
+            })
+            .sum();
+        if missing == 0 {
+            return Ok(batches);
+        }
+        let mut next = self.allocate_row_ids(table, missing as u64).await?;
+
+        let mut assigned = Vec::with_capacity(batches.len());
+        for batch in batches {
+            let mut fields = batch.schema().fields().to_vec();
+            let mut columns = batch.columns().to_vec();
+            match batch.schema().index_of(ROW_ID_COLUMN) {
+                Ok(i) => {
+                    let ids = columns[i].as_primitive::<UInt64Type>();
+                    let filled: UInt64Array = ids
+                        .iter()
+                        .map(|id| {
+                            Some(id.unwrap_or_else(|| {
+                                next += 1;
+                                next - 1
+                            }))
+                        })
+                        .collect();
+                    columns[i] = Arc::new(filled) as ArrayRef;
+                }
+                Err(_) => {
+                    let ids = UInt64Array::from_iter_values(next..next + batch.num_rows() as u64);
+                    next += batch.num_rows() as u64;
+                    let at = index.min(columns.len());
+                    fields.insert(at, schema.field(index).clone().into());
+                    columns.insert(at, Arc::new(ids));
+                }
+            }
+            let batch_schema = Schema::new_with_metadata(fields, batch.schema().metadata().clone());
+            assigned.push(RecordBatch::try_new(Arc::new(batch_schema), columns)?);
+        }
+        Ok(assigned)
+    }
+
+    // 分配 count 个连续的 ID，返回第一个；DB 重启或者从备份恢复后从表中现有的最大值继续
+    async fn allocate_row_ids(&self, table: &str, count: u64) -> Result<u64> {
+        let key = self.table_ref(table).table().to_string();
+        let known = self.row_ids.lock().unwrap().contains_key(&key);
+        let initial = if known {
+            0
+        } else {
+            let batches = self
+                .ctx
+                .sql(&format!(
+                    "SELECT max({}) FROM {}",
+                    ROW_ID_COLUMN,
+                    self.quote_table(table)
+                ))
+                .await?
+                .collect()
+                .await?;
+            batches
+                .first()
+                .map(|b| b.column(0).as_primitive::<UInt64Type>())
+                .filter(|max| max.is_valid(0))
+                .map_or(0, |max| max.value(0) + 1)
+        };
+        let mut row_ids = self.row_ids.lock().unwrap();
+        let next = row_ids.entry(key).or_insert(initial);
+        let first = *next;
+        *next += count;
+        Ok(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ids(db: &DB<()>) -> Result<Vec<u64>> {
+        let batches = db
+            .query_to_batches("SELECT _row_id FROM t ORDER BY _row_id")
+            .await?;
+        Ok(batches
+            .iter()
+            .flat_map(|b| b.column(0).as_primitive::<UInt64Type>().values().to_vec())
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_row_ids() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (name VARCHAR)").await?;
+        db.execute("INSERT INTO t VALUES ('a'), ('b')").await?;
+        db.enable_row_ids("t").await?;
+        assert_eq!(ids(&db).await?, vec![0, 1]);
+
+        db.execute("INSERT INTO t (name) VALUES ('c')").await?;
+        let batch = db.query_to_batches("SELECT 'd' AS name").await?;
+        db.insert_batches("t", batch).await?;
+        assert_eq!(ids(&db).await?, vec![0, 1, 2, 3]);
+
+        assert_eq!(db.delete_rows("t", &[1, 7]).await?, 1);
+        assert_eq!(ids(&db).await?, vec![0, 2, 3]);
+
+        // 按 _row_id 修改，ID 不变
+        let update = db
+            .query_to_batches("SELECT 'C' AS name, arrow_cast(2, 'UInt64') AS _row_id")
+            .await?;
+        let schema = db.table_schema("t").await?;
+        let update = RecordBatch::try_new(schema, update[0].columns().to_vec())?;
+        db.upsert("t", &[ROW_ID_COLUMN], update).await?;
+        let names = db
+            .query_to_batches("SELECT name FROM t WHERE _row_id = 2")
+            .await?;
+        assert_eq!(names[0].column(0).as_string::<i32>().value(0), "C");
+        assert_eq!(ids(&db).await?, vec![0, 2, 3]);
+        Ok(())
+    }
+}
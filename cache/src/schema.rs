@@ -1,7 +1,7 @@
 use arrow::datatypes::{DataType, Field, Schema};
 use prost_reflect::{DescriptorPool, MessageDescriptor};
 use std::path::Path;
-fn create_schema_from_proto_file(
+pub(crate) fn create_schema_from_proto_file(
     proto_file: &Path,
     message_name: &str,
 ) -> Result<Schema, Box<dyn std::error::Error>> {
@@ -0,0 +1,332 @@
+use crate::pool::DB;
+use crate::swap::SwapSchemaProvider;
+use anyhow::Result;
+use async_trait::async_trait;
+use datafusion::catalog::{CatalogProvider, CatalogProviderList, SchemaProvider};
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::execution::SessionStateBuilder;
+use datafusion::logical_expr::{DdlStatement, LogicalPlan};
+use datafusion::prelude::{DataFrame, SessionContext};
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::{CreateTable, Statement};
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+/// 会话：CREATE TEMP TABLE 创建的表只在这个会话中可见，会话关闭（或者 drop）时释放
+///
+/// 其他表和 DB 共享，会话中的语句直接由 DataFusion 执行，
+/// 需要写 WAL、触发插件的写入仍然通过 DB::execute
+pub struct CacheSession {
+    ctx: SessionContext,
+    temp: Arc<SwapSchemaProvider>,
+}
+
+/// 叠加在默认 schema 上的表：tables 中的表优先于共享的同名表，hidden 中的共享表不可见
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Overlay {
+    pub(crate) tables: Arc<SwapSchemaProvider>,
+    pub(crate) hidden: Arc<RwLock<HashSet<String>>>,
+}
+
+/// 和 ctx 共享 catalog、UDF 和配置，默认 schema 叠加 overlay 的 SessionContext
+pub(crate) fn overlay_context(ctx: &SessionContext, overlay: Overlay) -> SessionContext {
+    let state = ctx.state();
+    let options = &state.config().options().catalog;
+    let catalogs = SessionCatalogList {
+        default_catalog: options.default_catalog.clone(),
+        default_schema: options.default_schema.clone(),
+        overlay,
+        shared: state.catalog_list().clone(),
+    };
+    // 默认 catalog 由 SessionCatalogList 提供，不能再创建一个空的覆盖掉
+    let config = state
+        .config()
+        .clone()
+        .with_create_default_catalog_and_schema(false);
+    let state = SessionStateBuilder::new_from_existing(state)
+        .with_config(config)
+        .with_catalog_list(Arc::new(catalogs))
+        .build();
+    SessionContext::new_with_state(state)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 创建会话，UDF 和配置取创建时 DB 的状态
+    pub fn session(&self) -> CacheSession {
+        let overlay = Overlay::default();
+        CacheSession {
+            temp: overlay.tables.clone(),
+            ctx: overlay_context(&self.ctx, overlay),
+        }
+    }
+}
+
+impl CacheSession {
+    /// 执行 SQL，临时表和共享表同名时优先使用临时表
+    pub async fn sql(&self, sql: &str) -> Result<DataFrame> {
+        let mut statements = DFParser::parse_sql(sql)?;
+        if statements.len() == 1 {
+            if let Some(DFStatement::Statement(statement)) = statements.pop_front() {
+                if let Statement::CreateTable(create) = *statement {
+                    if create.temporary {
+                        return self.create_temp_table(create).await;
+                    }
+                }
+            }
+        }
+        Ok(self.ctx.sql(sql).await?)
+    }
+
+    pub fn temp_tables(&self) -> Vec<String> {
+        let mut tables = self.temp.table_names();
+        tables.sort();
+        tables
+    }
+
+    /// 关闭会话，释放所有临时表，返回释放的表的数量
+    pub fn close(self) -> usize {
+        self.temp.table_names().len()
+    }
+
+    // 按普通表规划，把结果放到会话自己的 schema 中；约束和列默认值不保留
+    async fn create_temp_table(&self, mut create: CreateTable) -> Result<DataFrame> {
+        if create.name.0.len() != 1 {
+            return Err(anyhow::anyhow!(
+                "Temporary table name {} must not be qualified",
+                create.name
+            ));
+        }
+        let name = create.name.0[0].value.clone();
+        create.temporary = false;
+        let plan = self
+            .ctx
+            .state()
+            .statement_to_plan(DFStatement::Statement(Box::new(Statement::CreateTable(
+                create,
+            ))))
+            .await?;
+        let LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(cmd)) = plan else {
+            return Err(anyhow::anyhow!("Unsupported temporary table statement"));
+        };
+        if self.temp.table_exist(&name) {
+            if cmd.if_not_exists {
+                return Ok(self.ctx.read_empty()?);
+            }
+            if !cmd.or_replace {
+                return Err(anyhow::anyhow!("Temporary table {} already exists", name));
+            }
+        }
+        let schema = cmd.input.schema().inner().clone();
+        let input = Arc::unwrap_or_clone(cmd.input);
+        let batches = self
+            .ctx
+            .execute_logical_plan(input)
+            .await?
+            .collect()
+            .await?;
+        let table = MemTable::try_new(schema, vec![batches])?;
+        self.temp.replace_table(&name, Arc::new(table));
+        Ok(self.ctx.read_empty()?)
+    }
+}
+
+// 默认 catalog 换成 SessionCatalog，其他 catalog 和 DB 共享
+#[derive(Debug)]
+struct SessionCatalogList {
+    default_catalog: String,
+    default_schema: String,
+    overlay: Overlay,
+    shared: Arc<dyn CatalogProviderList>,
+}
+
+impl CatalogProviderList for SessionCatalogList {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn register_catalog(
+        &self,
+        name: String,
+        catalog: Arc<dyn CatalogProvider>,
+    ) -> Option<Arc<dyn CatalogProvider>> {
+        self.shared.register_catalog(name, catalog)
+    }
+
+    fn catalog_names(&self) -> Vec<String> {
+        self.shared.catalog_names()
+    }
+
+    fn catalog(&self, name: &str) -> Option<Arc<dyn CatalogProvider>> {
+        let shared = self.shared.catalog(name)?;
+        if name != self.default_catalog {
+            return Some(shared);
+        }
+        Some(Arc::new(SessionCatalog {
+            default_schema: self.default_schema.clone(),
+            overlay: self.overlay.clone(),
+            shared,
+        }))
+    }
+}
+
+// 默认 schema 换成 SessionSchema
+#[derive(Debug)]
+struct SessionCatalog {
+    default_schema: String,
+    overlay: Overlay,
+    shared: Arc<dyn CatalogProvider>,
+}
+
+impl CatalogProvider for SessionCatalog {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.shared.schema_names()
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        let shared = self.shared.schema(name)?;
+        if name != self.default_schema {
+            return Some(shared);
+        }
+        Some(Arc::new(SessionSchema {
+            overlay: self.overlay.clone(),
+            shared,
+        }))
+    }
+
+    fn register_schema(
+        &self,
+        name: &str,
+        schema: Arc<dyn SchemaProvider>,
+    ) -> datafusion::error::Result<Option<Arc<dyn SchemaProvider>>> {
+        self.shared.register_schema(name, schema)
+    }
+
+    fn deregister_schema(
+        &self,
+        name: &str,
+        cascade: bool,
+    ) -> datafusion::error::Result<Option<Arc<dyn SchemaProvider>>> {
+        self.shared.deregister_schema(name, cascade)
+    }
+}
+
+// 查找表时先找 overlay 中的表；普通的 CREATE TABLE 注册到共享的 schema，DROP TABLE 先删 overlay 中的表
+#[derive(Debug)]
+struct SessionSchema {
+    overlay: Overlay,
+    shared: Arc<dyn SchemaProvider>,
+}
+
+impl SessionSchema {
+    fn hidden(&self, name: &str) -> bool {
+        self.overlay.hidden.read().unwrap().contains(name)
+    }
+}
+
+#[async_trait]
+impl SchemaProvider for SessionSchema {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        let mut names = self.overlay.tables.table_names();
+        for name in self.shared.table_names() {
+            if !names.contains(&name) && !self.hidden(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    async fn table(&self, name: &str) -> datafusion::error::Result<Option<Arc<dyn TableProvider>>> {
+        match self.overlay.tables.table(name).await? {
+            Some(table) => Ok(Some(table)),
+            None if self.hidden(name) => Ok(None),
+            None => self.shared.table(name).await,
+        }
+    }
+
+    fn register_table(
+        &self,
+        name: String,
+        table: Arc<dyn TableProvider>,
+    ) -> datafusion::error::Result<Option<Arc<dyn TableProvider>>> {
+        self.shared.register_table(name, table)
+    }
+
+    fn deregister_table(
+        &self,
+        name: &str,
+    ) -> datafusion::error::Result<Option<Arc<dyn TableProvider>>> {
+        match self.overlay.tables.deregister_table(name)? {
+            Some(table) => Ok(Some(table)),
+            None if self.hidden(name) => Ok(None),
+            None => self.shared.deregister_table(name),
+        }
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        self.overlay.tables.table_exist(name)
+            || (!self.hidden(name) && self.shared.table_exist(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_session_temp_table() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3)").await?;
+
+        let session = db.session();
+        session
+            .sql("CREATE TEMP TABLE big AS SELECT id FROM t WHERE id > 1")
+            .await?;
+        session
+            .sql("INSERT INTO big VALUES (10)")
+            .await?
+            .collect()
+            .await?;
+        let batches = session
+            .sql("SELECT count(*) FROM big JOIN t USING (id)")
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(batches[0].num_rows(), 1);
+        assert_eq!(session.temp_tables(), vec!["big"]);
+        assert!(session.sql("CREATE TEMP TABLE big (id INT)").await.is_err());
+
+        // 其他会话和 DB 都看不到临时表
+        assert!(db.query_to_batches("SELECT * FROM big").await.is_err());
+        assert!(db.session().sql("SELECT * FROM big").await.is_err());
+
+        // 同名时临时表优先，DROP 后重新看到共享表
+        session.sql("CREATE TEMP TABLE t (id BIGINT)").await?;
+        let rows = |batches: Vec<datafusion::arrow::record_batch::RecordBatch>| {
+            batches.iter().map(|b| b.num_rows()).sum::<usize>()
+        };
+        assert_eq!(
+            rows(session.sql("SELECT * FROM t").await?.collect().await?),
+            0
+        );
+        session.sql("DROP TABLE t").await?.collect().await?;
+        assert_eq!(
+            rows(session.sql("SELECT * FROM t").await?.collect().await?),
+            3
+        );
+
+        assert_eq!(session.close(), 1);
+        assert_eq!(rows(db.query_to_batches("SELECT * FROM t").await?), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use crate::pool::DB;
+use anyhow::{Context, Result};
+use arrow_schema::SchemaRef;
+use async_trait::async_trait;
+use datafusion::arrow::buffer::Buffer;
+use datafusion::arrow::ipc::convert::fb_to_schema;
+use datafusion::arrow::ipc::reader::{read_footer_length, FileDecoder};
+use datafusion::arrow::ipc::root_as_footer;
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::datasource::{MemTable, TableProvider, TableType};
+use datafusion::logical_expr::{Expr, TableProviderFilterPushDown};
+use datafusion::physical_plan::ExecutionPlan;
+use memmap2::Mmap;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+/// 共享表文件默认放在 /dev/shm，没有时使用系统临时目录
+pub fn default_shared_dir() -> PathBuf {
+    let shm = PathBuf::from("/dev/shm");
+    if shm.is_dir() {
+        shm
+    } else {
+        std::env::temp_dir()
+    }
+}
+
+/// export_shared_table 写出的文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedTableFile {
+    pub path: PathBuf,
+    pub rows: usize,
+    pub bytes: u64,
+}
+
+/// 从共享文件映射的只读表，batch 直接引用映射的内存，不会复制数据
+#[derive(Debug)]
+pub struct SharedTable {
+    path: PathBuf,
+    inner: MemTable,
+}
+
+impl SharedTable {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl TableProvider for SharedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        self.inner.scan(state, projection, filters, limit).await
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> datafusion::error::Result<Vec<TableProviderFilterPushDown>> {
+        self.inner.supports_filters_pushdown(filters)
+    }
+
+    // 没有实现 insert_into，写入会报错
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 把表的当前内容写成 Arrow IPC 文件，同一台机器上的其他进程可以用 attach_shared_table 映射
+    ///
+    /// 先写临时文件再重命名，已经映射旧文件的进程不受影响，重新 attach 后看到新数据
+    pub async fn export_shared_table(
+        &self,
+        table: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<SharedTableFile> {
+        let path = path.as_ref();
+        let df = self
+            .ctx
+            .table(self.table_ref(table))
+            .await
+            .map_err(|_| anyhow::anyhow!("Table {} not found", table))?;
+        let schema = df.schema().inner().clone();
+        let batches = df.collect().await?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let tmp = tempfile::NamedTempFile::new_in(dir)
+            .with_context(|| format!("create temp file in {}", dir.display()))?;
+        let mut writer = FileWriter::try_new(tmp.as_file(), &schema)?;
+        for batch in &batches {
+            writer.write(batch)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        tmp.as_file().sync_all()?;
+        tmp.persist(path)
+            .with_context(|| format!("rename to {}", path.display()))?;
+
+        Ok(SharedTableFile {
+            path: path.to_path_buf(),
+            rows: batches.iter().map(|b| b.num_rows()).sum(),
+            bytes: std::fs::metadata(path)?.len(),
+        })
+    }
+
+    /// 映射共享文件并注册为只读表，原子替换同名表，返回行数
+    pub fn attach_shared_table(&self, table: &str, path: impl AsRef<Path>) -> Result<usize> {
+        let path = path.as_ref();
+        let (schema, batches) = map_ipc_file(path)?;
+        let rows = batches.iter().map(|b| b.num_rows()).sum();
+        let provider = SharedTable {
+            path: path.to_path_buf(),
+            inner: MemTable::try_new(schema, vec![batches])?,
+        };
+        self.replace_table(self.table_ref(table).table(), Arc::new(provider))?;
+        Ok(rows)
+    }
+}
+
+// 按 Arrow IPC 文件格式解析映射的内存，batch 中的 buffer 持有映射，表被删除后才会 munmap
+fn map_ipc_file(path: &Path) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    // 文件只会被整体替换，不会被原地修改
+    let mmap = unsafe { Mmap::map(&file)? };
+    if mmap.len() < 10 {
+        return Err(anyhow::anyhow!(
+            "{} is not an Arrow IPC file",
+            path.display()
+        ));
+    }
+    let ptr = NonNull::new(mmap.as_ptr() as *mut u8).context("empty mapping")?;
+    let len = mmap.len();
+    let buffer = unsafe { Buffer::from_custom_allocation(ptr, len, Arc::new(mmap)) };
+
+    let trailer_start = buffer.len() - 10;
+    let footer_len = read_footer_length(buffer[trailer_start..].try_into()?)?;
+    let footer = root_as_footer(
+        buffer
+            .get(trailer_start.saturating_sub(footer_len)..trailer_start)
+            .context("truncated footer")?,
+    )
+    .map_err(|e| anyhow::anyhow!("Invalid footer in {}: {}", path.display(), e))?;
+    let schema = Arc::new(fb_to_schema(footer.schema().context("missing schema")?));
+
+    let mut decoder = FileDecoder::new(schema.clone(), footer.version());
+    for block in footer.dictionaries().iter().flatten() {
+        let block_len = block.bodyLength() as usize + block.metaDataLength() as usize;
+        let data = buffer.slice_with_length(block.offset() as usize, block_len);
+        decoder.read_dictionary(block, &data)?;
+    }
+    let mut batches = Vec::new();
+    for block in footer.recordBatches().iter().flatten() {
+        let block_len = block.bodyLength() as usize + block.metaDataLength() as usize;
+        let data = buffer.slice_with_length(block.offset() as usize, block_len);
+        if let Some(batch) = decoder.read_record_batch(block, &data)? {
+            batches.push(batch);
+        }
+    }
+    Ok((schema, batches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shared_table() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rates.arrow");
+        let writer = DB::<()>::new("writer");
+        writer
+            .execute("CREATE TABLE rates (currency VARCHAR, rate DOUBLE)")
+            .await?;
+        writer
+            .execute("INSERT INTO rates VALUES ('USD', 1.0), ('EUR', 1.1)")
+            .await?;
+        let file = writer.export_shared_table("rates", &path).await?;
+        assert_eq!(file.rows, 2);
+        assert!(file.bytes > 0);
+
+        // 另一个 DB 映射同一个文件
+        let reader = DB::<()>::new("reader");
+        assert_eq!(reader.attach_shared_table("rates", &path)?, 2);
+        let batches = reader
+            .query_to_batches("SELECT currency FROM rates WHERE rate > 1")
+            .await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+        assert!(reader
+            .execute("INSERT INTO rates VALUES ('GBP', 1.2)")
+            .await
+            .is_err());
+
+        // 重新导出不影响已经映射的表
+        writer
+            .execute("INSERT INTO rates VALUES ('JPY', 0.007)")
+            .await?;
+        writer.export_shared_table("rates", &path).await?;
+        assert_eq!(reader.ctx.table("rates").await?.count().await?, 2);
+        assert_eq!(reader.attach_shared_table("rates", &path)?, 3);
+        Ok(())
+    }
+}
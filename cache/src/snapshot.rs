@@ -0,0 +1,147 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use async_trait::async_trait;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::common::TableReference;
+use datafusion::datasource::{MemTable, TableProvider, TableType};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::ExecutionPlan;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::sync::Arc;
+
+// 快照的表名是 {table}@{snapshot}，查询时需要加引号：SELECT * FROM "ledger@2024_05"
+pub const SNAPSHOT_SEPARATOR: char = '@';
+
+pub fn snapshot_table_name(table: &str, snapshot: &str) -> String {
+    format!("{}{}{}", table, SNAPSHOT_SEPARATOR, snapshot)
+}
+
+/// 只读的快照表，不支持 INSERT
+#[derive(Debug)]
+pub struct FrozenTable {
+    inner: MemTable,
+}
+
+#[async_trait]
+impl TableProvider for FrozenTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        self.inner.scan(state, projection, filters, limit).await
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 把表当前的内容固定成一个命名的只读快照，之后原表的修改不影响快照
+    /// 比如月结报表可以固定在 "ledger@2024_05" 上
+    pub async fn freeze(&self, table: &str, snapshot: &str) -> Result<()> {
+        if snapshot.is_empty() || table.contains(SNAPSHOT_SEPARATOR) {
+            return Err(anyhow::anyhow!(
+                "Invalid snapshot {} of table {}",
+                snapshot,
+                table
+            ));
+        }
+        let name = snapshot_table_name(table, snapshot);
+        if self.ctx.table_exist(TableReference::bare(name.as_str()))? {
+            return Err(anyhow::anyhow!("Snapshot {} already exists", name));
+        }
+
+        let df = self.ctx.table(table).await?;
+        let schema = df.schema().inner().clone();
+        let batches = df.collect().await?;
+        self.register_frozen(&name, schema, batches)
+    }
+
+    pub(crate) fn register_frozen(
+        &self,
+        name: &str,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<()> {
+        let frozen = FrozenTable {
+            inner: MemTable::try_new(schema, vec![batches])?,
+        };
+        self.ctx
+            .register_table(TableReference::bare(name), Arc::new(frozen))?;
+        Ok(())
+    }
+
+    /// 删除快照，原表不受影响
+    pub fn drop_snapshot(&self, table: &str, snapshot: &str) -> Result<()> {
+        let name = snapshot_table_name(table, snapshot);
+        match self
+            .ctx
+            .deregister_table(TableReference::bare(name.as_str()))?
+        {
+            Some(_) => Ok(()),
+            None => Err(anyhow::anyhow!("Snapshot {} not found", name)),
+        }
+    }
+
+    /// 表的所有快照名，按名字排序
+    pub fn snapshots(&self, table: &str) -> Result<Vec<String>> {
+        let prefix = snapshot_table_name(table, "");
+        let mut snapshots: Vec<String> = self
+            .default_schema()?
+            .table_names()
+            .into_iter()
+            .filter_map(|name| name.strip_prefix(&prefix).map(|s| s.to_string()))
+            .collect();
+        snapshots.sort();
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn count(db: &DB<()>, sql: &str) -> Result<usize> {
+        let batches = db.query_to_batches(sql).await?;
+        Ok(batches.iter().map(|b| b.num_rows()).sum())
+    }
+
+    #[tokio::test]
+    async fn test_freeze() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE ledger (id BIGINT)").await?;
+        db.execute("INSERT INTO ledger VALUES (1), (2)").await?;
+
+        db.freeze("ledger", "2024_05").await?;
+        assert!(db.freeze("ledger", "2024_05").await.is_err());
+        db.execute("INSERT INTO ledger VALUES (3)").await?;
+
+        assert_eq!(count(&db, "SELECT * FROM ledger").await?, 3);
+        assert_eq!(count(&db, "SELECT * FROM \"ledger@2024_05\"").await?, 2);
+        assert!(db
+            .execute("INSERT INTO \"ledger@2024_05\" VALUES (4)")
+            .await
+            .is_err());
+        assert_eq!(db.snapshots("ledger")?, vec!["2024_05".to_string()]);
+
+        db.drop_snapshot("ledger", "2024_05")?;
+        assert!(db.snapshots("ledger")?.is_empty());
+        assert_eq!(count(&db, "SELECT * FROM ledger").await?, 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use crate::refresh::{RefreshReport, RefreshStatus};
+use std::time::Duration;
+
+/// init_storages 注册的一个存储
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageStartup {
+    pub name: String,
+    // 比如 s3://bucket
+    pub root: String,
+    pub prefix: Option<String>,
+}
+
+/// init_from_config 创建或者加载的一个表
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStartup {
+    pub table: String,
+    // memory/external/query/clickhouse
+    pub source: String,
+    // 加载到内存的行数，memory 和 external 表创建时不加载数据，为 None
+    pub rows: Option<usize>,
+    pub elapsed: Duration,
+    // 加载失败、超时或者因为依赖失败被跳过的原因
+    pub error: Option<String>,
+}
+
+/// init_storages/init_from_config 的结果，同时通过 tracing 输出，部署问题可以只看日志排查
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StartupReport {
+    pub storages: Vec<StorageStartup>,
+    pub storage_groups: Vec<String>,
+    pub tables: Vec<TableStartup>,
+    // 不影响启动但需要关注的问题，比如明文 HTTP endpoint、没有设置 TTL 的表
+    pub warnings: Vec<String>,
+    pub elapsed: Duration,
+}
+
+impl StartupReport {
+    /// 所有表都创建或加载成功
+    pub fn is_ok(&self) -> bool {
+        self.tables.iter().all(|t| t.error.is_none())
+    }
+
+    pub fn rows(&self) -> usize {
+        self.tables.iter().filter_map(|t| t.rows).sum()
+    }
+
+    pub fn table(&self, table: &str) -> Option<&TableStartup> {
+        self.tables.iter().find(|t| t.table == table)
+    }
+
+    // 合并 refresh_all 的结果，source 是每个表的数据来源
+    pub(crate) fn add_refreshed(
+        &mut self,
+        refresh: RefreshReport,
+        source: impl Fn(&str) -> String,
+    ) {
+        for table in refresh.tables {
+            let (rows, error) = match table.status {
+                RefreshStatus::Refreshed { rows } => (Some(rows), None),
+                RefreshStatus::Failed { error } => (None, Some(error)),
+                RefreshStatus::TimedOut => (None, Some("timed out".to_string())),
+                RefreshStatus::Skipped { dependency } => (
+                    None,
+                    Some(format!("skipped, {} was not loaded", dependency)),
+                ),
+            };
+            self.tables.push(TableStartup {
+                source: source(&table.table),
+                table: table.table,
+                rows,
+                elapsed: table.elapsed,
+                error,
+            });
+        }
+    }
+
+    /// 每个存储和表输出一行 info，失败的表和警告输出 warn，最后输出一行汇总
+    pub fn log(&self) {
+        for storage in &self.storages {
+            tracing::info!(
+                storage = %storage.name,
+                root = %storage.root,
+                prefix = ?storage.prefix,
+                "storage registered"
+            );
+        }
+        for group in &self.storage_groups {
+            tracing::info!(storage_group = %group, "storage group registered");
+        }
+        for table in &self.tables {
+            match &table.error {
+                None => tracing::info!(
+                    table = %table.table,
+                    source = %table.source,
+                    rows = ?table.rows,
+                    elapsed_ms = table.elapsed.as_millis() as u64,
+                    "table ready"
+                ),
+                Some(error) => tracing::warn!(
+                    table = %table.table,
+                    source = %table.source,
+                    elapsed_ms = table.elapsed.as_millis() as u64,
+                    error = %error,
+                    "table failed to load"
+                ),
+            }
+        }
+        for warning in &self.warnings {
+            tracing::warn!("{}", warning);
+        }
+        tracing::info!(
+            storages = self.storages.len(),
+            tables = self.tables.len(),
+            failed = self.tables.iter().filter(|t| t.error.is_some()).count(),
+            rows = self.rows(),
+            warnings = self.warnings.len(),
+            elapsed_ms = self.elapsed.as_millis() as u64,
+            "startup finished"
+        );
+    }
+}
@@ -1,24 +1,112 @@
+use crate::accounting::{AccountingStore, StorageCounters};
 use crate::config::Config;
 use crate::config::StorageConfig;
+use crate::config::StorageGroupConfig;
+use crate::credential::RefreshingCredentialProvider;
+use crate::events::CacheEvent;
+use crate::hedged::HedgedStore;
+use crate::ident::quote_literal;
+use crate::location::{reject_control_chars, validate_storage_name, StorageLocation};
 use crate::pool::StorageEntry;
 use crate::pool::DB;
+use crate::ratelimit::RateLimitedStore;
+use crate::startup::{StartupReport, StorageStartup};
+use crate::template::PathTemplate;
 use anyhow::Context;
+use chrono::Utc;
 use datafusion::datasource::listing::ListingTableUrl;
 use datafusion::prelude::*;
+use object_store::aws::{AwsAuthorizer, AwsCredential, AwsCredentialProvider};
+use object_store::prefix::PrefixStore;
+use object_store::{ObjectStore, StaticCredentialProvider};
+use serde::{de::DeserializeOwned, Serialize};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 存储组的 url scheme，比如 group://ledger/2024/05/01.parquet
+pub const STORAGE_GROUP_SCHEME: &str = "group";
 
 impl DB<()> {
-    pub fn init_storages(&self, config: Config) -> anyhow::Result<()> {
-        for (name, storage_config) in config.storages {
+    /// 注册配置中的存储和存储组，结果同时通过 tracing 输出
+    pub fn init_storages(&self, config: Config) -> anyhow::Result<StartupReport> {
+        let report = self.register_configured_storages(config)?;
+        report.log();
+        Ok(report)
+    }
+
+    // 和 init_storages 一样，但不输出日志，init_from_config 合并结果后统一输出
+    pub(crate) fn register_configured_storages(
+        &self,
+        config: Config,
+    ) -> anyhow::Result<StartupReport> {
+        let started = Instant::now();
+        let mut report = StartupReport::default();
+        // 按名字顺序注册，每次启动的日志顺序一样
+        let mut storages: Vec<_> = config.storages.into_iter().collect();
+        storages.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, storage_config) in storages {
+            report
+                .warnings
+                .extend(storage_warnings(&name, &storage_config));
+            let root = StorageLocation::new(&storage_config.schema, &storage_config.bucket).root();
+            let prefix = storage_config.prefix.clone();
             self.register_storage(&name, storage_config)?;
+            report.storages.push(StorageStartup {
+                name,
+                root: root?,
+                prefix,
+            });
+        }
+        let mut groups: Vec<_> = config.storage_groups.into_iter().collect();
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, group_config) in groups {
+            self.register_storage_group(&name, &group_config)?;
+            report.storage_groups.push(name);
         }
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    /// 注册存储组，之后可以用 group://{name}/path 读取，读请求会在主存储和副本之间 hedge
+    /// 副本中的对象路径需要和主存储一致
+    pub fn register_storage_group(
+        &self,
+        name: &str,
+        group: &StorageGroupConfig,
+    ) -> anyhow::Result<()> {
+        validate_storage_name(name)?;
+        let (primary, replicas) = {
+            let storages = self.registered_storages.read().unwrap();
+            let get = |storage: &str| {
+                storages
+                    .get(storage)
+                    .map(|entry| entry.store.clone())
+                    .with_context(|| format!("storage {} not registered", storage))
+            };
+            let primary = get(&group.primary)?;
+            let replicas = group
+                .replicas
+                .iter()
+                .map(|r| get(r))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            (primary, replicas)
+        };
+
+        let store = HedgedStore::new(
+            primary,
+            replicas,
+            Duration::from_millis(group.hedge_after_ms),
+        );
+        let url = ListingTableUrl::parse(format!("{STORAGE_GROUP_SCHEME}://{name}"))?;
+        self.ctx
+            .register_object_store(url.as_ref(), Arc::new(store));
         Ok(())
     }
 
     fn register_storage(&self, name: &str, config: StorageConfig) -> anyhow::Result<()> {
+        validate_storage_name(name)?;
+        let root = StorageLocation::new(&config.schema, &config.bucket).root()?;
         let mut object_store = object_store::aws::AmazonS3Builder::new()
-            .with_access_key_id(&config.access_key)
-            .with_secret_access_key(&config.access_secret)
             .with_bucket_name(&config.bucket)
             .with_allow_http(true)
             .with_region(&config.region);
@@ -29,14 +117,42 @@ impl DB<()> {
             object_store = object_store.with_endpoint(endpoint);
         }
 
+        // 临时凭证：有 token_provider 时由它负责刷新，否则使用固定的 ak/sk(+session token)
+        let credentials: AwsCredentialProvider = match &config.token_provider {
+            Some(provider) => Arc::new(RefreshingCredentialProvider::new(provider.clone())),
+            None => Arc::new(StaticCredentialProvider::new(AwsCredential {
+                key_id: config.access_key.clone(),
+                secret_key: config.access_secret.clone(),
+                token: config.session_token.clone(),
+            })),
+        };
+        object_store = object_store.with_credentials(credentials.clone());
+
         // TODO: oss may using const or enum
         // 注意 virtual_hosted_style_request 的 endpoint 是 https://{bucket}.oss-cn-hongkong.aliyuncs.com
         if schema == "oss" {
             object_store = object_store.with_virtual_hosted_style_request(true)
         }
-        let object_store = Arc::new(object_store.build()?);
+        let mut object_store: Arc<dyn ObjectStore> = Arc::new(object_store.build()?);
+
+        // 所有读写都限制在 prefix 下，多个环境可以共用一个 bucket
+        if let Some(prefix) = config.prefix.as_deref().map(|p| p.trim_matches('/')) {
+            if !prefix.is_empty() {
+                object_store = Arc::new(PrefixStore::new(object_store, prefix));
+            }
+        }
+
+        if let Some(rate_limit) = &config.rate_limit {
+            object_store = Arc::new(RateLimitedStore::new(object_store, rate_limit.clone()));
+        }
+        let object_store = self.apply_store_middleware(name, object_store);
 
-        let url = ListingTableUrl::parse(format!("{schema}://{}", config.bucket))?;
+        // 最外层统计读写字节数，见 DB::accounting_report
+        let counters = Arc::new(StorageCounters::default());
+        let object_store: Arc<dyn ObjectStore> =
+            Arc::new(AccountingStore::new(object_store, counters.clone()));
+
+        let url = ListingTableUrl::parse(root)?;
         self.ctx
             .register_object_store(url.as_ref(), object_store.clone());
 
@@ -45,35 +161,78 @@ impl DB<()> {
             name.to_string(),
             StorageEntry {
                 store: object_store,
+                credentials,
                 config,
+                counters,
             },
         );
 
         Ok(())
     }
 
+    /// 为配置了 create_bucket_if_missing 的存储创建不存在的 bucket
+    /// 需要在 init_storages 之后调用
+    pub async fn ensure_buckets(&self) -> anyhow::Result<()> {
+        let targets: Vec<(String, Arc<dyn ObjectStore>, AwsCredentialProvider)> = {
+            let storages = self.registered_storages.read().unwrap();
+            storages
+                .iter()
+                .filter(|(_, entry)| entry.config.create_bucket_if_missing)
+                .map(|(name, entry)| (name.clone(), entry.store.clone(), entry.credentials.clone()))
+                .collect()
+        };
+
+        for (name, store, credentials) in targets {
+            match store.list_with_delimiter(None).await {
+                Ok(_) => continue,
+                Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e).context(format!("check bucket of storage {}", name)),
+            }
+
+            let (url, region) = {
+                let storages = self.registered_storages.read().unwrap();
+                let storage = storages.get(&name).context("get storage")?;
+                (bucket_url(&storage.config), storage.config.region.clone())
+            };
+            let credential = credentials.get_credential().await?;
+            create_bucket(&url, &region, &credential)
+                .await
+                .with_context(|| format!("create bucket for storage {}", name))?;
+            tracing::info!(storage = %name, bucket = %url, "bucket created");
+        }
+        Ok(())
+    }
+
     pub async fn query_from_storage(&self, storage: &str, path: &str) -> anyhow::Result<DataFrame> {
-        let sql = format!("SELECT * FROM '{}/{}'", storage, path);
+        let location = format!("{}/{}", storage, path);
+        reject_control_chars(&location, "location")?;
+        let sql = format!("SELECT * FROM {}", quote_literal(&location));
         self.query(&sql).await
     }
 
+    /// 导出到存储，path 支持日期时间模板变量（见 PathTemplate），返回实际写入的路径
     pub async fn export_to_storage(
         &self,
         df: DataFrame,
         storage_name: &str,
         path: &str,
         format: &str,
-    ) -> anyhow::Result<()> {
-        let (schema, bucket, path) = {
-            let storages = self.registered_storages.read().unwrap();
-            let storage = storages.get(storage_name).context("get storage")?;
-            (
-                storage.config.schema.clone(),
-                storage.config.bucket.clone(),
-                path,
-            )
-        };
-        let location = format!("{}://{}/{}", schema, bucket, path);
+    ) -> anyhow::Result<String> {
+        self.export_to_storage_templated(df, storage_name, &PathTemplate::new(path), format)
+            .await
+    }
+
+    /// 使用带自定义变量（比如 {table}）的路径模板导出
+    pub async fn export_to_storage_templated(
+        &self,
+        df: DataFrame,
+        storage_name: &str,
+        template: &PathTemplate,
+        format: &str,
+    ) -> anyhow::Result<String> {
+        let path = template.render(Utc::now())?;
+        let path = path.as_str();
+        let location = self.storage_location(storage_name, path)?;
         println!("export to storage: {}", location);
 
         match format.to_lowercase().as_str() {
@@ -87,8 +246,93 @@ impl DB<()> {
             }
             _ => return Err(anyhow::anyhow!("Unsupported format: {}", format)),
         }
-        Ok(())
+        self.emit(CacheEvent::ExportCompleted {
+            storage: storage_name.to_string(),
+            location,
+        });
+        Ok(path.to_string())
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 存储中 path 对应的完整地址，比如 s3://bucket/path
+    pub(crate) fn storage_location(
+        &self,
+        storage_name: &str,
+        path: &str,
+    ) -> anyhow::Result<String> {
+        let (schema, bucket) = {
+            let storages = self.registered_storages.read().unwrap();
+            let storage = storages.get(storage_name).context("get storage")?;
+            (storage.config.schema.clone(), storage.config.bucket.clone())
+        };
+        StorageLocation::new(&schema, &bucket).path(path).build()
+    }
+}
+
+// 明文 HTTP 和空的密钥不影响注册，但很可能是配置错误
+fn storage_warnings(name: &str, config: &StorageConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(endpoint) = config
+        .endpoint
+        .as_deref()
+        .filter(|e| e.starts_with("http://"))
+    {
+        warnings.push(format!(
+            "Storage {} uses plain HTTP endpoint {}",
+            name, endpoint
+        ));
+    }
+    if config.token_provider.is_none()
+        && (config.access_key.is_empty() || config.access_secret.is_empty())
+    {
+        warnings.push(format!(
+            "Storage {} has an empty access key or secret",
+            name
+        ));
+    }
+    warnings
+}
+
+// bucket 的根地址，oss 使用 virtual hosted style，endpoint 里已经带了 bucket
+fn bucket_url(config: &StorageConfig) -> String {
+    let endpoint = config
+        .endpoint
+        .clone()
+        .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", config.region));
+    let endpoint = endpoint.trim_end_matches('/');
+    if config.schema == "oss" {
+        endpoint.to_string()
+    } else {
+        format!("{}/{}", endpoint, config.bucket)
+    }
+}
+
+// 发送签名后的 CreateBucket 请求，object_store 本身不提供建 bucket 的接口
+async fn create_bucket(url: &str, region: &str, credential: &AwsCredential) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let mut builder = client.put(url);
+    // us-east-1 以外的 region 必须显式指定 LocationConstraint
+    if region != "us-east-1" {
+        builder = builder.body(format!(
+            "<CreateBucketConfiguration><LocationConstraint>{}</LocationConstraint></CreateBucketConfiguration>",
+            region
+        ));
+    }
+    let mut request = builder.build()?;
+    AwsAuthorizer::new(credential, "s3", region).authorize(&mut request, None);
+
+    let response = client.execute(request).await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(anyhow::anyhow!(
+            "create bucket failed, status: {}, body: {}",
+            status,
+            body
+        ));
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -134,9 +378,24 @@ mod tests {
                     bucket = bucket
                 )),
                 schema: "oss".to_string(),
+                session_token: None,
+                token_provider: None,
+                create_bucket_if_missing: false,
+                cost_per_gb_read: None,
+                cost_per_gb_written: None,
+                rate_limit: None,
+                prefix: None,
             },
         );
-        let config = Config { storages };
+        let config = Config {
+            storages,
+            storage_groups: HashMap::new(),
+            guard: Default::default(),
+            clickhouse: None,
+            notifications: Vec::new(),
+            tables: Vec::new(),
+            compression: Default::default(),
+        };
 
         // 初始化数据库
         let db = DB::<()>::new("test_db");
@@ -268,4 +527,39 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_bucket_url() {
+        let mut config = StorageConfig {
+            access_key: "ak".to_string(),
+            access_secret: "sk".to_string(),
+            endpoint: Some("http://localhost:9000/".to_string()),
+            region: "us-east-1".to_string(),
+            bucket: "demo".to_string(),
+            schema: "minio".to_string(),
+            session_token: None,
+            token_provider: None,
+            create_bucket_if_missing: true,
+            cost_per_gb_read: None,
+            cost_per_gb_written: None,
+            rate_limit: None,
+            prefix: Some("cache/prod/".to_string()),
+        };
+        assert_eq!(bucket_url(&config), "http://localhost:9000/demo");
+
+        config.schema = "oss".to_string();
+        config.endpoint = Some("https://demo.oss-cn-hongkong.aliyuncs.com".to_string());
+        assert_eq!(
+            bucket_url(&config),
+            "https://demo.oss-cn-hongkong.aliyuncs.com"
+        );
+
+        config.schema = "s3".to_string();
+        config.endpoint = None;
+        config.region = "ap-east-1".to_string();
+        assert_eq!(
+            bucket_url(&config),
+            "https://s3.ap-east-1.amazonaws.com/demo"
+        );
+    }
 }
@@ -0,0 +1,276 @@
+use crate::bloom::BloomFilteredTable;
+use crate::inverted::InvertedIndexTable;
+use crate::pool::DB;
+use crate::wal::WalRecord;
+use crate::zonemap::ZoneMappedTable;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use async_trait::async_trait;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::SchemaProvider;
+use datafusion::common::TableReference;
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::error::DataFusionError;
+use datafusion::prelude::SessionContext;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::OwnedMutexGuard;
+
+/// 和 MemorySchemaProvider 行为一致，另外支持原子替换表
+///
+/// 先 deregister 再 register 的中间，新的查询会找不到表；
+/// replace_table 在同一把锁里完成替换，查询看到的要么是旧表要么是新表
+#[derive(Default)]
+pub(crate) struct SwapSchemaProvider {
+    tables: RwLock<HashMap<String, Arc<dyn TableProvider>>>,
+}
+
+impl SwapSchemaProvider {
+    pub(crate) fn replace_table(
+        &self,
+        name: &str,
+        table: Arc<dyn TableProvider>,
+    ) -> Option<Arc<dyn TableProvider>> {
+        self.tables.write().unwrap().insert(name.to_string(), table)
+    }
+
+    // 在同一把锁里替换或删除多个表，None 表示删除
+    pub(crate) fn apply_changes(&self, changes: Vec<(String, Option<Arc<dyn TableProvider>>)>) {
+        let mut tables = self.tables.write().unwrap();
+        for (name, table) in changes {
+            match table {
+                Some(table) => tables.insert(name, table),
+                None => tables.remove(&name),
+            };
+        }
+    }
+}
+
+impl std::fmt::Debug for SwapSchemaProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SwapSchemaProvider")
+            .field("tables", &self.table_names())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl SchemaProvider for SwapSchemaProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.tables.read().unwrap().keys().cloned().collect()
+    }
+
+    async fn table(&self, name: &str) -> datafusion::error::Result<Option<Arc<dyn TableProvider>>> {
+        Ok(self.tables.read().unwrap().get(name).cloned())
+    }
+
+    fn register_table(
+        &self,
+        name: String,
+        table: Arc<dyn TableProvider>,
+    ) -> datafusion::error::Result<Option<Arc<dyn TableProvider>>> {
+        let mut tables = self.tables.write().unwrap();
+        if tables.contains_key(&name) {
+            return Err(DataFusionError::Execution(format!(
+                "The table {name} already exists"
+            )));
+        }
+        Ok(tables.insert(name, table))
+    }
+
+    fn deregister_table(
+        &self,
+        name: &str,
+    ) -> datafusion::error::Result<Option<Arc<dyn TableProvider>>> {
+        Ok(self.tables.write().unwrap().remove(name))
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        self.tables.read().unwrap().contains_key(name)
+    }
+}
+
+/// 用 SwapSchemaProvider 替换默认 schema，DB::new 时调用，此时 schema 中还没有表
+pub(crate) fn install_swap_schema(ctx: &SessionContext) {
+    let state = ctx.state();
+    let options = &state.config().options().catalog;
+    if let Some(catalog) = ctx.catalog(&options.default_catalog) {
+        let _ = catalog.register_schema(
+            &options.default_schema,
+            Arc::new(SwapSchemaProvider::default()),
+        );
+    }
+}
+
+/// 在内存数据上建了索引的表：Bloom 过滤器、zone map 和倒排索引
+///
+/// 整表重写（upsert、TTL 清理、truncate 等）时用新的数据建立同样的索引，替换之后索引和约束都还在
+pub(crate) trait IndexedTable {
+    /// 数据都在内存中，可以读出来整表重写
+    fn in_memory(&self) -> bool;
+
+    /// 用新的数据建立同样的索引，沿用原表的约束
+    fn reindex(
+        &self,
+        schema: SchemaRef,
+        partitions: Vec<Vec<RecordBatch>>,
+    ) -> Result<Arc<dyn TableProvider>>;
+}
+
+pub(crate) fn indexed(provider: &dyn TableProvider) -> Option<&dyn IndexedTable> {
+    let any = provider.as_any();
+    if let Some(bloom) = any.downcast_ref::<BloomFilteredTable>() {
+        return Some(bloom);
+    }
+    if let Some(zoned) = any.downcast_ref::<ZoneMappedTable>() {
+        return Some(zoned);
+    }
+    if let Some(inverted) = any.downcast_ref::<InvertedIndexTable>() {
+        return Some(inverted);
+    }
+    None
+}
+
+/// MemTable 或者建在内存数据上的索引表，只有这些表支持整表重写
+pub(crate) fn is_memory_table(provider: &dyn TableProvider) -> bool {
+    provider.as_any().is::<MemTable>() || indexed(provider).is_some_and(|t| t.in_memory())
+}
+
+/// 用 partitions 构造替换 base 的表：沿用 base 的约束，base 建了索引时重建同样的索引
+pub(crate) fn replacement_table(
+    schema: SchemaRef,
+    partitions: Vec<Vec<RecordBatch>>,
+    base: Option<&Arc<dyn TableProvider>>,
+) -> Result<Arc<dyn TableProvider>> {
+    if let Some(index) = base.and_then(|base| indexed(base.as_ref())) {
+        return index.reindex(schema, partitions);
+    }
+    let mut table = MemTable::try_new(schema, partitions)?;
+    if let Some(constraints) = base.and_then(|base| base.constraints()) {
+        table = table.with_constraints(constraints.clone());
+    }
+    Ok(Arc::new(table))
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 原子替换默认 schema 中的表，返回旧表；表不存在时直接注册
+    pub(crate) fn replace_table(
+        &self,
+        table: &str,
+        provider: Arc<dyn TableProvider>,
+    ) -> Result<Option<Arc<dyn TableProvider>>> {
+        let schema = self.default_schema()?;
+        self.record_version(table, Some(provider.clone()));
+        if let Some(swap) = schema.as_any().downcast_ref::<SwapSchemaProvider>() {
+            return Ok(swap.replace_table(table, provider));
+        }
+        let old = schema.deregister_table(table)?;
+        schema.register_table(table.to_string(), provider)?;
+        Ok(old)
+    }
+
+    /// 在同一把锁里替换或删除默认 schema 中的多个表，None 表示删除
+    pub(crate) fn replace_tables(
+        &self,
+        changes: Vec<(String, Option<Arc<dyn TableProvider>>)>,
+    ) -> Result<()> {
+        let schema = self.default_schema()?;
+        for (name, table) in &changes {
+            self.record_version(name, table.clone());
+        }
+        if let Some(swap) = schema.as_any().downcast_ref::<SwapSchemaProvider>() {
+            swap.apply_changes(changes);
+            return Ok(());
+        }
+        for (name, table) in changes {
+            schema.deregister_table(&name)?;
+            if let Some(table) = table {
+                schema.register_table(name, table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 表的写锁，追加和整表重写（upsert、truncate、TTL 清理等）都先拿到这把锁，
+    /// 读取旧数据再替换的操作不会覆盖掉同时进行的写入
+    pub(crate) async fn lock_table(&self, table: &str) -> OwnedMutexGuard<()> {
+        self.lock_table_ref(&self.table_ref(table)).await
+    }
+
+    pub(crate) async fn lock_table_ref(&self, table: &TableReference) -> OwnedMutexGuard<()> {
+        let key = self.table_lock_key(table);
+        self.table_lock(key).lock_owned().await
+    }
+
+    /// 同时锁住多个表，按名字顺序加锁，不会和其他多表写入互相等待
+    pub(crate) async fn lock_tables(&self, tables: &[String]) -> Vec<OwnedMutexGuard<()>> {
+        let mut keys: Vec<String> = tables
+            .iter()
+            .map(|table| self.table_lock_key(&self.table_ref(table)))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        let mut guards = Vec::with_capacity(keys.len());
+        for key in keys {
+            guards.push(self.table_lock(key).lock_owned().await);
+        }
+        guards
+    }
+
+    fn table_lock_key(&self, table: &TableReference) -> String {
+        let state = self.ctx.state();
+        let options = &state.config().options().catalog;
+        table
+            .clone()
+            .resolve(&options.default_catalog, &options.default_schema)
+            .to_string()
+    }
+
+    fn table_lock(&self, key: String) -> Arc<tokio::sync::Mutex<()>> {
+        self.table_locks
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .clone()
+    }
+
+    /// 用 partitions 整体替换表的内容，开启 WAL 时先写入 WAL 再替换
+    ///
+    /// 调用方需要持有表的写锁；base 是被替换的表，新表沿用它的约束和索引
+    pub(crate) fn rewrite_table(
+        &self,
+        table: &str,
+        schema: SchemaRef,
+        partitions: Vec<Vec<RecordBatch>>,
+        base: Option<&Arc<dyn TableProvider>>,
+    ) -> Result<()> {
+        if let Some(wal) = self.wal() {
+            wal.append(&WalRecord::Replace {
+                table: table.to_string(),
+                schema: schema.clone(),
+                batches: partitions.iter().flatten().cloned().collect(),
+            })?;
+        }
+        self.apply_replace(table, schema, partitions, base)
+    }
+
+    // 不写 WAL 的 rewrite_table，WAL 重放时直接调用
+    pub(crate) fn apply_replace(
+        &self,
+        table: &str,
+        schema: SchemaRef,
+        partitions: Vec<Vec<RecordBatch>>,
+        base: Option<&Arc<dyn TableProvider>>,
+    ) -> Result<()> {
+        let replacement = replacement_table(schema, partitions, base)?;
+        self.replace_table(self.table_ref(table).table(), replacement)?;
+        Ok(())
+    }
+}
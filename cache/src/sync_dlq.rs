@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::ipc::reader::FileReader;
+use datafusion::arrow::ipc::writer::FileWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 同步失败、暂存起来等待重放的一块数据
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParkedBatch {
+    pub id: String,
+    pub table: String,
+    pub rows: usize,
+    // 最后一次失败的原因
+    pub error: String,
+    pub parked_at: DateTime<Utc>,
+}
+
+/// 同步到下游（ClickHouse）多次重试仍然失败的数据块保存在本地目录，
+/// 每块数据一个 Arrow IPC 文件和一个 JSON 元数据文件，进程重启后仍然可以重放
+#[derive(Debug)]
+pub struct SyncDeadLetter {
+    dir: PathBuf,
+    seq: AtomicU64,
+}
+
+impl SyncDeadLetter {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            seq: AtomicU64::new(0),
+        })
+    }
+
+    pub(crate) fn park(
+        &self,
+        table: &str,
+        block: &RecordBatch,
+        error: &str,
+    ) -> Result<ParkedBatch> {
+        let now = Utc::now();
+        let parked = ParkedBatch {
+            id: format!(
+                "{}-{}-{}",
+                now.format("%Y%m%d%H%M%S%f"),
+                std::process::id(),
+                self.seq.fetch_add(1, Ordering::Relaxed)
+            ),
+            table: table.to_string(),
+            rows: block.num_rows(),
+            error: error.to_string(),
+            parked_at: now,
+        };
+
+        let mut writer =
+            FileWriter::try_new(File::create(self.data_path(&parked.id))?, &block.schema())?;
+        writer.write(block)?;
+        writer.finish()?;
+        // 元数据最后写，没有元数据的数据文件不会被重放
+        fs::write(self.meta_path(&parked.id), serde_json::to_vec(&parked)?)?;
+        Ok(parked)
+    }
+
+    /// 所有暂存的数据块，按暂存时间排序
+    pub fn parked(&self) -> Result<Vec<ParkedBatch>> {
+        let mut parked = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                parked.push(serde_json::from_slice::<ParkedBatch>(&fs::read(&path)?)?);
+            }
+        }
+        parked.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(parked)
+    }
+
+    pub(crate) fn load(&self, parked: &ParkedBatch) -> Result<RecordBatch> {
+        let reader = FileReader::try_new(File::open(self.data_path(&parked.id))?, None)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(datafusion::arrow::compute::concat_batches(
+            &schema, &batches,
+        )?)
+    }
+
+    pub(crate) fn remove(&self, parked: &ParkedBatch) -> Result<()> {
+        fs::remove_file(self.meta_path(&parked.id))?;
+        fs::remove_file(self.data_path(&parked.id))?;
+        Ok(())
+    }
+
+    fn data_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.arrow", id))
+    }
+
+    fn meta_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
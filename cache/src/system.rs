@@ -0,0 +1,60 @@
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::{CatalogProvider, SchemaProvider};
+use datafusion::catalog_common::MemorySchemaProvider;
+use datafusion::datasource::MemTable;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+// cache 自己维护的系统表都放在这个 schema 下，比如 system.column_lineage
+pub const SYSTEM_SCHEMA: &str = "system";
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub(crate) fn default_catalog(&self) -> Result<Arc<dyn CatalogProvider>> {
+        let name = self
+            .ctx
+            .state()
+            .config()
+            .options()
+            .catalog
+            .default_catalog
+            .clone();
+        self.ctx
+            .catalog(&name)
+            .ok_or_else(|| anyhow::anyhow!("Default catalog {} not found", name))
+    }
+
+    /// 用户表所在的默认 schema（datafusion.public）
+    pub(crate) fn default_schema(&self) -> Result<Arc<dyn SchemaProvider>> {
+        let name = self
+            .ctx
+            .state()
+            .config()
+            .options()
+            .catalog
+            .default_schema
+            .clone();
+        self.default_catalog()?
+            .schema(&name)
+            .ok_or_else(|| anyhow::anyhow!("Default schema {} not found", name))
+    }
+
+    /// 用最新的内容替换 system.{name}
+    pub(crate) fn refresh_system_table(&self, name: &str, batch: RecordBatch) -> Result<()> {
+        let catalog = self.default_catalog()?;
+        let schema = match catalog.schema(SYSTEM_SCHEMA) {
+            Some(schema) => schema,
+            None => {
+                let schema = Arc::new(MemorySchemaProvider::new());
+                catalog.register_schema(SYSTEM_SCHEMA, schema.clone())?;
+                schema
+            }
+        };
+
+        let table = MemTable::try_new(batch.schema(), vec![vec![batch]])?;
+        schema.deregister_table(name)?;
+        schema.register_table(name.to_string(), Arc::new(table))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// 导出路径模板，比如 `exports/{table}/{yyyy}/{MM}/{dd}/run-{ts}.parquet`
+///
+/// 内置变量（按写入时间展开，UTC）：
+/// - `{yyyy}` `{MM}` `{dd}` `{HH}` `{mm}` `{ss}`
+/// - `{date}`：yyyyMMdd
+/// - `{ts}`：unix 秒
+///
+/// 其他变量通过 `with_var` 设置，未设置的变量会报错
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    template: String,
+    vars: HashMap<String, String>,
+}
+
+impl PathTemplate {
+    pub fn new(template: &str) -> Self {
+        Self {
+            template: template.to_string(),
+            vars: HashMap::new(),
+        }
+    }
+
+    pub fn with_var(mut self, name: &str, value: &str) -> Self {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn render(&self, now: DateTime<Utc>) -> anyhow::Result<String> {
+        let mut out = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let end = rest[start..].find('}').map(|i| start + i).ok_or_else(|| {
+                anyhow::anyhow!("Unclosed variable in path template: {}", self.template)
+            })?;
+            let name = &rest[start + 1..end];
+            out.push_str(&self.resolve(name, now)?);
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn resolve(&self, name: &str, now: DateTime<Utc>) -> anyhow::Result<String> {
+        if let Some(value) = self.vars.get(name) {
+            return Ok(value.clone());
+        }
+        let value = match name {
+            "yyyy" => now.format("%Y").to_string(),
+            "MM" => now.format("%m").to_string(),
+            "dd" => now.format("%d").to_string(),
+            "HH" => now.format("%H").to_string(),
+            "mm" => now.format("%M").to_string(),
+            "ss" => now.format("%S").to_string(),
+            "date" => now.format("%Y%m%d").to_string(),
+            "ts" => now.timestamp().to_string(),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unknown variable '{}' in path template: {}",
+                    name,
+                    self.template
+                ))
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_render_path_template() -> anyhow::Result<()> {
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 8, 30, 0).unwrap();
+        let path = PathTemplate::new("exports/{table}/{yyyy}/{MM}/{dd}/run-{ts}.parquet")
+            .with_var("table", "orders")
+            .render(now)?;
+        assert_eq!(path, "exports/orders/2024/05/01/run-1714552200.parquet");
+
+        let path = PathTemplate::new("daily/{date}/{HH}{mm}{ss}.csv").render(now)?;
+        assert_eq!(path, "daily/20240501/083000.csv");
+
+        // 没有变量的路径保持不变
+        let path = PathTemplate::new("tests/reverse_id.csv").render(now)?;
+        assert_eq!(path, "tests/reverse_id.csv");
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_path_template_errors() {
+        let now = Utc::now();
+        assert!(PathTemplate::new("exports/{table}/a.csv")
+            .render(now)
+            .is_err());
+        assert!(PathTemplate::new("exports/{yyyy/a.csv")
+            .render(now)
+            .is_err());
+    }
+}
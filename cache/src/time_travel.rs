@@ -0,0 +1,320 @@
+use crate::live::TableChange;
+use crate::pool::DB;
+use crate::session::{overlay_context, Overlay};
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::TableReference;
+use datafusion::datasource::TableProvider;
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::prelude::DataFrame;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// 表的一个历史版本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableVersion {
+    pub version: u64,
+    pub committed_at: DateTime<Utc>,
+    // 这个版本删除了表
+    pub dropped: bool,
+}
+
+struct VersionSnapshot {
+    version: u64,
+    committed_at: DateTime<Utc>,
+    // 写入后的表，None 表示表被删除
+    table: Option<Arc<dyn TableProvider>>,
+}
+
+#[derive(Default)]
+struct TableHistory {
+    version: u64,
+    updated_at: Option<DateTime<Utc>>,
+    snapshots: VecDeque<VersionSnapshot>,
+    // false 表示最早的快照之前还有版本（开启前的写入或者已经被淘汰），无法读取
+    complete: bool,
+}
+
+/// 表的版本号和保留的历史版本
+///
+/// 版本号来自 DB 内全局递增的序号，同一个版本号在所有表上对应同一个时间点，
+/// 所以 query_at_version 读到的多个表是一致的
+#[derive(Default)]
+pub(crate) struct VersionState {
+    sequence: u64,
+    // 每个表最多保留的历史版本数，0 表示没有开启时间旅行，只维护版本号
+    retention: usize,
+    tables: HashMap<String, TableHistory>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 开启时间旅行，每个表最多保留 max_versions 个历史版本
+    ///
+    /// 开启后内存表的写入改成写时复制，旧版本继续引用原来的 batch，
+    /// 保留的版本越多占用的内存越多；开启之前的版本无法读取
+    pub async fn enable_time_travel(&self, max_versions: usize) -> Result<()> {
+        if max_versions == 0 {
+            return Err(anyhow::anyhow!("max_versions must be greater than 0"));
+        }
+        let schema = self.default_schema()?;
+        let mut current = Vec::new();
+        for name in schema.table_names() {
+            if let Some(table) = schema.table(&name).await? {
+                current.push((name, table));
+            }
+        }
+
+        let now = Utc::now();
+        let mut state = self.versions.write().unwrap();
+        state.retention = max_versions;
+        for (name, table) in current {
+            let history = state.tables.entry(name).or_default();
+            if history.snapshots.is_empty() {
+                history.snapshots.push_back(VersionSnapshot {
+                    version: history.version,
+                    committed_at: history.updated_at.unwrap_or(now),
+                    table: Some(table),
+                });
+                history.complete = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// 关闭时间旅行并释放所有历史版本，版本号继续递增
+    pub fn disable_time_travel(&self) {
+        let mut state = self.versions.write().unwrap();
+        state.retention = 0;
+        for history in state.tables.values_mut() {
+            history.snapshots.clear();
+        }
+    }
+
+    pub fn time_travel_enabled(&self) -> bool {
+        self.versions.read().unwrap().retention > 0
+    }
+
+    /// DB 当前的版本号，可以记下来之后传给 query_at_version
+    pub fn current_version(&self) -> u64 {
+        self.versions.read().unwrap().sequence
+    }
+
+    /// 表最后一次写入的版本号，没有写入过时返回 0
+    pub fn table_version(&self, table: &str) -> u64 {
+        let Some(key) = self.version_key(&self.table_ref(table)) else {
+            return 0;
+        };
+        let state = self.versions.read().unwrap();
+        state.tables.get(&key).map_or(0, |history| history.version)
+    }
+
+    /// 保留的历史版本，从旧到新
+    pub fn table_history(&self, table: &str) -> Vec<TableVersion> {
+        let Some(key) = self.version_key(&self.table_ref(table)) else {
+            return Vec::new();
+        };
+        let state = self.versions.read().unwrap();
+        let Some(history) = state.tables.get(&key) else {
+            return Vec::new();
+        };
+        history
+            .snapshots
+            .iter()
+            .map(|snapshot| TableVersion {
+                version: snapshot.version,
+                committed_at: snapshot.committed_at,
+                dropped: snapshot.table.is_none(),
+            })
+            .collect()
+    }
+
+    /// 按 version 时各个表的内容执行只读查询，之后的写入不影响结果
+    pub async fn query_at_version(&self, sql: &str, version: u64) -> Result<DataFrame> {
+        if version > self.current_version() {
+            return Err(anyhow::anyhow!("Version {} does not exist yet", version));
+        }
+        let overlay = self.pin_tables(|snapshot| snapshot.version <= version)?;
+        self.query_pinned(sql, overlay).await
+    }
+
+    /// 按 ts 时各个表的内容执行只读查询
+    pub async fn query_at_timestamp(&self, sql: &str, ts: DateTime<Utc>) -> Result<DataFrame> {
+        let overlay = self.pin_tables(|snapshot| snapshot.committed_at <= ts)?;
+        self.query_pinned(sql, overlay).await
+    }
+
+    // 表被写入后调用，table 是写入后的表，None 表示表被删除
+    pub(crate) fn record_version(&self, key: &str, table: Option<Arc<dyn TableProvider>>) {
+        let mut state = self.versions.write().unwrap();
+        state.sequence += 1;
+        let (version, retention) = (state.sequence, state.retention);
+        let now = Utc::now();
+        let history = state
+            .tables
+            .entry(key.to_string())
+            .or_insert_with(|| TableHistory {
+                // 开启时间旅行之后才出现的表，第一个版本就是创建
+                complete: true,
+                ..TableHistory::default()
+            });
+        history.version = version;
+        history.updated_at = Some(now);
+        // 没有订阅者时发送会失败，直接忽略
+        let _ = self.table_changes.send(TableChange {
+            table: key.to_string(),
+            version,
+        });
+        if retention == 0 {
+            return;
+        }
+        history.snapshots.push_back(VersionSnapshot {
+            version,
+            committed_at: now,
+            table,
+        });
+        while history.snapshots.len() > retention {
+            history.snapshots.pop_front();
+            history.complete = false;
+        }
+    }
+
+    // 只有默认 schema 中的表有版本
+    pub(crate) fn version_key(&self, table: &TableReference) -> Option<String> {
+        let state = self.ctx.state();
+        let options = &state.config().options().catalog;
+        let in_default = table
+            .catalog()
+            .map_or(true, |c| c == options.default_catalog)
+            && table.schema().map_or(true, |s| s == options.default_schema);
+        in_default.then(|| table.table().to_string())
+    }
+
+    // 开启时间旅行时，把 batch 和表原有的 batch 合成新表再替换，而不是原地追加，
+    // 旧版本引用的表不会被修改。这里只读出合并后的数据，不修改表，由调用方写入 WAL 后
+    // 用 apply_replace 替换；不是内存表时返回 None，由调用方原地追加。
+    // 调用方需要持有表的写锁
+    pub(crate) async fn copy_on_write_rows(
+        &self,
+        key: &str,
+        batches: &[RecordBatch],
+    ) -> Result<Option<(Arc<dyn TableProvider>, Vec<RecordBatch>)>> {
+        let provider = match self.ctx.table_provider(TableReference::bare(key)).await {
+            Ok(provider) => provider,
+            Err(_) => return Err(anyhow::anyhow!("Table {} not found", key)),
+        };
+        if !is_memory_table(provider.as_ref()) {
+            return Ok(None);
+        }
+        let schema = provider.schema();
+        let mut rows = self.ctx.read_table(provider.clone())?.collect().await?;
+        for batch in batches {
+            rows.push(batch.with_schema(schema.clone())?);
+        }
+        Ok(Some((provider, rows)))
+    }
+
+    // 每个有历史的表取满足 at 的最新版本，不存在的表隐藏起来；没有历史的表读当前内容
+    fn pin_tables(&self, at: impl Fn(&VersionSnapshot) -> bool) -> Result<Overlay> {
+        let state = self.versions.read().unwrap();
+        if state.retention == 0 {
+            return Err(anyhow::anyhow!("Time travel is not enabled"));
+        }
+        let overlay = Overlay::default();
+        for (name, history) in &state.tables {
+            match history.snapshots.iter().rev().find(|s| at(s)) {
+                Some(VersionSnapshot {
+                    table: Some(table), ..
+                }) => {
+                    overlay.tables.replace_table(name, table.clone());
+                }
+                Some(VersionSnapshot { table: None, .. }) => {
+                    overlay.hidden.write().unwrap().insert(name.clone());
+                }
+                None if history.snapshots.is_empty() => {}
+                None if history.complete => {
+                    overlay.hidden.write().unwrap().insert(name.clone());
+                }
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "The requested version of table {} is no longer retained",
+                        name
+                    ))
+                }
+            }
+        }
+        Ok(overlay)
+    }
+
+    async fn query_pinned(&self, sql: &str, overlay: Overlay) -> Result<DataFrame> {
+        let ctx = overlay_context(&self.ctx, overlay);
+        let plan = ctx.state().create_logical_plan(sql).await?;
+        if matches!(
+            plan,
+            LogicalPlan::Dml(_) | LogicalPlan::Ddl(_) | LogicalPlan::Copy(_)
+        ) {
+            return Err(anyhow::anyhow!("Time travel queries are read-only"));
+        }
+        Ok(ctx.execute_logical_plan(plan).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn count(df: DataFrame) -> Result<usize> {
+        Ok(df.count().await?)
+    }
+
+    #[tokio::test]
+    async fn test_time_travel() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE orders (id BIGINT)").await?;
+        db.execute("INSERT INTO orders VALUES (1), (2)").await?;
+        assert_eq!(db.table_version("orders"), 2);
+        assert!(db
+            .query_at_version("SELECT * FROM orders", 2)
+            .await
+            .is_err());
+
+        db.enable_time_travel(4).await?;
+        let v1 = db.current_version();
+        let t1 = Utc::now();
+        db.execute("INSERT INTO orders VALUES (3)").await?;
+        db.execute("CREATE TABLE refunds AS SELECT id FROM orders")
+            .await?;
+        let v2 = db.current_version();
+        db.truncate_table("orders").await?;
+        db.drop_table("refunds").await?;
+
+        let sql = "SELECT * FROM orders";
+        assert_eq!(count(db.query_at_version(sql, v1).await?).await?, 2);
+        assert_eq!(count(db.query_at_version(sql, v2).await?).await?, 3);
+        assert_eq!(count(db.query_at_timestamp(sql, t1).await?).await?, 2);
+        assert_eq!(count(db.ctx.table("orders").await?).await?, 0);
+        // refunds 在 v1 时还不存在，在 v2 时存在
+        assert!(db
+            .query_at_version("SELECT * FROM refunds", v1)
+            .await
+            .is_err());
+        let refunds = db.query_at_version("SELECT * FROM refunds", v2).await?;
+        assert_eq!(count(refunds).await?, 3);
+        assert!(db
+            .query_at_version("INSERT INTO orders VALUES (4)", v2)
+            .await
+            .is_err());
+        assert!(db.table_history("refunds").last().unwrap().dropped);
+
+        // 超过保留数量的旧版本被淘汰
+        for id in 0..4 {
+            db.execute(&format!("INSERT INTO orders VALUES ({})", id))
+                .await?;
+        }
+        assert_eq!(db.table_history("orders").len(), 4);
+        assert!(db.query_at_version(sql, v1).await.is_err());
+        Ok(())
+    }
+}
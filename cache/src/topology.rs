@@ -0,0 +1,347 @@
+#[cfg(feature = "clickhouse")]
+use crate::ck_client::{ClickHouseClient, QueryHints};
+use crate::config::{Config, TableConfig, TableSourceConfig};
+use crate::ident::{quote_ident, quote_literal};
+use crate::pool::DB;
+#[cfg(feature = "clickhouse")]
+use crate::refresh::ClickHouseSource;
+use crate::refresh::{RefreshTask, SqlSource};
+use crate::startup::{StartupReport, TableStartup};
+use anyhow::Result;
+use std::collections::HashSet;
+#[cfg(feature = "proto")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 同时加载的 query/clickhouse 表数
+const CONFIG_REFRESH_PARALLELISM: usize = 4;
+
+impl DB<()> {
+    /// 按配置注册存储、设置语句检查、启动事件通知，并创建 tables 中声明的表
+    ///
+    /// memory/external 表先创建，query/clickhouse 表按依赖顺序加载；
+    /// 单个表加载失败不会返回错误，结果见返回的 StartupReport，同时通过 tracing 输出
+    pub async fn init_from_config(&self, mut config: Config) -> Result<StartupReport> {
+        let started = Instant::now();
+        let tables = std::mem::take(&mut config.tables);
+        check_table_names(&tables)?;
+        self.set_statement_guard(config.guard.clone());
+        self.set_compression_config(&config.compression)?;
+        // 在加载表之前启动，启动过程中的刷新失败也会通知
+        let notifications = std::mem::take(&mut config.notifications);
+        #[cfg(feature = "notify")]
+        if !notifications.is_empty() {
+            self.spawn_notifier(notifications);
+        }
+        #[cfg(not(feature = "notify"))]
+        if !notifications.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Notifications are configured but the notify feature is disabled"
+            ));
+        }
+        #[cfg(feature = "clickhouse")]
+        let clickhouse = config.clickhouse.clone().map(ClickHouseClient::new);
+
+        #[cfg(feature = "storage")]
+        let mut report = self.register_configured_storages(config)?;
+        #[cfg(not(feature = "storage"))]
+        if !config.storages.is_empty() || !config.storage_groups.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Storages are configured but the storage feature is disabled"
+            ));
+        }
+        #[cfg(not(feature = "storage"))]
+        let mut report = StartupReport::default();
+
+        let mut tasks = Vec::new();
+        for table in &tables {
+            let created = Instant::now();
+            match &table.source {
+                TableSourceConfig::Memory => self.create_configured_table(table).await?,
+                TableSourceConfig::External { location, format } => {
+                    if table.proto.is_some() {
+                        return Err(anyhow::anyhow!(
+                            "Table {}: proto schema is only supported for memory tables",
+                            table.name
+                        ));
+                    }
+                    let columns = column_list(table);
+                    self.execute(&format!(
+                        "CREATE EXTERNAL TABLE IF NOT EXISTS {} {} STORED AS {} LOCATION {}",
+                        quote_ident(&table.name),
+                        columns,
+                        format.to_uppercase(),
+                        quote_literal(location)
+                    ))
+                    .await?;
+                }
+                TableSourceConfig::Query { sql } => {
+                    let source = SqlSource(sql.clone());
+                    tasks.push(refresh_task(table, RefreshTask::new(&table.name, source)));
+                    continue;
+                }
+                #[cfg(feature = "clickhouse")]
+                TableSourceConfig::ClickHouse { sql, settings } => {
+                    let Some(client) = &clickhouse else {
+                        return Err(anyhow::anyhow!(
+                            "Table {} reads from ClickHouse but clickhouse is not configured",
+                            table.name
+                        ));
+                    };
+                    let source = ClickHouseSource {
+                        client: client.clone(),
+                        sql: sql.clone(),
+                        hints: QueryHints::new().with_settings(settings),
+                    };
+                    tasks.push(refresh_task(table, RefreshTask::new(&table.name, source)));
+                    continue;
+                }
+                #[cfg(not(feature = "clickhouse"))]
+                TableSourceConfig::ClickHouse { .. } => {
+                    return Err(anyhow::anyhow!(
+                        "Table {} reads from ClickHouse but the clickhouse feature is disabled",
+                        table.name
+                    ));
+                }
+            }
+            report.tables.push(TableStartup {
+                table: table.name.clone(),
+                source: source_kind(&table.source).to_string(),
+                rows: None,
+                elapsed: created.elapsed(),
+                error: None,
+            });
+        }
+
+        let refresh = self.refresh_all(tasks, CONFIG_REFRESH_PARALLELISM).await?;
+        report.add_refreshed(refresh, |name| {
+            tables
+                .iter()
+                .find(|t| t.name == name)
+                .map_or("", |t| source_kind(&t.source))
+                .to_string()
+        });
+
+        // 加载失败的表不存在，不设置 TTL
+        for table in &tables {
+            let Some(ttl) = &table.ttl else {
+                continue;
+            };
+            if !self.ctx.table_exist(table.name.as_str())? {
+                report.warnings.push(format!(
+                    "Table {} was not loaded, TTL on {} is not set",
+                    table.name, ttl.column
+                ));
+                continue;
+            }
+            match ttl.after_secs {
+                Some(secs) => {
+                    self.set_row_ttl_after(&table.name, &ttl.column, Duration::from_secs(secs))
+                        .await?
+                }
+                None => self.set_row_ttl(&table.name, &ttl.column).await?,
+            }
+        }
+        report.elapsed = started.elapsed();
+        report.log();
+        Ok(report)
+    }
+
+    // recovery 已经从 WAL 恢复的表保留原来的数据，不再创建；新建的表和 SQL 建表一样写入 WAL
+    async fn create_configured_table(&self, table: &TableConfig) -> Result<()> {
+        if self.ctx.table_exist(table.name.as_str())? {
+            return Ok(());
+        }
+        #[cfg(feature = "proto")]
+        if let Some(proto) = &table.proto {
+            let schema = crate::schema::create_schema_from_proto_file(
+                std::path::Path::new(&proto.file),
+                &proto.message,
+            )
+            .map_err(|e| anyhow::anyhow!("Table {}: {}", table.name, e))?;
+            let _guard = self.lock_table(&table.name).await;
+            return self.rewrite_table(&table.name, Arc::new(schema), vec![vec![]], None);
+        }
+        #[cfg(not(feature = "proto"))]
+        if table.proto.is_some() {
+            return Err(anyhow::anyhow!(
+                "Table {} uses a proto schema but the proto feature is disabled",
+                table.name
+            ));
+        }
+        if table.columns.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Memory table {} needs columns or a proto schema",
+                table.name
+            ));
+        }
+        self.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} {}",
+            quote_ident(&table.name),
+            column_list(table)
+        ))
+        .await
+    }
+}
+
+fn source_kind(source: &TableSourceConfig) -> &'static str {
+    match source {
+        TableSourceConfig::Memory => "memory",
+        TableSourceConfig::External { .. } => "external",
+        TableSourceConfig::Query { .. } => "query",
+        TableSourceConfig::ClickHouse { .. } => "clickhouse",
+    }
+}
+
+fn refresh_task(table: &TableConfig, mut task: RefreshTask) -> RefreshTask {
+    task.depends_on = table.refresh.depends_on.clone();
+    task.timeout = table.refresh.timeout_secs.map(Duration::from_secs);
+    task.sla = table.refresh.sla_secs.map(Duration::from_secs);
+    task
+}
+
+// (a BIGINT, b VARCHAR NOT NULL)，没有列时为空
+fn column_list(table: &TableConfig) -> String {
+    if table.columns.is_empty() {
+        return String::new();
+    }
+    let columns: Vec<String> = table
+        .columns
+        .iter()
+        .map(|column| {
+            let not_null = if column.nullable { "" } else { " NOT NULL" };
+            format!(
+                "{} {}{}",
+                quote_ident(&column.name),
+                column.data_type,
+                not_null
+            )
+        })
+        .collect();
+    format!("({})", columns.join(", "))
+}
+
+fn check_table_names(tables: &[TableConfig]) -> Result<()> {
+    let mut seen = HashSet::new();
+    for table in tables {
+        if !seen.insert(table.name.as_str()) {
+            return Err(anyhow::anyhow!("Table {} is configured twice", table.name));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config as ConfigRs, File, FileFormat};
+
+    #[tokio::test]
+    async fn test_init_from_config() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let csv = dir.path().join("users.csv");
+        std::fs::write(&csv, "id,name\n1,alice\n2,bob\n3,carol\n")?;
+
+        let toml = format!(
+            r#"
+            storages = {{}}
+
+            [[tables]]
+            name = "users_raw"
+            source = {{ kind = "external", location = "{}", format = "csv" }}
+            columns = [
+                {{ name = "id", data_type = "BIGINT" }},
+                {{ name = "name", data_type = "VARCHAR" }},
+            ]
+
+            [[tables]]
+            name = "users"
+            source = {{ kind = "query", sql = "SELECT * FROM users_raw" }}
+
+            [[tables]]
+            name = "user_names"
+            source = {{ kind = "query", sql = "SELECT name FROM users" }}
+            refresh = {{ depends_on = ["users"], timeout_secs = 10 }}
+
+            [[tables]]
+            name = "sessions"
+            source = {{ kind = "memory" }}
+            columns = [
+                {{ name = "id", data_type = "BIGINT", nullable = false }},
+                {{ name = "created_at", data_type = "BIGINT" }},
+            ]
+            ttl = {{ column = "created_at", after_secs = 3600 }}
+            "#,
+            csv.display()
+        );
+        let config: Config = ConfigRs::builder()
+            .add_source(File::from_str(&toml, FileFormat::Toml))
+            .build()?
+            .try_deserialize()?;
+        assert_eq!(config.tables.len(), 4);
+
+        let db = DB::<()>::new("test_db");
+        let report = db.init_from_config(config).await?;
+        assert!(report.is_ok());
+        assert_eq!(report.rows(), 6);
+        assert_eq!(report.table("users").unwrap().rows, Some(3));
+        assert_eq!(report.table("users_raw").unwrap().source, "external");
+        assert!(report.warnings.is_empty());
+        assert_eq!(db.ctx.table("user_names").await?.count().await?, 3);
+        assert_eq!(
+            db.get_table_meta("sessions", crate::ttl::TTL_AFTER_META_KEY),
+            Some("3600000".to_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_init_after_recovery() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let csv = dir.path().join("users.csv");
+        std::fs::write(&csv, "id,name\n1,alice\n2,bob\n")?;
+        let toml = format!(
+            r#"
+            storages = {{}}
+
+            [[tables]]
+            name = "users"
+            source = {{ kind = "external", location = "{}", format = "csv" }}
+            columns = [
+                {{ name = "id", data_type = "BIGINT" }},
+                {{ name = "name", data_type = "VARCHAR" }},
+            ]
+
+            [[tables]]
+            name = "sessions"
+            source = {{ kind = "memory" }}
+            columns = [{{ name = "id", data_type = "BIGINT" }}]
+            "#,
+            csv.display()
+        );
+        let config = || -> Result<Config> {
+            Ok(ConfigRs::builder()
+                .add_source(File::from_str(&toml, FileFormat::Toml))
+                .build()?
+                .try_deserialize()?)
+        };
+        let wal = dir.path().join("wal");
+
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(crate::wal::WalConfig::new(&wal))?;
+        db.init_from_config(config()?).await?;
+        db.execute("INSERT INTO sessions VALUES (1), (2)").await?;
+        drop(db);
+
+        // 重启：先重放 WAL，再按配置启动，已经恢复的表不会重新创建
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(crate::wal::WalConfig::new(&wal))?;
+        let recovery = db.recovery().await?;
+        assert!(recovery.errors.is_empty(), "{:?}", recovery.errors);
+        let report = db.init_from_config(config()?).await?;
+        assert!(report.is_ok());
+        assert_eq!(db.ctx.table("sessions").await?.count().await?, 2);
+        assert_eq!(db.ctx.table("users").await?.count().await?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use crate::pool::DB;
+use crate::session::{overlay_context, Overlay};
+use crate::swap::{is_memory_table, replacement_table};
+use crate::wal::WalRecord;
+use anyhow::Result;
+use datafusion::catalog::SchemaProvider;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::logical_expr::{DdlStatement, DmlStatement, LogicalPlan, WriteOp};
+use datafusion::prelude::{DataFrame, SessionContext};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// 事务第一次修改表时共享表的状态，None 表示表当时不存在
+type Base = Option<(Arc<dyn TableProvider>, usize)>;
+
+/// 事务：其中的 INSERT、CREATE TABLE、DROP TABLE 先写到事务自己的表副本中，
+/// commit 时一次替换共享的表，rollback 或者 drop 时丢弃
+///
+/// 事务中的查询能看到事务自己的修改，其他查询在 commit 之前看不到；
+/// 没有修改过的表读取的是共享表的最新内容
+pub struct Transaction<'a, V: Serialize + DeserializeOwned + Send + Sync> {
+    db: &'a DB<V>,
+    ctx: SessionContext,
+    overlay: Overlay,
+    bases: BTreeMap<String, Base>,
+    // commit 时先按顺序写入 WAL 再替换表
+    log: Vec<WalRecord>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    pub fn begin(&self) -> Transaction<'_, V> {
+        let overlay = Overlay::default();
+        Transaction {
+            db: self,
+            ctx: overlay_context(&self.ctx, overlay.clone()),
+            overlay,
+            bases: BTreeMap::new(),
+            log: Vec::new(),
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> Transaction<'_, V> {
+    /// 在事务中执行一条语句，只支持默认 schema 中的内存表
+    pub async fn execute(&mut self, sql: &str) -> Result<DataFrame> {
+        let plan = self.ctx.state().create_logical_plan(sql).await?;
+        match plan {
+            LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(cmd)) => {
+                let name = self.local_name(&cmd.name)?;
+                if self.exists(&name) {
+                    if cmd.if_not_exists {
+                        return Ok(self.ctx.read_empty()?);
+                    }
+                    if !cmd.or_replace {
+                        return Err(anyhow::anyhow!("Table {} already exists", name));
+                    }
+                }
+                let schema = cmd.input.schema().inner().clone();
+                let input = Arc::unwrap_or_clone(cmd.input);
+                let batches = self
+                    .ctx
+                    .execute_logical_plan(input)
+                    .await?
+                    .collect()
+                    .await?;
+                self.remember_base(&name).await?;
+                self.overlay
+                    .tables
+                    .replace_table(&name, Arc::new(MemTable::try_new(schema, vec![batches])?));
+                self.overlay.hidden.write().unwrap().remove(&name);
+                self.log.push(WalRecord::Ddl {
+                    sql: sql.to_string(),
+                });
+                Ok(self.ctx.read_empty()?)
+            }
+            LogicalPlan::Ddl(DdlStatement::DropTable(cmd)) => {
+                let name = self.local_name(&cmd.name)?;
+                if !self.exists(&name) {
+                    if cmd.if_exists {
+                        return Ok(self.ctx.read_empty()?);
+                    }
+                    return Err(anyhow::anyhow!("Table {} not found", name));
+                }
+                self.remember_base(&name).await?;
+                self.overlay.tables.deregister_table(&name)?;
+                self.overlay.hidden.write().unwrap().insert(name);
+                self.log.push(WalRecord::Ddl {
+                    sql: sql.to_string(),
+                });
+                Ok(self.ctx.read_empty()?)
+            }
+            LogicalPlan::Ddl(ddl) => Err(anyhow::anyhow!(
+                "{} is not supported in a transaction",
+                ddl.name()
+            )),
+            LogicalPlan::Dml(DmlStatement {
+                table_name,
+                op: WriteOp::Insert(_),
+                input,
+                ..
+            }) => {
+                let name = self.local_name(&table_name)?;
+                self.stage(&name).await?;
+                let batches = self
+                    .ctx
+                    .execute_logical_plan(Arc::unwrap_or_clone(input))
+                    .await?
+                    .collect()
+                    .await?;
+                if batches.iter().all(|b| b.num_rows() == 0) {
+                    return Ok(self.ctx.read_batches(Vec::new())?);
+                }
+                let count = self
+                    .ctx
+                    .read_batches(batches.clone())?
+                    .write_table(&name, DataFrameWriteOptions::new())
+                    .await?;
+                self.log.push(WalRecord::Insert {
+                    table: name,
+                    batches,
+                });
+                Ok(self.ctx.read_batches(count)?)
+            }
+            LogicalPlan::Dml(_) => {
+                Err(anyhow::anyhow!("Only INSERT is supported in a transaction"))
+            }
+            plan => Ok(self.ctx.execute_logical_plan(plan).await?),
+        }
+    }
+
+    /// 事务修改过的表，按名字排序
+    pub fn modified_tables(&self) -> Vec<String> {
+        self.bases.keys().cloned().collect()
+    }
+
+    /// 替换所有修改过的表，事务期间其他写入修改了同一个表时返回错误并丢弃事务
+    ///
+    /// 检查和替换期间持有所有修改过的表的写锁，不会和同时进行的写入交错
+    pub async fn commit(self) -> Result<()> {
+        let names: Vec<String> = self.bases.keys().cloned().collect();
+        let _guards = self.db.lock_tables(&names).await;
+        let schema = self.db.default_schema()?;
+        for (name, base) in &self.bases {
+            let current = snapshot(self.db, schema.as_ref(), name).await?;
+            let unchanged = match (base, &current) {
+                (None, None) => true,
+                (Some((base, base_rows)), Some((current, current_rows))) => {
+                    Arc::ptr_eq(base, current) && base_rows == current_rows
+                }
+                _ => false,
+            };
+            if !unchanged {
+                return Err(anyhow::anyhow!(
+                    "Table {} was modified by another writer, transaction aborted",
+                    name
+                ));
+            }
+        }
+
+        let mut changes = Vec::new();
+        for name in self.bases.keys() {
+            changes.push((name.clone(), self.overlay.tables.table(name).await?));
+        }
+        if let Some(wal) = self.db.wal() {
+            for record in &self.log {
+                wal.append(record)?;
+            }
+        }
+        self.db.replace_tables(changes)
+    }
+
+    /// 丢弃事务中的所有修改，和直接 drop 事务一样
+    pub fn rollback(self) {}
+
+    // 事务只修改默认 schema 中的表
+    fn local_name(&self, table: &datafusion::common::TableReference) -> Result<String> {
+        let options = self.ctx.state().config().options().catalog.clone();
+        let in_default = table
+            .catalog()
+            .map_or(true, |c| c == options.default_catalog)
+            && table.schema().map_or(true, |s| s == options.default_schema);
+        if !in_default {
+            return Err(anyhow::anyhow!(
+                "Transactions only support tables in the default schema, got {}",
+                table
+            ));
+        }
+        Ok(table.table().to_string())
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.overlay.tables.table_exist(name)
+            || (!self.overlay.hidden.read().unwrap().contains(name)
+                && self
+                    .db
+                    .default_schema()
+                    .is_ok_and(|schema| schema.table_exist(name)))
+    }
+
+    async fn remember_base(&mut self, name: &str) -> Result<()> {
+        if !self.bases.contains_key(name) {
+            let schema = self.db.default_schema()?;
+            let base = snapshot(self.db, schema.as_ref(), name).await?;
+            self.bases.insert(name.to_string(), base);
+        }
+        Ok(())
+    }
+
+    // 第一次写入时复制共享表的 batch 列表，batch 本身不会被复制
+    async fn stage(&mut self, name: &str) -> Result<()> {
+        if self.overlay.tables.table_exist(name) {
+            return Ok(());
+        }
+        if !self.exists(name) {
+            return Err(anyhow::anyhow!("Table {} not found", name));
+        }
+        self.remember_base(name).await?;
+        let Some((provider, _)) = self.bases[name].clone() else {
+            return Err(anyhow::anyhow!("Table {} not found", name));
+        };
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Table {} is not a memory table and cannot be written in a transaction",
+                name
+            ));
+        }
+        let batches = self
+            .db
+            .ctx
+            .table(self.db.table_ref(name))
+            .await?
+            .collect()
+            .await?;
+        let table = replacement_table(provider.schema(), vec![batches], Some(&provider))?;
+        self.overlay.tables.replace_table(name, table);
+        Ok(())
+    }
+}
+
+// 共享表当前的 provider 和行数，内存表的追加写入不会换 provider，所以同时比较行数
+async fn snapshot<V: Serialize + DeserializeOwned + Send + Sync>(
+    db: &DB<V>,
+    schema: &dyn SchemaProvider,
+    name: &str,
+) -> Result<Base> {
+    let Some(provider) = schema.table(name).await? else {
+        return Ok(None);
+    };
+    let rows = db.ctx.table(db.table_ref(name)).await?.count().await?;
+    Ok(Some((provider, rows)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn count(db: &DB<()>, table: &str) -> Result<usize> {
+        Ok(db.ctx.table(table).await?.count().await?)
+    }
+
+    #[tokio::test]
+    async fn test_transaction_commit_and_rollback() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE accounts (id BIGINT, balance BIGINT)")
+            .await?;
+        db.execute("INSERT INTO accounts VALUES (1, 100)").await?;
+
+        let mut tx = db.begin();
+        tx.execute("INSERT INTO accounts VALUES (2, 50)").await?;
+        tx.execute("CREATE TABLE audit AS SELECT id FROM accounts")
+            .await?;
+        tx.execute("INSERT INTO audit VALUES (3)").await?;
+        // 事务内能看到自己的修改，外面看不到
+        let rows = tx.execute("SELECT * FROM audit").await?.count().await?;
+        assert_eq!(rows, 3);
+        assert_eq!(count(&db, "accounts").await?, 1);
+        assert!(!db.ctx.table_exist("audit")?);
+        assert_eq!(tx.modified_tables(), vec!["accounts", "audit"]);
+        tx.commit().await?;
+        assert_eq!(count(&db, "accounts").await?, 2);
+        assert_eq!(count(&db, "audit").await?, 3);
+
+        let mut tx = db.begin();
+        tx.execute("DROP TABLE audit").await?;
+        tx.execute("INSERT INTO accounts VALUES (4, 0)").await?;
+        assert!(tx.execute("SELECT * FROM audit").await.is_err());
+        tx.rollback();
+        assert_eq!(count(&db, "accounts").await?, 2);
+        assert_eq!(count(&db, "audit").await?, 3);
+
+        // 事务期间其他写入修改了同一个表
+        let mut tx = db.begin();
+        tx.execute("INSERT INTO accounts VALUES (5, 10)").await?;
+        db.execute("INSERT INTO accounts VALUES (6, 10)").await?;
+        assert!(tx.commit().await.is_err());
+        assert_eq!(count(&db, "accounts").await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit_keeps_constraints() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE accounts (id BIGINT PRIMARY KEY, balance BIGINT)")
+            .await?;
+        let mut tx = db.begin();
+        tx.execute("INSERT INTO accounts VALUES (1, 100)").await?;
+        tx.commit().await?;
+        let provider = db.ctx.table_provider("accounts").await?;
+        assert!(provider.constraints().is_some_and(|c| !c.is_empty()));
+        assert_eq!(count(&db, "accounts").await?, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use crate::ident::quote_ident;
+use crate::job::{JobHandle, JobOptions};
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use arrow_schema::{DataType, SchemaRef};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 行级 TTL 列保存在表的自定义信息中的 key
+pub const TTL_COLUMN_META_KEY: &str = "ttl_column";
+/// 行的存活时间（毫秒），设置后过期时间为 TTL 列的值加上这个时间
+pub const TTL_AFTER_META_KEY: &str = "ttl_after_ms";
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 指定行的过期时间列，和 ClickHouse 的 `TTL expire_at` 一样，列值早于当前时间的行
+    /// 会在 reap_expired_rows 时删除；列值为 NULL 的行不会过期
+    pub async fn set_row_ttl(&self, table: &str, column: &str) -> Result<()> {
+        self.check_ttl_column(table, column).await?;
+        self.set_table_meta(table, TTL_COLUMN_META_KEY, column)?;
+        self.remove_table_meta(table, TTL_AFTER_META_KEY)?;
+        Ok(())
+    }
+
+    /// 按时间列和存活时间过期，和 ClickHouse 的 `TTL created_at + INTERVAL 1 HOUR` 一样，
+    /// 比如 created_at 早于当前时间减去 ttl 的行会被删除
+    pub async fn set_row_ttl_after(&self, table: &str, column: &str, ttl: Duration) -> Result<()> {
+        self.check_ttl_column(table, column).await?;
+        self.set_table_meta(table, TTL_COLUMN_META_KEY, column)?;
+        self.set_table_meta(table, TTL_AFTER_META_KEY, &ttl.as_millis().to_string())
+    }
+
+    // 和 reap_expired_rows 一样只支持内存表，快照（FrozenTable）和外部表只读，不能删除行
+    async fn check_ttl_column(&self, table: &str, column: &str) -> Result<()> {
+        let provider = self.ctx.table_provider(table).await?;
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Row TTL is only supported on in-memory tables, {} is not",
+                table
+            ));
+        }
+        let schema = provider.schema();
+        let field = schema.field_with_name(column)?;
+        if !matches!(
+            field.data_type(),
+            DataType::Timestamp(_, _) | DataType::Int64
+        ) {
+            return Err(anyhow::anyhow!(
+                "TTL column {} should be a timestamp or millisecond BIGINT, got {}",
+                column,
+                field.data_type()
+            ));
+        }
+        Ok(())
+    }
+
+    // TTL 列和存活时间（毫秒）
+    fn row_ttl(&self, table: &str) -> Option<(String, Option<u64>)> {
+        let column = self.get_table_meta(table, TTL_COLUMN_META_KEY)?;
+        let after = self
+            .get_table_meta(table, TTL_AFTER_META_KEY)
+            .and_then(|ms| ms.parse().ok());
+        Some((column, after))
+    }
+
+    /// 删除表中已经过期的行，返回删除的行数
+    ///
+    /// 只支持内存表；删除持有表的写锁，同时进行的写入会等删除完成后再执行
+    pub async fn reap_expired_rows(&self, table: &str) -> Result<usize> {
+        let Some((column, after)) = self.row_ttl(table) else {
+            return Err(anyhow::anyhow!(
+                "No TTL column configured for table {}",
+                table
+            ));
+        };
+        let _guard = self.lock_table(table).await;
+        let provider = self.ctx.table_provider(self.table_ref(table)).await?;
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Row TTL is only supported on in-memory tables, {} is not",
+                table
+            ));
+        }
+
+        let schema = provider.schema();
+        let expire_at = expire_at_expr(&schema, &column, after)?;
+        let before = self.ctx.table(table).await?.count().await?;
+        let batches = self
+            .ctx
+            .sql(&format!(
+                "SELECT * FROM {} WHERE {} IS NULL OR {} > now()",
+                self.quote_table(table),
+                quote_ident(&column),
+                expire_at
+            ))
+            .await?
+            .collect()
+            .await?;
+        let remaining: usize = batches.iter().map(|b| b.num_rows()).sum();
+        if remaining == before {
+            return Ok(0);
+        }
+
+        self.rewrite_table(table, schema, vec![batches], Some(&provider))?;
+        Ok(before - remaining)
+    }
+
+    /// 已经过期但还没有被删除的行数，没有配置行级 TTL 时为 None
+    pub(crate) async fn count_expired_rows(&self, table: &str) -> Result<Option<usize>> {
+        let Some((column, after)) = self.row_ttl(table) else {
+            return Ok(None);
+        };
+        let schema = self.ctx.table_provider(table).await?.schema();
+        let expired = self
+            .ctx
+            .sql(&format!(
+                "SELECT * FROM {} WHERE {} <= now()",
+                self.quote_table(table),
+                expire_at_expr(&schema, &column, after)?
+            ))
+            .await?
+            .count()
+            .await?;
+        Ok(Some(expired))
+    }
+
+    /// 对所有配置了行级 TTL 的表执行 reap_expired_rows，返回每个表删除的行数
+    ///
+    /// 单个表失败只打印日志并跳过，不影响其他表
+    pub async fn reap_all_expired_rows(&self) -> Result<BTreeMap<String, usize>> {
+        let tables: Vec<String> = self
+            .table_meta
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, meta)| meta.contains_key(TTL_COLUMN_META_KEY))
+            .map(|(table, _)| table.clone())
+            .collect();
+        let mut reaped = BTreeMap::new();
+        for table in tables {
+            match self.reap_expired_rows(&table).await {
+                Ok(rows) => {
+                    reaped.insert(table, rows);
+                }
+                Err(e) => tracing::warn!(table = %table, error = %e, "reap expired rows failed"),
+            }
+        }
+        Ok(reaped)
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync + 'static> DB<V> {
+    /// 后台按 sync_interval 定期执行 reap_all_expired_rows，直到任务被取消
+    ///
+    /// 单次清理失败不会结束任务，下一轮会重试
+    pub fn row_ttl_job(self: &Arc<Self>, options: JobOptions) -> JobHandle<()> {
+        self.spawn_job(options, |db, token| async move {
+            loop {
+                if let Err(e) = db.reap_all_expired_rows().await {
+                    tracing::warn!(error = %e, "reap expired rows failed");
+                }
+                tokio::time::sleep(db.sync_interval()).await;
+                if token.is_cancelled() {
+                    return Ok(());
+                }
+            }
+        })
+    }
+}
+
+// 过期时间转换成 timestamp 的 SQL 表达式
+fn expire_at_expr(schema: &SchemaRef, column: &str, after: Option<u64>) -> Result<String> {
+    let expire_at = match schema.field_with_name(column)?.data_type() {
+        DataType::Int64 => format!("to_timestamp_millis({})", quote_ident(column)),
+        _ => quote_ident(column),
+    };
+    Ok(match after {
+        Some(ms) => format!("({} + INTERVAL '{} milliseconds')", expire_at, ms),
+        None => expire_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn test_reap_expired_rows() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE sessions (id BIGINT, expire_at BIGINT)")
+            .await?;
+        let now = Utc::now();
+        db.execute(&format!(
+            "INSERT INTO sessions VALUES (1, {}), (2, {}), (3, NULL)",
+            (now - Duration::hours(1)).timestamp_millis(),
+            (now + Duration::hours(1)).timestamp_millis()
+        ))
+        .await?;
+
+        assert!(db.reap_expired_rows("sessions").await.is_err());
+        assert!(db.set_row_ttl("sessions", "id_missing").await.is_err());
+        db.set_row_ttl("sessions", "expire_at").await?;
+
+        assert_eq!(db.reap_all_expired_rows().await?.get("sessions"), Some(&1));
+        assert_eq!(db.reap_expired_rows("sessions").await?, 0);
+        let batches = db
+            .query_to_batches("SELECT id FROM sessions ORDER BY id")
+            .await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_row_ttl_job() -> Result<()> {
+        let db = Arc::new(DB::<()>::new("test_db"));
+        db.set_sync_interval(std::time::Duration::from_millis(10));
+        db.execute("CREATE TABLE events (id BIGINT, created_at BIGINT)")
+            .await?;
+        let now = Utc::now();
+        db.execute(&format!(
+            "INSERT INTO events VALUES (1, {}), (2, {})",
+            (now - Duration::hours(2)).timestamp_millis(),
+            now.timestamp_millis()
+        ))
+        .await?;
+        db.set_row_ttl_after("events", "created_at", std::time::Duration::from_secs(3600))
+            .await?;
+
+        let job = db.row_ttl_job(JobOptions::default());
+        for _ in 0..100 {
+            if db.ctx.table("events").await?.count().await? == 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(db.ctx.table("events").await?.count().await?, 1);
+        job.cancel();
+        assert!(job.wait().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reap_skips_failed_tables() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE a (id BIGINT, expire_at BIGINT)")
+            .await?;
+        db.execute("CREATE TABLE b (id BIGINT, expire_at BIGINT)")
+            .await?;
+        let expired = (Utc::now() - Duration::hours(1)).timestamp_millis();
+        db.execute(&format!("INSERT INTO a VALUES (1, {})", expired))
+            .await?;
+        db.execute(&format!("INSERT INTO b VALUES (1, {})", expired))
+            .await?;
+        db.set_row_ttl("a", "expire_at").await?;
+        db.set_row_ttl("b", "expire_at").await?;
+
+        // 快照不能设置 TTL
+        db.freeze("b", "v1").await?;
+        assert!(db.set_row_ttl("b@v1", "expire_at").await.is_err());
+
+        // a 被删除后清理失败，b 照常清理
+        db.ctx.deregister_table("a")?;
+        let reaped = db.reap_all_expired_rows().await?;
+        assert_eq!(reaped.get("a"), None);
+        assert_eq!(reaped.get("b"), Some(&1));
+        Ok(())
+    }
+}
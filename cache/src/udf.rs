@@ -0,0 +1,115 @@
+use crate::pool::DB;
+use datafusion::datasource::function::TableFunctionImpl;
+use datafusion::logical_expr::{AggregateUDF, ScalarUDF};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 注册标量函数，之后所有查询都可以使用；同名函数会被替换
+    ///
+    /// 缓存的计划可能引用了被替换的函数，注册后清空计划缓存
+    pub fn register_udf(&self, udf: ScalarUDF) {
+        self.ctx.register_udf(udf);
+        self.clear_plan_cache();
+    }
+
+    /// 注册聚合函数
+    pub fn register_udaf(&self, udaf: AggregateUDF) {
+        self.ctx.register_udaf(udaf);
+        self.clear_plan_cache();
+    }
+
+    /// 注册表函数，在 FROM 中使用，例如 SELECT * FROM name(...)
+    pub fn register_udtf(&self, name: &str, udtf: Arc<dyn TableFunctionImpl>) {
+        self.ctx.register_udtf(name, udtf);
+        self.clear_plan_cache();
+    }
+
+    pub fn deregister_udf(&self, name: &str) {
+        self.ctx.deregister_udf(name);
+        self.clear_plan_cache();
+    }
+
+    pub fn deregister_udaf(&self, name: &str) {
+        self.ctx.deregister_udaf(name);
+        self.clear_plan_cache();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use datafusion::arrow::array::{AsArray, Int64Array};
+    use datafusion::arrow::datatypes::{DataType, Int64Type};
+    use datafusion::logical_expr::{ColumnarValue, ScalarUDFImpl, Signature, Volatility};
+    use std::any::Any;
+
+    // bucket(x, width)：x 向下取整到 width 的倍数
+    #[derive(Debug)]
+    struct Bucket {
+        signature: Signature,
+    }
+
+    impl ScalarUDFImpl for Bucket {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn name(&self) -> &str {
+            "bucket"
+        }
+
+        fn signature(&self) -> &Signature {
+            &self.signature
+        }
+
+        fn return_type(&self, _arg_types: &[DataType]) -> datafusion::common::Result<DataType> {
+            Ok(DataType::Int64)
+        }
+
+        fn invoke_batch(
+            &self,
+            args: &[ColumnarValue],
+            number_rows: usize,
+        ) -> datafusion::common::Result<ColumnarValue> {
+            let values = args[0].clone().into_array(number_rows)?;
+            let widths = args[1].clone().into_array(number_rows)?;
+            let result: Int64Array = values
+                .as_primitive::<Int64Type>()
+                .iter()
+                .zip(widths.as_primitive::<Int64Type>().iter())
+                .map(|(v, w)| Some(v?.div_euclid(w?) * w?))
+                .collect();
+            Ok(ColumnarValue::Array(Arc::new(result)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_udf() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (v BIGINT)").await?;
+        db.execute("INSERT INTO t VALUES (3), (12), (17)").await?;
+        db.register_udf(ScalarUDF::from(Bucket {
+            signature: Signature::exact(
+                vec![DataType::Int64, DataType::Int64],
+                Volatility::Immutable,
+            ),
+        }));
+
+        let batches = db
+            .query_to_batches("SELECT bucket(v, 10) FROM t ORDER BY v")
+            .await?;
+        assert_eq!(
+            batches[0].column(0).as_primitive::<Int64Type>().values(),
+            &[0, 10, 10]
+        );
+
+        db.deregister_udf("bucket");
+        assert!(db
+            .query_to_batches("SELECT bucket(v, 10) FROM t")
+            .await
+            .is_err());
+        Ok(())
+    }
+}
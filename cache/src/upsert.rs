@@ -0,0 +1,220 @@
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::Result;
+use datafusion::arrow::array::{Array, UInt32Array};
+use datafusion::arrow::compute::take_record_batch;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::row::{RowConverter, SortField};
+use datafusion::common::JoinType;
+use datafusion::physical_plan::collect_partitioned;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UpsertReport {
+    // 被新数据替换掉的旧行
+    pub replaced: usize,
+    // 写入的新行（包括替换的和新增的）
+    pub written: usize,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 按主键写入：表中和 batch 主键相同的行被替换，其它行追加
+    ///
+    /// batch 的列需要和表一致；batch 中主键相同的多行只保留最后一行，主键为 NULL 的行不会匹配任何行。
+    /// 新表构建完成后一次性替换，查询看到的要么是写入前的数据要么是写入后的数据；
+    /// upsert 持有表的写锁，同时进行的写入会等它完成后再执行，不会丢失
+    pub async fn upsert(
+        &self,
+        table: &str,
+        key_columns: &[&str],
+        batch: RecordBatch,
+    ) -> Result<UpsertReport> {
+        if key_columns.is_empty() {
+            return Err(anyhow::anyhow!("Upsert requires at least one key column"));
+        }
+        let _guard = self.lock_table(table).await;
+
+        let table_ref = self.table_ref(table);
+        let provider = self.ctx.table_provider(table_ref.clone()).await?;
+        if !is_memory_table(provider.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Upsert is only supported on in-memory tables, {} is not",
+                table
+            ));
+        }
+        let schema = provider.schema();
+        if schema.fields() != batch.schema().fields() {
+            return Err(anyhow::anyhow!(
+                "Upsert batch schema does not match table {}",
+                table
+            ));
+        }
+        for key in key_columns {
+            schema.field_with_name(key)?;
+        }
+        let batch = dedup_by_key(batch, key_columns)?;
+
+        // 两边都是未命名表，起别名避免主键列名冲突
+        let existing = self.ctx.read_table(provider.clone())?.alias("existing")?;
+        let existing_rows = existing.clone().count().await?;
+        let updates = self
+            .ctx
+            .read_batch(batch.clone())?
+            .select_columns(key_columns)?
+            .alias("updates")?;
+        let kept = existing.join(updates, JoinType::LeftAnti, key_columns, key_columns, None)?;
+        let (state, plan) = kept.into_parts();
+        let plan = state.create_physical_plan(&plan).await?;
+        let mut partitions = collect_partitioned(plan, self.ctx.task_ctx()).await?;
+        let kept_rows: usize = partitions
+            .iter()
+            .flatten()
+            .map(|batch| batch.num_rows())
+            .sum();
+
+        let written = batch.num_rows();
+        match partitions.first_mut() {
+            Some(first) => first.push(batch),
+            None => partitions.push(vec![batch]),
+        }
+        self.rewrite_table(table_ref.table(), schema, partitions, Some(&provider))?;
+        Ok(UpsertReport {
+            replaced: existing_rows - kept_rows,
+            written,
+        })
+    }
+}
+
+// batch 中主键相同的行只保留最后一行，主键有 NULL 的行都保留
+fn dedup_by_key(batch: RecordBatch, key_columns: &[&str]) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    let columns = key_columns
+        .iter()
+        .map(|key| Ok(batch.column(schema.index_of(key)?).clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let converter = RowConverter::new(
+        columns
+            .iter()
+            .map(|c| SortField::new(c.data_type().clone()))
+            .collect(),
+    )?;
+    let rows = converter.convert_columns(&columns)?;
+    let mut seen = HashSet::new();
+    let mut keep = Vec::new();
+    for i in (0..batch.num_rows()).rev() {
+        if columns.iter().any(|c| c.is_null(i)) || seen.insert(rows.row(i)) {
+            keep.push(i as u32);
+        }
+    }
+    if keep.len() == batch.num_rows() {
+        return Ok(batch);
+    }
+    keep.reverse();
+    Ok(take_record_batch(&batch, &UInt32Array::from(keep))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use std::sync::Arc;
+
+    fn prices(rows: &[(&str, &str, i64)]) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("market", DataType::Utf8, true),
+            Field::new("symbol", DataType::Utf8, true),
+            Field::new("price", DataType::Int64, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.0))),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.1))),
+                Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.2))),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upsert() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE prices (market VARCHAR, symbol VARCHAR, price BIGINT)")
+            .await?;
+        let keys = ["market", "symbol"];
+
+        let report = db
+            .upsert(
+                "prices",
+                &keys,
+                prices(&[("us", "AAPL", 100), ("us", "MSFT", 200), ("hk", "AAPL", 90)]),
+            )
+            .await?;
+        assert_eq!(
+            report,
+            UpsertReport {
+                replaced: 0,
+                written: 3
+            }
+        );
+
+        let report = db
+            .upsert(
+                "prices",
+                &keys,
+                prices(&[("us", "AAPL", 101), ("us", "TSLA", 300)]),
+            )
+            .await?;
+        assert_eq!(
+            report,
+            UpsertReport {
+                replaced: 1,
+                written: 2
+            }
+        );
+
+        let batches = db
+            .query_to_batches("SELECT price FROM prices ORDER BY market, symbol")
+            .await?;
+        let values = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(values.values(), &[90, 101, 200, 300]);
+
+        // batch 中重复的主键只保留最后一行
+        let report = db
+            .upsert(
+                "prices",
+                &keys,
+                prices(&[("us", "TSLA", 301), ("us", "TSLA", 302)]),
+            )
+            .await?;
+        assert_eq!(
+            report,
+            UpsertReport {
+                replaced: 1,
+                written: 1
+            }
+        );
+        let batches = db
+            .query_to_batches("SELECT price FROM prices WHERE symbol = 'TSLA'")
+            .await?;
+        let values = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(values.values(), &[302]);
+
+        assert!(db
+            .upsert("prices", &["missing"], prices(&[]))
+            .await
+            .is_err());
+        assert!(db.upsert("prices", &[], prices(&[])).await.is_err());
+        Ok(())
+    }
+}
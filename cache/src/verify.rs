@@ -0,0 +1,240 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::prelude::{CsvReadOptions, DataFrame, ParquetReadOptions};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// 导出后重新读取校验的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportVerification {
+    // 实际写入的路径（模板变量已替换）
+    pub path: String,
+    pub location: String,
+    pub expected_rows: usize,
+    pub actual_rows: usize,
+    pub expected_hash: String,
+    pub actual_hash: String,
+    // 不一致的地方，为空表示校验通过
+    pub mismatches: Vec<String>,
+}
+
+impl ExportVerification {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 导出到存储后重新读取写入的对象，比较行数和内容哈希
+    ///
+    /// 用于发现不稳定的 endpoint 造成的静默截断。df 只执行一次，写入和校验用的是同一份数据；
+    /// 不一致时不会返回错误，调用方通过 ExportVerification::is_ok 判断
+    pub async fn export_to_storage_verified(
+        &self,
+        df: DataFrame,
+        storage_name: &str,
+        path: &str,
+        format: &str,
+    ) -> Result<ExportVerification> {
+        let schema: SchemaRef = df.schema().inner().clone();
+        let batches = df.collect().await?;
+        let expected_rows = batches.iter().map(|b| b.num_rows()).sum();
+        let expected_hash = content_hash(&schema, &batches)?;
+
+        let source = self.ctx.read_batches(batches)?;
+        let path = self
+            .export_to_storage(source, storage_name, path, format)
+            .await?;
+        let location = self.storage_location(storage_name, &path)?;
+
+        let written = match format.to_lowercase().as_str() {
+            "csv" => {
+                self.ctx
+                    .read_csv(&location, CsvReadOptions::new().schema(&schema))
+                    .await?
+            }
+            "parquet" => {
+                self.ctx
+                    .read_parquet(&location, ParquetReadOptions::default())
+                    .await?
+            }
+            _ => return Err(anyhow::anyhow!("Unsupported format: {}", format)),
+        };
+        let actual = written.collect().await?;
+        let actual_rows = actual.iter().map(|b| b.num_rows()).sum();
+
+        let mut mismatches = Vec::new();
+        let actual_hash = match content_hash(&schema, &actual) {
+            Ok(hash) => hash,
+            Err(e) => {
+                mismatches.push(format!("cannot read back as source schema: {}", e));
+                String::new()
+            }
+        };
+        if actual_rows != expected_rows {
+            mismatches.push(format!(
+                "row count {} does not match source {}",
+                actual_rows, expected_rows
+            ));
+        }
+        if mismatches.is_empty() && actual_hash != expected_hash {
+            mismatches.push(format!(
+                "content hash {} does not match source {}",
+                actual_hash, expected_hash
+            ));
+        }
+        Ok(ExportVerification {
+            path,
+            location,
+            expected_rows,
+            actual_rows,
+            expected_hash,
+            actual_hash,
+            mismatches,
+        })
+    }
+}
+
+// FNV-1a，和 etag、workload::fingerprint 一样，结果不依赖进程和 Rust 版本
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// 按行计算内容哈希，和行的顺序、分批方式无关
+///
+/// 列按位置转换成 schema 中的类型后格式化成文本，CSV 这类不保存类型的格式读回来也能比较；
+/// NULL 和空字符串的哈希相同
+pub fn content_hash(schema: &SchemaRef, batches: &[RecordBatch]) -> Result<String> {
+    let options = FormatOptions::default();
+    let mut total: u64 = 0;
+    for batch in batches {
+        if batch.num_columns() != schema.fields().len() {
+            return Err(anyhow::anyhow!(
+                "expected {} columns, got {}",
+                schema.fields().len(),
+                batch.num_columns()
+            ));
+        }
+        let columns = batch
+            .columns()
+            .iter()
+            .zip(schema.fields())
+            .map(|(column, field)| cast(column, field.data_type()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let formatters = columns
+            .iter()
+            .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let mut row = String::new();
+        for index in 0..batch.num_rows() {
+            row.clear();
+            for formatter in &formatters {
+                row.push_str(&formatter.value(index).to_string());
+                row.push('\u{1f}');
+            }
+            // 相加而不是串联，行的顺序不影响结果
+            total = total.wrapping_add(fnv1a(row.as_bytes()));
+        }
+    }
+    Ok(format!("{:016x}", total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StorageConfig;
+    use crate::pool::StorageEntry;
+    use object_store::aws::AwsCredential;
+    use object_store::local::LocalFileSystem;
+    use object_store::StaticCredentialProvider;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn register_local_storage(db: &DB<()>) -> Result<()> {
+        let config = StorageConfig {
+            access_key: String::new(),
+            access_secret: String::new(),
+            endpoint: None,
+            region: String::new(),
+            bucket: String::new(),
+            schema: "file".to_string(),
+            session_token: None,
+            token_provider: None,
+            create_bucket_if_missing: false,
+            prefix: None,
+            cost_per_gb_read: None,
+            cost_per_gb_written: None,
+            rate_limit: None,
+        };
+        let credentials = Arc::new(StaticCredentialProvider::new(AwsCredential {
+            key_id: String::new(),
+            secret_key: String::new(),
+            token: None,
+        }));
+        db.registered_storages.write().unwrap().insert(
+            "local".to_string(),
+            StorageEntry {
+                store: Arc::new(LocalFileSystem::new()),
+                credentials,
+                config,
+                counters: Default::default(),
+            },
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_verified() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        register_local_storage(&db)?;
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR, amount DECIMAL(10, 2))")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a', 100.50), (2, NULL, 200.75), (3, 'c', 3)")
+            .await?;
+
+        for format in ["csv", "parquet"] {
+            let path = format!("{}/out.{}", dir.path().display(), format);
+            let df = db.query("SELECT * FROM t ORDER BY id DESC").await?;
+            let report = db
+                .export_to_storage_verified(df, "local", &path, format)
+                .await?;
+            assert!(report.is_ok(), "{:?}", report.mismatches);
+            assert_eq!(report.actual_rows, 3);
+            assert_eq!(report.actual_hash, report.expected_hash);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_content_hash() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        let batches = db
+            .query_to_batches("SELECT * FROM (VALUES (1, 'a'), (2, 'b')) AS t(id, name)")
+            .await?;
+        let schema = batches[0].schema();
+        let reversed = db
+            .query_to_batches("SELECT * FROM (VALUES (2, 'b'), (1, 'a')) AS t(id, name)")
+            .await?;
+        let truncated = db
+            .query_to_batches("SELECT * FROM (VALUES (1, 'a')) AS t(id, name)")
+            .await?;
+        assert_eq!(
+            content_hash(&schema, &batches)?,
+            content_hash(&schema, &reversed)?
+        );
+        assert_ne!(
+            content_hash(&schema, &batches)?,
+            content_hash(&schema, &truncated)?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1011 @@
+use crate::compression::{Artifact, CompressionCodec, CompressionRegistry};
+use crate::integrity::{crc32c, crc32c_append, IntegrityError};
+use crate::pool::DB;
+use crate::swap::is_memory_table;
+use anyhow::{Context, Result};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::UInt64Array;
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::TableReference;
+use datafusion::logical_expr::dml::InsertOp;
+use datafusion::logical_expr::{
+    CreateMemoryTable, DdlStatement, DmlStatement, LogicalPlan, LogicalPlanBuilder, WriteOp,
+};
+use datafusion::physical_plan::collect;
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::prelude::DataFrame;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) const SEGMENT_EXTENSION: &str = "wal";
+// 隔离的损坏 segment 加上这个后缀，不再参与读取
+const CORRUPT_SUFFIX: &str = "corrupt";
+const DEFAULT_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+// 记录头：类型(1) + payload 长度(4) + CRC32C(4)，CRC32C 覆盖类型、长度和 payload
+const HEADER_LEN: usize = 9;
+// payload 开头是写入时间，UTC 微秒，按时间点恢复时用来选择重放到哪条记录
+const TIME_LEN: usize = 8;
+const KIND_DDL: u8 = 1;
+const KIND_INSERT: u8 = 2;
+const KIND_REPLACE: u8 = 3;
+const KIND_META: u8 = 4;
+// 类型的最高位表示 payload 被压缩过，写入时间之后为 codec 名字的长度(1) + 名字 + 压缩后的数据
+const KIND_COMPRESSED: u8 = 0x80;
+
+/// 什么时候把 WAL fsync 到磁盘
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalSync {
+    // 每次写入都 fsync，进程或机器崩溃都不会丢失已经确认的写入
+    #[default]
+    Always,
+    // 距离上次 fsync 超过这个时间才 fsync，机器崩溃时可能丢失这段时间内的写入
+    Interval(Duration),
+    // 只写入操作系统缓存，只能防止进程崩溃
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    pub dir: PathBuf,
+    // 当前 segment 超过这个大小后切换到新的 segment
+    pub segment_bytes: u64,
+    pub sync: WalSync,
+    // 新写入的记录使用的压缩算法，None 时 enable_wal 使用 DB::set_compression 选择的算法
+    pub compression: Option<Arc<dyn CompressionCodec>>,
+    // 读取时按名字查找 codec，需要包含写入时用过的自定义 codec
+    pub codecs: CompressionRegistry,
+}
+
+impl WalConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+            sync: WalSync::default(),
+            compression: None,
+            codecs: CompressionRegistry::default(),
+        }
+    }
+
+    pub fn with_compression(mut self, codec: Arc<dyn CompressionCodec>) -> Self {
+        self.codecs.register(codec.clone());
+        self.compression = Some(codec);
+        self
+    }
+
+    pub fn with_sync(mut self, sync: WalSync) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    pub fn with_segment_bytes(mut self, segment_bytes: u64) -> Self {
+        self.segment_bytes = segment_bytes;
+        self
+    }
+}
+
+/// WAL 中的一条记录
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalRecord {
+    // CREATE/DROP 等 DDL，重放时重新执行 SQL
+    Ddl {
+        sql: String,
+    },
+    // 写入的数据，重放时直接追加，不依赖写入时的数据源
+    Insert {
+        table: String,
+        batches: Vec<RecordBatch>,
+    },
+    // upsert、truncate、TTL 清理等整表重写后的完整内容，重放时替换整个表
+    Replace {
+        table: String,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    },
+    // 表修改后的全部自定义信息（set_table_meta 等），空表示清除
+    Meta {
+        table: String,
+        meta: BTreeMap<String, String>,
+    },
+}
+
+/// WAL 中的一条记录和它的写入时间
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalEntry {
+    pub written_at: DateTime<Utc>,
+    pub record: WalRecord,
+}
+
+/// 按顺序编号的 segment 文件组成的 WAL，文件名是 {序号}.wal
+///
+/// 每条记录带长度和校验和，崩溃时最后一个 segment 末尾写了一半的记录会被丢弃；
+/// 校验失败的记录，以及其他 segment 中不完整的记录都返回 IntegrityError
+#[derive(Debug)]
+pub struct Wal {
+    config: WalConfig,
+    writer: Mutex<SegmentWriter>,
+}
+
+#[derive(Debug)]
+struct SegmentWriter {
+    file: File,
+    seq: u64,
+    written: u64,
+    last_sync: Instant,
+}
+
+impl SegmentWriter {
+    // 关闭当前 segment，开始写下一个
+    fn roll(&mut self, dir: &Path) -> Result<()> {
+        self.file.sync_all()?;
+        self.seq += 1;
+        self.file = create_segment(dir, self.seq)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Wal {
+    /// 打开目录下的 WAL，新的写入从一个新的 segment 开始
+    ///
+    /// 上次最后写入的 segment 末尾如果有崩溃时写了一半的记录，先截掉，
+    /// 之后它不再是最后一个 segment，其中不完整的记录会被当成损坏
+    pub fn open(config: WalConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir)
+            .with_context(|| format!("create {}", config.dir.display()))?;
+        let segments = segment_files(&config.dir)?;
+        if let Some((_, last)) = segments.last() {
+            truncate_torn_tail(last)?;
+        }
+        let seq = segments.last().map_or(0, |(seq, _)| seq + 1);
+        let writer = SegmentWriter {
+            file: create_segment(&config.dir, seq)?,
+            seq,
+            written: 0,
+            last_sync: Instant::now(),
+        };
+        Ok(Self {
+            config,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.config.dir
+    }
+
+    /// 追加一条记录，按 WalSync 决定是否 fsync，返回后记录已经写入
+    pub fn append(&self, record: &WalRecord) -> Result<()> {
+        let frame = encode(record, Utc::now(), self.config.compression.as_deref())?;
+        let mut writer = self.writer.lock().unwrap();
+        if writer.written > 0 && writer.written + frame.len() as u64 > self.config.segment_bytes {
+            writer.roll(&self.config.dir)?;
+        }
+        writer.file.write_all(&frame)?;
+        writer.written += frame.len() as u64;
+
+        let sync = match self.config.sync {
+            WalSync::Always => true,
+            WalSync::Interval(interval) => writer.last_sync.elapsed() >= interval,
+            WalSync::Never => false,
+        };
+        if sync {
+            writer.file.sync_data()?;
+            writer.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// 立即 fsync 当前 segment
+    pub fn sync(&self) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.file.sync_data()?;
+        writer.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// 关闭当前 segment，之后的写入从新的 segment 开始，返回新 segment 的序号
+    pub fn rotate(&self) -> Result<u64> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.roll(&self.config.dir)?;
+        Ok(writer.seq)
+    }
+
+    /// 已经关闭、不会再写入的 segment 和它们的序号，按序号排序
+    pub fn closed_segments(&self) -> Result<Vec<(u64, PathBuf)>> {
+        let writer = self.writer.lock().unwrap();
+        Ok(segment_files(&self.config.dir)?
+            .into_iter()
+            .filter(|(seq, _)| *seq < writer.seq)
+            .collect())
+    }
+
+    /// 所有 segment 文件，按序号排序
+    pub fn segments(&self) -> Result<Vec<PathBuf>> {
+        Ok(segment_files(&self.config.dir)?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    /// 按写入顺序读取所有 segment 中的记录，有记录校验失败时返回 IntegrityError
+    pub fn read_all(&self) -> Result<Vec<WalRecord>> {
+        let (records, corruption) = self.read_valid()?;
+        match corruption {
+            Some(corruption) => Err(corruption.into()),
+            None => Ok(records),
+        }
+    }
+
+    /// 按写入顺序读取到第一条校验失败的记录为止，返回之前的所有记录和损坏的位置
+    pub fn read_valid(&self) -> Result<(Vec<WalRecord>, Option<IntegrityError>)> {
+        let mut records = Vec::new();
+        let segments = self.segments()?;
+        for (i, segment) in segments.iter().enumerate() {
+            // 只有最后一个 segment 可能正在写入，允许末尾有不完整的记录
+            let last = i + 1 == segments.len();
+            let data = fs::read(segment).with_context(|| format!("read {}", segment.display()))?;
+            let (valid, corruption) = parse_entries(
+                &segment.display().to_string(),
+                &data,
+                &self.config.codecs,
+                last,
+            )?;
+            records.extend(valid.into_iter().map(|entry| entry.record));
+            if corruption.is_some() {
+                return Ok((records, corruption));
+            }
+        }
+        Ok((records, None))
+    }
+
+    /// 把损坏的 segment 截断到最后一条完整的记录，之后的 segment 不再参与读取
+    ///
+    /// 原始文件改名为 {序号}.wal.corrupt 保留下来方便排查，当前正在写入的 segment 不受影响
+    pub fn quarantine(&self, corruption: &IntegrityError) -> Result<()> {
+        let Some(offset) = corruption.offset else {
+            return Err(anyhow::anyhow!(
+                "{} has no offset to truncate at",
+                corruption.segment
+            ));
+        };
+        let writer = self.writer.lock().unwrap();
+        let mut found = false;
+        for (seq, path) in segment_files(&self.config.dir)? {
+            if seq == writer.seq {
+                continue;
+            }
+            let quarantined =
+                path.with_extension(format!("{}.{}", SEGMENT_EXTENSION, CORRUPT_SUFFIX));
+            if path.display().to_string() == corruption.segment {
+                fs::copy(&path, &quarantined)?;
+                let file = OpenOptions::new().write(true).open(&path)?;
+                file.set_len(offset)?;
+                file.sync_all()?;
+                found = true;
+            } else if found {
+                fs::rename(&path, &quarantined)?;
+            }
+        }
+        if !found {
+            return Err(anyhow::anyhow!(
+                "Segment {} is not a closed segment of the WAL",
+                corruption.segment
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 读取一个 segment，末尾不完整的记录（崩溃时写了一半）会被丢弃，有记录校验失败时返回 IntegrityError
+///
+/// 只能解压内置 codec 压缩的记录，使用了自定义 codec 时用 read_segment_with
+pub fn read_segment(path: &Path) -> Result<Vec<WalRecord>> {
+    read_segment_with(path, &CompressionRegistry::default())
+}
+
+pub fn read_segment_with(path: &Path, codecs: &CompressionRegistry) -> Result<Vec<WalRecord>> {
+    let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    let entries = read_entries(&path.display().to_string(), &data, codecs, true)?;
+    Ok(entries.into_iter().map(|entry| entry.record).collect())
+}
+
+/// 解析一个已经关闭的 segment 的内容（比如归档到存储中的 segment），带上每条记录的写入时间
+///
+/// 不完整或者校验失败的记录都返回 IntegrityError，name 是错误中使用的 segment 名字
+pub fn read_segment_entries(
+    name: &str,
+    data: &[u8],
+    codecs: &CompressionRegistry,
+) -> Result<Vec<WalEntry>> {
+    read_entries(name, data, codecs, false)
+}
+
+fn read_entries(
+    name: &str,
+    data: &[u8],
+    codecs: &CompressionRegistry,
+    torn_tail: bool,
+) -> Result<Vec<WalEntry>> {
+    let (entries, corruption) = parse_entries(name, data, codecs, torn_tail)?;
+    match corruption {
+        Some(corruption) => Err(corruption.into()),
+        None => Ok(entries),
+    }
+}
+
+// 读取到第一条校验失败的记录为止；校验通过但无法解码（比如未知的 codec）不算损坏，直接返回错误
+//
+// torn_tail 为 true 时末尾不完整的记录当成崩溃时写了一半，直接丢弃，否则也算损坏
+fn parse_entries(
+    name: &str,
+    data: &[u8],
+    codecs: &CompressionRegistry,
+    torn_tail: bool,
+) -> Result<(Vec<WalEntry>, Option<IntegrityError>)> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let Some(len) = frame_len(&data[offset..]) else {
+            if torn_tail {
+                break;
+            }
+            let corruption =
+                IntegrityError::new(name, format!("truncated record {}", records.len()))
+                    .at(offset as u64);
+            return Ok((records, Some(corruption)));
+        };
+        let frame = &data[offset..offset + HEADER_LEN + len];
+        let checksum = u32::from_le_bytes(frame[5..HEADER_LEN].try_into()?);
+        if frame_checksum(&frame[..5], &frame[HEADER_LEN..]) != checksum {
+            let corruption = IntegrityError::new(
+                name,
+                format!("checksum mismatch in record {}", records.len()),
+            )
+            .at(offset as u64);
+            return Ok((records, Some(corruption)));
+        }
+        records.push(decode_entry(frame[0], &frame[HEADER_LEN..], codecs)?);
+        offset += frame.len();
+    }
+    Ok((records, None))
+}
+
+// data 开头的记录完整时返回 payload 的长度
+fn frame_len(data: &[u8]) -> Option<usize> {
+    let header = data.get(..HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[1..5].try_into().ok()?) as usize;
+    (data.len() - HEADER_LEN >= len).then_some(len)
+}
+
+// 截掉 segment 末尾不完整的记录，不校验已有的记录
+fn truncate_torn_tail(path: &Path) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    let mut offset = 0;
+    while let Some(len) = frame_len(&data[offset..]) {
+        offset += HEADER_LEN + len;
+    }
+    if offset < data.len() {
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(offset as u64)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+// header 是类型和长度，和 payload 一起计算 CRC32C
+fn frame_checksum(header: &[u8], payload: &[u8]) -> u32 {
+    crc32c_append(crc32c(header), payload)
+}
+
+fn encode(
+    record: &WalRecord,
+    written_at: DateTime<Utc>,
+    codec: Option<&dyn CompressionCodec>,
+) -> Result<Vec<u8>> {
+    let (mut kind, mut payload) = match record {
+        WalRecord::Ddl { sql } => (KIND_DDL, sql.as_bytes().to_vec()),
+        WalRecord::Insert { table, batches } => {
+            let Some(first) = batches.first() else {
+                return Err(anyhow::anyhow!("WAL insert into {} has no batches", table));
+            };
+            (
+                KIND_INSERT,
+                encode_batches(table, &first.schema(), batches)?,
+            )
+        }
+        // 没有 batch 时 IPC 流中也带着 schema，重放可以得到同样 schema 的空表
+        WalRecord::Replace {
+            table,
+            schema,
+            batches,
+        } => (KIND_REPLACE, encode_batches(table, schema, batches)?),
+        WalRecord::Meta { table, meta } => (KIND_META, serde_json::to_vec(&(table, meta))?),
+    };
+    if let Some(codec) = codec.filter(|codec| codec.name() != "none") {
+        let name = codec.name().as_bytes();
+        let name_len = u8::try_from(name.len())
+            .map_err(|_| anyhow::anyhow!("Codec name {} is too long", codec.name()))?;
+        let mut compressed = vec![name_len];
+        compressed.extend_from_slice(name);
+        compressed.extend_from_slice(&codec.compress(&payload)?);
+        kind |= KIND_COMPRESSED;
+        payload = compressed;
+    }
+    payload.splice(0..0, written_at.timestamp_micros().to_le_bytes());
+    let len = u32::try_from(payload.len())
+        .map_err(|_| anyhow::anyhow!("WAL record of {} bytes is too large", payload.len()))?;
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(&frame_checksum(&frame, &payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+fn decode_entry(kind: u8, payload: &[u8], codecs: &CompressionRegistry) -> Result<WalEntry> {
+    let micros = i64::from_le_bytes(
+        payload
+            .get(..TIME_LEN)
+            .context("short record")?
+            .try_into()?,
+    );
+    Ok(WalEntry {
+        written_at: DateTime::from_timestamp_micros(micros).context("invalid record time")?,
+        record: decode(kind, &payload[TIME_LEN..], codecs)?,
+    })
+}
+
+fn decode(kind: u8, payload: &[u8], codecs: &CompressionRegistry) -> Result<WalRecord> {
+    if kind & KIND_COMPRESSED != 0 {
+        let name_len = *payload.first().context("short record")? as usize;
+        let name = std::str::from_utf8(payload.get(1..1 + name_len).context("short record")?)?;
+        let codec = codecs
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("WAL record compressed with unknown codec {}", name))?;
+        let payload = codec.decompress(&payload[1 + name_len..])?;
+        return decode(kind & !KIND_COMPRESSED, &payload, codecs);
+    }
+    match kind {
+        KIND_DDL => Ok(WalRecord::Ddl {
+            sql: String::from_utf8(payload.to_vec())?,
+        }),
+        KIND_INSERT => {
+            let (table, _, batches) = decode_batches(payload)?;
+            Ok(WalRecord::Insert { table, batches })
+        }
+        KIND_REPLACE => {
+            let (table, schema, batches) = decode_batches(payload)?;
+            Ok(WalRecord::Replace {
+                table,
+                schema,
+                batches,
+            })
+        }
+        KIND_META => {
+            let (table, meta) = serde_json::from_slice(payload)?;
+            Ok(WalRecord::Meta { table, meta })
+        }
+        _ => Err(anyhow::anyhow!("Unknown WAL record kind {}", kind)),
+    }
+}
+
+// 表名的长度(4) + 表名 + Arrow IPC 流
+fn encode_batches(table: &str, schema: &Schema, batches: &[RecordBatch]) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&(table.len() as u32).to_le_bytes());
+    payload.extend_from_slice(table.as_bytes());
+    let mut writer = StreamWriter::try_new(&mut payload, schema)?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.finish()?;
+    drop(writer);
+    Ok(payload)
+}
+
+fn decode_batches(payload: &[u8]) -> Result<(String, SchemaRef, Vec<RecordBatch>)> {
+    let len = u32::from_le_bytes(payload.get(..4).context("short record")?.try_into()?) as usize;
+    let table = std::str::from_utf8(payload.get(4..4 + len).context("short record")?)?;
+    let reader = StreamReader::try_new(&payload[4 + len..], None)?;
+    let schema = reader.schema();
+    let batches = reader.collect::<std::result::Result<Vec<RecordBatch>, _>>()?;
+    Ok((table.to_string(), schema, batches))
+}
+
+fn create_segment(dir: &Path, seq: u64) -> Result<File> {
+    let path = dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION));
+    OpenOptions::new()
+        .create_new(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("create {}", path.display()))
+}
+
+fn segment_files(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+            continue;
+        }
+        let Some(seq) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        segments.push((seq, path));
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 开启 WAL，之后修改表的操作都先写入 WAL 再执行：DDL、INSERT（包括 insert_batch 等 API）、
+    /// upsert、delete_rows、truncate、TTL 清理等整表重写，以及表的自定义信息（set_table_meta、TTL 设置）
+    ///
+    /// 写入 WAL 失败时不会修改表；写入 WAL 之后执行失败的记录，重放时的错误记录在 RecoveryReport::errors 中
+    pub fn enable_wal(&self, mut config: WalConfig) -> Result<Arc<Wal>> {
+        let registry = self.compression_registry();
+        for name in registry.names() {
+            if config.codecs.get(&name).is_none() {
+                config.codecs.register(registry.get(&name).unwrap());
+            }
+        }
+        if config.compression.is_none() {
+            config = config.with_compression(self.compression_codec(Artifact::Wal));
+        }
+        let wal = Arc::new(Wal::open(config)?);
+        *self.wal.write().unwrap() = Some(wal.clone());
+        Ok(wal)
+    }
+
+    pub fn wal(&self) -> Option<Arc<Wal>> {
+        self.wal.read().unwrap().clone()
+    }
+
+    // 执行 DDL 和 INSERT：拿到表的写锁，开启 WAL 时先写入 WAL，再修改表并记录表的版本
+    //
+    // INSERT 和 CREATE TABLE ... AS SELECT 先计算出要写入的数据，写入 WAL 的是数据而不是 SQL，
+    // 重放时不依赖数据源
+    pub(crate) async fn execute_logged(&self, sql: &str, plan: LogicalPlan) -> Result<DataFrame> {
+        match plan {
+            LogicalPlan::Ddl(ref ddl) => {
+                let target = ddl_target(ddl);
+                let _guard = match &target {
+                    Some(target) => Some(self.lock_table_ref(target).await),
+                    None => None,
+                };
+                let key = target.and_then(|t| self.version_key(&t));
+                if let (DdlStatement::CreateMemoryTable(cmd), Some(key)) = (ddl, &key) {
+                    if !matches!(cmd.input.as_ref(), LogicalPlan::EmptyRelation(_)) {
+                        return self.create_table_as(key, cmd).await;
+                    }
+                }
+                let before = match &key {
+                    Some(key) => self
+                        .ctx
+                        .table_provider(TableReference::bare(key.as_str()))
+                        .await
+                        .ok(),
+                    None => None,
+                };
+                if let Some(wal) = self.wal() {
+                    self.check_ddl(ddl)?;
+                    wal.append(&WalRecord::Ddl {
+                        sql: sql.to_string(),
+                    })?;
+                }
+                let df = self.ctx.execute_logical_plan(plan).await?;
+                if let Some(key) = key {
+                    let after = self
+                        .ctx
+                        .table_provider(TableReference::bare(key.as_str()))
+                        .await
+                        .ok();
+                    let changed = match (&before, &after) {
+                        (Some(before), Some(after)) => !Arc::ptr_eq(before, after),
+                        (before, after) => before.is_some() != after.is_some(),
+                    };
+                    if changed {
+                        self.record_version(&key, after);
+                    }
+                }
+                Ok(df)
+            }
+            LogicalPlan::Dml(DmlStatement {
+                ref table_name,
+                op: WriteOp::Insert(ref op @ (InsertOp::Append | InsertOp::Overwrite)),
+                ref input,
+                ..
+            }) => {
+                let table = table_name.to_string();
+                let batches = self
+                    .ctx
+                    .execute_logical_plan(input.as_ref().clone())
+                    .await?
+                    .collect()
+                    .await?;
+                if *op == InsertOp::Append && batches.iter().all(|b| b.num_rows() == 0) {
+                    return Ok(self.ctx.execute_logical_plan(plan).await?);
+                }
+                let schema = self.ctx.table_provider(table_name.clone()).await?.schema();
+                let batches = batches
+                    .into_iter()
+                    .map(|batch| self.coerce_batch(&table, &schema, batch))
+                    .collect::<Result<Vec<_>>>()?;
+                let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+                match op {
+                    InsertOp::Append => self.append_batches(&table, batches).await?,
+                    _ => self.overwrite_table(table_name, batches).await?,
+                }
+                let count = RecordBatch::try_new(
+                    Arc::new(Schema::new(vec![Field::new(
+                        "count",
+                        DataType::UInt64,
+                        false,
+                    )])),
+                    vec![Arc::new(UInt64Array::from(vec![rows as u64]))],
+                )?;
+                Ok(self.ctx.read_batch(count)?)
+            }
+            _ => Ok(self.ctx.execute_logical_plan(plan).await?),
+        }
+    }
+
+    // CREATE TABLE ... AS SELECT：调用方持有表的写锁。WAL 中记录一条 Replace，
+    // 带着表的 schema 和计算出的数据，重放时直接注册成内存表
+    async fn create_table_as(&self, key: &str, cmd: &CreateMemoryTable) -> Result<DataFrame> {
+        let empty = DataFrame::new(self.ctx.state(), LogicalPlanBuilder::empty(false).build()?);
+        if self.ctx.table_exist(cmd.name.clone())? {
+            if cmd.if_not_exists {
+                return Ok(empty);
+            }
+            if !cmd.or_replace {
+                return Err(anyhow::anyhow!("Table {} already exists", cmd.name));
+            }
+        }
+        let schema: SchemaRef = Arc::new(cmd.input.schema().as_arrow().clone());
+        let batches = self
+            .ctx
+            .execute_logical_plan(cmd.input.as_ref().clone())
+            .await?
+            .collect()
+            .await?
+            .into_iter()
+            .map(|batch| RecordBatch::try_new(schema.clone(), batch.columns().to_vec()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        self.rewrite_table(key, schema, vec![batches], None)?;
+        Ok(empty)
+    }
+
+    // INSERT OVERWRITE：持有表的写锁，先写入 WAL 再替换表的内容
+    async fn overwrite_table(
+        &self,
+        table_name: &TableReference,
+        batches: Vec<RecordBatch>,
+    ) -> Result<()> {
+        let _guard = self.lock_table_ref(table_name).await;
+        let table = table_name.to_string();
+        let provider = self.ctx.table_provider(table_name.clone()).await?;
+        let schema = provider.schema();
+        let batches = self
+            .assign_row_ids(&table, &schema, batches)
+            .await?
+            .into_iter()
+            .map(|batch| RecordBatch::try_new(schema.clone(), batch.columns().to_vec()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let key = self.version_key(table_name);
+        if let Some(key) = &key {
+            if is_memory_table(provider.as_ref()) {
+                return self.rewrite_table(key, schema, vec![batches], Some(&provider));
+            }
+        }
+        // 索引表等自己处理覆盖写入，WAL 中记录覆盖后的完整内容
+        if let Some(wal) = self.wal() {
+            wal.append(&WalRecord::Replace {
+                table: table.clone(),
+                schema: schema.clone(),
+                batches: batches.clone(),
+            })?;
+        }
+        let input = Arc::new(MemoryExec::try_new(&[batches], schema, None)?);
+        let exec = provider
+            .insert_into(&self.ctx.state(), input, InsertOp::Overwrite)
+            .await?;
+        collect(exec, self.ctx.task_ctx()).await?;
+        if let Some(key) = key {
+            self.record_version(&key, Some(provider));
+        }
+        Ok(())
+    }
+
+    // 明显会失败的 DDL 不写入 WAL，重放时不会报错
+    fn check_ddl(&self, ddl: &DdlStatement) -> Result<()> {
+        match ddl {
+            DdlStatement::CreateMemoryTable(cmd)
+                if !cmd.if_not_exists
+                    && !cmd.or_replace
+                    && self.ctx.table_exist(cmd.name.clone())? =>
+            {
+                Err(anyhow::anyhow!("Table {} already exists", cmd.name))
+            }
+            DdlStatement::CreateExternalTable(cmd)
+                if !cmd.if_not_exists && self.ctx.table_exist(cmd.name.clone())? =>
+            {
+                Err(anyhow::anyhow!("Table {} already exists", cmd.name))
+            }
+            DdlStatement::DropTable(cmd)
+                if !cmd.if_exists && !self.ctx.table_exist(cmd.name.clone())? =>
+            {
+                Err(anyhow::anyhow!("Table {} doesn't exist", cmd.name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // append_batches 在修改表之前写入 WAL
+    pub(crate) fn log_insert(&self, table: &str, batches: &[RecordBatch]) -> Result<()> {
+        let Some(wal) = self.wal() else {
+            return Ok(());
+        };
+        if batches.iter().all(|b| b.num_rows() == 0) {
+            return Ok(());
+        }
+        wal.append(&WalRecord::Insert {
+            table: table.to_string(),
+            batches: batches.to_vec(),
+        })
+    }
+}
+
+// DDL 创建、替换或者删除的表
+fn ddl_target(ddl: &DdlStatement) -> Option<TableReference> {
+    match ddl {
+        DdlStatement::CreateMemoryTable(cmd) => Some(cmd.name.clone()),
+        DdlStatement::CreateExternalTable(cmd) => Some(cmd.name.clone()),
+        DdlStatement::CreateView(cmd) => Some(cmd.name.clone()),
+        DdlStatement::DropTable(cmd) => Some(cmd.name.clone()),
+        DdlStatement::DropView(cmd) => Some(cmd.name.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_wal_logs_writes() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE before_wal (id BIGINT)").await?;
+        let wal = db.enable_wal(WalConfig::new(dir.path()))?;
+
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .await?;
+        db.query_to_batches("SELECT * FROM t").await?;
+        let batch = db.query_to_batches("SELECT 3 AS id, 'c' AS name").await?;
+        db.insert_batches("t", batch).await?;
+        assert_eq!(db.ctx.table("t").await?.count().await?, 3);
+
+        let records = wal.read_all()?;
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0],
+            WalRecord::Ddl {
+                sql: "CREATE TABLE t (id BIGINT, name VARCHAR)".to_string()
+            }
+        );
+        let WalRecord::Insert { table, batches } = &records[1] else {
+            panic!("expected insert, got {:?}", records[1]);
+        };
+        assert_eq!(table, "t");
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+        // 整表重写记录重写后的内容，空表也带着 schema
+        db.truncate_table("t").await?;
+        let records = wal.read_all()?;
+        assert_eq!(records.len(), 4);
+        let WalRecord::Replace {
+            table,
+            schema,
+            batches,
+        } = &records[3]
+        else {
+            panic!("expected replace, got {:?}", records[3]);
+        };
+        assert_eq!(table, "t");
+        assert_eq!(schema.fields().len(), 2);
+        assert!(batches.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ctas_logs_data() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        // 数据源在开启 WAL 之前创建，重放时不存在
+        db.execute("CREATE TABLE src (id BIGINT)").await?;
+        db.execute("INSERT INTO src VALUES (1), (2)").await?;
+        let wal = db.enable_wal(WalConfig::new(dir.path()))?;
+
+        db.execute("CREATE TABLE amounts AS SELECT id * 10 AS amount FROM src")
+            .await?;
+        db.execute("CREATE TABLE IF NOT EXISTS amounts AS SELECT 1 AS amount")
+            .await?;
+        assert!(db
+            .execute("CREATE TABLE amounts AS SELECT 1 AS amount")
+            .await
+            .is_err());
+        let records = wal.read_all()?;
+        assert_eq!(records.len(), 1);
+        let WalRecord::Replace { table, batches, .. } = &records[0] else {
+            panic!("expected replace, got {:?}", records[0]);
+        };
+        assert_eq!(table, "amounts");
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        drop(db);
+
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(WalConfig::new(dir.path()))?;
+        let report = db.recovery().await?;
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        let batches = db
+            .query_to_batches("SELECT sum(amount) FROM amounts")
+            .await?;
+        let sum = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<datafusion::arrow::array::Int64Array>()
+            .unwrap()
+            .value(0);
+        assert_eq!(sum, 30);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_append_is_applied() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        let wal = db.enable_wal(WalConfig::new(dir.path()))?;
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        let batches = db
+            .query_to_batches("SELECT value AS id FROM generate_series(1, 10000)")
+            .await?;
+
+        // 写入到一半被取消：已经写入 WAL 的数据最终都会写到表里
+        for _ in 0..20 {
+            let insert = db.insert_batches("t", batches.clone());
+            let _ = tokio::time::timeout(Duration::from_micros(1), insert).await;
+        }
+        let logged: usize = wal
+            .read_all()?
+            .iter()
+            .map(|record| match record {
+                WalRecord::Insert { batches, .. } => batches.iter().map(|b| b.num_rows()).sum(),
+                _ => 0,
+            })
+            .sum();
+        for _ in 0..100 {
+            if db.ctx.table("t").await?.count().await? == logged {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(db.ctx.table("t").await?.count().await?, logged);
+        Ok(())
+    }
+
+    #[test]
+    fn test_segments_and_torn_tail() -> Result<()> {
+        let dir = tempdir()?;
+        let wal = Wal::open(
+            WalConfig::new(dir.path())
+                .with_segment_bytes(64)
+                .with_sync(WalSync::Never),
+        )?;
+        for i in 0..3 {
+            wal.append(&WalRecord::Ddl {
+                sql: format!("CREATE TABLE t{} (id BIGINT, name VARCHAR)", i),
+            })?;
+        }
+        wal.sync()?;
+        let segments = wal.segments()?;
+        assert_eq!(segments.len(), 3);
+
+        // 模拟崩溃时最后一条记录只写了一半
+        let last = segments.last().unwrap();
+        let data = fs::read(last)?;
+        fs::write(last, &data[..data.len() - 3])?;
+        assert_eq!(wal.read_all()?.len(), 2);
+
+        // 重新打开时截掉写了一半的记录，从新的 segment 开始写
+        drop(wal);
+        let wal = Wal::open(WalConfig::new(dir.path()))?;
+        assert_eq!(wal.segments()?.len(), 4);
+        assert!(fs::read(last)?.is_empty());
+        assert_eq!(wal.read_all()?.len(), 2);
+
+        // 不是最后一个 segment 中的不完整记录是损坏
+        let first = &segments[0];
+        let data = fs::read(first)?;
+        fs::write(first, &data[..data.len() - 3])?;
+        let err = wal.read_all().unwrap_err();
+        let corruption = err.downcast_ref::<IntegrityError>().unwrap();
+        assert_eq!(corruption.segment, first.display().to_string());
+        assert_eq!(corruption.offset, Some(0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compressed_wal() -> Result<()> {
+        let dir = tempdir()?;
+        let db = DB::<()>::new("test_db");
+        db.set_compression(Artifact::Wal, &crate::config::CodecConfig::zstd(5))?;
+        let wal = db.enable_wal(WalConfig::new(dir.path()))?;
+        db.execute("CREATE TABLE t (id BIGINT)").await?;
+        db.execute("INSERT INTO t SELECT value FROM generate_series(1, 1000)")
+            .await?;
+
+        let records = wal.read_all()?;
+        assert_eq!(records.len(), 2);
+        let WalRecord::Insert { batches, .. } = &records[1] else {
+            panic!("expected insert, got {:?}", records[1]);
+        };
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1000);
+        // 内置 codec 压缩的记录不需要额外注册就能读取
+        let segment = &wal.segments()?[0];
+        assert_eq!(read_segment(segment)?, records);
+        assert_eq!(fs::read(segment)?[0], KIND_DDL | KIND_COMPRESSED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_record() -> Result<()> {
+        let dir = tempdir()?;
+        let wal = Wal::open(WalConfig::new(dir.path()).with_sync(WalSync::Never))?;
+        for i in 0..3 {
+            wal.append(&WalRecord::Ddl {
+                sql: format!("CREATE TABLE t{} (id BIGINT)", i),
+            })?;
+        }
+        wal.sync()?;
+
+        // 第二条记录的类型中翻转一位，校验和同样覆盖记录头
+        let segment = wal.segments()?[0].clone();
+        let mut data = fs::read(&segment)?;
+        let second = HEADER_LEN + TIME_LEN + "CREATE TABLE t0 (id BIGINT)".len();
+        data[second] ^= 1;
+        fs::write(&segment, &data)?;
+
+        let err = wal.read_all().unwrap_err();
+        let corruption = err.downcast_ref::<IntegrityError>().unwrap();
+        assert_eq!(corruption.segment, segment.display().to_string());
+        assert_eq!(corruption.offset, Some(second as u64));
+        let (records, _) = wal.read_valid()?;
+        assert_eq!(records.len(), 1);
+
+        // 隔离后只剩完整的记录，原始文件保留
+        drop(wal);
+        let wal = Wal::open(WalConfig::new(dir.path()))?;
+        wal.quarantine(&corruption.clone())?;
+        assert_eq!(wal.read_all()?.len(), 1);
+        assert_eq!(fs::read(segment.with_extension("wal.corrupt"))?, data);
+        Ok(())
+    }
+}
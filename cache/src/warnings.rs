@@ -0,0 +1,143 @@
+use crate::pool::DB;
+use anyhow::Result;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::logical_expr::{Expr, LogicalPlan};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// 不影响执行但可能让结果出乎意料的问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryWarning {
+    // 为了类型匹配自动加上的 CAST，比如字符串和数字比较
+    TypeCoercion { expr: String },
+    // 表配置了行级 TTL，但过期的行还没有被删除，仍然出现在结果里
+    ExpiredRows { table: String, rows: usize },
+}
+
+impl std::fmt::Display for QueryWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryWarning::TypeCoercion { expr } => write!(f, "implicit type coercion: {}", expr),
+            QueryWarning::ExpiredRows { table, rows } => {
+                write!(f, "{} expired rows in {} have not been reaped", rows, table)
+            }
+        }
+    }
+}
+
+/// 查询结果和警告
+#[derive(Debug, Clone)]
+pub struct QueryOutput {
+    pub batches: Vec<RecordBatch>,
+    pub warnings: Vec<QueryWarning>,
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 和 query_to_batches 一样执行查询，同时返回执行过程中发现的警告
+    pub async fn query_with_warnings(&self, sql: &str) -> Result<QueryOutput> {
+        let state = self.ctx.state();
+        let plan = state.create_logical_plan(sql).await?;
+        let analyzed =
+            state
+                .analyzer()
+                .execute_and_check(plan.clone(), state.config_options(), |_, _| {})?;
+
+        // 分析之后多出来的 CAST 都是自动加上的
+        let mut explicit = HashMap::new();
+        for cast in casts(&plan)? {
+            *explicit.entry(cast).or_insert(0) += 1;
+        }
+        let mut warnings = Vec::new();
+        for cast in casts(&analyzed)? {
+            match explicit.get_mut(&cast) {
+                Some(n) if *n > 0 => *n -= 1,
+                _ => warnings.push(QueryWarning::TypeCoercion { expr: cast }),
+            }
+        }
+
+        let mut tables = BTreeSet::new();
+        plan.apply(|node| {
+            if let LogicalPlan::TableScan(scan) = node {
+                tables.insert(scan.table_name.to_string());
+            }
+            Ok(TreeNodeRecursion::Continue)
+        })?;
+        for table in tables {
+            if let Some(rows) = self.count_expired_rows(&table).await? {
+                if rows > 0 {
+                    warnings.push(QueryWarning::ExpiredRows { table, rows });
+                }
+            }
+        }
+
+        let batches = self.query_to_batches(sql).await?;
+        Ok(QueryOutput { batches, warnings })
+    }
+}
+
+// 计划中所有 CAST/TRY_CAST 表达式
+fn casts(plan: &LogicalPlan) -> Result<Vec<String>> {
+    let mut casts = Vec::new();
+    plan.apply(|node| {
+        for expr in node.expressions() {
+            expr.apply(|e| {
+                if matches!(e, Expr::Cast(_) | Expr::TryCast(_)) {
+                    casts.push(e.to_string());
+                }
+                Ok(TreeNodeRecursion::Continue)
+            })?;
+        }
+        Ok(TreeNodeRecursion::Continue)
+    })?;
+    Ok(casts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn test_query_with_warnings() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id INT, code VARCHAR, expire_at BIGINT)")
+            .await?;
+        db.execute(&format!(
+            "INSERT INTO t VALUES (1, '1', {}), (2, '2', NULL)",
+            (Utc::now() - Duration::hours(1)).timestamp_millis()
+        ))
+        .await?;
+
+        let output = db
+            .query_with_warnings("SELECT CAST(id AS BIGINT) FROM t")
+            .await?;
+        assert!(output.warnings.is_empty());
+        assert_eq!(output.batches[0].num_rows(), 2);
+
+        let output = db
+            .query_with_warnings("SELECT * FROM t WHERE id = code")
+            .await?;
+        assert!(matches!(
+            output.warnings.as_slice(),
+            [QueryWarning::TypeCoercion { .. }]
+        ));
+
+        db.set_row_ttl("t", "expire_at").await?;
+        let output = db.query_with_warnings("SELECT id FROM t").await?;
+        assert_eq!(
+            output.warnings,
+            vec![QueryWarning::ExpiredRows {
+                table: "t".to_string(),
+                rows: 1
+            }]
+        );
+        db.reap_expired_rows("t").await?;
+        assert!(db
+            .query_with_warnings("SELECT id FROM t")
+            .await?
+            .warnings
+            .is_empty());
+        Ok(())
+    }
+}
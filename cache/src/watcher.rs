@@ -0,0 +1,391 @@
+use crate::drift::{project_to_schema, DriftPolicy, SchemaDrift, SchemaDriftEvent};
+use crate::ident::quote_literal;
+use crate::location::StorageLocation;
+use crate::migration::INGESTED_OBJECTS_DDL;
+use crate::pool::DB;
+use anyhow::Context;
+use chrono::Utc;
+use datafusion::arrow::array::{Array, StringArray};
+use datafusion::prelude::*;
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::ObjectMeta;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+// 记录已经导入过的对象
+const INGESTED_OBJECTS_TABLE: &str = "__ingested_objects";
+
+/// 监听存储中的某个目录，把新出现的文件自动导入到目标表
+#[derive(Debug, Clone)]
+pub struct WatchSpec {
+    // 监听的名字，同一个目录可以被不同的 watch 导入到不同的表
+    pub name: String,
+    pub storage: String,
+    pub prefix: String,
+    // 目标表，需要提前创建
+    pub table: String,
+    // csv / parquet
+    pub format: String,
+    pub interval: Duration,
+    // 文件 schema 和目标表不一致时的处理方式
+    pub drift_policy: DriftPolicy,
+}
+
+impl DB<()> {
+    /// 执行一次扫描，返回本次导入的对象路径
+    pub async fn poll_directory(&self, spec: &WatchSpec) -> anyhow::Result<Vec<String>> {
+        self.ensure_ingested_objects_table().await?;
+
+        let (store, schema, bucket) = {
+            let storages = self.registered_storages.read().unwrap();
+            let storage = storages.get(&spec.storage).context("get storage")?;
+            (
+                storage.store.clone(),
+                storage.config.schema.clone(),
+                storage.config.bucket.clone(),
+            )
+        };
+
+        let extension = format!(".{}", spec.format.to_lowercase());
+        let prefix = Path::from(spec.prefix.as_str());
+        let mut objects: Vec<ObjectMeta> = store
+            .list(Some(&prefix))
+            .try_filter(|meta| futures::future::ready(meta.location.as_ref().ends_with(&extension)))
+            .try_collect()
+            .await?;
+        // 按修改时间顺序导入，保证先到先入
+        objects.sort_by_key(|meta| meta.last_modified);
+
+        let processed = self.ingested_objects(&spec.name).await?;
+        let target_schema = self.ctx.table_provider(spec.table.as_str()).await?.schema();
+
+        let mut ingested = Vec::new();
+        for meta in objects {
+            let location = meta.location.to_string();
+            if processed.contains(&location) {
+                continue;
+            }
+
+            let url = StorageLocation::new(&schema, &bucket)
+                .path(&location)
+                .build()?;
+            let source = match spec.format.to_lowercase().as_str() {
+                "csv" => {
+                    self.ctx
+                        .read_csv(url.as_str(), CsvReadOptions::new())
+                        .await?
+                }
+                "parquet" => {
+                    self.ctx
+                        .read_parquet(url.as_str(), Default::default())
+                        .await?
+                }
+                _ => return Err(anyhow::anyhow!("Unsupported format: {}", spec.format)),
+            };
+            let actual_schema = Arc::new(source.schema().as_arrow().clone());
+
+            let df = match SchemaDrift::detect(&target_schema, &actual_schema) {
+                None if spec.format.eq_ignore_ascii_case("csv") => {
+                    // 没有 drift 时按目标表的类型读取 CSV，避免推断出来的类型不一致
+                    self.ctx
+                        .read_csv(url, CsvReadOptions::new().schema(target_schema.as_ref()))
+                        .await?
+                }
+                None => source,
+                Some(drift) => {
+                    let event = SchemaDriftEvent {
+                        source: spec.name.clone(),
+                        table: spec.table.clone(),
+                        location: location.clone(),
+                        expected: target_schema.clone(),
+                        actual: actual_schema,
+                        drift: drift.clone(),
+                        policy: spec.drift_policy,
+                        detected_at: Utc::now(),
+                    };
+                    // Fail 时下次扫描还会遇到这个文件，已经报告过的 drift 不再重复记录
+                    if !self.schema_drift_recorded(&event).await? {
+                        self.record_schema_drift(event).await?;
+                    }
+                    match spec.drift_policy {
+                        DriftPolicy::Fail => {
+                            return Err(anyhow::anyhow!(
+                                "schema drift on {}: {:?}",
+                                location,
+                                drift
+                            ))
+                        }
+                        DriftPolicy::Skip => {
+                            self.mark_ingested(&spec.name, &location, meta.size).await?;
+                            continue;
+                        }
+                        DriftPolicy::Project => project_to_schema(source, &target_schema)?,
+                    }
+                }
+            };
+            // 和 INSERT 一样加表锁、分配行 ID、先写 WAL，写入成功之后才标记为已导入，
+            // 重启后不会出现对象标记为已导入但数据没有恢复的情况
+            let batches = project_to_schema(df, &target_schema)?
+                .collect()
+                .await
+                .with_context(|| format!("read {}", location))?;
+            self.insert_batches(&spec.table, batches)
+                .await
+                .with_context(|| format!("ingest {} into {}", location, spec.table))?;
+
+            self.mark_ingested(&spec.name, &location, meta.size).await?;
+            ingested.push(location);
+        }
+
+        Ok(ingested)
+    }
+
+    async fn mark_ingested(&self, watch: &str, location: &str, size: usize) -> anyhow::Result<()> {
+        self.execute(&format!(
+            "INSERT INTO {} VALUES ({}, {}, {}, {})",
+            INGESTED_OBJECTS_TABLE,
+            quote_literal(watch),
+            quote_literal(location),
+            size,
+            Utc::now().timestamp_millis()
+        ))
+        .await
+    }
+
+    /// 后台定时扫描目录，直到返回的 JoinHandle 被 abort
+    pub fn spawn_directory_watcher(
+        self: &Arc<Self>,
+        spec: WatchSpec,
+    ) -> tokio::task::JoinHandle<()> {
+        let db = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(spec.interval);
+            loop {
+                ticker.tick().await;
+                match db.poll_directory(&spec).await {
+                    Ok(ingested) if !ingested.is_empty() => {
+                        tracing::info!(watch = %spec.name, ?ingested, "objects ingested")
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(watch = %spec.name, error = %e, "watch failed"),
+                }
+            }
+        })
+    }
+
+    async fn ensure_ingested_objects_table(&self) -> anyhow::Result<()> {
+        self.execute(INGESTED_OBJECTS_DDL).await
+    }
+
+    async fn ingested_objects(&self, watch: &str) -> anyhow::Result<HashSet<String>> {
+        let batches = self
+            .query_to_batches(&format!(
+                "SELECT location FROM {} WHERE watch = {}",
+                INGESTED_OBJECTS_TABLE,
+                quote_literal(watch)
+            ))
+            .await?;
+
+        let mut locations = HashSet::new();
+        for batch in batches {
+            let column = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .context("location column should be utf8")?;
+            for i in 0..column.len() {
+                locations.insert(column.value(i).to_string());
+            }
+        }
+        Ok(locations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StorageConfig;
+    use crate::pool::StorageEntry;
+    use crate::wal::WalConfig;
+    use object_store::aws::AwsCredential;
+    use object_store::local::LocalFileSystem;
+    use object_store::StaticCredentialProvider;
+    use std::fs;
+    use tempfile::tempdir;
+
+    // 用本地文件系统模拟一个存储，location 是去掉开头 / 的绝对路径
+    fn register_local_storage(db: &DB<()>) {
+        let config = StorageConfig {
+            access_key: String::new(),
+            access_secret: String::new(),
+            endpoint: None,
+            region: String::new(),
+            bucket: String::new(),
+            schema: "file".to_string(),
+            session_token: None,
+            token_provider: None,
+            create_bucket_if_missing: false,
+            cost_per_gb_read: None,
+            cost_per_gb_written: None,
+            rate_limit: None,
+            prefix: None,
+        };
+        let credentials = Arc::new(StaticCredentialProvider::new(AwsCredential {
+            key_id: String::new(),
+            secret_key: String::new(),
+            token: None,
+        }));
+        db.registered_storages.write().unwrap().insert(
+            "local".to_string(),
+            StorageEntry {
+                store: Arc::new(LocalFileSystem::new()),
+                credentials,
+                config,
+                counters: Default::default(),
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_directory() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("a.csv"), "id,name\n1,Alice\n2,Bob\n")?;
+        fs::write(dir.path().join("ignored.txt"), "not a csv")?;
+
+        let db = DB::<()>::new("test_db");
+        register_local_storage(&db);
+        db.execute("CREATE TABLE users (id BIGINT, name VARCHAR)")
+            .await?;
+
+        let spec = WatchSpec {
+            name: "users_drop".to_string(),
+            storage: "local".to_string(),
+            prefix: dir
+                .path()
+                .to_string_lossy()
+                .trim_start_matches('/')
+                .to_string(),
+            table: "users".to_string(),
+            format: "csv".to_string(),
+            interval: Duration::from_secs(1),
+            drift_policy: DriftPolicy::Fail,
+        };
+
+        let ingested = db.poll_directory(&spec).await?;
+        assert_eq!(ingested.len(), 1);
+        assert!(ingested[0].ends_with("a.csv"));
+
+        // 已经导入的文件不会重复导入
+        fs::write(dir.path().join("b.csv"), "id,name\n3,Charlie\n")?;
+        let ingested = db.poll_directory(&spec).await?;
+        assert_eq!(ingested.len(), 1);
+        assert!(ingested[0].ends_with("b.csv"));
+
+        let count = db.query("SELECT * FROM users").await?.count().await?;
+        assert_eq!(count, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_directory_schema_drift() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join("drift.csv"),
+            "id,nickname,score\n1,Al,9.5\n2,Bo,7.0\n",
+        )?;
+
+        let db = DB::<()>::new("test_db");
+        register_local_storage(&db);
+        db.execute("CREATE TABLE users (id BIGINT, name VARCHAR)")
+            .await?;
+        let mut events = db.subscribe();
+
+        let mut spec = WatchSpec {
+            name: "users_drop".to_string(),
+            storage: "local".to_string(),
+            prefix: dir
+                .path()
+                .to_string_lossy()
+                .trim_start_matches('/')
+                .to_string(),
+            table: "users".to_string(),
+            format: "csv".to_string(),
+            interval: Duration::from_secs(1),
+            drift_policy: DriftPolicy::Fail,
+        };
+        assert!(db.poll_directory(&spec).await.is_err());
+        match events.try_recv()? {
+            crate::events::CacheEvent::SchemaDrift(event) => {
+                assert_eq!(event.drift.missing_columns, vec!["name".to_string()]);
+                assert_eq!(
+                    event.drift.extra_columns,
+                    vec!["nickname".to_string(), "score".to_string()]
+                );
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        // 再次扫描仍然失败，但同一个 drift 只记录一次
+        assert!(db.poll_directory(&spec).await.is_err());
+        assert!(events.try_recv().is_err());
+
+        // Project 策略：缺少的 name 填 NULL，多出来的列丢弃
+        spec.drift_policy = DriftPolicy::Project;
+        let ingested = db.poll_directory(&spec).await?;
+        assert_eq!(ingested.len(), 1);
+        let count = db
+            .query("SELECT * FROM users WHERE name IS NULL")
+            .await?
+            .count()
+            .await?;
+        assert_eq!(count, 2);
+
+        let drift_count = db
+            .query("SELECT * FROM __schema_drift")
+            .await?
+            .count()
+            .await?;
+        assert_eq!(drift_count, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_directory_survives_recovery() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let wal_dir = tempdir()?;
+        fs::write(dir.path().join("a.csv"), "id,name\n1,Alice\n2,Bob\n")?;
+        let spec = WatchSpec {
+            name: "users_drop".to_string(),
+            storage: "local".to_string(),
+            prefix: dir
+                .path()
+                .to_string_lossy()
+                .trim_start_matches('/')
+                .to_string(),
+            table: "users".to_string(),
+            format: "csv".to_string(),
+            interval: Duration::from_secs(1),
+            drift_policy: DriftPolicy::Fail,
+        };
+        {
+            let db = DB::<()>::new("test_db");
+            db.enable_wal(WalConfig::new(wal_dir.path()))?;
+            register_local_storage(&db);
+            db.execute("CREATE TABLE users (id BIGINT, name VARCHAR)")
+                .await?;
+            assert_eq!(db.poll_directory(&spec).await?.len(), 1);
+        }
+
+        // 导入的数据和导入标记一起恢复，重启后不会再导入同一个文件
+        let db = DB::<()>::new("test_db");
+        db.enable_wal(WalConfig::new(wal_dir.path()))?;
+        db.recovery().await?;
+        register_local_storage(&db);
+        assert_eq!(db.ctx.table("users").await?.count().await?, 2);
+        assert!(db.poll_directory(&spec).await?.is_empty());
+        assert_eq!(db.ctx.table("users").await?.count().await?, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use crate::pool::DB;
+use anyhow::Result;
+use arrow_schema::{DataType, TimeUnit};
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::{Array, BooleanArray, Int64Array};
+use datafusion::arrow::compute::{cast, filter_record_batch, not};
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// 事件时间早于 watermark 的迟到数据的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatePolicy {
+    // 照常写入表，按事件时间落到正确的窗口
+    Accept,
+    // 写入 `{table}__corrections`，由下游单独修正聚合结果
+    Corrections,
+    // 丢弃，只计数
+    Drop,
+}
+
+/// 按事件时间写入的表的 watermark 配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatermarkConfig {
+    // 事件时间列，timestamp 类型或者毫秒时间戳
+    pub event_time_column: String,
+    // watermark = 已见到的最大事件时间 - allowed_lateness
+    pub allowed_lateness_secs: u64,
+    pub late_policy: LatePolicy,
+}
+
+/// 一次 insert_events 的结果
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WatermarkReport {
+    // 按时到达的行
+    pub on_time: usize,
+    // 迟到但是按 LatePolicy::Accept 写入的行
+    pub late_accepted: usize,
+    pub corrected: usize,
+    pub dropped: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct WatermarkState {
+    config: WatermarkConfig,
+    // 已见到的最大事件时间，毫秒
+    max_event_time: Option<i64>,
+    // 累计的迟到行数
+    late_events: usize,
+}
+
+/// 迟到数据写入的修正表名
+pub fn corrections_table_name(table: &str) -> String {
+    format!("{}__corrections", table)
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 为表设置 watermark，之后通过 insert_events 写入的数据按配置处理迟到数据
+    pub fn set_watermark(&self, table: &str, config: WatermarkConfig) {
+        self.watermarks.write().unwrap().insert(
+            table.to_string(),
+            WatermarkState {
+                config,
+                max_event_time: None,
+                late_events: 0,
+            },
+        );
+    }
+
+    /// 当前的 watermark，还没有写入数据或者没有配置时为 None
+    pub fn watermark(&self, table: &str) -> Option<DateTime<Utc>> {
+        let watermarks = self.watermarks.read().unwrap();
+        let state = watermarks.get(table)?;
+        let max = state.max_event_time?;
+        DateTime::from_timestamp_millis(max - state.config.allowed_lateness_secs as i64 * 1000)
+    }
+
+    /// 累计的迟到行数，包括被丢弃的
+    pub fn late_events(&self, table: &str) -> usize {
+        self.watermarks
+            .read()
+            .unwrap()
+            .get(table)
+            .map_or(0, |s| s.late_events)
+    }
+
+    /// 写入乱序的事件数据，事件时间早于 watermark 的行按 LatePolicy 处理
+    /// 批内的乱序数据使用写入前的 watermark 判断，不会互相影响
+    pub async fn insert_events(
+        &self,
+        table: &str,
+        batches: Vec<RecordBatch>,
+    ) -> Result<WatermarkReport> {
+        let config = self
+            .watermarks
+            .read()
+            .unwrap()
+            .get(table)
+            .map(|s| s.config.clone())
+            .ok_or_else(|| anyhow::anyhow!("No watermark configured for table {}", table))?;
+
+        let mut report = WatermarkReport::default();
+        let mut on_time = Vec::new();
+        let mut late = Vec::new();
+        let mut max_event_time = None;
+        let watermark = self.watermark(table).map(|w| w.timestamp_millis());
+        for batch in batches {
+            let event_times = event_times(&batch, &config.event_time_column)?;
+            max_event_time = max_event_time.max(datafusion::arrow::compute::max(&event_times));
+            let is_late: BooleanArray = event_times
+                .iter()
+                .map(|t| Some(matches!((t, watermark), (Some(t), Some(w)) if t < w)))
+                .collect();
+            let late_batch = filter_record_batch(&batch, &is_late)?;
+            let on_time_batch = filter_record_batch(&batch, &not(&is_late)?)?;
+            report.on_time += on_time_batch.num_rows();
+            on_time.push(on_time_batch);
+            late.push(late_batch);
+        }
+
+        let late_rows = late.iter().map(|b| b.num_rows()).sum::<usize>();
+        match config.late_policy {
+            LatePolicy::Accept => {
+                report.late_accepted = late_rows;
+                on_time.extend(late);
+            }
+            LatePolicy::Corrections if late_rows > 0 => {
+                let corrections = corrections_table_name(table);
+                if !self.ctx.table_exist(corrections.as_str())? {
+                    // 通过 rewrite_table 创建，开启 WAL 时重放能找到这个表
+                    let _guard = self.lock_table(&corrections).await;
+                    let schema = self.ctx.table_provider(table).await?.schema();
+                    self.rewrite_table(&corrections, schema, vec![vec![]], None)?;
+                }
+                self.append_batches(&corrections, late).await?;
+                report.corrected = late_rows;
+            }
+            LatePolicy::Corrections => {}
+            LatePolicy::Drop => report.dropped = late_rows,
+        }
+        on_time.retain(|b| b.num_rows() > 0);
+        self.append_batches(table, on_time).await?;
+
+        if let Some(state) = self.watermarks.write().unwrap().get_mut(table) {
+            state.max_event_time = state.max_event_time.max(max_event_time);
+            state.late_events += late_rows;
+        }
+        Ok(report)
+    }
+}
+
+// 事件时间列转换成毫秒
+fn event_times(batch: &RecordBatch, column: &str) -> Result<Int64Array> {
+    let array = batch
+        .column_by_name(column)
+        .ok_or_else(|| anyhow::anyhow!("Event time column {} not found", column))?;
+    let millis = match array.data_type() {
+        DataType::Int64 => array.clone(),
+        _ => cast(
+            &cast(array, &DataType::Timestamp(TimeUnit::Millisecond, None))?,
+            &DataType::Int64,
+        )?,
+    };
+    Ok(millis
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .ok_or_else(|| anyhow::anyhow!("Event time column {} should be a timestamp", column))?
+        .clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_schema::{Field, Schema};
+    use std::sync::Arc;
+
+    fn events(times: &[i64]) -> Result<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![Field::new("ts", DataType::Int64, false)]));
+        Ok(RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int64Array::from(times.to_vec()))],
+        )?)
+    }
+
+    #[tokio::test]
+    async fn test_late_policies() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        for (table, policy) in [
+            ("accepted", LatePolicy::Accept),
+            ("corrected", LatePolicy::Corrections),
+            ("dropped", LatePolicy::Drop),
+        ] {
+            db.execute(&format!("CREATE TABLE {} (ts BIGINT NOT NULL)", table))
+                .await?;
+            db.set_watermark(
+                table,
+                WatermarkConfig {
+                    event_time_column: "ts".to_string(),
+                    allowed_lateness_secs: 10,
+                    late_policy: policy,
+                },
+            );
+            // 批内乱序不算迟到
+            let report = db
+                .insert_events(table, vec![events(&[60_000, 20_000, 30_000])?])
+                .await?;
+            assert_eq!(report.on_time, 3);
+            assert_eq!(
+                db.watermark(table).map(|w| w.timestamp_millis()),
+                Some(50_000)
+            );
+            db.insert_events(table, vec![events(&[40_000, 55_000])?])
+                .await?;
+            assert_eq!(db.late_events(table), 1);
+        }
+
+        let count = |sql: &'static str| {
+            let db = &db;
+            async move { Ok::<_, anyhow::Error>(db.query(sql).await?.count().await?) }
+        };
+        assert_eq!(count("SELECT * FROM accepted").await?, 5);
+        assert_eq!(count("SELECT * FROM corrected").await?, 4);
+        assert_eq!(count("SELECT * FROM corrected__corrections").await?, 1);
+        assert_eq!(count("SELECT * FROM dropped").await?, 4);
+        assert!(db.insert_events("missing", vec![]).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use crate::pool::DB;
+use crate::system::SYSTEM_SCHEMA;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use async_trait::async_trait;
+use datafusion::arrow::array::{ArrayRef, Float64Array, StringArray, UInt64Array};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::catalog_common::MemorySchemaProvider;
+use datafusion::datasource::{MemTable, TableProvider, TableType};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::prelude::SessionContext;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::tokenizer::{Token, Tokenizer};
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+pub const WORKLOAD_TABLE: &str = "workload";
+
+// 每个 fingerprint 最多保留多少次延迟用于计算分位数
+const MAX_LATENCY_SAMPLES: usize = 1024;
+
+/// 某一类查询（fingerprint 相同）的累计统计
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WorkloadStats {
+    sample_sql: String,
+    executions: u64,
+    errors: u64,
+    total_ms: f64,
+    rows_scanned: u64,
+    latencies_ms: VecDeque<f64>,
+}
+
+/// 对外暴露的 workload 汇总
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkloadSummary {
+    pub fingerprint: String,
+    // 归一化之后的 SQL
+    pub normalized_sql: String,
+    // 最近一次执行的原始 SQL
+    pub sample_sql: String,
+    pub executions: u64,
+    pub errors: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub rows_scanned: u64,
+}
+
+/// 把 SQL 中的字面量替换成 ?，关键字转小写，IN 列表合并成一个 ?
+/// 解析失败时按原文去掉多余空白
+pub fn normalize_sql(sql: &str) -> String {
+    let tokens = match Tokenizer::new(&GenericDialect {}, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return sql.split_whitespace().collect::<Vec<_>>().join(" "),
+    };
+
+    let parts: Vec<String> = tokens
+        .iter()
+        .filter(|t| !matches!(t, Token::Whitespace(_)))
+        .map(|t| match t {
+            Token::Number(_, _)
+            | Token::SingleQuotedString(_)
+            | Token::DoubleQuotedString(_)
+            | Token::NationalStringLiteral(_)
+            | Token::EscapedStringLiteral(_)
+            | Token::HexStringLiteral(_) => "?".to_string(),
+            Token::Word(w) if w.quote_style.is_none() => w.value.to_lowercase(),
+            other => other.to_string(),
+        })
+        .collect();
+
+    let mut normalized = parts.join(" ");
+    while normalized.contains("? , ?") {
+        normalized = normalized.replace("? , ?", "?");
+    }
+    normalized
+}
+
+/// 归一化后的 SQL 的 FNV-1a 哈希，跨进程稳定
+pub fn fingerprint(sql: &str) -> String {
+    fingerprint_normalized(&normalize_sql(sql))
+}
+
+pub(crate) fn fingerprint_normalized(normalized: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in normalized.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+// system.workload 在查询时才根据当前的统计生成，执行查询时只更新统计，不用每次重建整张表
+#[derive(Debug)]
+struct WorkloadTable {
+    stats: Arc<RwLock<HashMap<String, WorkloadStats>>>,
+}
+
+#[async_trait]
+impl TableProvider for WorkloadTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        workload_schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        let batch = workload_batch(&summarize(&self.stats.read().unwrap()))?;
+        MemTable::try_new(batch.schema(), vec![vec![batch]])?
+            .scan(state, projection, filters, limit)
+            .await
+    }
+}
+
+// 在 system schema 下注册 workload 表，DB 创建时调用
+pub(crate) fn install_workload_table(
+    ctx: &SessionContext,
+    stats: Arc<RwLock<HashMap<String, WorkloadStats>>>,
+) {
+    let state = ctx.state();
+    let Some(catalog) = ctx.catalog(&state.config().options().catalog.default_catalog) else {
+        return;
+    };
+    let schema = match catalog.schema(SYSTEM_SCHEMA) {
+        Some(schema) => schema,
+        None => {
+            let schema = Arc::new(MemorySchemaProvider::new());
+            let _ = catalog.register_schema(SYSTEM_SCHEMA, schema.clone());
+            schema
+        }
+    };
+    let _ = schema.register_table(
+        WORKLOAD_TABLE.to_string(),
+        Arc::new(WorkloadTable { stats }),
+    );
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 按 fingerprint 汇总的查询统计，按执行次数倒序
+    pub fn workload_summary(&self) -> Vec<WorkloadSummary> {
+        summarize(&self.workload.read().unwrap())
+    }
+
+    // 记录一次执行，system.workload 在查询时读取最新的统计
+    pub(crate) fn record_workload(
+        &self,
+        sql: &str,
+        elapsed: Duration,
+        rows_scanned: usize,
+        success: bool,
+    ) {
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+        let mut workload = self.workload.write().unwrap();
+        let stats = workload.entry(normalize_sql(sql)).or_default();
+        stats.sample_sql = sql.to_string();
+        stats.executions += 1;
+        if !success {
+            stats.errors += 1;
+        }
+        stats.total_ms += elapsed_ms;
+        stats.rows_scanned += rows_scanned as u64;
+        if stats.latencies_ms.len() == MAX_LATENCY_SAMPLES {
+            stats.latencies_ms.pop_front();
+        }
+        stats.latencies_ms.push_back(elapsed_ms);
+    }
+}
+
+fn summarize(workload: &HashMap<String, WorkloadStats>) -> Vec<WorkloadSummary> {
+    let mut summaries: Vec<WorkloadSummary> = workload
+        .iter()
+        .map(|(normalized, stats)| {
+            let mut sorted: Vec<f64> = stats.latencies_ms.iter().copied().collect();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            WorkloadSummary {
+                fingerprint: fingerprint_normalized(normalized),
+                normalized_sql: normalized.clone(),
+                sample_sql: stats.sample_sql.clone(),
+                executions: stats.executions,
+                errors: stats.errors,
+                mean_ms: stats.total_ms / stats.executions.max(1) as f64,
+                p50_ms: percentile(&sorted, 0.50),
+                p95_ms: percentile(&sorted, 0.95),
+                p99_ms: percentile(&sorted, 0.99),
+                rows_scanned: stats.rows_scanned,
+            }
+        })
+        .collect();
+    summaries.sort_by(|a, b| {
+        b.executions
+            .cmp(&a.executions)
+            .then_with(|| a.fingerprint.cmp(&b.fingerprint))
+    });
+    summaries
+}
+
+fn workload_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("fingerprint", DataType::Utf8, false),
+        Field::new("normalized_sql", DataType::Utf8, false),
+        Field::new("sample_sql", DataType::Utf8, false),
+        Field::new("executions", DataType::UInt64, false),
+        Field::new("errors", DataType::UInt64, false),
+        Field::new("mean_ms", DataType::Float64, false),
+        Field::new("p50_ms", DataType::Float64, false),
+        Field::new("p95_ms", DataType::Float64, false),
+        Field::new("p99_ms", DataType::Float64, false),
+        Field::new("rows_scanned", DataType::UInt64, false),
+    ]))
+}
+
+fn workload_batch(summaries: &[WorkloadSummary]) -> std::result::Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            summaries.iter().map(|s| &s.fingerprint),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            summaries.iter().map(|s| &s.normalized_sql),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            summaries.iter().map(|s| &s.sample_sql),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            summaries.iter().map(|s| s.executions),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            summaries.iter().map(|s| s.errors),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            summaries.iter().map(|s| s.mean_ms),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            summaries.iter().map(|s| s.p50_ms),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            summaries.iter().map(|s| s.p95_ms),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            summaries.iter().map(|s| s.p99_ms),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            summaries.iter().map(|s| s.rows_scanned),
+        )),
+    ];
+    RecordBatch::try_new(workload_schema(), columns)
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[rank]
+}
+
+/// 叶子节点（数据源）读取的行数；没有 metrics 的节点（比如内存表）使用统计信息
+pub(crate) fn rows_scanned(plan: &Arc<dyn ExecutionPlan>) -> usize {
+    let children = plan.children();
+    if !children.is_empty() {
+        return children.into_iter().map(rows_scanned).sum();
+    }
+    if let Some(rows) = plan.metrics().and_then(|m| m.output_rows()) {
+        return rows;
+    }
+    plan.statistics()
+        .ok()
+        .and_then(|s| s.num_rows.get_value().copied())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_normalize_sql() {
+        assert_eq!(
+            normalize_sql("SELECT * FROM t WHERE id = 42 AND name = 'Alice'"),
+            "select * from t where id = ? and name = ?"
+        );
+        assert_eq!(
+            normalize_sql("select *  from t where id in (1, 2, 3)"),
+            "select * from t where id in ( ? )"
+        );
+        assert_eq!(
+            fingerprint("SELECT * FROM t WHERE id = 1"),
+            fingerprint("select * from t where id = 2")
+        );
+        assert_ne!(
+            fingerprint("SELECT * FROM t WHERE id = 1"),
+            fingerprint("SELECT * FROM t WHERE name = 'a'")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workload_summary() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE t (id BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b'), (3, 'c')")
+            .await?;
+
+        for id in 1..=3 {
+            db.query_to_batches(&format!("SELECT * FROM t WHERE id = {}", id))
+                .await?;
+        }
+        assert!(db.query_to_batches("SELECT * FROM missing").await.is_err());
+
+        let summary = db.workload_summary();
+        let select = summary
+            .iter()
+            .find(|s| s.normalized_sql == "select * from t where id = ?")
+            .unwrap();
+        assert_eq!(select.executions, 3);
+        assert_eq!(select.errors, 0);
+        assert_eq!(select.rows_scanned, 9);
+        assert!(select.p99_ms >= select.p50_ms);
+
+        let failed = summary
+            .iter()
+            .find(|s| s.normalized_sql == "select * from missing")
+            .unwrap();
+        assert_eq!(failed.errors, 1);
+
+        let batches = db
+            .query_to_batches("SELECT executions FROM system.workload WHERE executions = 3")
+            .await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+        // system.workload 在读取时生成，包含之后的执行
+        db.query_to_batches("SELECT * FROM t WHERE id = 4").await?;
+        let batches = db
+            .query_to_batches("SELECT executions FROM system.workload WHERE executions = 4")
+            .await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+        Ok(())
+    }
+}
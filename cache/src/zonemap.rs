@@ -0,0 +1,378 @@
+use crate::pool::DB;
+use crate::swap::IndexedTable;
+use anyhow::Result;
+use arrow_schema::SchemaRef;
+use async_trait::async_trait;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::common::{Constraints, ScalarValue};
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::functions_aggregate::min_max::{MaxAccumulator, MinAccumulator};
+use datafusion::logical_expr::dml::InsertOp;
+use datafusion::logical_expr::{
+    Accumulator, BinaryExpr, Expr, Operator, TableProviderFilterPushDown,
+};
+use datafusion::physical_plan::insert::{DataSink, DataSinkExec};
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::metrics::MetricsSet;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan};
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::Any;
+use std::cmp::Ordering as CmpOrdering;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+// 一个 batch 和它在各个 zone map 列上的 (min, max)
+#[derive(Debug)]
+struct Zone {
+    batch: RecordBatch,
+    ranges: Vec<(ScalarValue, ScalarValue)>,
+}
+
+#[derive(Debug)]
+struct ZoneData {
+    schema: SchemaRef,
+    // 建了 zone map 的列
+    columns: Vec<usize>,
+    zones: RwLock<Vec<Zone>>,
+    // 转换之前表上的约束
+    constraints: Option<Constraints>,
+}
+
+impl ZoneData {
+    fn zone(&self, batch: RecordBatch) -> datafusion::error::Result<Zone> {
+        let mut ranges = Vec::with_capacity(self.columns.len());
+        for index in &self.columns {
+            let array = batch.column(*index);
+            let mut min = MinAccumulator::try_new(array.data_type())?;
+            let mut max = MaxAccumulator::try_new(array.data_type())?;
+            min.update_batch(&[array.clone()])?;
+            max.update_batch(&[array.clone()])?;
+            ranges.push((min.evaluate()?, max.evaluate()?));
+        }
+        Ok(Zone { batch, ranges })
+    }
+
+    fn zones(&self, batches: impl IntoIterator<Item = RecordBatch>) -> Result<Vec<Zone>> {
+        Ok(batches
+            .into_iter()
+            .filter(|b| b.num_rows() > 0)
+            .map(|b| self.zone(b))
+            .collect::<datafusion::error::Result<Vec<_>>>()?)
+    }
+
+    // 根据 min/max 判断 batch 中不可能有满足 filter 的行
+    fn can_skip(&self, zone: &Zone, filter: &Expr) -> bool {
+        let Expr::BinaryExpr(BinaryExpr { left, op, right }) = filter else {
+            return false;
+        };
+        let (column, op, value) = match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(c), Expr::Literal(v)) => (c, *op, v),
+            (Expr::Literal(v), Expr::Column(c)) => match op.swap() {
+                Some(op) => (c, op, v),
+                None => return false,
+            },
+            _ => return false,
+        };
+        let Some(position) = self
+            .columns
+            .iter()
+            .position(|i| self.schema.field(*i).name() == &column.name)
+        else {
+            return false;
+        };
+        let (min, max) = &zone.ranges[position];
+        // 全是 NULL 的 batch，比较结果都不为真
+        if min.is_null() || max.is_null() {
+            return true;
+        }
+        let Ok(value) = value.cast_to(&min.data_type()) else {
+            return false;
+        };
+        let (Some(to_min), Some(to_max)) = (value.partial_cmp(min), value.partial_cmp(max)) else {
+            return false;
+        };
+        match op {
+            Operator::Eq => to_min == CmpOrdering::Less || to_max == CmpOrdering::Greater,
+            // col < v
+            Operator::Lt => to_min != CmpOrdering::Greater,
+            Operator::LtEq => to_min == CmpOrdering::Less,
+            Operator::Gt => to_max != CmpOrdering::Less,
+            Operator::GtEq => to_max == CmpOrdering::Greater,
+            _ => false,
+        }
+    }
+}
+
+/// 按 batch 记录指定列 min/max 的追加表，过滤条件落在 batch 范围之外时跳过整个 batch
+/// 适合按时间顺序追加的事件表上的时间范围查询
+#[derive(Debug)]
+pub struct ZoneMappedTable {
+    data: Arc<ZoneData>,
+    pruned_batches: AtomicU64,
+}
+
+/// zone map 的统计
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneMapStats {
+    pub batches: usize,
+    // 累计跳过的 batch 数
+    pub pruned_batches: u64,
+}
+
+#[async_trait]
+impl TableProvider for ZoneMappedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.data.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn constraints(&self) -> Option<&Constraints> {
+        self.data.constraints.as_ref()
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> datafusion::error::Result<Vec<TableProviderFilterPushDown>> {
+        // 只用来跳过 batch，DataFusion 仍然会逐行过滤
+        Ok(vec![TableProviderFilterPushDown::Inexact; filters.len()])
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        let zones = self.data.zones.read().unwrap();
+        let batches: Vec<RecordBatch> = zones
+            .iter()
+            .filter(|zone| !filters.iter().any(|f| self.data.can_skip(zone, f)))
+            .map(|zone| zone.batch.clone())
+            .collect();
+        self.pruned_batches
+            .fetch_add((zones.len() - batches.len()) as u64, Ordering::Relaxed);
+        Ok(Arc::new(MemoryExec::try_new(
+            &[batches],
+            self.schema(),
+            projection.cloned(),
+        )?))
+    }
+
+    async fn insert_into(
+        &self,
+        _state: &dyn Session,
+        input: Arc<dyn ExecutionPlan>,
+        insert_op: InsertOp,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        if insert_op == InsertOp::Replace {
+            return Err(datafusion::error::DataFusionError::NotImplemented(
+                "REPLACE is not supported on zone mapped tables".to_string(),
+            ));
+        }
+        let sink = ZoneMapSink {
+            data: self.data.clone(),
+            overwrite: insert_op == InsertOp::Overwrite,
+        };
+        Ok(Arc::new(DataSinkExec::new(
+            input,
+            Arc::new(sink),
+            self.schema(),
+            None,
+        )))
+    }
+}
+
+#[derive(Debug)]
+struct ZoneMapSink {
+    data: Arc<ZoneData>,
+    overwrite: bool,
+}
+
+impl DisplayAs for ZoneMapSink {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ZoneMapSink")
+    }
+}
+
+#[async_trait]
+impl DataSink for ZoneMapSink {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        None
+    }
+
+    async fn write_all(
+        &self,
+        mut data: SendableRecordBatchStream,
+        _context: &Arc<TaskContext>,
+    ) -> datafusion::error::Result<u64> {
+        let mut zones = Vec::new();
+        let mut rows = 0;
+        while let Some(batch) = data.next().await.transpose()? {
+            if batch.num_rows() > 0 {
+                rows += batch.num_rows() as u64;
+                zones.push(self.data.zone(batch)?);
+            }
+        }
+        let mut all = self.data.zones.write().unwrap();
+        if self.overwrite {
+            all.clear();
+        }
+        all.extend(zones);
+        Ok(rows)
+    }
+}
+
+impl IndexedTable for ZoneMappedTable {
+    fn in_memory(&self) -> bool {
+        true
+    }
+
+    // zone map 列按名字对应到新的 schema，重写可能增加列（比如 _row_id）
+    fn reindex(
+        &self,
+        schema: SchemaRef,
+        partitions: Vec<Vec<RecordBatch>>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        let columns = self
+            .data
+            .columns
+            .iter()
+            .map(|i| schema.index_of(self.data.schema.field(*i).name()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let data = ZoneData {
+            schema,
+            columns,
+            zones: RwLock::new(Vec::new()),
+            constraints: self.data.constraints.clone(),
+        };
+        *data.zones.write().unwrap() = data.zones(partitions.into_iter().flatten())?;
+        Ok(Arc::new(ZoneMappedTable {
+            data: Arc::new(data),
+            pruned_batches: AtomicU64::new(self.pruned_batches.load(Ordering::Relaxed)),
+        }))
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + Send + Sync> DB<V> {
+    /// 把表转换成在 columns 上带 zone map 的表，之后的写入也会记录 min/max
+    ///
+    /// 原表的约束会保留；转换在表的写锁里完成，不会丢失同时进行的写入
+    pub async fn create_zone_map(&self, table: &str, columns: &[&str]) -> Result<()> {
+        let _guard = self.lock_table(table).await;
+        let provider = self.ctx.table_provider(self.table_ref(table)).await?;
+        let schema = provider.schema();
+        let columns = columns
+            .iter()
+            .map(|c| schema.index_of(c))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let data = Arc::new(ZoneData {
+            schema: schema.clone(),
+            columns,
+            zones: RwLock::new(Vec::new()),
+            constraints: provider.constraints().cloned(),
+        });
+
+        let plan = provider.scan(&self.ctx.state(), None, &[], None).await?;
+        let batches = datafusion::physical_plan::collect(plan, self.ctx.task_ctx()).await?;
+        *data.zones.write().unwrap() = data.zones(batches)?;
+
+        self.replace_table(
+            self.table_ref(table).table(),
+            Arc::new(ZoneMappedTable {
+                data,
+                pruned_batches: AtomicU64::new(0),
+            }),
+        )?;
+        Ok(())
+    }
+
+    pub async fn zone_map_stats(&self, table: &str) -> Result<ZoneMapStats> {
+        let provider = self.ctx.table_provider(table).await?;
+        let zoned = provider
+            .as_any()
+            .downcast_ref::<ZoneMappedTable>()
+            .ok_or_else(|| anyhow::anyhow!("Table {} has no zone map", table))?;
+        let stats = ZoneMapStats {
+            batches: zoned.data.zones.read().unwrap().len(),
+            pruned_batches: zoned.pruned_batches.load(Ordering::Relaxed),
+        };
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_zone_map_pruning() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE events (ts BIGINT, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO events VALUES (1, 'a'), (50, 'b')")
+            .await?;
+        db.execute("INSERT INTO events VALUES (100, 'c'), (150, 'd')")
+            .await?;
+        db.create_zone_map("events", &["ts"]).await?;
+        // 转换之后的写入也有 zone map
+        db.execute("INSERT INTO events VALUES (200, 'e'), (250, 'f')")
+            .await?;
+        assert_eq!(db.zone_map_stats("events").await?.batches, 3);
+
+        let count = |sql: &'static str| {
+            let db = &db;
+            async move { Ok::<_, anyhow::Error>(db.query(sql).await?.count().await?) }
+        };
+        assert_eq!(count("SELECT * FROM events WHERE ts >= 200").await?, 2);
+        assert_eq!(db.zone_map_stats("events").await?.pruned_batches, 2);
+        assert_eq!(count("SELECT * FROM events WHERE 120 > ts").await?, 3);
+        assert_eq!(db.zone_map_stats("events").await?.pruned_batches, 3);
+        assert_eq!(count("SELECT * FROM events WHERE ts = 75").await?, 0);
+        assert_eq!(count("SELECT * FROM events WHERE name = 'a'").await?, 1);
+        assert_eq!(db.zone_map_stats("events").await?.pruned_batches, 6);
+
+        db.execute("INSERT OVERWRITE events VALUES (1, 'x')")
+            .await?;
+        assert_eq!(count("SELECT * FROM events").await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_zone_map_survives_rewrite() -> Result<()> {
+        let db = DB::<()>::new("test_db");
+        db.execute("CREATE TABLE events (ts BIGINT PRIMARY KEY, name VARCHAR)")
+            .await?;
+        db.execute("INSERT INTO events VALUES (1, 'a'), (50, 'b')")
+            .await?;
+        db.create_zone_map("events", &["ts"]).await?;
+        let provider = db.ctx.table_provider("events").await?;
+        assert!(provider.constraints().is_some_and(|c| !c.is_empty()));
+
+        // upsert 整表重写之后仍然是 zone map 表，约束也还在
+        let batch = db.query("SELECT 100::BIGINT AS ts, 'c' AS name").await?;
+        let batch = batch.collect().await?.remove(0);
+        db.upsert("events", &["ts"], batch.with_schema(provider.schema())?)
+            .await?;
+        assert!(db.zone_map_stats("events").await?.batches >= 2);
+        assert_eq!(db.query("SELECT * FROM events").await?.count().await?, 3);
+        let provider = db.ctx.table_provider("events").await?;
+        assert!(provider.constraints().is_some_and(|c| !c.is_empty()));
+        Ok(())
+    }
+}
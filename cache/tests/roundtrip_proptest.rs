@@ -0,0 +1,103 @@
+//! 随机生成各种类型的值，经过 create_table -> insert -> query_to_schema 之后应该和原来的值相等
+use arrow::array::{
+    ArrayRef, BooleanArray, Decimal128Array, Float64Array, Int32Array, Int64Array, StringArray,
+    UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use cache::pool::DB;
+use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// query_to_schema 支持的全部类型，每种类型一个可空列
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Row {
+    flag: Option<bool>,
+    small: Option<i32>,
+    big: Option<i64>,
+    unsigned: Option<u64>,
+    // DECIMAL(18, 2)，按分生成
+    amount: Option<f64>,
+    ratio: Option<f64>,
+    name: Option<String>,
+}
+
+fn row_strategy() -> impl Strategy<Value = (Row, Option<i64>)> {
+    (
+        any::<Option<bool>>(),
+        any::<Option<i32>>(),
+        any::<Option<i64>>(),
+        any::<Option<u64>>(),
+        // f64 能精确表示到分的范围
+        proptest::option::of(-(1i64 << 50)..(1i64 << 50)),
+        proptest::option::of(
+            prop::num::f64::NORMAL | prop::num::f64::ZERO | prop::num::f64::SUBNORMAL,
+        ),
+        any::<Option<String>>(),
+    )
+        .prop_map(|(flag, small, big, unsigned, cents, ratio, name)| {
+            (
+                Row {
+                    flag,
+                    small,
+                    big,
+                    unsigned,
+                    amount: cents.map(|c| format!("{:.2}", c as f64 / 100.0).parse().unwrap()),
+                    ratio,
+                    name,
+                },
+                cents,
+            )
+        })
+}
+
+fn schema() -> Arc<Schema> {
+    Arc::new(Schema::new(vec![
+        Field::new("flag", DataType::Boolean, true),
+        Field::new("small", DataType::Int32, true),
+        Field::new("big", DataType::Int64, true),
+        Field::new("unsigned", DataType::UInt64, true),
+        Field::new("amount", DataType::Decimal128(18, 2), true),
+        Field::new("ratio", DataType::Float64, true),
+        Field::new("name", DataType::Utf8, true),
+    ]))
+}
+
+fn to_batch(rows: &[(Row, Option<i64>)]) -> RecordBatch {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(BooleanArray::from_iter(rows.iter().map(|(r, _)| r.flag))),
+        Arc::new(Int32Array::from_iter(rows.iter().map(|(r, _)| r.small))),
+        Arc::new(Int64Array::from_iter(rows.iter().map(|(r, _)| r.big))),
+        Arc::new(UInt64Array::from_iter(rows.iter().map(|(r, _)| r.unsigned))),
+        Arc::new(
+            Decimal128Array::from_iter(rows.iter().map(|(_, cents)| cents.map(i128::from)))
+                .with_precision_and_scale(18, 2)
+                .unwrap(),
+        ),
+        Arc::new(Float64Array::from_iter(rows.iter().map(|(r, _)| r.ratio))),
+        Arc::new(StringArray::from_iter(
+            rows.iter().map(|(r, _)| r.name.as_deref()),
+        )),
+    ];
+    RecordBatch::try_new(schema(), columns).unwrap()
+}
+
+async fn round_trip(rows: &[(Row, Option<i64>)]) -> anyhow::Result<Vec<Row>> {
+    let db = DB::<Row>::new("roundtrip");
+    db.create_table(schema()).await?;
+    db.insert_batch("roundtrip", to_batch(rows)).await?;
+    db.query_to_schema("SELECT * FROM roundtrip").await
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn query_to_schema_round_trips(rows in proptest::collection::vec(row_strategy(), 0..20)) {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let loaded = runtime.block_on(round_trip(&rows)).unwrap();
+        let expected: Vec<Row> = rows.into_iter().map(|(row, _)| row).collect();
+        prop_assert_eq!(loaded, expected);
+    }
+}